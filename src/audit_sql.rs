@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+// For db.audit_sql_file: a record of each statement the tool sends that changes the db,
+// or could (COPY, DDL, INSERT, ANALYZE, and the transactions around them). Reads aren't recorded.
+// One line per statement: timestamp, run id and the statement, tab separated.
+// Every connection appends to the same file, each line written whole.
+#[derive(Debug, Default)]
+pub struct AuditSql {
+    file: Option<Mutex<File>>, // None if not configured, so nothing is recorded
+    run_id: String,
+}
+
+impl AuditSql {
+    pub fn new(path: Option<&str>, run_id: &str) -> Result<Self> {
+        let file = match path {
+            None => None,
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("can't open db.audit_sql_file {}", path))?,
+            )),
+        };
+        Ok(AuditSql {
+            file,
+            run_id: run_id.to_string(),
+        })
+    }
+
+    // Called before the statement is sent, so even one that fails is recorded.
+    // Failing to record it fails the statement, as the record would be incomplete.
+    pub fn record(&self, sql: &str) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let line = format!(
+            "{}\t{}\t{}\n",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.run_id,
+            redact(sql)
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" "),
+        );
+        file.lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .context("can't write to db.audit_sql_file")
+    }
+}
+
+// The statements we build hold no secrets, but in case one ever does, hide the value
+// after password= or PASSWORD, the way they'd appear in a conn str or CREATE ROLE.
+fn redact(sql: &str) -> String {
+    let lower = sql.to_ascii_lowercase(); // same byte offsets as sql
+    let mut redacted = String::with_capacity(sql.len());
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("password") {
+        let after = from + found + "password".len();
+        let rest = &sql[after..];
+        let trimmed = rest.trim_start();
+        let value = match trimmed.strip_prefix('=') {
+            Some(value) => value.trim_start(),
+            None if trimmed.starts_with('\'') => trimmed,
+            None => {
+                redacted.push_str(&sql[from..after]);
+                from = after;
+                continue;
+            }
+        };
+        let value_len = match value.starts_with('\'') {
+            true => quoted_len(value),
+            false => value.find(char::is_whitespace).unwrap_or(value.len()),
+        };
+        let value_at = sql.len() - value.len();
+        redacted.push_str(&sql[from..value_at]);
+        redacted.push_str("[REDACTED]");
+        from = value_at + value_len;
+    }
+    redacted.push_str(&sql[from..]);
+    redacted
+}
+
+// Length of the sql string literal value starts with, taking '' as an escaped quote
+fn quoted_len(value: &str) -> usize {
+    let mut at = 1;
+    while let Some(i) = value[at..].find('\'') {
+        let end = at + i + 1;
+        if !value[end..].starts_with('\'') {
+            return end;
+        }
+        at = end + 1;
+    }
+    value.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::fixture::TempDir;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("COPY \"orders\" (\"id\") FROM STDIN BINARY"),
+            "COPY \"orders\" (\"id\") FROM STDIN BINARY"
+        );
+        assert_eq!(
+            redact("host=db password=s3cret user=me"),
+            "host=db password=[REDACTED] user=me"
+        );
+        assert_eq!(
+            redact("ALTER ROLE loader PASSWORD 'it''s me'"),
+            "ALTER ROLE loader PASSWORD [REDACTED]"
+        );
+        assert_eq!(
+            redact("CREATE ROLE loader WITH PASSWORD 'pw' LOGIN; password = other"),
+            "CREATE ROLE loader WITH PASSWORD [REDACTED] LOGIN; password = [REDACTED]"
+        );
+        // a col named password isn't followed by a value
+        assert_eq!(
+            redact("INSERT INTO users (\"password\") SELECT \"password\" FROM tmp"),
+            "INSERT INTO users (\"password\") SELECT \"password\" FROM tmp"
+        );
+    }
+
+    #[test]
+    fn test_record() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = format!("{}/audit.sql.log", tmp_dir.path().display());
+
+        let audit = AuditSql::new(Some(&path), "run-1")?;
+        audit.record("BEGIN")?;
+        audit.record("COPY \"t\" (\"a\")\n  FROM STDIN BINARY")?;
+        // appends to what's there
+        AuditSql::new(Some(&path), "run-2")?.record("COMMIT")?;
+
+        let lines: Vec<Vec<String>> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(|line| line.split('\t').map(String::from).collect())
+            .collect();
+        tmp_dir.close().unwrap();
+
+        let recorded: Vec<(&str, &str)> = lines
+            .iter()
+            .map(|line| (line[1].as_str(), line[2].as_str()))
+            .collect();
+        assert_eq!(
+            recorded,
+            vec![
+                ("run-1", "BEGIN"),
+                ("run-1", "COPY \"t\" (\"a\") FROM STDIN BINARY"),
+                ("run-2", "COMMIT"),
+            ]
+        );
+        assert!(chrono::DateTime::parse_from_rfc3339(&lines[0][0]).is_ok());
+
+        // not configured, nothing to record to
+        AuditSql::new(None, "run-3")?.record("BEGIN")?;
+
+        Ok(())
+    }
+}
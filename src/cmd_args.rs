@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::error;
 use std::error::Error;
 use std::fmt;
@@ -13,11 +13,105 @@ impl fmt::Display for IncorrectArgs {
 
 impl Error for IncorrectArgs {}
 
+const DRY_RUN_FLAG: &str = "--dry-run";
+const VALIDATE_COPY_FLAG: &str = "--validate-copy";
+const CONFIG_FLAG: &str = "--config";
+const RECONCILE_CMD: &str = "reconcile";
+const SCHEMA_CMD: &str = "schema";
+const VERSION_FLAG: &str = "--version";
+const HELP_FLAG: &str = "--help";
+
+pub const USAGE: &str = "\
+Usage:
+  s3-parquet-to-postgres [--dry-run | --validate-copy] [--config] /path/to/config.yml
+  s3-parquet-to-postgres reconcile [--config] /path/to/config.yml
+  s3-parquet-to-postgres schema /path/to/file.parquet
+  s3-parquet-to-postgres --version
+  s3-parquet-to-postgres --help
+
+Options:
+  --dry-run        download and convert each file, without writing to the db
+  --validate-copy  as --dry-run, but COPY each file's rows in a transaction that's rolled back
+  --config         the config yaml, or - to read it from stdin
+  --version        print the version
+  --help           print this";
+
+// --version and --help can go anywhere after the binary, and need no config
+pub fn version<F>(get_args: F) -> bool
+where
+    F: Fn() -> Vec<String>,
+{
+    get_args().iter().skip(1).any(|arg| arg == VERSION_FLAG)
+}
+
+pub fn help<F>(get_args: F) -> bool
+where
+    F: Fn() -> Vec<String>,
+{
+    get_args().iter().skip(1).any(|arg| arg == HELP_FLAG)
+}
+
+// flags can go anywhere after the binary
+pub fn dry_run<F>(get_args: F) -> bool
+where
+    F: Fn() -> Vec<String>,
+{
+    get_args().iter().skip(1).any(|arg| arg == DRY_RUN_FLAG)
+}
+
+pub fn validate_copy<F>(get_args: F) -> bool
+where
+    F: Fn() -> Vec<String>,
+{
+    get_args()
+        .iter()
+        .skip(1)
+        .any(|arg| arg == VALIDATE_COPY_FLAG)
+}
+
+// the subcommand must come straight after the binary e.g. bin reconcile config.yml
+pub fn reconcile<F>(get_args: F) -> bool
+where
+    F: Fn() -> Vec<String>,
+{
+    get_args().get(1).is_some_and(|arg| arg == RECONCILE_CMD)
+}
+
+// e.g. bin schema file.parquet. Returns the parquet file, or None when it's not the schema subcommand.
+pub fn schema_file<F>(get_args: F) -> Result<Option<String>>
+where
+    F: Fn() -> Vec<String>,
+{
+    let args = get_args();
+    if args.get(1).map(String::as_str) != Some(SCHEMA_CMD) {
+        return Ok(None);
+    }
+    if args.len() != 3 {
+        error!("Incorrect number of args passed");
+        bail!("You must pass path to the parquet file e.g. schema /path/to/file.parquet");
+    }
+    Ok(Some(args[2].clone()))
+}
+
 pub fn config_yaml<F>(get_args: F) -> Result<String>
 where
     F: Fn() -> Vec<String>,
 {
-    let args: Vec<String> = get_args();
+    let mut args: Vec<String> = get_args()
+        .into_iter()
+        .filter(|arg| arg != DRY_RUN_FLAG && arg != VALIDATE_COPY_FLAG)
+        .collect();
+    if reconcile(|| args.clone()) {
+        args.remove(1);
+    }
+    // --config path can go anywhere after the binary too. Path - is stdin
+    if let Some(i) = args.iter().position(|arg| arg == CONFIG_FLAG) {
+        if i + 1 == args.len() {
+            bail!("--config needs the path to the config yaml, or - for stdin");
+        }
+        let path = args.remove(i + 1);
+        args[i] = path;
+    }
 
     // 1st arg is the binary. 2nd arg on is what we want
     if args.len() != 2 {
@@ -85,4 +179,214 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_config_yaml_succeeds_with_dry_run_flag() -> Result<()> {
+        let mock_args = || {
+            vec![
+                "test_binary".to_string(),
+                "--dry-run".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+
+        let result = config_yaml(mock_args);
+
+        assert!(result.is_ok());
+        assert_eq!(result?, "/path/to/config.yaml".to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_yaml_config_flag() -> Result<()> {
+        let with_flag = || {
+            vec![
+                "test_binary".to_string(),
+                "--config".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+        let flag_last = || {
+            vec![
+                "test_binary".to_string(),
+                "reconcile".to_string(),
+                "--dry-run".to_string(),
+                "--config".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+        let no_path = || vec!["test_binary".to_string(), "--config".to_string()];
+        let and_bare_path = || {
+            vec![
+                "test_binary".to_string(),
+                "--config".to_string(),
+                "/path/to/config.yaml".to_string(),
+                "/path/to/other.yaml".to_string(),
+            ]
+        };
+
+        assert_eq!(config_yaml(with_flag)?, "/path/to/config.yaml".to_string());
+        assert_eq!(config_yaml(flag_last)?, "/path/to/config.yaml".to_string());
+        assert!(config_yaml(no_path).is_err());
+        assert!(config_yaml(and_bare_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_yaml_stdin() -> Result<()> {
+        let with_flag = || {
+            vec![
+                "test_binary".to_string(),
+                "--config".to_string(),
+                "-".to_string(),
+            ]
+        };
+        let bare = || vec!["test_binary".to_string(), "-".to_string()];
+
+        assert_eq!(config_yaml(with_flag)?, crate::config::STDIN.to_string());
+        assert_eq!(config_yaml(bare)?, crate::config::STDIN.to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run() -> Result<()> {
+        let with_flag = || {
+            vec![
+                "test_binary".to_string(),
+                "/path/to/config.yaml".to_string(),
+                "--dry-run".to_string(),
+            ]
+        };
+        let without_flag = || {
+            vec![
+                "test_binary".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+
+        assert!(dry_run(with_flag));
+        assert!(!dry_run(without_flag));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile() -> Result<()> {
+        let with_cmd = || {
+            vec![
+                "test_binary".to_string(),
+                "reconcile".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+        let cmd_after_config = || {
+            vec![
+                "test_binary".to_string(),
+                "/path/to/config.yaml".to_string(),
+                "reconcile".to_string(),
+            ]
+        };
+
+        assert!(reconcile(with_cmd));
+        assert_eq!(config_yaml(with_cmd)?, "/path/to/config.yaml".to_string());
+        assert!(!reconcile(cmd_after_config));
+        assert!(config_yaml(cmd_after_config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_copy() -> Result<()> {
+        let with_flag = || {
+            vec![
+                "test_binary".to_string(),
+                "--validate-copy".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+        let without_flag = || {
+            vec![
+                "test_binary".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+
+        assert!(validate_copy(with_flag));
+        assert!(!validate_copy(without_flag));
+        assert_eq!(config_yaml(with_flag)?, "/path/to/config.yaml".to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_and_help() -> Result<()> {
+        let with_version = || vec!["test_binary".to_string(), "--version".to_string()];
+        let with_help = || vec!["test_binary".to_string(), "--help".to_string()];
+        let with_config = || {
+            vec![
+                "test_binary".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+
+        assert!(version(with_version));
+        assert!(!help(with_version));
+        assert!(help(with_help));
+        assert!(!version(with_help));
+        assert!(!version(with_config));
+        assert!(!help(with_config));
+        assert_eq!(
+            config_yaml(with_config)?,
+            "/path/to/config.yaml".to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_file() -> Result<()> {
+        let with_cmd = || {
+            vec![
+                "test_binary".to_string(),
+                "schema".to_string(),
+                "/path/to/file.parquet".to_string(),
+            ]
+        };
+        let run_cmd = || {
+            vec![
+                "test_binary".to_string(),
+                "/path/to/config.yaml".to_string(),
+            ]
+        };
+
+        assert_eq!(
+            schema_file(with_cmd)?,
+            Some("/path/to/file.parquet".to_string())
+        );
+        assert_eq!(schema_file(run_cmd)?, None);
+        assert_eq!(config_yaml(run_cmd)?, "/path/to/config.yaml".to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_file_fails_without_one_file() -> Result<()> {
+        let no_file = || vec!["test_binary".to_string(), "schema".to_string()];
+        let two_files = || {
+            vec![
+                "test_binary".to_string(),
+                "schema".to_string(),
+                "a.parquet".to_string(),
+                "b.parquet".to_string(),
+            ]
+        };
+
+        assert!(schema_file(no_file).is_err());
+        assert!(schema_file(two_files).is_err());
+
+        Ok(())
+    }
 }
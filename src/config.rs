@@ -1,48 +1,800 @@
-use anyhow::Result;
-use serde::Deserialize;
-use serde_yml::from_reader;
+use anyhow::{bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Deserializer};
+use serde_yml::{from_reader, from_value, Value};
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
+use std::io::Read;
 
-#[derive(Debug, Deserialize)]
+// In place of the config file's path, to read the config yaml from stdin
+pub const STDIN: &str = "-";
+
+// As parquet.desired_fields, every field a file has, in schema order
+pub const ALL_FIELDS: &str = "*";
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub db: DbConfig,
     pub s3: S3Config,
     pub parquet: ParquetConfig,
-    pub parquet_to_db: Option<HashMap<String, Option<String>>>,
+    pub parquet_to_db: Option<ParquetToDbConfig>,
     pub work_lists: WorkListsConfig,
+    #[serde(default)]
+    pub runner: RunnerConfig,
+    #[serde(default)]
+    pub source: Source,
+    pub sqs: Option<SqsConfig>, // needed for source sqs
+    // loads run one after another, each into its own table, sharing the rest of the config
+    #[serde(default)]
+    pub jobs: Vec<JobConfig>,
+}
+
+// For jobs: what differs from the rest of the config for one load
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobConfig {
+    pub name: String, // also its work lists' dir, under work_lists.dir
+    pub table_name: String,
+    pub prefix: Option<String>, // s3.prefix, if not set
+    pub desired_fields: Vec<String>,
+    pub parquet_to_db: Option<ParquetToDbConfig>, // parquet_to_db, if not set
+    pub schema_policy: Option<SchemaPolicy>,      // parquet.schema_policy, if not set
+}
+
+// Where the keys to load come from
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    #[default]
+    WorkLists, // the todo file
+    Sqs, // S3 event notifications on sqs.queue_url, added to todo as they arrive
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SqsConfig {
+    pub queue_url: String,
+    pub endpoint_url: Option<String>, // instead of AWS_ENDPOINT_URL, e.g. for localstack
+    // how long each receive waits for a message, up to 20. The run ends on one that gets none.
+    #[serde(default = "default_sqs_wait_time_secs")]
+    pub wait_time_secs: i32,
+    #[serde(default = "default_sqs_max_messages")]
+    pub max_messages: i32, // per receive, up to 10
+}
+
+fn default_sqs_wait_time_secs() -> i32 {
+    20
+}
+
+fn default_sqs_max_messages() -> i32 {
+    10
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DbConfig {
+    #[serde(default)] // given per job instead, with jobs
     pub table_name: String,
     pub conn_str: String,
+    #[serde(default)]
+    pub sslmode: SslMode,
+    pub ca_cert: Option<String>, // pem file, only used with verify-full
+    #[serde(default)]
+    pub connect_retries: u32,
+    #[serde(default = "default_connect_backoff_ms")]
+    pub connect_backoff_ms: u64, // doubled after each failed attempt
+    // if on_conflict is set, rows are upserted instead of COPYed straight into the table
+    pub on_conflict: Option<OnConflict>,
+    #[serde(default)]
+    pub conflict_columns: Vec<String>, // must have a unique index or constraint in the db
+    #[serde(default)]
+    pub load_mode: LoadMode,
+    #[serde(default)]
+    pub enforce_column_order: bool, // error if desired fields aren't in the table's column order
+    #[serde(default, deserialize_with = "non_finite_float_or_null")]
+    pub on_non_finite_float: NonFiniteFloat, // NaN, Infinity and -Infinity in float and double fields
+    #[serde(default)]
+    pub on_type_mismatch: TypeMismatch, // a value that's not what its field's type says it is
+    #[serde(default)]
+    pub load_metadata: LoadMetadataConfig,
+    // db col to now(), source_file, or a literal, for every row, e.g. cols not in the parquet
+    #[serde(default)]
+    pub extra_columns: HashMap<String, String>,
+    // COPYs a file's row groups over this many connections at once. Append loads only.
+    #[serde(default = "default_copy_parallelism")]
+    pub copy_parallelism: usize,
+    pub audit_sql_file: Option<String>, // appended with each statement that changes the db
+    // db col to the parquet BOOLEAN fields packed into it as bits, the first as bit 0
+    #[serde(default)]
+    pub bitmask_columns: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub write_mode: WriteMode,
+    #[serde(default = "default_insert_batch_size")]
+    pub insert_batch_size: usize, // rows per INSERT, with write_mode insert
+    pub max_rows_per_copy: Option<usize>, // rows per COPY, a file's split over several if more
+    pub statement_timeout_ms: Option<u64>, // postgres cancels any statement running longer
+    pub copy_timeout_ms: Option<u64>,     // we give up on a COPY running longer
+    pub run_report_table: Option<String>, // a row is inserted for each run, with its totals
+    #[serde(default)]
+    pub analyze_after_load: bool, // ANALYZE each table loaded, once the run's succeeded
+    #[serde(default)]
+    pub vacuum_after_load: bool, // VACUUM ANALYZE instead, with analyze_after_load
+}
+
+// Columns filled in for every row from the load itself, rather than from a parquet field
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LoadMetadataConfig {
+    pub source_key_column: Option<String>, // the s3 key of the file the row came from
+    pub loaded_at_column: Option<String>,  // when the run started
+    pub run_id_column: Option<String>,     // the same for every row a run writes
+}
+
+fn default_connect_backoff_ms() -> u64 {
+    500
+}
+
+fn default_copy_parallelism() -> usize {
+    1
+}
+
+fn default_insert_batch_size() -> usize {
+    1000
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    #[default]
+    Disable, // no TLS, for local and testing dbs
+    Require,    // encrypted, but server cert not verified
+    VerifyFull, // encrypted, with server cert and hostname verified against ca_cert
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    DoNothing, // keep the row already in the table
+    DoUpdate,  // overwrite the row already in the table with the new values
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadMode {
+    #[default]
+    Append, // write rows into the table as is
+    Swap, // write rows into a copy of the table, then swap it in at the end of the run
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    #[default]
+    Copy, // COPY FROM STDIN BINARY, the fastest
+    Insert, // multi-row INSERTs, for tables with rules that must see each row
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NonFiniteFloat {
+    #[default]
+    Pass, // write them as is, fine for FLOAT4 and FLOAT8 columns
+    Null,  // write NULL instead
+    Error, // fail the file
+    Clamp, // as pass, but a DOUBLE too big for a FLOAT4 column is written as the largest FLOAT4
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TypeMismatch {
+    #[default]
+    Warn, // write NULL, counted per col and logged as a warning for the file
+    Error, // fail the file
+}
+
+// An unquoted null in the yaml is a yaml null, not the string "null", but means the same.
+fn non_finite_float_or_null<'de, D: Deserializer<'de>>(d: D) -> Result<NonFiniteFloat, D::Error> {
+    Ok(Option::<NonFiniteFloat>::deserialize(d)?.unwrap_or(NonFiniteFloat::Null))
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct S3Config {
     pub bucket: String,
     pub download_batch_size: usize,
     pub downloads_dir: String,
+    // each run downloads to its own downloads_dir/<run id>, removed when the run ends
+    #[serde(default)]
+    pub downloads_per_run: bool,
+    #[serde(default = "default_download_concurrency")]
+    pub download_concurrency: usize, // most objects downloading at once
+    pub max_inflight_bytes: Option<u64>, // total size of objects downloading at once
+    #[serde(default)]
+    pub get_retries: u32, // per key, only for throttling, 5xx and connection errors
+    #[serde(default = "default_get_backoff_ms")]
+    pub get_backoff_ms: u64, // doubled after each failed attempt, plus jitter
+    pub sdk_max_attempts: Option<u32>, // the SDK client's own attempts per call, under get_retries
+    pub sdk_operation_timeout_ms: Option<u64>, // the SDK client's limit on a whole call
+    pub prefix: Option<String>,        // where to list keys from, if work_lists.generate_from_s3
+    pub receipt_prefix: Option<String>, // where to write a receipt for each key loaded
+    pub endpoint_url: Option<String>,  // instead of AWS_ENDPOINT_URL, e.g. for localstack
+    pub region: Option<String>,        // instead of AWS_REGION or AWS_DEFAULT_REGION
+    pub assume_role_arn: Option<String>, // a role to assume with the env's credentials
+    pub external_id: Option<String>,   // if the role's trust policy wants one
+    // tags an object must have, with these values, to be downloaded. None needed if empty
+    #[serde(default)]
+    pub require_tags: HashMap<String, String>,
+}
+
+fn default_download_concurrency() -> usize {
+    5
 }
 
-#[derive(Debug, Deserialize)]
+fn default_get_backoff_ms() -> u64 {
+    200
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct ParquetConfig {
+    #[serde(default)] // given per job instead, with jobs
     pub desired_fields: Vec<String>,
+    // desired fields a file may not have
+    #[serde(default)]
+    pub optional_fields: Vec<OptionalField>,
+    // desired fields to read as a type other than the file says
+    #[serde(default)]
+    pub field_types: HashMap<String, FieldType>,
+    // codecs a file's columns may be compressed with, any if None
+    #[serde(default)]
+    pub allowed_codecs: Option<Vec<Codec>>,
+    // only the rows it keeps are loaded, all if None
+    #[serde(default)]
+    pub row_filter: Option<RowFilter>,
+    // which fields a file must, and mustn't, have
+    #[serde(default)]
+    pub schema_policy: SchemaPolicy,
+    // string fields going into DATE cols are parsed here, not by postgres, if set
+    #[serde(default)]
+    pub parse_dates: Option<ParseDates>,
 }
 
-#[derive(Debug, Deserialize)]
+// For parquet.parse_dates: YYYY-MM-DD, or for lenient, the first of formats a string parses with
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct ParseDates {
+    #[serde(default)]
+    pub mode: DateMode,
+    // chrono strftime formats e.g. %Y/%m/%d, tried in order after YYYY-MM-DD. Lenient only.
+    #[serde(default)]
+    pub formats: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DateMode {
+    #[default]
+    Strict, // ISO-8601 YYYY-MM-DD only
+    Lenient,
+}
+
+impl ParseDates {
+    // The formats to try, in order. The ISO-8601 one's always first.
+    pub fn formats(&self) -> Vec<String> {
+        let mut formats = vec![String::from(ISO_DATE_FORMAT)];
+        if self.mode == DateMode::Lenient {
+            formats.extend(self.formats.iter().cloned());
+        }
+        formats
+    }
+}
+
+pub const ISO_DATE_FORMAT: &str = "%Y-%m-%d";
+
+// For parquet.schema_policy: how far a file's fields may differ from those read
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaPolicy {
+    Strict,  // every field read, optional fields too, and no others
+    Lenient, // as strict, but optional fields may be missing
+    #[default]
+    Superset, // as lenient, and any other fields are ignored
+}
+
+// For parquet.row_filter: a row is kept if its field compares true with value, e.g. gear = 4.
+// The field needn't be a desired field.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct RowFilter {
+    pub column: String,
+    pub op: FilterOp,
+    #[serde(deserialize_with = "scalar_as_string")]
+    pub value: String, // parsed for the field's type later on
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub enum FilterOp {
+    #[serde(rename = "=")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = "<")]
+    Lt,
+}
+
+// For parquet.allowed_codecs: a parquet compression codec, whatever its level
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lzo,
+    Brotli,
+    Lz4,
+    Zstd,
+    Lz4Raw,
+}
+
+// For parquet.field_types: how to read a field, whatever converted type the file gives it
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    Date, // an INT32 of days since 1970-01-01
+}
+
+// A field written as NULL when a file doesn't have it, or as the fill value, if given.
+// Either way, a file that has the field but a null in it still gets NULL.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum OptionalField {
+    Name(String),
+    WithFill {
+        name: String,
+        #[serde(deserialize_with = "scalar_as_string")]
+        fill: String,
+    },
+}
+
+impl OptionalField {
+    pub fn name(&self) -> &str {
+        match self {
+            OptionalField::Name(name) => name,
+            OptionalField::WithFill { name, .. } => name,
+        }
+    }
+
+    pub fn fill(&self) -> Option<&str> {
+        match self {
+            OptionalField::Name(_) => None,
+            OptionalField::WithFill { fill, .. } => Some(fill),
+        }
+    }
+}
+
+// So fill: 0 and fill: true can be written without quotes. Parsed for the db col later on.
+fn scalar_as_string<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    match Value::deserialize(d)? {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a string, number or bool, but got {:?}",
+            other
+        ))),
+    }
+}
+
+// How a parquet field's db col is named, if it has no alias
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum NamingStrategy {
+    #[default]
+    Identity, // the parquet field name as is
+    SnakeCase,           // orderId, OrderID, order-id and order.id all to order_id
+    Lowercase,           // OrderId to orderid
+    DotsToUnderscores,   // sepal.length to sepal_length
+    StripPrefix(String), // e.g. {strip_prefix: raw_}, raw_order_id to order_id
+}
+
+// A strategy's name, or {strip_prefix: <prefix>}, as serde_yml would want a yaml tag for that
+fn naming_strategy<'de, D: Deserializer<'de>>(d: D) -> Result<NamingStrategy, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Strategy {
+        Name(String),
+        StripPrefix { strip_prefix: String },
+    }
+    match Strategy::deserialize(d)? {
+        Strategy::StripPrefix { strip_prefix } => Ok(NamingStrategy::StripPrefix(strip_prefix)),
+        Strategy::Name(name) => match name.as_str() {
+            "identity" => Ok(NamingStrategy::Identity),
+            "snake_case" => Ok(NamingStrategy::SnakeCase),
+            "lowercase" => Ok(NamingStrategy::Lowercase),
+            "dots_to_underscores" => Ok(NamingStrategy::DotsToUnderscores),
+            _ => Err(serde::de::Error::custom(format!(
+                "unknown parquet_to_db.strategy {}, expected identity, snake_case, lowercase, \
+                 dots_to_underscores or {{strip_prefix: <prefix>}}",
+                name
+            ))),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecryptConfig {
+    pub col: String,     // the parquet field
+    pub key_env: String, // env var with the 32 byte key, as base64
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ParquetToDbConfig {
+    // parquet string fields holding base64, decoded into a BYTEA col
+    #[serde(default)]
+    pub base64_decode: Vec<String>,
+    // parquet string fields of timestamps without an offset, to the zone they're in,
+    // e.g. America/New_York, for going into a TIMESTAMPTZ col
+    #[serde(default)]
+    pub assume_timezone: HashMap<String, String>,
+    // parquet byte array fields holding AES-256-GCM ciphertext, decrypted on the way in
+    #[serde(default)]
+    pub decrypt: Vec<DecryptConfig>,
+    // how a parquet field without an alias gets its db col name
+    #[serde(default, deserialize_with = "naming_strategy")]
+    pub strategy: NamingStrategy,
+    // every other key is a parquet field name, mapped to its db col name
+    #[serde(flatten)]
+    pub aliases: HashMap<String, Option<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct WorkListsConfig {
     pub dir: String,
+    // write completed items to file every n items, instead of after each one
+    #[serde(default = "default_completed_flush_every")]
+    pub completed_flush_every: usize,
+    // if there's no todo file, write one listing the .parquet keys under s3.prefix
+    #[serde(default)]
+    pub generate_from_s3: bool,
+    // db column holding the todo item (s3 key) each row was loaded from, for reconcile
+    pub reconcile_key_column: Option<String>,
+    // failed attempts at a file before it's moved to dead_letter. No limit if not set.
+    pub max_attempts: Option<u32>,
+    // what to do if wip has items but there's no completed file, or it's empty
+    #[serde(default)]
+    pub on_startup_wip: OnStartupWip,
+    // trims whitespace around each todo item. Off for s3 keys that start or end with a space.
+    #[serde(default = "default_trim_keys")]
+    pub trim_keys: bool,
+}
+
+// Items in wip with nothing in completed is what's left if completed was deleted. Whether
+// the wip items were loaded before that isn't known, so it's warned about whatever's set.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnStartupWip {
+    #[default]
+    Resume, // carry on with the wip items, as for any other run
+    Requeue, // move the wip items back to the front of todo
+    Abort,   // stop the run, to sort out by hand
+}
+
+fn default_completed_flush_every() -> usize {
+    1
+}
+
+fn default_trim_keys() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunnerConfig {
+    // one file at a time, in todo order, for reproducible loads. Slower.
+    #[serde(default)]
+    pub deterministic: bool,
+    // most files loaded into the db at once, each over its own connection
+    #[serde(default = "default_parallel_files")]
+    pub parallel_files: usize,
+    #[serde(default)]
+    pub on_file_error: OnFileError,
+    // times to download a file again if it won't open as parquet, before it's a failed file
+    #[serde(default)]
+    pub parse_error_redownloads: u32,
+    // also print the end of run summary as a JSON line, to stdout
+    #[serde(default)]
+    pub summary_json: bool,
+    // once todo's done, sleep this long and check it again, until stopped, instead of ending
+    #[serde(default)]
+    pub poll_interval_secs: Option<u64>,
+    // rows that won't convert go to <dir>/<key>.jsonl, instead of failing their file
+    pub quarantine_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFileError {
+    #[default]
+    Abort, // stop the run
+    Skip, // record the file in the failed work list, and carry on with the rest
+}
+
+fn default_parallel_files() -> usize {
+    1
+}
+
+// for when there's no runner section at all
+impl Default for RunnerConfig {
+    fn default() -> Self {
+        RunnerConfig {
+            deterministic: false,
+            parallel_files: default_parallel_files(),
+            on_file_error: OnFileError::Abort,
+            parse_error_redownloads: 0,
+            summary_json: false,
+            poll_interval_secs: None,
+            quarantine_dir: None,
+        }
+    }
+}
+
+// Expands ${VAR} in every string value, not the keys, e.g. to keep a password out of the file.
+// Done after parsing, so a value with quotes or a # in it can't change how the yaml reads.
+fn expand_env_vars(yaml: &mut Value) -> Result<()> {
+    match yaml {
+        Value::String(s) => *s = expand_env_vars_in_str(s)?,
+        Value::Sequence(seq) => {
+            for v in seq {
+                expand_env_vars(v)?;
+            }
+        }
+        Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                expand_env_vars(v)?;
+            }
+        }
+        Value::Tagged(tagged) => expand_env_vars(&mut tagged.value)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+// A $ not followed by { is left as is. A referenced env var that's not set is an error.
+fn expand_env_vars_in_str(s: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            bail!("config value has a ${{ without a closing }}: {}", s);
+        };
+        let name = &after[..end];
+        match env::var(name) {
+            Ok(value) => expanded.push_str(&value),
+            Err(_) => bail!("config refers to env var {}, but it is not set", name),
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
 impl Config {
     pub fn from_yaml_file(filename: &str) -> Result<Self> {
         let file = File::open(filename)?;
-        let config: Config = from_reader(file)?;
-        // TODO: verify all elements non-empty (inc desired_fields list)
+        Self::from_reader(file)
+    }
+
+    // e.g. stdin, for --config -
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut yaml: Value = from_reader(reader)?;
+        expand_env_vars(&mut yaml)?;
+        let config: Config = from_value(yaml)?;
+        config.validate()?;
         Ok(config)
     }
+
+    // The config for one of jobs: the rest of this one, with the job's own table, fields etc
+    pub fn for_job(&self, job: &JobConfig) -> Config {
+        let mut cfg = self.clone();
+        cfg.jobs = vec![];
+        cfg.db.table_name = job.table_name.clone();
+        cfg.parquet.desired_fields = job.desired_fields.clone();
+        cfg.work_lists.dir = format!("{}/{}", self.work_lists.dir, job.name);
+        if job.prefix.is_some() {
+            cfg.s3.prefix = job.prefix.clone();
+        }
+        if job.parquet_to_db.is_some() {
+            cfg.parquet_to_db = job.parquet_to_db.clone();
+        }
+        if let Some(schema_policy) = job.schema_policy {
+            cfg.parquet.schema_policy = schema_policy;
+        }
+        cfg
+    }
+
+    // Each job's config, in order, or just this one if there are no jobs
+    pub fn job_configs(self) -> Vec<Config> {
+        match self.jobs.is_empty() {
+            true => vec![self],
+            false => self.jobs.iter().map(|job| self.for_job(job)).collect(),
+        }
+    }
+
+    // Catches values that deserialize fine, but would only fail confusingly later on
+    pub fn validate(&self) -> Result<()> {
+        if !self.jobs.is_empty() {
+            return self.validate_jobs();
+        }
+        let required = [
+            ("db.table_name", &self.db.table_name),
+            ("db.conn_str", &self.db.conn_str),
+            ("s3.bucket", &self.s3.bucket),
+            ("s3.downloads_dir", &self.s3.downloads_dir),
+            ("work_lists.dir", &self.work_lists.dir),
+        ];
+        for (name, value) in required {
+            if value.trim().is_empty() {
+                bail!("config {} must not be empty", name);
+            }
+        }
+        let optional = [
+            ("db.ca_cert", &self.db.ca_cert),
+            ("db.audit_sql_file", &self.db.audit_sql_file),
+            ("db.run_report_table", &self.db.run_report_table),
+            ("runner.quarantine_dir", &self.runner.quarantine_dir),
+            (
+                "db.load_metadata.source_key_column",
+                &self.db.load_metadata.source_key_column,
+            ),
+            (
+                "db.load_metadata.loaded_at_column",
+                &self.db.load_metadata.loaded_at_column,
+            ),
+            (
+                "db.load_metadata.run_id_column",
+                &self.db.load_metadata.run_id_column,
+            ),
+            (
+                "work_lists.reconcile_key_column",
+                &self.work_lists.reconcile_key_column,
+            ),
+        ];
+        for (name, value) in optional {
+            if value.as_ref().is_some_and(|v| v.trim().is_empty()) {
+                bail!("config {} must not be empty if set", name);
+            }
+        }
+
+        if self.parquet.desired_fields.is_empty() {
+            bail!("config parquet.desired_fields must list at least one field");
+        }
+        if let Some(i) = self
+            .parquet
+            .desired_fields
+            .iter()
+            .position(|f| f.trim().is_empty())
+        {
+            bail!(
+                "config parquet.desired_fields has an empty field at index {}",
+                i
+            );
+        }
+        if self.parquet.desired_fields.iter().any(|f| f == ALL_FIELDS) {
+            if self.parquet.desired_fields.len() > 1 {
+                bail!("config parquet.desired_fields * is every field, so is ambiguous listed with others");
+            }
+            // each needs the fields known before any file is read
+            let per_field = [
+                (
+                    "parquet.optional_fields",
+                    !self.parquet.optional_fields.is_empty(),
+                ),
+                ("parquet.field_types", !self.parquet.field_types.is_empty()),
+                ("db.bitmask_columns", !self.db.bitmask_columns.is_empty()),
+                ("db.on_conflict", self.db.on_conflict.is_some()),
+                ("db.enforce_column_order", self.db.enforce_column_order),
+                (
+                    "parquet_to_db.base64_decode",
+                    self.parquet_to_db
+                        .as_ref()
+                        .is_some_and(|p| !p.base64_decode.is_empty()),
+                ),
+                (
+                    "parquet_to_db.decrypt",
+                    self.parquet_to_db
+                        .as_ref()
+                        .is_some_and(|p| !p.decrypt.is_empty()),
+                ),
+                (
+                    "parquet_to_db.assume_timezone",
+                    self.parquet_to_db
+                        .as_ref()
+                        .is_some_and(|p| !p.assume_timezone.is_empty()),
+                ),
+            ];
+            for (name, set) in per_field {
+                if set {
+                    bail!(
+                        "config {} can't be used with parquet.desired_fields *",
+                        name
+                    );
+                }
+            }
+        }
+        if let Some(filter) = &self.parquet.row_filter {
+            if filter.column.trim().is_empty() {
+                bail!("config parquet.row_filter.column must not be empty");
+            }
+        }
+        if let Some(parse_dates) = &self.parquet.parse_dates {
+            if parse_dates.mode == DateMode::Strict && !parse_dates.formats.is_empty() {
+                bail!("config parquet.parse_dates.formats are only tried in mode lenient");
+            }
+            for format in &parse_dates.formats {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    bail!(
+                        "config parquet.parse_dates.formats has an invalid format {}",
+                        format
+                    );
+                }
+            }
+        }
+        if self.db.vacuum_after_load && !self.db.analyze_after_load {
+            bail!("config db.vacuum_after_load is only used with db.analyze_after_load, which is not set");
+        }
+        if self.db.conflict_columns.iter().any(|c| c.trim().is_empty()) {
+            bail!("config db.conflict_columns has an empty column name");
+        }
+        if self.s3.external_id.is_some() && self.s3.assume_role_arn.is_none() {
+            bail!("config s3.external_id is only used with s3.assume_role_arn, which is not set");
+        }
+        if self.s3.max_inflight_bytes.is_some_and(|max| max < 1024) {
+            bail!("config s3.max_inflight_bytes must be at least 1024, as downloads are counted in KiB");
+        }
+        if self.source == Source::Sqs {
+            let Some(sqs) = &self.sqs else {
+                bail!("config source sqs needs an sqs section, with at least queue_url");
+            };
+            if sqs.queue_url.trim().is_empty() {
+                bail!("config sqs.queue_url must not be empty");
+            }
+            if !(0..=20).contains(&sqs.wait_time_secs) {
+                bail!("config sqs.wait_time_secs must be 0 to 20");
+            }
+            if !(1..=10).contains(&sqs.max_messages) {
+                bail!("config sqs.max_messages must be 1 to 10");
+            }
+        }
+        if let Some(secs) = self.runner.poll_interval_secs {
+            if secs == 0 {
+                bail!("config runner.poll_interval_secs must be at least 1, or left unset to end the run once todo's done");
+            }
+            if self.source == Source::Sqs {
+                bail!("config runner.poll_interval_secs is not used with source sqs, which waits on the queue instead");
+            }
+        }
+        Ok(())
+    }
+
+    // Each job's config is checked as a config of its own
+    fn validate_jobs(&self) -> Result<()> {
+        if self.source == Source::Sqs {
+            bail!("config jobs can't be used with source sqs, as the queue's keys aren't split by job");
+        }
+        if self.runner.poll_interval_secs.is_some() {
+            bail!("config jobs can't be used with runner.poll_interval_secs, as the first job would never end");
+        }
+        let mut names: Vec<&str> = Vec::with_capacity(self.jobs.len());
+        for job in &self.jobs {
+            let name = job.name.as_str();
+            if name.trim().is_empty() || name.contains('/') || name == "." || name == ".." {
+                bail!("config jobs name {:?} must be a plain dir name", name);
+            }
+            if names.contains(&name) {
+                bail!("config jobs name {} is used more than once", name);
+            }
+            names.push(name);
+            self.for_job(job)
+                .validate()
+                .with_context(|| format!("in config jobs {}", name))?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -67,6 +819,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_reader() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "good.yml");
+        let yaml: Vec<u8> = std::fs::read(config_yml.as_str())?;
+
+        let config: Config = Config::from_reader(yaml.as_slice())?;
+        let from_file: Config = Config::from_yaml_file(config_yml.as_str())?;
+        assert_eq!(config.db.table_name, from_file.db.table_name);
+        assert_eq!(
+            config.parquet.desired_fields,
+            from_file.parquet.desired_fields
+        );
+
+        // validated as a file's is
+        assert!(Config::from_reader("db: {}".as_bytes()).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_from_yaml_file_valid_yaml() -> Result<()> {
         let config_yml = format!("{}/{}", TESTDATA_DIR, "good.yml");
@@ -77,18 +848,95 @@ mod tests {
             config.db.conn_str,
             "host=127.0.0.1 password=postgres user=postgres dbname=warehouse"
         );
+        assert_eq!(config.db.sslmode, SslMode::Disable); // default
+        assert_eq!(config.db.ca_cert, None);
+        assert_eq!(config.db.connect_retries, 0); // default
+        assert_eq!(config.db.connect_backoff_ms, 500); // default
+        assert_eq!(config.db.on_conflict, None);
+        assert!(config.db.conflict_columns.is_empty());
+        assert_eq!(config.db.load_mode, LoadMode::Append); // default
         assert_eq!(config.s3.bucket, "deliveries-parquet");
         assert_eq!(config.s3.download_batch_size, 2);
         assert_eq!(config.s3.downloads_dir, "out");
+        assert!(!config.s3.downloads_per_run); // default
+        assert_eq!(config.s3.download_concurrency, 5); // default
+        assert_eq!(config.s3.max_inflight_bytes, None);
+        assert_eq!(config.s3.get_retries, 0); // default
+        assert_eq!(config.s3.get_backoff_ms, 200); // default
+        assert_eq!(config.s3.prefix, None);
+        assert_eq!(config.s3.sdk_max_attempts, None);
+        assert_eq!(config.s3.sdk_operation_timeout_ms, None);
+        assert_eq!(config.db.on_non_finite_float, NonFiniteFloat::Pass); // default
+        assert_eq!(config.db.on_type_mismatch, TypeMismatch::Warn); // default
+        assert!(!config.db.enforce_column_order); // default
+        assert_eq!(config.db.load_metadata.source_key_column, None); // default
+        assert_eq!(config.db.load_metadata.loaded_at_column, None); // default
+        assert_eq!(config.db.load_metadata.run_id_column, None); // default
+        assert_eq!(config.db.copy_parallelism, 1); // default
+        assert_eq!(config.db.write_mode, WriteMode::Copy); // default
+        assert!(config.db.extra_columns.is_empty()); // default
+        assert!(config.db.bitmask_columns.is_empty()); // default
+        assert_eq!(config.db.insert_batch_size, 1000); // default
+        assert_eq!(config.db.max_rows_per_copy, None); // default
+        assert_eq!(config.db.statement_timeout_ms, None); // default
+        assert_eq!(config.db.copy_timeout_ms, None); // default
+        assert_eq!(config.db.run_report_table, None); // default
+        assert!(!config.db.analyze_after_load); // default
+        assert!(!config.db.vacuum_after_load); // default
+        assert_eq!(config.db.audit_sql_file, None);
+        assert!(!config.work_lists.generate_from_s3); // default
+        assert_eq!(config.work_lists.reconcile_key_column, None); // default
+        assert_eq!(config.work_lists.max_attempts, None); // default
+        assert_eq!(config.work_lists.on_startup_wip, OnStartupWip::Resume); // default
+        assert!(config.work_lists.trim_keys); // default
         assert_eq!(
             config.parquet.desired_fields,
             vec!["delivery_id".to_string(), "body".to_string()]
         );
+        assert!(config.parquet.optional_fields.is_empty()); // default
+        assert!(config.parquet.field_types.is_empty()); // default
+        assert_eq!(config.parquet.allowed_codecs, None); // default
+        assert_eq!(config.parquet.row_filter, None); // default
+        assert_eq!(config.parquet.parse_dates, None); // default
+        assert_eq!(config.parquet.schema_policy, SchemaPolicy::Superset); // default
+        assert_eq!(config.s3.receipt_prefix, None); // default
+        assert_eq!(config.s3.endpoint_url, None); // default
+        assert_eq!(config.s3.region, None); // default
+        assert_eq!(config.s3.assume_role_arn, None); // default
+        assert_eq!(config.s3.external_id, None); // default
+        assert!(config.s3.require_tags.is_empty()); // default
         assert_eq!(config.work_lists.dir, "work");
+        assert_eq!(config.work_lists.completed_flush_every, 1); // default
+        assert!(!config.runner.deterministic); // default
+        assert_eq!(config.runner.parallel_files, 1); // default
+        assert_eq!(config.runner.on_file_error, OnFileError::Abort); // default
+        assert_eq!(config.runner.parse_error_redownloads, 0); // default
+        assert!(!config.runner.summary_json); // default
+        assert!(config.runner.poll_interval_secs.is_none()); // default
+        assert_eq!(config.runner.quarantine_dir, None); // default
+        assert_eq!(config.source, Source::WorkLists); // default
+        assert!(config.sqs.is_none());
 
         Ok(())
     }
 
+    #[test]
+    fn test_from_yaml_file_empty_bucket() {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "empty-bucket.yml");
+        let err = Config::from_yaml_file(config_yml.as_str()).unwrap_err();
+        assert_eq!(err.to_string(), "config s3.bucket must not be empty");
+    }
+
+    #[test]
+    fn test_from_yaml_file_empty_desired_fields() {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "empty-desired-fields.yml");
+        let err = Config::from_yaml_file(config_yml.as_str()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config parquet.desired_fields must list at least one field"
+        );
+    }
+
     #[test]
     fn test_from_yaml_file_missing_fields() -> Result<()> {
         let config_yml = format!("{}/{}", TESTDATA_DIR, "missing-fields.yml");
@@ -102,4 +950,468 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_from_yaml_file_special_chars_in_field_names() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "special-field-names.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(
+            config.parquet.desired_fields,
+            vec![
+                "order-id".to_string(),
+                "full name".to_string(),
+                "sepal.length".to_string(),
+                "#hashtag".to_string(),
+            ]
+        );
+
+        let parquet_to_db = config.parquet_to_db.unwrap().aliases;
+        assert_eq!(
+            parquet_to_db.get("order-id"),
+            Some(&Some("order_id".to_string()))
+        );
+        assert_eq!(parquet_to_db.get("full name"), Some(&None));
+        assert_eq!(
+            parquet_to_db.get("#hashtag"),
+            Some(&Some("hashtag".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_sslmode() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "sslmode-verify-full.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(config.db.sslmode, SslMode::VerifyFull);
+        assert_eq!(config.db.ca_cert, Some("/path/to/ca.pem".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_on_conflict() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "on-conflict-do-update.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(config.db.on_conflict, Some(OnConflict::DoUpdate));
+        assert_eq!(config.db.conflict_columns, vec!["delivery_id".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_load_mode_swap() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "load-mode-swap.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(config.db.load_mode, LoadMode::Swap);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_on_non_finite_float() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "non-finite-float-null.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(config.db.on_non_finite_float, NonFiniteFloat::Null);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_base64_decode() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "base64-decode.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let parquet_to_db = config.parquet_to_db.unwrap();
+        assert_eq!(parquet_to_db.base64_decode, vec!["body".to_string()]);
+        // base64_decode is not a field to alias
+        assert_eq!(parquet_to_db.aliases.len(), 1);
+        assert_eq!(
+            parquet_to_db.aliases.get("delivery_id"),
+            Some(&Some("id".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_decrypt() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "decrypt.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let parquet_to_db = config.parquet_to_db.unwrap();
+        assert_eq!(parquet_to_db.decrypt.len(), 1);
+        assert_eq!(parquet_to_db.decrypt[0].col, "body");
+        assert_eq!(parquet_to_db.decrypt[0].key_env, "DELIVERY_BODY_KEY");
+        // decrypt is not a field to alias
+        assert_eq!(parquet_to_db.aliases.len(), 1);
+        assert_eq!(parquet_to_db.strategy, NamingStrategy::Identity); // default
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_naming_strategy() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "naming-strategy.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let parquet_to_db = config.parquet_to_db.unwrap();
+        assert_eq!(
+            parquet_to_db.strategy,
+            NamingStrategy::StripPrefix("raw_".to_string())
+        );
+        // strategy is not a field to alias
+        assert_eq!(parquet_to_db.aliases.len(), 1);
+
+        let snake: ParquetToDbConfig = serde_yml::from_str("strategy: snake_case")?;
+        assert_eq!(snake.strategy, NamingStrategy::SnakeCase);
+        let unknown = serde_yml::from_str::<ParquetToDbConfig>("strategy: camel_case");
+        assert!(unknown
+            .unwrap_err()
+            .to_string()
+            .contains("unknown parquet_to_db.strategy camel_case"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_jobs() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "jobs.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+        assert_eq!(config.jobs.len(), 2);
+
+        let jobs = config.job_configs();
+        let (orders, deliveries) = (&jobs[0], &jobs[1]);
+        assert_eq!(orders.db.table_name, "orders");
+        assert_eq!(orders.s3.prefix, Some("orders/".to_string()));
+        assert_eq!(orders.parquet.desired_fields, vec!["order_id", "notes"]);
+        assert_eq!(orders.parquet.optional_fields.len(), 1); // shared
+        assert_eq!(orders.parquet.schema_policy, SchemaPolicy::Lenient); // shared
+        assert_eq!(orders.work_lists.dir, "work/orders");
+        assert_eq!(
+            orders
+                .parquet_to_db
+                .as_ref()
+                .unwrap()
+                .aliases
+                .get("order_id"),
+            Some(&Some("id".to_string()))
+        );
+        assert!(orders.jobs.is_empty());
+
+        assert_eq!(deliveries.db.table_name, "deliveries");
+        assert_eq!(deliveries.s3.prefix, Some("exports/".to_string())); // s3.prefix
+        assert_eq!(deliveries.work_lists.dir, "work/deliveries");
+        assert_eq!(deliveries.parquet.schema_policy, SchemaPolicy::Superset);
+        let aliases = &deliveries.parquet_to_db.as_ref().unwrap().aliases;
+        assert_eq!(aliases.get("delivery_id"), Some(&Some("id".to_string())));
+        assert_eq!(aliases.get("order_id"), None);
+
+        // without jobs, it's the one config as is
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "good.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+        assert!(config.jobs.is_empty()); // default
+        let jobs = config.job_configs();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].work_lists.dir, "work");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_jobs() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "jobs.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let mut same_names = config.clone();
+        same_names.jobs[1].name = "orders".to_string();
+        assert_eq!(
+            same_names.validate().unwrap_err().to_string(),
+            "config jobs name orders is used more than once"
+        );
+
+        let mut nested_name = config.clone();
+        nested_name.jobs[0].name = "a/b".to_string();
+        assert_eq!(
+            nested_name.validate().unwrap_err().to_string(),
+            "config jobs name \"a/b\" must be a plain dir name"
+        );
+
+        // each job's config is checked as if it were the whole config
+        let mut no_fields = config.clone();
+        no_fields.jobs[1].desired_fields = vec![];
+        let err = no_fields.validate().unwrap_err();
+        assert_eq!(err.to_string(), "in config jobs deliveries");
+        assert_eq!(
+            err.root_cause().to_string(),
+            "config parquet.desired_fields must list at least one field"
+        );
+
+        let mut polling = config;
+        polling.runner.poll_interval_secs = Some(10);
+        assert!(polling
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("runner.poll_interval_secs"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_max_inflight_bytes() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "good.yml");
+        let mut config: Config = Config::from_yaml_file(config_yml.as_str())?;
+        config.s3.max_inflight_bytes = Some(1024);
+        assert!(config.validate().is_ok());
+
+        config.s3.max_inflight_bytes = Some(1023);
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "config s3.max_inflight_bytes must be at least 1024, as downloads are counted in KiB"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_all_fields() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "good.yml");
+        let mut config: Config = Config::from_yaml_file(config_yml.as_str())?;
+        config.parquet.desired_fields = vec!["*".to_string()];
+        assert!(config.validate().is_ok());
+
+        let mut with_others = config.clone();
+        with_others.parquet.desired_fields.push("body".to_string());
+        assert_eq!(
+            with_others.validate().unwrap_err().to_string(),
+            "config parquet.desired_fields * is every field, so is ambiguous listed with others"
+        );
+
+        let mut with_field_types = config.clone();
+        with_field_types.parquet.field_types =
+            HashMap::from([("body".to_string(), FieldType::Date)]);
+        assert_eq!(
+            with_field_types.validate().unwrap_err().to_string(),
+            "config parquet.field_types can't be used with parquet.desired_fields *"
+        );
+
+        let parquet_to_db = config.parquet_to_db.clone().unwrap_or_default();
+        let cases = [
+            (
+                ParquetToDbConfig {
+                    base64_decode: vec!["body".to_string()],
+                    ..parquet_to_db.clone()
+                },
+                "parquet_to_db.base64_decode",
+            ),
+            (
+                ParquetToDbConfig {
+                    decrypt: vec![DecryptConfig {
+                        col: "body".to_string(),
+                        key_env: "BODY_KEY".to_string(),
+                    }],
+                    ..parquet_to_db.clone()
+                },
+                "parquet_to_db.decrypt",
+            ),
+            (
+                ParquetToDbConfig {
+                    assume_timezone: HashMap::from([(
+                        "sent_at".to_string(),
+                        "Europe/London".to_string(),
+                    )]),
+                    ..parquet_to_db
+                },
+                "parquet_to_db.assume_timezone",
+            ),
+        ];
+        for (parquet_to_db, name) in cases {
+            let mut with_per_field = config.clone();
+            with_per_field.parquet_to_db = Some(parquet_to_db);
+            assert_eq!(
+                with_per_field.validate().unwrap_err().to_string(),
+                format!(
+                    "config {} can't be used with parquet.desired_fields *",
+                    name
+                )
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_parse_dates() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "parse-dates.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let parse_dates = config.parquet.parse_dates.clone().unwrap();
+        assert_eq!(parse_dates.mode, DateMode::Lenient);
+        assert_eq!(
+            parse_dates.formats(),
+            vec![
+                "%Y-%m-%d".to_string(),
+                "%Y/%m/%d".to_string(),
+                "%d-%m-%Y".to_string()
+            ]
+        );
+
+        // strict is YYYY-MM-DD only, so has no formats to try
+        let mut strict = config.clone();
+        strict.parquet.parse_dates = Some(ParseDates::default());
+        assert!(strict.validate().is_ok());
+        assert_eq!(
+            strict.parquet.parse_dates.as_ref().unwrap().formats(),
+            vec!["%Y-%m-%d".to_string()]
+        );
+        strict.parquet.parse_dates.as_mut().unwrap().formats = vec!["%Y/%m/%d".to_string()];
+        assert!(strict.validate().is_err());
+
+        let mut bad_format = config;
+        bad_format.parquet.parse_dates.as_mut().unwrap().formats = vec!["%Y/%m/%Q".to_string()];
+        assert_eq!(
+            bad_format.validate().unwrap_err().to_string(),
+            "config parquet.parse_dates.formats has an invalid format %Y/%m/%Q"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_row_filter() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "row-filter.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(
+            config.parquet.row_filter,
+            Some(RowFilter {
+                column: "gear".to_string(),
+                op: FilterOp::Eq,
+                value: "4".to_string(),
+            })
+        );
+
+        let ne: RowFilter = serde_yml::from_str("{column: status, op: '!=', value: done}")?;
+        assert_eq!(ne.op, FilterOp::Ne);
+        assert!(serde_yml::from_str::<RowFilter>("{column: n, op: '>=', value: 1}").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_assume_timezone() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "assume-timezone.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let parquet_to_db = config.parquet_to_db.unwrap();
+        assert_eq!(
+            parquet_to_db.assume_timezone,
+            HashMap::from([("sold_at".to_string(), "America/New_York".to_string())])
+        );
+        assert!(parquet_to_db.base64_decode.is_empty()); // default
+        assert_eq!(parquet_to_db.aliases.len(), 1); // assume_timezone is not a field to alias
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_load_metadata() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "load-metadata.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let load_metadata = config.db.load_metadata;
+        assert_eq!(
+            load_metadata.source_key_column,
+            Some("source_key".to_string())
+        );
+        assert_eq!(
+            load_metadata.loaded_at_column,
+            Some("loaded_at".to_string())
+        );
+        assert_eq!(load_metadata.run_id_column, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_env_vars_resolved() -> Result<()> {
+        // each test sets its own vars, as tests run in parallel
+        env::set_var("S3PQ_TEST_PG_PASSWORD", "pa ss\"#word");
+        env::set_var("S3PQ_TEST_BUCKET", "deliveries-parquet");
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "env-vars.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(
+            config.db.conn_str,
+            "host=127.0.0.1 password=pa ss\"#word user=postgres dbname=warehouse"
+        );
+        assert_eq!(config.s3.bucket, "deliveries-parquet");
+        assert_eq!(config.s3.downloads_dir, "out/$month/{day}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_env_var_unset() {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "env-var-unset.yml");
+        let err = Config::from_yaml_file(config_yml.as_str()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config refers to env var S3PQ_TEST_NEVER_SET, but it is not set"
+        );
+    }
+
+    #[test]
+    fn test_expand_env_vars_in_str_literal_dollar() -> Result<()> {
+        assert_eq!(
+            expand_env_vars_in_str("out/$month/{day}")?,
+            "out/$month/{day}"
+        );
+        assert_eq!(expand_env_vars_in_str("costs $5")?, "costs $5");
+        assert_eq!(expand_env_vars_in_str("$")?, "$");
+        assert!(expand_env_vars_in_str("password=${PG_PASSWORD").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_optional_fields() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "optional-fields.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        let optional_fields = config.parquet.optional_fields;
+        assert_eq!(
+            optional_fields,
+            vec![
+                OptionalField::Name("courier".to_string()),
+                OptionalField::WithFill {
+                    name: "attempts".to_string(),
+                    fill: "1".to_string(), // a number in the yaml
+                },
+            ]
+        );
+        assert_eq!(optional_fields[0].fill(), None);
+        assert_eq!(optional_fields[1].name(), "attempts");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_file_field_types() -> Result<()> {
+        let config_yml = format!("{}/{}", TESTDATA_DIR, "field-types.yml");
+        let config: Config = Config::from_yaml_file(config_yml.as_str())?;
+
+        assert_eq!(
+            config.parquet.field_types,
+            HashMap::from([("delivered_on".to_string(), FieldType::Date)])
+        );
+
+        Ok(())
+    }
 }
@@ -1,16 +1,29 @@
-use anyhow::Result;
-use chrono::NaiveDate;
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{bail, Context, Result};
+use arrow_schema::TimeUnit;
+use base64::Engine;
+use chrono::{DateTime, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use log::{debug, error};
 use parquet::basic::{ConvertedType, Type as PqType};
-use parquet::record::Field;
+use parquet::data_type::Decimal;
+use parquet::record::{Field, Row};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
 use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type as PgType};
+use uuid::Uuid;
+
+use crate::config::{FieldType, NonFiniteFloat, ISO_DATE_FORMAT};
+use crate::parquet_ops::{Int64Unit, PqTypeData};
 
 const NAIVE_EPOCH: NaiveDate = match NaiveDate::from_ymd_opt(1970, 1, 1) {
     Some(naive_epoch) => naive_epoch,
     None => panic!("Invalid date for epoch"),
 };
 
-type ConverterFn = dyn Fn(&Field) -> Box<dyn ToSql + Sync>;
+pub type ConverterFn = dyn Fn(&Field) -> Box<dyn ToSql + Sync>;
 type Converters<'a> = Vec<&'a ConverterFn>;
 
 fn parquet_date_to_naive_date(parquet_date: i32) -> NaiveDate {
@@ -38,6 +51,663 @@ impl ToSql for NullVal {
     to_sql_checked!();
 }
 
+thread_local! {
+    static NULL_SUBSTITUTED: Cell<bool> = const { Cell::new(false) };
+}
+
+// A converter's fallback for a field it doesn't expect, after its Field::Null arm.
+// Written as NULL, but it's a value lost, so is flagged for take_null_substituted.
+fn mismatch() -> Box<dyn ToSql + Sync> {
+    NULL_SUBSTITUTED.with(|substituted| substituted.set(true));
+    Box::new(NullVal)
+}
+
+// Whether a converter called since the last check nulled a value it didn't expect.
+// Converters are sync, so checking straight after calling one tells if it was that one.
+pub fn take_null_substituted() -> bool {
+    NULL_SUBSTITUTED.with(|substituted| substituted.replace(false))
+}
+
+// PG XML is not accepted by the String ToSql impl, so we wrap the parquet string.
+// The binary wire format for xml is just the text, so we write the bytes as is.
+// Postgres parses it, per the session's xmloption (content, by default, so e.g. several
+// top-level elements or bare text are fine), and fails the COPY on a value it won't take.
+#[derive(Debug)]
+struct XmlVal(String);
+
+impl ToSql for XmlVal {
+    fn to_sql(
+        &self,
+        _ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        buf.extend_from_slice(self.0.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::XML)
+    }
+
+    to_sql_checked!();
+}
+
+// A single JSON value, with only whitespace around it. Postgres would reject anything
+// else too, but only once the COPY is under way, so it's checked as the value's written.
+// Skipped over as IgnoredAny, it's not built, and nesting's followed without recursing.
+fn is_valid_json(json: &str) -> bool {
+    serde_json::from_str::<serde::de::IgnoredAny>(json).is_ok()
+}
+
+// PG JSON and JSONB are not accepted by the String ToSql impl, so we wrap the parquet string.
+// The binary wire format for json is just the text. For jsonb it's a version byte, 1, first.
+#[derive(Debug)]
+struct JsonVal(String);
+
+impl ToSql for JsonVal {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        if !is_valid_json(self.0.as_str()) {
+            return Err(format!("value is not valid json: {}", self.0).into());
+        }
+        if *ty == PgType::JSONB {
+            buf.extend_from_slice(&[1]);
+        }
+        buf.extend_from_slice(self.0.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::JSON | PgType::JSONB)
+    }
+
+    to_sql_checked!();
+}
+
+// Six hex octets, all separated by : or all by -, e.g. 08:00:2b:01:02:03 or 08-00-2B-01-02-03
+fn parse_mac_addr(mac: &str) -> Option<[u8; 6]> {
+    let sep = if mac.contains(':') { ':' } else { '-' };
+    let mut octets = [0u8; 6];
+    let mut parts = mac.split(sep);
+    for octet in octets.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        *octet = u8::from_str_radix(part, 16).ok()?;
+    }
+    match parts.next() {
+        Some(_) => None, // more than 6 octets
+        None => Some(octets),
+    }
+}
+
+// PG MACADDR needs the eui48 feature of tokio_postgres for a ToSql impl, so we wrap the
+// parquet string. The binary wire format for macaddr is just the 6 bytes.
+// Errors on write if the string isn't a MAC address.
+#[derive(Debug)]
+struct MacAddrVal(String);
+
+impl ToSql for MacAddrVal {
+    fn to_sql(
+        &self,
+        _ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match parse_mac_addr(self.0.as_str()) {
+            Some(octets) => {
+                buf.extend_from_slice(&octets);
+                Ok(IsNull::No)
+            }
+            None => Err(format!("value is not a MAC address: {}", self.0).into()),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::MACADDR)
+    }
+
+    to_sql_checked!();
+}
+
+// A UUID as a string, e.g. 67e55044-10b1-426f-9247-bb680e5fe0c8, going into a UUID col.
+// Hyphens and braces are optional, as for postgres. Errors on write if it won't parse.
+#[derive(Debug)]
+struct StrAsUuid(String);
+
+impl ToSql for StrAsUuid {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match Uuid::parse_str(self.0.as_str()) {
+            Ok(uuid) => uuid.to_sql(ty, buf),
+            Err(e) => Err(format!("value is not a uuid: {} ({})", self.0, e).into()),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::UUID)
+    }
+
+    to_sql_checked!();
+}
+
+// A UUID as its 16 raw bytes, e.g. a FIXED_LEN_BYTE_ARRAY(16), going into a UUID col.
+// Errors on write if it's any other length.
+#[derive(Debug)]
+struct BytesAsUuid(Vec<u8>);
+
+impl ToSql for BytesAsUuid {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match Uuid::from_slice(&self.0) {
+            Ok(uuid) => uuid.to_sql(ty, buf),
+            Err(_) => Err(format!("value is {} bytes, not a 16 byte uuid", self.0.len()).into()),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::UUID)
+    }
+
+    to_sql_checked!();
+}
+
+// For a POINT col, from a group of x and y fields. Sent as postgres's binary point, x then y.
+#[derive(Debug)]
+struct PointVal(f64, f64);
+
+impl ToSql for PointVal {
+    fn to_sql(
+        &self,
+        _ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        buf.extend_from_slice(&self.0.to_be_bytes());
+        buf.extend_from_slice(&self.1.to_be_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::POINT)
+    }
+
+    to_sql_checked!();
+}
+
+// The x and y of a group, whatever order they're in. None if either is null.
+fn xy_of(group: &Row) -> Option<(f64, f64)> {
+    let (mut x, mut y) = (None, None);
+    for (name, field) in group.get_column_iter() {
+        let value = match field {
+            Field::Double(v) => Some(*v),
+            Field::Float(v) => Some(f64::from(*v)),
+            _ => None,
+        };
+        match name.as_str() {
+            "x" => x = value,
+            "y" => y = value,
+            _ => {}
+        }
+    }
+    Some((x?, y?))
+}
+
+// For ISO-8601 / RFC 3339 string timestamps with an offset, e.g. 2024-09-24T12:00:00+02:00
+// or 2024-09-24T10:00:00Z, going into a TIMESTAMPTZ. The instant is kept, postgres stores it as UTC.
+// Errors on write if the string won't parse, or has no offset.
+#[derive(Debug)]
+struct StrAsTimestampTz(String);
+
+impl ToSql for StrAsTimestampTz {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match DateTime::parse_from_rfc3339(self.0.as_str()) {
+            Ok(v) => v.with_timezone(&Utc).to_sql(ty, buf),
+            Err(e) => Err(format!(
+                "value is not a timestamp with a timezone offset: {} ({})",
+                self.0, e
+            )
+            .into()),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::TIMESTAMPTZ)
+    }
+
+    to_sql_checked!();
+}
+
+// For parquet_to_db.assume_timezone: string timestamps without an offset, e.g. 2024-09-24T12:00:00,
+// taken as the wall clock time in the zone, going into a TIMESTAMPTZ. Postgres stores it as UTC.
+// A string with an offset or Z keeps its own, as with StrAsTimestampTz. Errors on write if the
+// string won't parse, or the time is skipped or repeated in the zone, by a daylight saving change.
+#[derive(Debug)]
+struct NaiveStrAsTimestampTz(String, Tz);
+
+impl ToSql for NaiveStrAsTimestampTz {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        if let Ok(v) = DateTime::parse_from_rfc3339(self.0.as_str()) {
+            return v.with_timezone(&Utc).to_sql(ty, buf);
+        }
+        let naive = match NaiveDateTime::parse_from_str(self.0.as_str(), "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(self.0.as_str(), "%Y-%m-%d %H:%M:%S%.f"))
+        {
+            Ok(naive) => naive,
+            Err(e) => return Err(format!("value is not a timestamp: {} ({})", self.0, e).into()),
+        };
+        match self.1.from_local_datetime(&naive) {
+            LocalResult::Single(v) => v.with_timezone(&Utc).to_sql(ty, buf),
+            LocalResult::Ambiguous(..) => {
+                Err(format!("value is ambiguous in {}: {}", self.1, self.0).into())
+            }
+            LocalResult::None => {
+                Err(format!("value does not exist in {}: {}", self.1, self.0).into())
+            }
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::TIMESTAMPTZ)
+    }
+
+    to_sql_checked!();
+}
+
+// For parquet.parse_dates: a string date, parsed with the first of the formats that fits,
+// rather than by postgres. Errors on write if none do.
+#[derive(Debug)]
+struct StrAsDate(String, &'static [String]);
+
+impl ToSql for StrAsDate {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let (date, _) = parse_date(self.0.as_str(), self.1)?;
+        date.to_sql(ty, buf)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::DATE)
+    }
+
+    to_sql_checked!();
+}
+
+// The date, and the first of formats it parses with. YYYY-MM-DD is only taken as just that,
+// chrono alone would take e.g. 2024-1-5 or +2024-01-05 too.
+pub fn parse_date<'f>(value: &str, formats: &'f [String]) -> Result<(NaiveDate, &'f str), String> {
+    for format in formats {
+        if format == ISO_DATE_FORMAT && !is_iso_date(value) {
+            continue;
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Ok((date, format));
+        }
+    }
+    Err(format!(
+        "value is not a date in any of {}: {}",
+        formats.join(", "),
+        value
+    ))
+}
+
+fn is_iso_date(value: &str) -> bool {
+    value.len() == 10
+        && value.bytes().enumerate().all(|(i, b)| match i {
+            4 | 7 => b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+// Parquet stores a decimal's unscaled value as big-endian two's complement bytes,
+// of whatever length the writer chose, e.g. 16 for Spark's DECIMAL(38,x).
+// Returns None if it won't fit in an i128.
+fn decimal_bytes_to_i128(data: &[u8]) -> Option<i128> {
+    let negative = data.first().is_some_and(|b| b & 0x80 != 0);
+    let sign_byte: u8 = if negative { 0xFF } else { 0x00 };
+
+    // anything beyond 16 bytes must just be sign extension
+    let extra = data.len().saturating_sub(16);
+    if data[..extra].iter().any(|b| *b != sign_byte) {
+        return None;
+    }
+    let significant = &data[extra..];
+    if extra > 0 && (significant[0] & 0x80 != 0) != negative {
+        return None;
+    }
+
+    let mut bytes = [sign_byte; 16];
+    bytes[16 - significant.len()..].copy_from_slice(significant);
+    Some(i128::from_be_bytes(bytes))
+}
+
+// For integer valued decimals (scale 0) that should go into a BIGINT rather than a NUMERIC.
+// Errors on write if the value has a fractional part (scale not 0) or is too big for an i64.
+#[derive(Debug)]
+struct DecimalAsBigInt(Decimal);
+
+impl ToSql for DecimalAsBigInt {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        if self.0.scale() != 0 {
+            return Err(format!(
+                "decimal has scale {}, only decimals with scale 0 can be written to a bigint",
+                self.0.scale()
+            )
+            .into());
+        }
+        match decimal_bytes_to_i128(self.0.data()).and_then(|v| i64::try_from(v).ok()) {
+            Some(v) => v.to_sql(ty, buf),
+            None => Err("decimal value is out of range for a bigint".into()),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::INT8)
+    }
+
+    to_sql_checked!();
+}
+
+// A decimal into a NUMERIC, its unscaled value scaled by the schema's scale.
+// Errors on write if the unscaled value is over 28 digits, or the scale is over 28,
+// as that's as much as rust_decimal takes.
+#[derive(Debug)]
+struct DecimalAsNumeric(Decimal);
+
+impl ToSql for DecimalAsNumeric {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let Some(unscaled) = decimal_bytes_to_i128(self.0.data()) else {
+            return Err("decimal value is out of range for a numeric".into());
+        };
+        let scale = u32::try_from(self.0.scale())
+            .map_err(|_| format!("decimal has a negative scale {}", self.0.scale()))?;
+        rust_decimal::Decimal::try_from_i128_with_scale(unscaled, scale)?.to_sql(ty, buf)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::NUMERIC)
+    }
+
+    to_sql_checked!();
+}
+
+// Converts an arrow duration (an INT64 count of the unit) to postgres interval microseconds.
+// Intervals only go down to microseconds, so any nanoseconds below that are truncated.
+fn duration_to_micros(v: i64, unit: TimeUnit) -> Option<i64> {
+    match unit {
+        TimeUnit::Second => v.checked_mul(1_000_000),
+        TimeUnit::Millisecond => v.checked_mul(1_000),
+        TimeUnit::Microsecond => Some(v),
+        TimeUnit::Nanosecond => Some(v / 1_000),
+    }
+}
+
+// tokio-postgres has no ToSql for INTERVAL, so we write the binary wire format ourselves:
+// microseconds (i64), days (i32), months (i32). A duration is an exact amount of time,
+// so it all goes in microseconds, e.g. 36 hours is stored as 36:00:00, not 1 day 12:00:00.
+// Errors on write if the duration overflows an interval.
+#[derive(Debug)]
+struct DurationAsInterval(i64, TimeUnit);
+
+impl ToSql for DurationAsInterval {
+    fn to_sql(
+        &self,
+        _ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let Some(micros) = duration_to_micros(self.0, self.1) else {
+            return Err(format!(
+                "duration of {} {:?}s is out of range for an interval",
+                self.0, self.1
+            )
+            .into());
+        };
+        buf.extend_from_slice(&micros.to_be_bytes());
+        buf.extend_from_slice(&0_i32.to_be_bytes()); // days
+        buf.extend_from_slice(&0_i32.to_be_bytes()); // months
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::INTERVAL)
+    }
+
+    to_sql_checked!();
+}
+
+// For on_non_finite_float: error. Errors on write if the float is NaN, Infinity or -Infinity.
+#[derive(Debug)]
+struct FiniteFloat<T>(T);
+
+impl<T: ToSql + Copy + Into<f64>> ToSql for FiniteFloat<T> {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let v: f64 = self.0.into();
+        if !v.is_finite() {
+            return Err(format!(
+                "float value is {}, set db.on_non_finite_float to pass or null to allow it",
+                v
+            )
+            .into());
+        }
+        self.0.to_sql(ty, buf)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        T::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+// Finite, but beyond what a FLOAT4 can hold
+fn overflows_real(v: f64) -> bool {
+    v.is_finite() && v.abs() > f32::MAX as f64
+}
+
+// A DOUBLE for a REAL col, narrowed. Errors on write if it's too big for one.
+#[derive(Debug)]
+struct DoubleAsReal(f64);
+
+impl ToSql for DoubleAsReal {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        if overflows_real(self.0) {
+            return Err(format!(
+                "double value {} is too big for a real col, set db.on_non_finite_float to null or clamp to allow it",
+                self.0
+            )
+            .into());
+        }
+        (self.0 as f32).to_sql(ty, buf)
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::FLOAT4)
+    }
+
+    to_sql_checked!();
+}
+
+// INT32 TIME_MILLIS or INT64 TIME_MICROS: since midnight, as a TIME, or as text, i.e.
+// HH:MM:SS.sss or HH:MM:SS.ssssss. Errors on write if it's not within a day, so midnight
+// is 0, not a day's worth, as chrono has no 24:00:00.
+#[derive(Debug)]
+enum TimeOfDay {
+    Millis(i32),
+    Micros(i64),
+}
+
+impl ToSql for TimeOfDay {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let (time, text_format) = match *self {
+            TimeOfDay::Millis(millis) => (
+                u32::try_from(millis).ok().and_then(|millis| {
+                    NaiveTime::from_num_seconds_from_midnight_opt(
+                        millis / 1000,
+                        (millis % 1000) * 1_000_000,
+                    )
+                }),
+                "%H:%M:%S%.3f",
+            ),
+            TimeOfDay::Micros(micros) => (
+                u32::try_from(micros / 1_000_000)
+                    .ok()
+                    .filter(|_| micros >= 0)
+                    .and_then(|secs| {
+                        NaiveTime::from_num_seconds_from_midnight_opt(
+                            secs,
+                            (micros % 1_000_000) as u32 * 1000,
+                        )
+                    }),
+                "%H:%M:%S%.6f",
+            ),
+        };
+        let Some(time) = time else {
+            return Err(match *self {
+                TimeOfDay::Millis(millis) => {
+                    format!("time of {}ms since midnight is not within a day", millis)
+                }
+                TimeOfDay::Micros(micros) => {
+                    format!("time of {}us since midnight is not within a day", micros)
+                }
+            }
+            .into());
+        };
+        match *ty {
+            PgType::TIME => time.to_sql(ty, buf),
+            _ => time.format(text_format).to_string().to_sql(ty, buf),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(
+            *ty,
+            PgType::TIME | PgType::VARCHAR | PgType::TEXT | PgType::BPCHAR
+        )
+    }
+
+    to_sql_checked!();
+}
+
+// For parquet_to_db.base64_decode: a string field holding base64, going into a BYTEA.
+// Standard alphabet, with padding. Errors on write if the string isn't valid base64.
+#[derive(Debug)]
+struct Base64Bytes(String);
+
+impl ToSql for Base64Bytes {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match base64::engine::general_purpose::STANDARD.decode(self.0.as_str()) {
+            Ok(bytes) => bytes.to_sql(ty, buf),
+            Err(e) => Err(format!("value is not valid base64: {} ({})", self.0, e).into()),
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(*ty, PgType::BYTEA)
+    }
+
+    to_sql_checked!();
+}
+
+// A parquet.optional_fields fill value, for when a file doesn't have the field.
+// Parsed for its db col's type up front, so a bad one fails the run before any file loads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FillVal {
+    Text(String),
+    Bool(bool),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Float4(f32),
+    Float8(f64),
+}
+
+impl ToSql for FillVal {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            FillVal::Text(v) => v.to_sql(ty, buf),
+            FillVal::Bool(v) => v.to_sql(ty, buf),
+            FillVal::Int2(v) => v.to_sql(ty, buf),
+            FillVal::Int4(v) => v.to_sql(ty, buf),
+            FillVal::Int8(v) => v.to_sql(ty, buf),
+            FillVal::Float4(v) => v.to_sql(ty, buf),
+            FillVal::Float8(v) => v.to_sql(ty, buf),
+        }
+    }
+
+    fn accepts(_ty: &tokio_postgres::types::Type) -> bool {
+        true // parse_fill made it for the col's type
+    }
+
+    to_sql_checked!();
+}
+
+pub fn parse_fill(fill: &str, db_col_type: &PgType) -> Result<FillVal> {
+    let parsed = match *db_col_type {
+        PgType::TEXT | PgType::VARCHAR | PgType::BPCHAR => Some(FillVal::Text(fill.to_string())),
+        PgType::BOOL => fill.parse().ok().map(FillVal::Bool),
+        PgType::INT2 => fill.parse().ok().map(FillVal::Int2),
+        PgType::INT4 => fill.parse().ok().map(FillVal::Int4),
+        PgType::INT8 => fill.parse().ok().map(FillVal::Int8),
+        PgType::FLOAT4 => fill.parse().ok().map(FillVal::Float4),
+        PgType::FLOAT8 => fill.parse().ok().map(FillVal::Float8),
+        _ => bail!(
+            "fill values are only for text, bool, integer and float cols, not {}",
+            db_col_type
+        ),
+    };
+    match parsed {
+        Some(fill_val) => Ok(fill_val),
+        None => bail!("fill value {} is not a valid {}", fill, db_col_type),
+    }
+}
+
 /*
 https://arrow.apache.org/rust/parquet/basic/enum.Type.html
 https://arrow.apache.org/rust/parquet/basic/enum.ConvertedType.html
@@ -53,9 +723,29 @@ BOOL        NONE        Bool        *BOOL, VARCHAR|TEXT|BPCHAR, SMALLINT (1 or 0
 INT32       INT_8       Byte        *INT2|SMALLINT(i16), INT|INT4(i32), BIGINT|INT8(i64)
 INT32       INT_16      Short       *INT2|SMALLINT(i16), INT|INT4(i32), BIGINT|INT8(i64)
 INT32       DATE        Date        *DATE, INT|INT4(i32), BIGINT|INT8(i64), VARCHAR|TEXT|BPCHAR assumes YYYY-mm-dd
+INT32       TIME_MILLIS Int         *TIME, VARCHAR|TEXT|BPCHAR as HH:MM:SS.sss (read as an Int, see parquet_ops::RowGroups)
 INT32       INT_32      Int
+INT32       DECIMAL     Decimal     *NUMERIC, BIGINT|INT8(i64) scale must be 0
 INT32       NONE        Int         *INT|INT4(i32), BIGINT|INT8(i64)
-BYTE_ARRAY  UTF8        Str         *VARCHAR|TEXT|CHAR(>0)
+BYTE_ARRAY  UTF8        Str         *VARCHAR|TEXT|CHAR(>0), XML (parsed by postgres, per xmloption),
+                                    TIMESTAMPTZ (ISO-8601 with offset or Z, or without if
+                                    in parquet_to_db.assume_timezone),
+                                    BYTEA if in parquet_to_db.base64_decode (must be valid base64),
+                                    MACADDR (6 hex octets separated by : or -),
+                                    JSON|JSONB (must be valid json), UUID (must parse as one),
+                                    TSVECTOR via to_tsvector() (db.write_mode insert only)
+BYTE_ARRAY  NONE        Bytes       UUID (must be 16 bytes)
+BYTE_ARRAY  DECIMAL     Decimal     BIGINT|INT8(i64) scale must be 0, NUMERIC (unscaled value up to 28 digits)
+INT64       NONE        Long        *INTERVAL if an arrow duration (s, ms, us or ns), BIGINT|INT8(i64)
+                                    TIMESTAMPTZ|TIMESTAMP if a TIMESTAMP(NANOS), truncated to microseconds
+INT64       DECIMAL     Decimal     *NUMERIC, BIGINT|INT8(i64) scale must be 0
+INT64       TIME_MICROS Long        *TIME, VARCHAR|TEXT|BPCHAR as HH:MM:SS.ssssss (read as a Long, see parquet_ops::RowGroups)
+FLOAT       NONE        Float       *FLOAT4(f32), NaN/Infinity passed, nulled or rejected per db.on_non_finite_float
+DOUBLE      NONE        Double      *FLOAT8(f64), NaN/Infinity passed, nulled or rejected per db.on_non_finite_float
+FIXED_LEN_BYTE_ARRAY  DECIMAL  Decimal  BIGINT|INT8(i64) scale must be 0, NUMERIC (unscaled value up to 28 digits)
+FIXED_LEN_BYTE_ARRAY  NONE     Bytes    UUID (must be 16 bytes)
+repeated    LIST        ListInternal  BOOL[], INT4[] (INT32), INT8[], FLOAT4[] (FLOAT), FLOAT8[], VARCHAR[]|TEXT[] (BYTE_ARRAY)
+                                    (a repeated primitive, marked LIST in parquet_ops, not a list group)
 */
 
 // INT32: https://github.com/apache/arrow-rs/blob/master/parquet/src/record/api.rs#L1025-L1060
@@ -63,16 +753,109 @@ fn p_int32<'a>(converted: &'a ConvertedType, db_col_type: &PgType) -> &'a Conver
     println!("Found a parquet physical INT32");
     match *converted {
         ConvertedType::DATE => field_is_date(converted, db_col_type), // parquet date
+        ConvertedType::INT_8 => field_is_byte(converted, db_col_type), // parquet tinyint/byte
         ConvertedType::INT_16 => field_is_short(converted, db_col_type), // parquet smallint/short
         ConvertedType::NONE | ConvertedType::INT_32 => field_is_int(converted, db_col_type),
+        ConvertedType::TIME_MILLIS => field_is_time(converted, db_col_type),
+        ConvertedType::DECIMAL => field_is_decimal(converted, db_col_type), // a scaled INT32
 
         _ => {
             println!("UNKNOWN CONVERTED TYPE {}", converted);
-            &|_f: &Field| -> Box<dyn ToSql + Sync> { Box::new(NullVal) as Box<dyn ToSql + Sync> }
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                match f {
+                    Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                    _ => mismatch(),
+                }
+            }
         }
     }
 }
 
+// INT64: https://github.com/apache/arrow-rs/blob/master/parquet/src/record/api.rs#L1061-L1090
+fn p_int64<'a>(
+    converted: &'a ConvertedType,
+    int64_unit: &Option<Int64Unit>,
+    db_col_type: &PgType,
+) -> &'a ConverterFn {
+    debug!("Found a parquet physical INT64");
+    match (*converted, int64_unit) {
+        (ConvertedType::NONE, Some(Int64Unit::Duration(unit))) => {
+            field_is_duration(*unit, db_col_type)
+        }
+        (ConvertedType::NONE, Some(Int64Unit::TimestampNanos)) => {
+            field_is_timestamp_nanos(db_col_type)
+        }
+        (ConvertedType::DECIMAL, _) => field_is_decimal(converted, db_col_type), // a scaled INT64
+        (ConvertedType::TIME_MICROS, _) => field_is_time(converted, db_col_type),
+        _ => field_as_is(),
+    }
+}
+
+// FLOAT and DOUBLE: the physical type is all there is, no converted types apply.
+// Only NaN and +/-Infinity are treated differently, per on_non_finite_float.
+fn p_float<'a>(on_non_finite_float: &NonFiniteFloat) -> &'a ConverterFn {
+    debug!("Found a parquet physical FLOAT or DOUBLE");
+    match on_non_finite_float {
+        NonFiniteFloat::Pass | NonFiniteFloat::Clamp => field_as_is(),
+        NonFiniteFloat::Null => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Float(v) if !v.is_finite() => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Double(v) if !v.is_finite() => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => field_as_is()(f),
+            }
+        },
+        NonFiniteFloat::Error => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Float(v) => Box::new(FiniteFloat(*v)) as Box<dyn ToSql + Sync>,
+                Field::Double(v) => Box::new(FiniteFloat(*v)) as Box<dyn ToSql + Sync>,
+                _ => field_as_is()(f),
+            }
+        },
+    }
+}
+
+// A DOUBLE into a REAL (FLOAT4) col. One too big for a FLOAT4 would become Infinity, so is
+// NULL for on_non_finite_float null, the largest FLOAT4 of its sign for clamp, else errors.
+// NaN and Infinity in the file itself are as for any float.
+fn field_is_double_to_real<'a>(on_non_finite_float: &NonFiniteFloat) -> &'a ConverterFn {
+    debug!("Found a parquet physical DOUBLE, for a REAL");
+    match on_non_finite_float {
+        NonFiniteFloat::Pass => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Double(v) => Box::new(DoubleAsReal(*v)) as Box<dyn ToSql + Sync>,
+                _ => field_as_is()(f),
+            }
+        },
+        NonFiniteFloat::Null => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Double(v) if !v.is_finite() || overflows_real(*v) => {
+                    Box::new(NullVal) as Box<dyn ToSql + Sync>
+                }
+                Field::Double(v) => Box::new(*v as f32) as Box<dyn ToSql + Sync>,
+                _ => field_as_is()(f),
+            }
+        },
+        NonFiniteFloat::Error => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Double(v) if !v.is_finite() => {
+                    Box::new(FiniteFloat(*v as f32)) as Box<dyn ToSql + Sync>
+                }
+                Field::Double(v) => Box::new(DoubleAsReal(*v)) as Box<dyn ToSql + Sync>,
+                _ => field_as_is()(f),
+            }
+        },
+        NonFiniteFloat::Clamp => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Double(v) if overflows_real(*v) => {
+                    Box::new(f32::MAX.copysign(*v as f32)) as Box<dyn ToSql + Sync>
+                }
+                Field::Double(v) => Box::new(*v as f32) as Box<dyn ToSql + Sync>,
+                _ => field_as_is()(f),
+            }
+        },
+    }
+}
+
 // BYTE_ARRAY: https://github.com/apache/arrow-rs/blob/master/parquet/src/record/api.rs#L725-L737
 fn p_byte_array<'a>(converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
     println!("Found a physical BYTE_ARRAY");
@@ -84,7 +867,35 @@ fn p_byte_array<'a>(converted: &'a ConvertedType, db_col_type: &PgType) -> &'a C
         ConvertedType::DECIMAL => field_is_decimal(converted, db_col_type),
         _ => {
             println!("UNHANDLED CONVERTED TYPE {}, will use NULL", converted);
-            &|_f: &Field| -> Box<dyn ToSql + Sync> { Box::new(NullVal) as Box<dyn ToSql + Sync> }
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                match f {
+                    Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                    _ => mismatch(),
+                }
+            }
+        }
+    }
+}
+
+// FIXED_LEN_BYTE_ARRAY: https://github.com/apache/arrow-rs/blob/master/parquet/src/record/api.rs#L738-L760
+fn p_fixed_len_byte_array<'a>(
+    converted: &'a ConvertedType,
+    db_col_type: &PgType,
+) -> &'a ConverterFn {
+    debug!("Found a physical FIXED_LEN_BYTE_ARRAY");
+    match *converted {
+        ConvertedType::DECIMAL => field_is_decimal(converted, db_col_type),
+        ConvertedType::NONE if *db_col_type == PgType::UUID => {
+            field_is_bytes(converted, db_col_type)
+        }
+        _ => {
+            debug!("UNHANDLED CONVERTED TYPE {}, will use NULL", converted);
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                match f {
+                    Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                    _ => mismatch(),
+                }
+            }
         }
     }
 }
@@ -92,53 +903,383 @@ fn p_byte_array<'a>(converted: &'a ConvertedType, db_col_type: &PgType) -> &'a C
 fn field_is_bytes<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
     println!("Found an unconverted BYTE_ARRAY or converted BSON (BYTE_ARRAY)");
     match *db_col_type {
+        PgType::UUID => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Bytes(ref v) => {
+                    Box::new(BytesAsUuid(v.data().to_vec())) as Box<dyn ToSql + Sync>
+                }
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        _ => {
+            todo!()
+        }
+    }
+}
+
+fn field_is_decimal<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
+    match *db_col_type {
+        // TODO: a decimal into FLOAT4, nulled for now
+        PgType::FLOAT4 => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        PgType::INT8 => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Decimal(v) => Box::new(DecimalAsBigInt(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        PgType::NUMERIC => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Decimal(v) => Box::new(DecimalAsNumeric(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        _ => {
+            todo!()
+        }
+    }
+}
+
+// TODO: we don't actually know that postgres crate will do the conversions from each of these types
+// to an acceptable type for writing to the db ...
+// Need to create test with every single type with incoming string data to check
+fn pgtype_accepts_str(pgtype: &PgType) -> bool {
+    matches!(
+        *pgtype,
+        PgType::BPCHAR
+            | PgType::CHAR
+            | PgType::TEXT
+            | PgType::DATE
+            | PgType::TIMESTAMP
+            | PgType::TIMESTAMPTZ
+            | PgType::VARCHAR
+            | PgType::UNKNOWN
+            | PgType::INET
+            | PgType::CIDR
+            | PgType::XML
+            | PgType::JSON
+            | PgType::JSONB
+            | PgType::UUID
+    ) || pgtype.name() == "citext" // from the extension, so without a fixed OID
+}
+
+fn field_is_str<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
+    println!("Found a UTF8 (Str)");
+    match *db_col_type {
+        PgType::XML => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(XmlVal(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        PgType::JSON | PgType::JSONB => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(JsonVal(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        PgType::TIMESTAMPTZ => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(StrAsTimestampTz(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        PgType::UUID => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(StrAsUuid(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        PgType::MACADDR => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(MacAddrVal(v.clone())) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        // only for db.write_mode insert, which sends it as text, for the INSERT's to_tsvector()
+        PgType::TS_VECTOR => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(v.clone()) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        _ if pgtype_accepts_str(db_col_type) => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => Box::new(v.clone()) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
         _ => {
             todo!()
         }
     }
 }
 
-fn field_is_decimal<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
-    println!("Found a converted DECIMAL");
+// For parquet_to_db.decrypt: the AES-256-GCM key, never printed
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecryptKey([u8; 32]);
+
+impl std::fmt::Debug for DecryptKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DecryptKey(..)")
+    }
+}
+
+impl DecryptKey {
+    // The env var holds the 32 byte key as base64
+    pub fn from_env(key_env: &str) -> Result<Self> {
+        let encoded = std::env::var(key_env)
+            .with_context(|| format!("env var {} for a decrypt key is not set", key_env))?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| anyhow::anyhow!("env var {} is not valid base64", key_env))?;
+        match <[u8; 32]>::try_from(bytes.as_slice()) {
+            Ok(key) => Ok(DecryptKey(key)),
+            Err(_) => bail!(
+                "env var {} must be a 32 byte AES-256 key, as base64, not {} bytes",
+                key_env,
+                bytes.len()
+            ),
+        }
+    }
+
+    // sealed is the 12 byte nonce, then the ciphertext with its 16 byte tag on the end
+    fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        if sealed.len() < DECRYPT_NONCE_LEN {
+            return Err(format!(
+                "value is too short to decrypt, at {} bytes",
+                sealed.len()
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(DECRYPT_NONCE_LEN);
+        Aes256Gcm::new(&self.0.into())
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "value could not be decrypted, with the wrong key or mangled".to_string())
+    }
+}
+
+const DECRYPT_NONCE_LEN: usize = 12;
+
+// A decrypted value, or why it couldn't be, only reported on write like Base64Bytes.
+// Text cols get it as utf8. Debug never prints the plaintext, as rows are logged with it.
+struct Decrypted(Result<Vec<u8>, String>);
+
+impl std::fmt::Debug for Decrypted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.0 {
+            Ok(plaintext) => write!(f, "<decrypted {} bytes>", plaintext.len()),
+            Err(e) => write!(f, "<not decrypted: {}>", e),
+        }
+    }
+}
+
+impl ToSql for Decrypted {
+    fn to_sql(
+        &self,
+        ty: &tokio_postgres::types::Type,
+        buf: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let plaintext = self.0.as_ref().map_err(|e| e.clone())?;
+        match *ty {
+            PgType::BYTEA => plaintext.to_sql(ty, buf),
+            _ => match std::str::from_utf8(plaintext) {
+                Ok(text) => text.to_sql(ty, buf),
+                Err(_) => {
+                    Err("decrypted value is not utf8, so can only go into a bytea col".into())
+                }
+            },
+        }
+    }
+
+    fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+        matches!(
+            *ty,
+            PgType::BYTEA | PgType::TEXT | PgType::VARCHAR | PgType::BPCHAR
+        )
+    }
+
+    to_sql_checked!();
+}
+
+// As field_is_str_in_zone, leaked once for each key. A string field holds its value as base64.
+fn field_is_encrypted<'a>(key: DecryptKey) -> &'a ConverterFn {
+    debug!("Found an encrypted BYTE_ARRAY");
+    static CONVERTERS: OnceLock<Mutex<HashMap<DecryptKey, &'static SyncConverterFn>>> =
+        OnceLock::new();
+    let mut converters = CONVERTERS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    *converters.entry(key).or_insert_with(|| {
+        Box::leak(Box::new(move |f: &Field| -> Box<dyn ToSql + Sync> {
+            let sealed: Result<Vec<u8>, String> = match f {
+                Field::Null => return Box::new(NullVal),
+                Field::Bytes(b) => Ok(b.data().to_vec()),
+                Field::Str(s) => base64::engine::general_purpose::STANDARD
+                    .decode(s.as_str())
+                    .map_err(|e| format!("encrypted value is not valid base64 ({})", e)),
+                _ => return mismatch(),
+            };
+            Box::new(Decrypted(sealed.and_then(|sealed| key.decrypt(&sealed))))
+        }))
+    })
+}
+
+fn field_is_base64<'a>() -> &'a ConverterFn {
+    debug!("Found a base64 UTF8 (Str)");
+    &|f: &Field| -> Box<dyn ToSql + Sync> {
+        match f {
+            Field::Str(ref v) => Box::new(Base64Bytes(v.clone())) as Box<dyn ToSql + Sync>,
+            Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+            _ => mismatch(),
+        }
+    }
+}
+
+type SyncConverterFn = dyn Fn(&Field) -> Box<dyn ToSql + Sync> + Sync;
+
+// Unlike the others, this converter has to capture something, the zone. Converters are &'static,
+// so the first one built for each zone is leaked, and reused for every file after.
+fn field_is_str_in_zone<'a>(tz: Tz) -> &'a ConverterFn {
+    debug!("Found a UTF8 (Str) timestamp in {}", tz);
+    static CONVERTERS: OnceLock<Mutex<HashMap<Tz, &'static SyncConverterFn>>> = OnceLock::new();
+    let mut converters = CONVERTERS.get_or_init(Default::default).lock().unwrap();
+    *converters.entry(tz).or_insert_with(|| {
+        Box::leak(Box::new(move |f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => {
+                    Box::new(NaiveStrAsTimestampTz(v.clone(), tz)) as Box<dyn ToSql + Sync>
+                }
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        }))
+    })
+}
+
+// For parquet.parse_dates, as field_is_str_in_zone, leaked once for each list of formats
+fn field_is_date_str<'a>(formats: &[String]) -> &'a ConverterFn {
+    debug!(
+        "Found a UTF8 (Str) date, parsed with {}",
+        formats.join(", ")
+    );
+    static CONVERTERS: OnceLock<Mutex<HashMap<Vec<String>, &'static SyncConverterFn>>> =
+        OnceLock::new();
+    let mut converters = CONVERTERS.get_or_init(Default::default).lock().unwrap();
+    *converters.entry(formats.to_vec()).or_insert_with(|| {
+        let formats: &'static [String] = Box::leak(formats.to_vec().into_boxed_slice());
+        Box::leak(Box::new(move |f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Str(ref v) => {
+                    Box::new(StrAsDate(v.clone(), formats)) as Box<dyn ToSql + Sync>
+                }
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        }))
+    })
+}
+
+fn field_is_duration<'a>(unit: TimeUnit, db_col_type: &PgType) -> &'a ConverterFn {
+    debug!("Found an arrow duration in {:?}s (Long)", unit);
+    if *db_col_type != PgType::INTERVAL {
+        return field_as_is(); // e.g. the raw count of units into a BIGINT
+    }
+    match unit {
+        TimeUnit::Second => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Long(v) => Box::new(DurationAsInterval(*v, TimeUnit::Second)),
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        TimeUnit::Millisecond => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Long(v) => Box::new(DurationAsInterval(*v, TimeUnit::Millisecond)),
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        TimeUnit::Microsecond => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Long(v) => Box::new(DurationAsInterval(*v, TimeUnit::Microsecond)),
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        TimeUnit::Nanosecond => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Long(v) => Box::new(DurationAsInterval(*v, TimeUnit::Nanosecond)),
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+    }
+}
+
+// Postgres timestamps only go down to microseconds, so the nanoseconds below that are
+// truncated, always to the earlier time. None is out of chrono's range, which it never is.
+fn nanos_to_timestamp(nanos: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_micros(nanos.div_euclid(1_000))
+}
+
+fn field_is_timestamp_nanos<'a>(db_col_type: &PgType) -> &'a ConverterFn {
+    debug!("Found a TIMESTAMP(NANOS) (Long)");
+    match *db_col_type {
+        PgType::TIMESTAMPTZ => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Long(v) => match nanos_to_timestamp(*v) {
+                    Some(timestamp) => Box::new(timestamp) as Box<dyn ToSql + Sync>,
+                    None => mismatch(),
+                },
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        // the UTC wall clock time
+        PgType::TIMESTAMP => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Long(v) => match nanos_to_timestamp(*v) {
+                    Some(timestamp) => Box::new(timestamp.naive_utc()) as Box<dyn ToSql + Sync>,
+                    None => mismatch(),
+                },
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
+        _ => field_as_is(), // e.g. the raw count of nanoseconds into a BIGINT
+    }
+}
+
+fn field_is_int<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
+    println!("Found an INT32 (Short)");
     match *db_col_type {
-        PgType::FLOAT4 => &|f: &Field| -> Box<dyn ToSql + Sync> {
+        PgType::INT4 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
-                Field::Decimal(v) => Box::new(NullVal) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Int(v) => Box::new(*v) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
-        _ => {
-            todo!()
-        }
-    }
-}
-
-// TODO: we don't actually know that postgres crate will do the conversions from each of these types
-// to an acceptable type for writing to the db ...
-// Need to create test with every single type with incoming string data to check
-fn pgtype_accepts_str(pgtype: &PgType) -> bool {
-    matches!(
-        *pgtype,
-        PgType::BPCHAR
-            | PgType::CHAR
-            | PgType::TEXT
-            | PgType::DATE
-            | PgType::TIMESTAMP
-            | PgType::TIMESTAMPTZ
-            | PgType::VARCHAR
-            | PgType::UNKNOWN
-            | PgType::INET
-            | PgType::CIDR
-    )
-}
-
-fn field_is_str<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
-    println!("Found a UTF8 (Str)");
-    match db_col_type {
-        _ if pgtype_accepts_str(db_col_type) => &|f: &Field| -> Box<dyn ToSql + Sync> {
+        PgType::INT8 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
-                Field::Str(ref v) => Box::new(v.clone()) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Int(v) => Box::new(*v as i64) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         _ => {
@@ -147,19 +1288,29 @@ fn field_is_str<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a
     }
 }
 
-fn field_is_int<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
-    println!("Found an INT32 (Short)");
+// There's no postgres tinyint, so a Byte's widened to whichever int the col is
+fn field_is_byte<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
+    debug!("Found a converted INT8 (Byte)");
     match *db_col_type {
+        PgType::INT2 => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            match f {
+                Field::Byte(v) => Box::new(*v as i16) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
+            }
+        },
         PgType::INT4 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
-                Field::Int(v) => Box::new(*v) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Byte(v) => Box::new(*v as i32) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         PgType::INT8 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
-                Field::Int(v) => Box::new(*v as i64) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Byte(v) => Box::new(*v as i64) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         _ => {
@@ -174,19 +1325,22 @@ fn field_is_short<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'
         PgType::INT2 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
                 Field::Short(v) => Box::new(*v) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         PgType::INT4 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
                 Field::Short(v) => Box::new(*v as i32) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         PgType::INT8 => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
                 Field::Short(v) => Box::new(*v as i64) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         _ => {
@@ -198,20 +1352,25 @@ fn field_is_short<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'
 fn field_is_date<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
     println!("Found a converted DATE");
     match *db_col_type {
+        // an Int is an INT32 without a converted type, read as a date per parquet.field_types
         PgType::DATE => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
-                Field::Date(v) => Box::new(parquet_date_to_naive_date(*v)) as Box<dyn ToSql + Sync>,
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Date(v) | Field::Int(v) => {
+                    Box::new(parquet_date_to_naive_date(*v)) as Box<dyn ToSql + Sync>
+                }
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         PgType::VARCHAR | PgType::TEXT | PgType::BPCHAR => &|f: &Field| -> Box<dyn ToSql + Sync> {
             match f {
-                Field::Date(v) => {
+                Field::Date(v) | Field::Int(v) => {
                     let date_fmt = "%Y-%m-%d";
                     let chrono_date = parquet_date_to_naive_date(*v);
                     Box::new(chrono_date.format(date_fmt).to_string()) as Box<dyn ToSql + Sync>
                 }
-                _ => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                _ => mismatch(),
             }
         },
         _ => {
@@ -221,14 +1380,185 @@ fn field_is_date<'a>(_converted: &'a ConvertedType, db_col_type: &PgType) -> &'a
     }
 }
 
+// TIME_MILLIS comes as an Int, TIME_MICROS as a Long, see parquet_ops::RowGroups
+fn field_is_time<'a>(converted: &'a ConvertedType, db_col_type: &PgType) -> &'a ConverterFn {
+    debug!("Found a converted {}", converted);
+    match *db_col_type {
+        PgType::TIME | PgType::VARCHAR | PgType::TEXT | PgType::BPCHAR => {
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                match f {
+                    Field::Int(v) => Box::new(TimeOfDay::Millis(*v)) as Box<dyn ToSql + Sync>,
+                    Field::Long(v) => Box::new(TimeOfDay::Micros(*v)) as Box<dyn ToSql + Sync>,
+                    Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+                    _ => mismatch(),
+                }
+            }
+        }
+        _ => {
+            error!("NOT YET IMPLEMENTED for PG type {:?}", db_col_type);
+            todo!()
+        }
+    }
+}
+
+// A repeated primitive field, see Parquet::get_desired_cols, read as a list of its values.
+// Only an array col can hold it, of a type its values go into.
+fn field_is_repeated(
+    col: usize,
+    physical: &PqType,
+    db_col_type: &PgType,
+) -> Result<&'static ConverterFn> {
+    debug!("Found a repeated {}", physical);
+    let converter_fn: &ConverterFn = match (physical, db_col_type) {
+        (&PqType::BOOLEAN, &PgType::BOOL_ARRAY) => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            Box::new(list_of(f, |e| match e {
+                Field::Bool(v) => Some(*v),
+                _ => None,
+            }))
+        },
+        (&PqType::INT32, &PgType::INT4_ARRAY) => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            Box::new(list_of(f, |e| match e {
+                Field::Byte(v) => Some(*v as i32),
+                Field::Short(v) => Some(*v as i32),
+                Field::Int(v) => Some(*v),
+                _ => None,
+            }))
+        },
+        (&PqType::INT32 | &PqType::INT64, &PgType::INT8_ARRAY) => {
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                Box::new(list_of(f, |e| match e {
+                    Field::Byte(v) => Some(*v as i64),
+                    Field::Short(v) => Some(*v as i64),
+                    Field::Int(v) => Some(*v as i64),
+                    Field::Long(v) => Some(*v),
+                    _ => None,
+                }))
+            }
+        }
+        (&PqType::FLOAT, &PgType::FLOAT4_ARRAY) => &|f: &Field| -> Box<dyn ToSql + Sync> {
+            Box::new(list_of(f, |e| match e {
+                Field::Float(v) => Some(*v),
+                _ => None,
+            }))
+        },
+        (&PqType::FLOAT | &PqType::DOUBLE, &PgType::FLOAT8_ARRAY) => {
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                Box::new(list_of(f, |e| match e {
+                    Field::Float(v) => Some(*v as f64),
+                    Field::Double(v) => Some(*v),
+                    _ => None,
+                }))
+            }
+        }
+        (&PqType::BYTE_ARRAY, &PgType::TEXT_ARRAY | &PgType::VARCHAR_ARRAY) => {
+            &|f: &Field| -> Box<dyn ToSql + Sync> {
+                Box::new(list_of(f, |e| match e {
+                    Field::Str(v) => Some(v.clone()),
+                    _ => None,
+                }))
+            }
+        }
+        (_, db_col_type) if db_col_type.name().starts_with('_') => bail!(
+            "the field for col {} is a repeated {}, which can't go into a {} col",
+            col,
+            physical,
+            db_col_type
+        ),
+        _ => bail!(
+            "the field for col {} is a repeated {}, so needs an array col, not {}",
+            col,
+            physical,
+            db_col_type
+        ),
+    };
+    Ok(converter_fn)
+}
+
+// A repeated field's values, each picked out by value_of. Null values stay null.
+fn list_of<T>(f: &Field, value_of: fn(&Field) -> Option<T>) -> Option<Vec<Option<T>>> {
+    match f {
+        Field::ListInternal(list) => Some(list.elements().iter().map(value_of).collect()),
+        _ => None,
+    }
+}
+
+// Just return v as Box, for all those mappings between parquet->rust->pg
+// that I don't need to implement right now.
+fn field_as_is<'a>() -> &'a ConverterFn {
+    &|f: &Field| -> Box<dyn ToSql + Sync> {
+        match *f {
+            Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>, // Use NullMarker for NULL values
+            Field::Bool(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Byte(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Short(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Int(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Long(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::UInt(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Float(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Double(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            Field::Str(ref v) => Box::new(v.clone()) as Box<dyn ToSql + Sync>,
+            _ => {
+                println!("NOT IMPLEMENTED - will return Null");
+                mismatch()
+            }
+        }
+    }
+}
+
+// POINT: a group of x and y fields, picked whole, see parquet_ops. A null group is NULL,
+// but a point missing its x or y is a value lost, as postgres has no such point.
+fn field_is_point<'a>() -> &'a ConverterFn {
+    debug!("Found a group of x and y, for a POINT");
+    &|f: &Field| -> Box<dyn ToSql + Sync> {
+        match f {
+            Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>,
+            Field::Group(group) => match xy_of(group) {
+                Some((x, y)) => Box::new(PointVal(x, y)) as Box<dyn ToSql + Sync>,
+                None => mismatch(),
+            },
+            _ => mismatch(),
+        }
+    }
+}
+
+// base64_decode says, per col, if it's a base64 string to decode. Empty means none are.
+// decrypt has, per col, the key to decrypt it with, if any.
+// assume_timezone has, per col, the zone of string timestamps without an offset, if any.
+// field_types has, per col, the type to read it as instead of its converted type, if any.
+// date_formats are those string fields going into DATE cols are parsed with. None leaves it to postgres.
+#[allow(clippy::too_many_arguments)]
 pub fn build<'a>(
-    pq_type_data: &'a [(PqType, ConvertedType)],
+    pq_type_data: &'a [PqTypeData],
     db_col_types: &'a [PgType],
+    base64_decode: &[bool],
+    decrypt: &[Option<DecryptKey>],
+    assume_timezone: &[Option<Tz>],
+    field_types: &[Option<FieldType>],
+    date_formats: Option<&[String]>,
+    on_non_finite_float: &NonFiniteFloat,
 ) -> Result<Vec<&'a ConverterFn>> {
     let mut converters: Converters = Vec::with_capacity(db_col_types.len());
 
-    for (i, (physical, converted)) in pq_type_data.iter().enumerate() {
+    for (i, (physical, converted, duration_unit)) in pq_type_data.iter().enumerate() {
         let db_col_type = db_col_types[i].clone();
+        let zone: Option<Tz> = assume_timezone.get(i).copied().flatten();
+        let key: Option<DecryptKey> = decrypt.get(i).copied().flatten();
+        if key.is_some() && *physical != PqType::BYTE_ARRAY {
+            bail!(
+                "parquet_to_db.decrypt is only for BYTE_ARRAY fields, but the field for col {} is {}",
+                i,
+                physical
+            );
+        }
+        let converted: &ConvertedType = match field_types.get(i).copied().flatten() {
+            None => converted,
+            Some(FieldType::Date) if *physical == PqType::INT32 => &ConvertedType::DATE,
+            Some(FieldType::Date) => bail!(
+                "parquet.field_types date is only for INT32 fields, but the field for col {} is {}",
+                i,
+                physical
+            ),
+        };
 
         println!(
             "{}: P:{:?}, C:{:?}, pg:{:?}",
@@ -237,33 +1567,1127 @@ pub fn build<'a>(
 
         let converter_fn: &ConverterFn = match physical {
             // TODO: add arms for physical -> converted -> db_col_type
+            _ if *converted == ConvertedType::LIST => field_is_repeated(i, physical, &db_col_type)?,
             PqType::INT32 => p_int32(converted, &db_col_type),
-            PqType::BYTE_ARRAY => p_byte_array(converted, &db_col_type),
+            PqType::INT64 => p_int64(converted, duration_unit, &db_col_type),
+            PqType::DOUBLE if db_col_type == PgType::POINT => field_is_point(),
+            PqType::DOUBLE if db_col_type == PgType::FLOAT4 => {
+                field_is_double_to_real(on_non_finite_float)
+            }
+            PqType::FLOAT | PqType::DOUBLE => p_float(on_non_finite_float),
+            PqType::BYTE_ARRAY if key.is_some() => field_is_encrypted(key.unwrap()),
+            PqType::BYTE_ARRAY if base64_decode.get(i) == Some(&true) => field_is_base64(),
+            PqType::BYTE_ARRAY => match (zone, date_formats) {
+                (Some(tz), _) => field_is_str_in_zone(tz),
+                (None, Some(formats)) if db_col_type == PgType::DATE => field_is_date_str(formats),
+                (None, _) => p_byte_array(converted, &db_col_type),
+            },
+            PqType::FIXED_LEN_BYTE_ARRAY => p_fixed_len_byte_array(converted, &db_col_type),
             _ => {
-                // Just return v as Box, for all those mappings between parquet->rust->pg
-                // that I don't need to implement right now.
                 println!("UNKNOWN PHYSICAL TYPE {}", physical);
-                &|f: &Field| -> Box<dyn ToSql + Sync> {
-                    match *f {
-                        Field::Null => Box::new(NullVal) as Box<dyn ToSql + Sync>, // Use NullMarker for NULL values
-                        Field::Bool(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Byte(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Short(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Int(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Long(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::UInt(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Float(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Double(v) => Box::new(v) as Box<dyn ToSql + Sync>,
-                        Field::Str(ref v) => Box::new(v.clone()) as Box<dyn ToSql + Sync>,
-                        _ => {
-                            println!("NOT IMPLEMENTED - will return Null");
-                            Box::new(NullVal) as Box<dyn ToSql + Sync>
-                        }
-                    }
-                }
+                field_as_is()
             }
         };
         converters.push(converter_fn);
     }
     Ok(converters)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DateMode, ParseDates};
+
+    #[test]
+    fn test_is_valid_json() {
+        assert!(is_valid_json(
+            r#"{"a": [1, -2.5e3, true, false, null], "b": {}}"#
+        ));
+        assert!(is_valid_json(r#" "caf\u00e9 \"quoted\" ☕" "#));
+        assert!(is_valid_json("0"));
+        assert!(is_valid_json("[]"));
+
+        assert!(!is_valid_json(""), "no value");
+        assert!(!is_valid_json("{'a': 1}"), "single quotes");
+        assert!(!is_valid_json(r#"{"a": 1,}"#), "trailing comma");
+        assert!(!is_valid_json(r#"{"a" 1}"#), "missing colon");
+        assert!(!is_valid_json("[1, 2"), "unclosed array");
+        assert!(!is_valid_json(r#""abc"#), "unterminated string");
+        assert!(!is_valid_json(r#""\x41""#), "bad escape");
+        assert!(!is_valid_json(r#""\u00g1""#), "bad unicode escape");
+        assert!(!is_valid_json("\"a\tb\""), "unescaped control char");
+        assert!(!is_valid_json("01"), "leading zero");
+        assert!(!is_valid_json("1."), "no digits after the point");
+        assert!(!is_valid_json("1e"), "no exponent digits");
+        assert!(!is_valid_json("nul"), "truncated literal");
+        assert!(!is_valid_json("{} {}"), "two values");
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(is_valid_json(&deep), "deep, but can't overflow the stack");
+    }
+
+    #[test]
+    fn test_parse_date_formats_in_order() {
+        let lenient = ParseDates {
+            mode: DateMode::Lenient,
+            formats: vec!["%Y/%m/%d".to_string(), "%d-%m-%Y".to_string()],
+        }
+        .formats();
+        let strict = ParseDates::default().formats();
+        let march_5th = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+
+        assert_eq!(
+            parse_date("2024-03-05", &lenient),
+            Ok((march_5th, "%Y-%m-%d"))
+        );
+        assert_eq!(
+            parse_date("2024/03/05", &lenient),
+            Ok((march_5th, "%Y/%m/%d"))
+        );
+        assert_eq!(
+            parse_date("05-03-2024", &lenient),
+            Ok((march_5th, "%d-%m-%Y"))
+        );
+        assert_eq!(
+            parse_date("2024-03-05", &strict),
+            Ok((march_5th, "%Y-%m-%d"))
+        );
+
+        // YYYY-MM-DD only as just that
+        assert!(parse_date("2024-3-5", &strict).is_err());
+        assert!(parse_date("+2024-03-05", &strict).is_err());
+        assert!(parse_date("2024/03/05", &strict).is_err());
+        assert_eq!(
+            parse_date("March 5th", &lenient),
+            Err(
+                "value is not a date in any of %Y-%m-%d, %Y/%m/%d, %d-%m-%Y: March 5th".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_str_to_date_on_to_sql() {
+        let pq_type_data = [(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let db_col_types = [PgType::DATE];
+        let formats = vec!["%Y-%m-%d".to_string(), "%d/%m/%Y".to_string()];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            Some(&formats),
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        // mixed formats in the one field
+        for value in ["2024-03-05", "05/03/2024"] {
+            buf.clear();
+            let date = converters[0](&Field::Str(value.to_string()));
+            assert!(date.to_sql_checked(&PgType::DATE, &mut buf).is_ok());
+            assert_eq!(&buf[..], 8_830i32.to_be_bytes(), "days since 2000-01-01");
+        }
+
+        let bad = converters[0](&Field::Str("2024.03.05".to_string()));
+        let Err(e) = bad.to_sql_checked(&PgType::DATE, &mut buf) else {
+            panic!("no format fits 2024.03.05")
+        };
+        assert!(e.to_string().contains("2024.03.05"), "{}", e);
+        let null = converters[0](&Field::Null);
+        assert!(null.to_sql_checked(&PgType::DATE, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn test_str_to_jsonb_on_to_sql() {
+        let pq_type_data = [(PqType::BYTE_ARRAY, ConvertedType::JSON, None)];
+        let db_col_types = [PgType::JSONB];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        let good = converters[0](&Field::Str(r#"{"a":1}"#.to_string()));
+        assert!(good.to_sql_checked(&PgType::JSONB, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"\x01{\"a\":1}", "jsonb is versioned");
+
+        buf.clear();
+        assert!(good.to_sql_checked(&PgType::JSON, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"{\"a\":1}");
+
+        let bad = converters[0](&Field::Str("{a:1}".to_string()));
+        let Err(e) = bad.to_sql_checked(&PgType::JSONB, &mut buf) else {
+            panic!("an unquoted key isn't json")
+        };
+        assert_eq!(e.to_string(), "value is not valid json: {a:1}");
+    }
+
+    #[test]
+    fn test_uuid_on_to_sql() {
+        let pq_type_data = [
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::FIXED_LEN_BYTE_ARRAY, ConvertedType::NONE, None),
+        ];
+        let db_col_types = [PgType::UUID, PgType::UUID];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let bytes: [u8; 16] = [
+            0x67, 0xe5, 0x50, 0x44, 0x10, 0xb1, 0x42, 0x6f, 0x92, 0x47, 0xbb, 0x68, 0x0e, 0x5f,
+            0xe0, 0xc8,
+        ];
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        let from_str = converters[0](&Field::Str(
+            "67e55044-10b1-426f-9247-bb680e5fe0c8".to_string(),
+        ));
+        assert!(from_str.to_sql_checked(&PgType::UUID, &mut buf).is_ok());
+        assert_eq!(&buf[..], &bytes);
+
+        buf.clear();
+        let from_bytes = converters[1](&Field::Bytes(bytes.to_vec().into()));
+        assert!(from_bytes.to_sql_checked(&PgType::UUID, &mut buf).is_ok());
+        assert_eq!(&buf[..], &bytes);
+
+        let bad_str = converters[0](&Field::Str("67e55044-10b1-426f".to_string()));
+        let Err(e) = bad_str.to_sql_checked(&PgType::UUID, &mut buf) else {
+            panic!("too short to be a uuid")
+        };
+        assert!(e
+            .to_string()
+            .starts_with("value is not a uuid: 67e55044-10b1-426f"));
+
+        let bad_bytes = converters[1](&Field::Bytes(bytes[..8].to_vec().into()));
+        let Err(e) = bad_bytes.to_sql_checked(&PgType::UUID, &mut buf) else {
+            panic!("8 bytes isn't a uuid")
+        };
+        assert_eq!(e.to_string(), "value is 8 bytes, not a 16 byte uuid");
+    }
+
+    #[test]
+    fn test_str_to_xml_as_is() {
+        let pq_type_data = [(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let db_col_types = [PgType::XML];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        let good = converters[0](&Field::Str("<a>1</a>".to_string()));
+        assert!(good.to_sql_checked(&PgType::XML, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"<a>1</a>");
+
+        // left to postgres to parse, per its xmloption
+        buf.clear();
+        let content = converters[0](&Field::Str("<a>1</a><b/>text".to_string()));
+        assert!(content.to_sql_checked(&PgType::XML, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"<a>1</a><b/>text");
+    }
+
+    #[test]
+    fn test_null_is_not_a_mismatch() {
+        let pq_type_data = [(PqType::INT32, ConvertedType::INT_16, None)];
+        let db_col_types = [PgType::INT4];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        take_null_substituted(); // from any test before, on this thread
+
+        let null = converters[0](&Field::Null);
+        assert!(
+            !take_null_substituted(),
+            "a parquet null is a legitimate NULL"
+        );
+        assert!(matches!(
+            null.to_sql_checked(&PgType::INT4, &mut buf),
+            Ok(IsNull::Yes)
+        ));
+
+        // an INT_16 should be read as a Short
+        let mismatched = converters[0](&Field::Int(1));
+        assert!(take_null_substituted(), "an Int isn't what it expects");
+        assert!(!take_null_substituted(), "taking it resets it");
+        assert!(matches!(
+            mismatched.to_sql_checked(&PgType::INT4, &mut buf),
+            Ok(IsNull::Yes)
+        ));
+
+        let short = converters[0](&Field::Short(1));
+        assert!(!take_null_substituted());
+        assert!(matches!(
+            short.to_sql_checked(&PgType::INT4, &mut buf),
+            Ok(IsNull::No)
+        ));
+    }
+
+    #[test]
+    fn test_repeated_needs_array_col() {
+        let pq_type_data = [(PqType::INT32, ConvertedType::LIST, None)];
+        let build_for = |db_col_type: PgType| {
+            build(
+                &pq_type_data,
+                &[db_col_type],
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                &NonFiniteFloat::Pass,
+            )
+            .map(|_| ())
+        };
+
+        let Err(e) = build_for(PgType::INT4) else {
+            panic!("a scalar col can't hold a repeated field")
+        };
+        assert_eq!(
+            e.to_string(),
+            "the field for col 0 is a repeated INT32, so needs an array col, not int4"
+        );
+        let Err(e) = build_for(PgType::TEXT_ARRAY) else {
+            panic!("ints don't go into a text array")
+        };
+        assert_eq!(
+            e.to_string(),
+            "the field for col 0 is a repeated INT32, which can't go into a _text col"
+        );
+
+        let converters = build(
+            &pq_type_data,
+            &[PgType::INT8_ARRAY],
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+        let converted = converters[0](&Field::Null);
+        assert!(converted
+            .to_sql_checked(&PgType::INT8_ARRAY, &mut buf)
+            .is_ok());
+        None::<Vec<Option<i64>>>
+            .to_sql(&PgType::INT8_ARRAY, &mut exp_buf)
+            .unwrap();
+        assert_eq!(buf, exp_buf, "a field that's not a list is null");
+    }
+
+    #[test]
+    fn test_int32_time_millis() {
+        let pq_type_data = [
+            (PqType::INT32, ConvertedType::TIME_MILLIS, None),
+            (PqType::INT32, ConvertedType::TIME_MILLIS, None),
+        ];
+        let db_col_types = [PgType::TIME, PgType::TEXT];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        // 12:34:56.789
+        let millis = Field::Int(45_296_789);
+        let exp = NaiveTime::from_hms_milli_opt(12, 34, 56, 789).unwrap();
+        exp.to_sql(&PgType::TIME, &mut exp_buf).unwrap();
+        assert!(converters[0](&millis)
+            .to_sql_checked(&PgType::TIME, &mut buf)
+            .is_ok());
+        assert_eq!(buf, exp_buf);
+
+        buf.clear();
+        assert!(converters[1](&millis)
+            .to_sql_checked(&PgType::TEXT, &mut buf)
+            .is_ok());
+        assert_eq!(&buf[..], b"12:34:56.789");
+
+        for v in [-1, 86_400_000] {
+            let converted = converters[0](&Field::Int(v));
+            assert!(
+                converted.to_sql_checked(&PgType::TIME, &mut buf).is_err(),
+                "{}ms is not within a day",
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn test_int64_time_micros() {
+        let pq_type_data = [
+            (PqType::INT64, ConvertedType::TIME_MICROS, None),
+            (PqType::INT64, ConvertedType::TIME_MICROS, None),
+        ];
+        let db_col_types = [PgType::TIME, PgType::VARCHAR];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        // midnight, and the last microsecond before the next one
+        for (micros, exp, text) in [
+            (0, NaiveTime::MIN, "00:00:00.000000"),
+            (
+                86_399_999_999,
+                NaiveTime::from_hms_micro_opt(23, 59, 59, 999_999).unwrap(),
+                "23:59:59.999999",
+            ),
+        ] {
+            buf.clear();
+            exp_buf.clear();
+            exp.to_sql(&PgType::TIME, &mut exp_buf).unwrap();
+            assert!(converters[0](&Field::Long(micros))
+                .to_sql_checked(&PgType::TIME, &mut buf)
+                .is_ok());
+            assert_eq!(buf, exp_buf, "{}us", micros);
+
+            buf.clear();
+            assert!(converters[1](&Field::Long(micros))
+                .to_sql_checked(&PgType::VARCHAR, &mut buf)
+                .is_ok());
+            assert_eq!(&buf[..], text.as_bytes());
+        }
+
+        for v in [-1, 86_400_000_000, i64::MAX] {
+            let converted = converters[0](&Field::Long(v));
+            assert!(
+                converted.to_sql_checked(&PgType::TIME, &mut buf).is_err(),
+                "{}us is not within a day",
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn test_str_to_timestamptz() {
+        let pq_type_data = [(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let db_col_types = [PgType::TIMESTAMPTZ];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        let exp = DateTime::parse_from_rfc3339("2024-09-24T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        exp.to_sql(&PgType::TIMESTAMPTZ, &mut exp_buf).unwrap();
+
+        // same instant, with offset and Z forms
+        for v in [
+            "2024-09-24T12:00:00+02:00",
+            "2024-09-24T10:00:00Z",
+            "2024-09-24T05:00:00-05:00",
+        ] {
+            buf.clear();
+            let converted = converters[0](&Field::Str(v.to_string()));
+            assert!(converted
+                .to_sql_checked(&PgType::TIMESTAMPTZ, &mut buf)
+                .is_ok());
+            assert_eq!(buf, exp_buf, "{} should be 2024-09-24 10:00:00 UTC", v);
+        }
+
+        for v in ["2024-09-24T12:00:00", "2024-09-24", "not a timestamp"] {
+            let converted = converters[0](&Field::Str(v.to_string()));
+            assert!(
+                converted
+                    .to_sql_checked(&PgType::TIMESTAMPTZ, &mut buf)
+                    .is_err(),
+                "{} has no offset, so should not convert",
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn test_duration_to_interval() {
+        let pq_type_data = [
+            (
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::Duration(TimeUnit::Second)),
+            ),
+            (
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::Duration(TimeUnit::Millisecond)),
+            ),
+            (
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::Duration(TimeUnit::Microsecond)),
+            ),
+            (
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::Duration(TimeUnit::Nanosecond)),
+            ),
+        ];
+        let db_col_types = [PgType::INTERVAL; 4];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        // 1.5 seconds in each unit, bar seconds
+        for (i, v) in [(0, 2), (1, 1_500), (2, 1_500_000), (3, 1_500_000_999)] {
+            buf.clear();
+            let converted = converters[i](&Field::Long(v));
+            assert!(converted
+                .to_sql_checked(&PgType::INTERVAL, &mut buf)
+                .is_ok());
+            let exp_micros: i64 = if i == 0 { 2_000_000 } else { 1_500_000 };
+            assert_eq!(
+                &buf[..8],
+                &exp_micros.to_be_bytes(),
+                "unit {:?}",
+                pq_type_data[i].2
+            );
+            assert_eq!(&buf[8..], &[0; 8], "no days or months");
+        }
+
+        let too_long = converters[0](&Field::Long(i64::MAX));
+        assert!(too_long
+            .to_sql_checked(&PgType::INTERVAL, &mut buf)
+            .is_err());
+
+        let null = converters[1](&Field::Null);
+        assert!(matches!(
+            null.to_sql_checked(&PgType::INTERVAL, &mut buf),
+            Ok(IsNull::Yes)
+        ));
+    }
+
+    #[test]
+    fn test_int64_decimal_to_numeric() {
+        let pq_type_data = [(PqType::INT64, ConvertedType::DECIMAL, None)];
+        let db_col_types = [PgType::NUMERIC];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        let converted = converters[0](&Field::Decimal(Decimal::from_i64(-123_456_789, 18, 4)));
+        converted
+            .to_sql_checked(&PgType::NUMERIC, &mut buf)
+            .unwrap();
+        let exp: rust_decimal::Decimal = "-12345.6789".parse().unwrap();
+        exp.to_sql(&PgType::NUMERIC, &mut exp_buf).unwrap();
+        assert_eq!(buf, exp_buf);
+
+        // beyond the 28 decimal places a numeric can be written with here
+        let too_fine = converters[0](&Field::Decimal(Decimal::from_i64(1, 38, 30)));
+        assert!(too_fine.to_sql_checked(&PgType::NUMERIC, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_fixed_len_decimal_to_numeric() {
+        let pq_type_data = [(PqType::FIXED_LEN_BYTE_ARRAY, ConvertedType::DECIMAL, None)];
+        let db_col_types = [PgType::NUMERIC];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        // a DECIMAL(38,6) of 25 digits, as Spark writes it, in 16 bytes
+        let unscaled: i128 = -1_234_567_890_123_456_789_012_345;
+        let decimal = Decimal::from_bytes(unscaled.to_be_bytes().to_vec().into(), 38, 6);
+        let converted = converters[0](&Field::Decimal(decimal));
+        converted
+            .to_sql_checked(&PgType::NUMERIC, &mut buf)
+            .unwrap();
+        let exp: rust_decimal::Decimal = "-1234567890123456789.012345".parse().unwrap();
+        exp.to_sql(&PgType::NUMERIC, &mut exp_buf).unwrap();
+        assert_eq!(buf, exp_buf);
+
+        // beyond the 28 digits a numeric can be written with here
+        let unscaled: i128 = 10_i128.pow(30);
+        let decimal = Decimal::from_bytes(unscaled.to_be_bytes().to_vec().into(), 38, 0);
+        let too_big = converters[0](&Field::Decimal(decimal));
+        assert!(too_big.to_sql_checked(&PgType::NUMERIC, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_nanos_truncated_to_micros() {
+        let pq_type_data = [
+            (
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::TimestampNanos),
+            ),
+            (
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::TimestampNanos),
+            ),
+        ];
+        let db_col_types = [PgType::TIMESTAMPTZ, PgType::TIMESTAMP];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        // before the epoch, the nanoseconds are still dropped to the earlier microsecond
+        let values = [
+            (1_727_179_200_123_456_789, "2024-09-24T12:00:00.123456Z"),
+            (-1, "1969-12-31T23:59:59.999999Z"),
+        ];
+        for (nanos, exp) in values {
+            let exp: DateTime<Utc> = exp.parse().unwrap();
+
+            buf.clear();
+            exp_buf.clear();
+            converters[0](&Field::Long(nanos))
+                .to_sql_checked(&PgType::TIMESTAMPTZ, &mut buf)
+                .unwrap();
+            exp.to_sql(&PgType::TIMESTAMPTZ, &mut exp_buf).unwrap();
+            assert_eq!(buf, exp_buf, "{} into a timestamptz", nanos);
+
+            buf.clear();
+            exp_buf.clear();
+            converters[1](&Field::Long(nanos))
+                .to_sql_checked(&PgType::TIMESTAMP, &mut buf)
+                .unwrap();
+            exp.naive_utc()
+                .to_sql(&PgType::TIMESTAMP, &mut exp_buf)
+                .unwrap();
+            assert_eq!(buf, exp_buf, "{} into a timestamp", nanos);
+        }
+    }
+
+    #[test]
+    fn test_double_to_real_overflow() {
+        let pq_type_data = [(PqType::DOUBLE, ConvertedType::NONE, None)];
+        let db_col_types = [PgType::FLOAT4];
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        for on_non_finite_float in [
+            NonFiniteFloat::Pass,
+            NonFiniteFloat::Null,
+            NonFiniteFloat::Error,
+            NonFiniteFloat::Clamp,
+        ] {
+            let converters = build(
+                &pq_type_data,
+                &db_col_types,
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                &on_non_finite_float,
+            )
+            .unwrap();
+
+            for overflowing in [1e39, -1e300] {
+                buf.clear();
+                let value = converters[0](&Field::Double(overflowing));
+                let result = value.to_sql_checked(&PgType::FLOAT4, &mut buf);
+                match on_non_finite_float {
+                    NonFiniteFloat::Pass | NonFiniteFloat::Error => {
+                        let Err(err) = result else {
+                            panic!("should fail on overflow")
+                        };
+                        assert!(
+                            err.to_string().contains("too big for a real col"),
+                            "{}",
+                            err
+                        );
+                    }
+                    NonFiniteFloat::Null => assert!(matches!(result, Ok(IsNull::Yes))),
+                    NonFiniteFloat::Clamp => {
+                        assert!(matches!(result, Ok(IsNull::No)));
+                        let clamped = f32::MAX.copysign(overflowing as f32);
+                        assert_eq!(&buf[..], &clamped.to_be_bytes());
+                    }
+                }
+            }
+
+            // one that fits is narrowed, whatever the mode
+            buf.clear();
+            let fits = converters[0](&Field::Double(1.5));
+            assert!(fits.to_sql_checked(&PgType::FLOAT4, &mut buf).is_ok());
+            assert_eq!(&buf[..], &1.5_f32.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_non_finite_floats() {
+        let pq_type_data = [
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::FLOAT, ConvertedType::NONE, None),
+        ];
+        let db_col_types = [PgType::FLOAT8, PgType::FLOAT4];
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        for on_non_finite_float in [
+            NonFiniteFloat::Pass,
+            NonFiniteFloat::Null,
+            NonFiniteFloat::Error,
+            NonFiniteFloat::Clamp,
+        ] {
+            let converters = build(
+                &pq_type_data,
+                &db_col_types,
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                &on_non_finite_float,
+            )
+            .unwrap();
+            let fields = [
+                (Field::Double(f64::NAN), Field::Float(f32::NAN)),
+                (
+                    Field::Double(f64::INFINITY),
+                    Field::Float(f32::NEG_INFINITY),
+                ),
+            ];
+            for (double, float) in fields {
+                let converted = [converters[0](&double), converters[1](&float)];
+                for (i, value) in converted.iter().enumerate() {
+                    buf.clear();
+                    let result = value.to_sql_checked(&db_col_types[i], &mut buf);
+                    match on_non_finite_float {
+                        NonFiniteFloat::Pass => assert!(matches!(result, Ok(IsNull::No))),
+                        NonFiniteFloat::Null => assert!(matches!(result, Ok(IsNull::Yes))),
+                        NonFiniteFloat::Error => assert!(result.is_err()),
+                        NonFiniteFloat::Clamp => assert!(matches!(result, Ok(IsNull::No))),
+                    }
+                }
+            }
+
+            // finite values are written as is, whatever the mode
+            buf.clear();
+            let finite = converters[0](&Field::Double(1.5));
+            assert!(finite.to_sql_checked(&PgType::FLOAT8, &mut buf).is_ok());
+            assert_eq!(&buf[..], &1.5_f64.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_decimal_bytes_to_i128() {
+        assert_eq!(decimal_bytes_to_i128(&[0x01, 0x00]), Some(256));
+        assert_eq!(decimal_bytes_to_i128(&[0xFF]), Some(-1));
+        assert_eq!(
+            decimal_bytes_to_i128(&i64::MAX.to_be_bytes()),
+            Some(i64::MAX.into())
+        );
+        assert_eq!(
+            decimal_bytes_to_i128(&i128::MIN.to_be_bytes()),
+            Some(i128::MIN)
+        );
+
+        // 16 byte, beyond an i64, as in a DECIMAL(38,x)
+        let mut beyond_i64 = [0x00; 16];
+        beyond_i64[7] = 0x01;
+        assert_eq!(decimal_bytes_to_i128(&beyond_i64), Some(1 << 64));
+
+        // longer, but fits
+        let mut sign_extended = [0xFF; 20];
+        sign_extended[19] = 0xFE;
+        assert_eq!(decimal_bytes_to_i128(&sign_extended), Some(-2));
+
+        // too big for an i128
+        let mut too_big = [0x00; 20];
+        too_big[3] = 0x01;
+        assert_eq!(decimal_bytes_to_i128(&too_big), None);
+        let mut too_big = [0x00; 17];
+        too_big[1] = 0x80;
+        assert_eq!(decimal_bytes_to_i128(&too_big), None);
+    }
+
+    #[test]
+    fn test_decimal_to_bigint() {
+        let pq_type_data = [
+            (PqType::BYTE_ARRAY, ConvertedType::DECIMAL, None),
+            (PqType::FIXED_LEN_BYTE_ARRAY, ConvertedType::DECIMAL, None),
+        ];
+        let db_col_types = [PgType::INT8, PgType::INT8];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        for converter in converters {
+            // scale 0 is fine
+            buf.clear();
+            let value = 1234567890123_i64;
+            let decimal = Decimal::from_bytes(value.to_be_bytes().to_vec().into(), 18, 0);
+            let good = converter(&Field::Decimal(decimal));
+            assert!(good.to_sql_checked(&PgType::INT8, &mut buf).is_ok());
+            assert_eq!(&buf[..], &value.to_be_bytes());
+
+            // 1234.5 is a decimal with a fractional part
+            let decimal = Decimal::from_bytes(12345_i64.to_be_bytes().to_vec().into(), 18, 1);
+            let bad = converter(&Field::Decimal(decimal));
+            match bad.to_sql_checked(&PgType::INT8, &mut buf) {
+                Ok(_) => panic!("non-zero scale should be rejected"),
+                Err(e) => assert!(e.to_string().contains("scale 1")),
+            }
+
+            // too big for a bigint
+            let decimal = Decimal::from_bytes(vec![0x01; 16].into(), 38, 0);
+            let bad = converter(&Field::Decimal(decimal));
+            assert!(bad.to_sql_checked(&PgType::INT8, &mut buf).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decrypt_to_text_and_bytea() {
+        let key = [7u8; 32];
+        let sealed = crate::test_setup::tests::aes_gcm_seal(&key, b"top secret");
+        let pq_type_data = [
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::BYTE_ARRAY, ConvertedType::NONE, None),
+        ];
+        let db_col_types = [PgType::TEXT, PgType::BYTEA];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[Some(DecryptKey(key)), Some(DecryptKey(key))],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        // a string field holds it as base64, a bytes field as is
+        let base64_sealed = base64::engine::general_purpose::STANDARD.encode(&sealed);
+        let text = converters[0](&Field::Str(base64_sealed));
+        assert!(text.to_sql_checked(&PgType::TEXT, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"top secret");
+
+        buf.clear();
+        let bytes = converters[1](&Field::Bytes(sealed.clone().into()));
+        assert!(bytes.to_sql_checked(&PgType::BYTEA, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"top secret");
+
+        // the wrong key, or one byte changed, fails the tag check
+        buf.clear();
+        let mut mangled = sealed.clone();
+        mangled[20] ^= 1;
+        let bad = converters[1](&Field::Bytes(mangled.into()));
+        match bad.to_sql_checked(&PgType::BYTEA, &mut buf) {
+            Ok(_) => panic!("mangled ciphertext should be rejected"),
+            Err(e) => assert!(e.to_string().contains("could not be decrypted")),
+        }
+        let other_key = DecryptKey([8u8; 32]);
+        assert!(other_key.decrypt(&sealed).is_err());
+
+        // as a row's values are when logged, or put in an error
+        let logged = format!("{:?}", [text, bytes, bad]);
+        assert!(!logged.contains("top secret"), "{}", logged);
+        assert!(logged.contains("<decrypted 10 bytes>"), "{}", logged);
+        assert!(logged.contains("could not be decrypted"), "{}", logged);
+
+        let result = build(
+            &[(PqType::INT32, ConvertedType::NONE, None)],
+            &[PgType::BYTEA],
+            &[],
+            &[Some(DecryptKey(key))],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        );
+        let Err(e) = result else {
+            panic!("should fail as only byte arrays can be decrypted")
+        };
+        assert!(e.to_string().contains("only for BYTE_ARRAY fields"));
+    }
+
+    #[test]
+    fn test_base64_to_bytea() {
+        let pq_type_data = [
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+        ];
+        let db_col_types = [PgType::BYTEA, PgType::TEXT];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[true, false],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        let good = converters[0](&Field::Str("AP8Q".to_string()));
+        assert!(good.to_sql_checked(&PgType::BYTEA, &mut buf).is_ok());
+        assert_eq!(&buf[..], &[0x00, 0xFF, 0x10]);
+
+        buf.clear();
+        let bad = converters[0](&Field::Str("not base64!".to_string()));
+        match bad.to_sql_checked(&PgType::BYTEA, &mut buf) {
+            Ok(_) => panic!("invalid base64 should be rejected"),
+            Err(e) => assert!(e.to_string().contains("not valid base64")),
+        }
+
+        // cols not in base64_decode are left as strings
+        buf.clear();
+        let text = converters[1](&Field::Str("AP8Q".to_string()));
+        assert!(text.to_sql_checked(&PgType::TEXT, &mut buf).is_ok());
+        assert_eq!(&buf[..], b"AP8Q");
+    }
+
+    #[test]
+    fn test_naive_str_to_timestamptz_in_zone() {
+        let pq_type_data = [
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+        ];
+        let db_col_types = [PgType::TIMESTAMPTZ, PgType::TIMESTAMPTZ];
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[Some(new_york), None],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+        let mut exp_buf = tokio_postgres::types::private::BytesMut::new();
+
+        // EDT is UTC-4 in September
+        let exp = DateTime::parse_from_rfc3339("2024-09-24T16:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        exp.to_sql(&PgType::TIMESTAMPTZ, &mut exp_buf).unwrap();
+
+        for v in [
+            "2024-09-24T12:00:00",
+            "2024-09-24 12:00:00",
+            "2024-09-24T12:00:00.000",
+            "2024-09-24T18:00:00+02:00", // its own offset wins
+        ] {
+            buf.clear();
+            let converted = converters[0](&Field::Str(v.to_string()));
+            assert!(converted
+                .to_sql_checked(&PgType::TIMESTAMPTZ, &mut buf)
+                .is_ok());
+            assert_eq!(buf, exp_buf, "{} should be 2024-09-24 16:00:00 UTC", v);
+        }
+
+        // 02:30 was skipped, and 01:30 repeated, by the daylight saving changes
+        for v in ["2024-03-10T02:30:00", "2024-11-03T01:30:00", "2024-09-24"] {
+            let converted = converters[0](&Field::Str(v.to_string()));
+            assert!(
+                converted
+                    .to_sql_checked(&PgType::TIMESTAMPTZ, &mut buf)
+                    .is_err(),
+                "{} is not a single instant in America/New_York",
+                v
+            );
+        }
+
+        // cols not in assume_timezone still need an offset
+        let converted = converters[1](&Field::Str("2024-09-24T12:00:00".to_string()));
+        assert!(converted
+            .to_sql_checked(&PgType::TIMESTAMPTZ, &mut buf)
+            .is_err());
+    }
+
+    #[test]
+    fn test_str_to_macaddr() {
+        let pq_type_data = [(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let db_col_types = [PgType::MACADDR];
+        let converters = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            &NonFiniteFloat::Pass,
+        )
+        .unwrap();
+        let mut buf = tokio_postgres::types::private::BytesMut::new();
+
+        for v in ["08:00:2b:01:02:03", "08-00-2B-01-02-03"] {
+            buf.clear();
+            let converted = converters[0](&Field::Str(v.to_string()));
+            assert!(converted.to_sql_checked(&PgType::MACADDR, &mut buf).is_ok());
+            assert_eq!(&buf[..], &[0x08, 0x00, 0x2b, 0x01, 0x02, 0x03], "{}", v);
+        }
+
+        for v in [
+            "08:00:2b:01:02",       // too few octets
+            "08:00:2b:01:02:03:04", // too many
+            "08:00:2b-01:02:03",    // mixed separators
+            "08:00:2b:01:02:0g",    // not hex
+            "8:00:2b:01:02:03",     // octet not 2 digits
+            "",
+        ] {
+            let converted = converters[0](&Field::Str(v.to_string()));
+            match converted.to_sql_checked(&PgType::MACADDR, &mut buf) {
+                Ok(_) => panic!("{} should be rejected", v),
+                Err(e) => assert!(e.to_string().contains("not a MAC address")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_fill() {
+        assert_eq!(
+            parse_fill("web", &PgType::TEXT).unwrap(),
+            FillVal::Text("web".to_string())
+        );
+        assert_eq!(parse_fill("4", &PgType::INT4).unwrap(), FillVal::Int4(4));
+        assert_eq!(
+            parse_fill("true", &PgType::BOOL).unwrap(),
+            FillVal::Bool(true)
+        );
+        assert_eq!(
+            parse_fill("0.5", &PgType::FLOAT8).unwrap(),
+            FillVal::Float8(0.5)
+        );
+
+        let err = parse_fill("four", &PgType::INT4).unwrap_err();
+        assert_eq!(err.to_string(), "fill value four is not a valid int4");
+        let err = parse_fill("2024-09-24", &PgType::DATE).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "fill values are only for text, bool, integer and float cols, not date"
+        );
+    }
+
+    #[test]
+    fn test_field_types_date_only_for_int32() {
+        let pq_type_data = [(PqType::INT64, ConvertedType::NONE, None)];
+        let db_col_types = [PgType::DATE];
+        let result = build(
+            &pq_type_data,
+            &db_col_types,
+            &[],
+            &[],
+            &[],
+            &[Some(FieldType::Date)],
+            None,
+            &NonFiniteFloat::Pass,
+        );
+        let Err(e) = result else {
+            panic!("an INT64 can't be read as a date")
+        };
+        assert_eq!(
+            e.to_string(),
+            "parquet.field_types date is only for INT32 fields, but the field for col 0 is INT64"
+        );
+    }
+}
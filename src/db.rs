@@ -1,16 +1,41 @@
 use anyhow::{bail, Result}; // don't need to return Result<T,E>
-use log::{debug, error};
-use parquet::record::Row;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use log::{debug, error, warn};
+use parquet::basic::Type as PqType;
+use parquet::errors::ParquetError;
+use parquet::file::reader::FileReader;
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row};
 use pin_utils::pin_mut;
 use std::any::type_name;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio_postgres::binary_copy::BinaryCopyInWriter; // let's us pg COPY from STDIN
-use tokio_postgres::types::{ToSql, Type as PgType};
-use tokio_postgres::Client; // used so data may be verified according to the pg data type
+use tokio_postgres::tls::MakeTlsConnect;
+use tokio_postgres::types::private::BytesMut;
+use tokio_postgres::types::{Kind, ToSql, Type as PgType};
+use tokio_postgres::{
+    AsyncMessage, Client, Connection, GenericClient, Socket, Statement, Transaction,
+}; // used so data may be verified according to the pg data type
 
-use crate::converters;
-use crate::parquet_ops::PqTypeData;
+use crate::audit_sql::AuditSql;
+use crate::config::{
+    DecryptConfig, FieldType, FilterOp, LoadMetadataConfig, LoadMode, NamingStrategy,
+    NonFiniteFloat, OnConflict, OptionalField, ParseDates, SslMode, TypeMismatch, WriteMode,
+    ALL_FIELDS,
+};
+use crate::converters::{self, DecryptKey, FillVal};
+use crate::parquet_ops::{ColPath, Parquet, PqTypeData};
+use crate::rejects::Rejects;
+use crate::tls;
 
 #[derive(Debug)]
 struct MultiLineError {
@@ -29,23 +54,83 @@ impl fmt::Display for MultiLineError {
 
 impl std::error::Error for MultiLineError {}
 
+// For db.copy_parallelism: a file whose first share committed, but not all of the rest, even
+// through the other shares' connections. The file's loaded, so mustn't be loaded again, but
+// the shares left prepared hold their locks, and their rows aren't seen, till a run starting
+// commits them. So the runner marks the file completed, then stops.
+#[derive(Debug)]
+pub struct SharesLeftPrepared {
+    pub gids: Vec<String>,
+}
+
+impl fmt::Display for SharesLeftPrepared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the file's first share committed, but {} of the rest are left prepared, \
+             till the next run commits them: {}",
+            self.gids.len(),
+            self.gids.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for SharesLeftPrepared {}
+
 #[allow(dead_code)]
 fn type_of<T>(_: T) -> &'static str {
     type_name::<T>()
 }
 
-async fn db_col_to_type(client: &Client, table_name: &str) -> Result<HashMap<String, PgType>> {
-    // The SQL query to get column names and type OIDs
-    let query = format!(
-        "SELECT a.attname as column_name, a.atttypid as type_oid
-         FROM pg_attribute a
-         JOIN pg_class c ON a.attrelid = c.oid
-         WHERE c.relname = '{}' AND a.attnum > 0 AND NOT a.attisdropped",
-        table_name
-    );
+// The table's columns, in their physical (attnum) order.
+// Without a schema, it's whichever table of that name the search_path finds.
+// Names are cast from text, so are truncated like postgres does for long identifiers.
+const TABLE_COLUMNS_SQL: &str = "SELECT a.attname as column_name, a.atttypid as type_oid,
+     t.typname::text as type_name, t.typnamespace::regnamespace::text as type_schema
+     FROM pg_attribute a
+     JOIN pg_class c ON a.attrelid = c.oid
+     JOIN pg_namespace n ON c.relnamespace = n.oid
+     JOIN pg_type t ON a.atttypid = t.oid
+     WHERE c.relname = $1::text::name
+     AND CASE WHEN $2::text IS NULL THEN pg_table_is_visible(c.oid)
+         ELSE n.nspname = $2::text::name END
+     AND a.attnum > 0 AND NOT a.attisdropped
+     ORDER BY a.attnum";
+
+// The views (and materialized views) on the table named by $1, quoted as for SQL
+const DEPENDENT_VIEWS_SQL: &str = "SELECT DISTINCT v.oid::regclass::text AS view_name
+     FROM pg_depend d
+     JOIN pg_rewrite r ON d.classid = 'pg_rewrite'::regclass AND d.objid = r.oid
+     JOIN pg_class v ON r.ev_class = v.oid
+     WHERE d.refobjid = to_regclass($1::text) AND v.oid <> d.refobjid
+     ORDER BY 1";
+
+// The sequences owned by the table named by $1's cols, i.e. its serial cols.
+// Identity cols' sequences aren't among them, as a copy made LIKE the table gets its own.
+const OWNED_SEQUENCES_SQL: &str = "SELECT s.oid::regclass::text AS sequence_name,
+     a.attname::text AS column_name
+     FROM pg_depend d
+     JOIN pg_class s ON d.classid = 'pg_class'::regclass AND d.objid = s.oid AND s.relkind = 'S'
+     JOIN pg_attribute a ON d.refobjid = a.attrelid AND d.refobjsubid = a.attnum
+     WHERE d.refobjid = to_regclass($1::text) AND d.deptype = 'a'
+     ORDER BY a.attnum";
+
+async fn db_col_order(client: &Client, table_name: &str) -> Result<Vec<String>> {
+    let (schema, table) = split_table_name(table_name);
+    let rows = client.query(TABLE_COLUMNS_SQL, &[&table, &schema]).await?;
+    Ok(rows.iter().map(|row| row.get("column_name")).collect())
+}
+
+// The table's column types, and separately the type OIDs tokio_postgres doesn't know,
+// e.g. enums, domains and composite types. Only a problem for a column we write to.
+async fn db_col_to_type(
+    client: &Client,
+    table_name: &str,
+) -> Result<(HashMap<String, PgType>, HashMap<String, u32>)> {
+    let (schema, table) = split_table_name(table_name);
 
     // Execute the query
-    let rows = client.query(&query, &[]).await?;
+    let rows = client.query(TABLE_COLUMNS_SQL, &[&table, &schema]).await?;
 
     if rows.is_empty() {
         let msg = format!("Table {} does not exist in connected db.", table_name);
@@ -55,6 +140,7 @@ async fn db_col_to_type(client: &Client, table_name: &str) -> Result<HashMap<Str
 
     // Create a HashMap to store the column names and their corresponding tokio_postgres::types::Type
     let mut db_col_to_type: HashMap<String, PgType> = HashMap::new();
+    let mut unknown_type_oids: HashMap<String, u32> = HashMap::new();
 
     // Iterate through the rows
     for row in rows {
@@ -64,479 +150,7270 @@ async fn db_col_to_type(client: &Client, table_name: &str) -> Result<HashMap<Str
         // Convert OID to tokio_postgres::types::Type
         if let Some(data_type) = PgType::from_oid(type_oid) {
             db_col_to_type.insert(column_name, data_type);
+        } else if let Some(data_type) =
+            extension_type(row.get("type_name"), type_oid, row.get("type_schema"))
+        {
+            db_col_to_type.insert(column_name, data_type);
         } else {
-            let msg = format!("Unknown type OID: {}", type_oid);
-            error!("{}", msg);
-            bail!("{}", msg);
+            unknown_type_oids.insert(column_name, type_oid);
         }
     }
 
-    Ok(db_col_to_type)
+    Ok((db_col_to_type, unknown_type_oids))
 }
 
-#[derive(Debug)]
-pub struct Db {
-    pub client: Client,
-    pub db_cols: Vec<String>,
-    pub db_col_types: Vec<PgType>,
-    pub table_name: String,
+type TableSchema = (HashMap<String, PgType>, HashMap<String, u32>);
+
+// db_col_to_type per (conn_str, table_name), so a run's connections to the same table
+// query its catalog just once. A run's table doesn't change under it, so nothing expires.
+#[derive(Debug, Default)]
+pub struct SchemaCache {
+    schemas: Mutex<HashMap<(String, String), TableSchema>>,
+    #[cfg(test)]
+    queries: std::sync::atomic::AtomicUsize, // catalog queries run
 }
 
-impl Db {
-    pub async fn connect(
+impl SchemaCache {
+    async fn db_col_to_type(
+        &self,
+        client: &Client,
         conn_str: &str,
         table_name: &str,
-        parquet_fields: Vec<String>,
-        parquet_to_db: Option<HashMap<String, Option<String>>>,
-    ) -> Result<Self> {
-        use tokio_postgres::{connect, NoTls};
+    ) -> Result<TableSchema> {
+        let key = (conn_str.to_string(), table_name.to_string());
+        if let Some(schema) = self.schemas.lock().unwrap().get(&key) {
+            return Ok(schema.clone());
+        }
+        #[cfg(test)]
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        let schema = db_col_to_type(client, table_name).await?;
+        self.schemas.lock().unwrap().insert(key, schema.clone());
+        Ok(schema)
+    }
+}
 
-        let (client, connection) = connect(conn_str, NoTls).await?;
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                let msg = format!("db connection error: {}", e);
-                error!("{}", msg);
-                bail!("{}", msg);
-            }
-            Ok(())
-        });
+// A type from an extension that we can write to. Its OID differs from db to db, so it's
+// known by name. citext is case-insensitive text, sent as text is.
+fn extension_type(type_name: &str, type_oid: u32, type_schema: &str) -> Option<PgType> {
+    match type_name {
+        "citext" => Some(PgType::new(
+            type_name.to_string(),
+            type_oid,
+            Kind::Simple,
+            type_schema.to_string(),
+        )),
+        _ => None,
+    }
+}
 
-        // query db table to get types for each column
-        let db_col_to_type: HashMap<String, PgType> = db_col_to_type(&client, table_name).await?;
-        debug!("db_col_to_type: {:?}", db_col_to_type);
+// db col names come from the table's metadata, so may contain chars (spaces, hyphens, dots)
+// that need quoting in sql. Quoting also keeps the name's case as is.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
 
-        // parquet_to_db: HashMap of parquet field name to the destination db col.
-        // It's useful when the db col name differs from the parquet field name.
-        // e.g. when parquet field name has characters not allowed in a db column name.
-        // Default assumes db col has same name as parquet field.
-        // For each desired parquet field use alias if defined, or else use parquet field name
-        let db_cols: Vec<String> = match parquet_to_db {
-            None => parquet_fields.clone(),
-            Some(field_aliases) => parquet_fields
-                .clone()
+// The table's own name for a configured col name, to be quoted as is in sql. One already
+// quoted as in sql, e.g. "MixedCase", is unquoted. Else it's an exact match, or failing that,
+// the one col that differs only in case, as unquoted sql names are case-insensitive.
+fn table_col_name(name: &str, table_cols: &[&String]) -> Result<Option<String>> {
+    if let Some(quoted) = name
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        let unquoted = quoted.replace("\"\"", "\"");
+        return Ok(table_cols
+            .iter()
+            .find(|col| col.as_str() == unquoted)
+            .map(|col| col.to_string()));
+    }
+    if let Some(col) = table_cols.iter().find(|col| **col == name) {
+        return Ok(Some(col.to_string()));
+    }
+    let matches: Vec<&&String> = table_cols
+        .iter()
+        .filter(|col| col.to_lowercase() == name.to_lowercase())
+        .collect();
+    match matches.as_slice() {
+        [] => Ok(None),
+        [col] => Ok(Some(col.to_string())),
+        _ => bail!(
+            "column {} could be any of {}, which differ only in case, so quote the one meant",
+            name,
+            matches
                 .iter()
-                .filter_map(|f| match field_aliases.contains_key(f) {
-                    false => Some(String::from(f)),
-                    true => match field_aliases.get(f.as_str()) {
-                        None => None,
-                        Some(alias) => {
-                            if alias.is_none() {
-                                Some(String::from(f))
-                            } else {
-                                alias.clone()
-                            }
-                        }
-                    },
-                })
-                .collect::<Vec<String>>(),
+                .map(|col| quote_ident(col))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
+
+// The db col for a parquet field with no alias, per parquet_to_db.strategy
+fn db_col_name(field: &str, strategy: &NamingStrategy) -> String {
+    match strategy {
+        NamingStrategy::Identity => field.to_string(),
+        NamingStrategy::Lowercase => field.to_lowercase(),
+        NamingStrategy::DotsToUnderscores => field.replace('.', "_"),
+        NamingStrategy::StripPrefix(prefix) => field
+            .strip_prefix(prefix.as_str())
+            .unwrap_or(field)
+            .to_string(),
+        NamingStrategy::SnakeCase => {
+            // a word starts at an upper case letter after a lower case one or digit, or at the
+            // last of a run of upper case letters if a lower case one follows, e.g. IDNumber
+            let chars: Vec<char> = field.chars().collect();
+            let mut snake = String::with_capacity(field.len() + 4);
+            for (i, c) in chars.iter().enumerate() {
+                if !c.is_alphanumeric() {
+                    if !snake.is_empty() && !snake.ends_with('_') {
+                        snake.push('_');
+                    }
+                    continue;
+                }
+                if c.is_uppercase() && i > 0 && !snake.is_empty() && !snake.ends_with('_') {
+                    let prev = chars[i - 1];
+                    let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+                    if prev.is_lowercase()
+                        || prev.is_numeric()
+                        || (prev.is_uppercase() && next_is_lower)
+                    {
+                        snake.push('_');
+                    }
+                }
+                snake.extend(c.to_lowercase());
+            }
+            snake.trim_end_matches('_').to_string()
+        }
+    }
+}
+
+// db.table_name may be schema qualified e.g. analytics.orders
+fn split_table_name(table_name: &str) -> (Option<&str>, &str) {
+    match table_name.split_once('.') {
+        Some((schema, table)) => (Some(schema), table),
+        None => (None, table_name),
+    }
+}
+
+fn quote_table_name(table_name: &str) -> String {
+    match split_table_name(table_name) {
+        (Some(schema), table) => format!("{}.{}", quote_ident(schema), quote_ident(table)),
+        (None, table) => quote_ident(table),
+    }
+}
+
+// The field at the col path, going down through groups. A null group's fields are all null.
+fn field_at(fields: &[(String, Field)], col_path: &[usize]) -> Field {
+    let Some((col_num, rest)) = col_path.split_first() else {
+        return Field::Null;
+    };
+    field_under(&fields[*col_num].1, rest)
+}
+
+// The field at the col path from a group field, or the field itself for an empty path
+fn field_under(field: &Field, col_path: &[usize]) -> Field {
+    let mut field = field;
+    for col_num in col_path {
+        field = match field {
+            Field::Group(group) => match group.get_column_iter().nth(*col_num) {
+                Some((_, group_field)) => group_field,
+                None => return Field::Null,
+            },
+            _ => return Field::Null,
         };
+    }
+    field.clone()
+}
 
-        // Check each user specified db col exists or error.
-        // For each that exists, collect its postgres column data type.
-        let mut db_col_types: Vec<PgType> = Vec::with_capacity(db_cols.len());
-        for col in &db_cols {
-            if let Some(col_type) = db_col_to_type.get(col.as_str()) {
-                db_col_types.push(col_type.clone());
-            } else {
-                let msg = format!("Table {} does not have column {}", table_name, col);
-                error!("{}", msg);
-                bail!("{}", msg);
+// Picks the desired fields out of the parquet row, and converts each for its db column.
+// A field the file doesn't have gets its fill value, if it has one, else NULL.
+// The fields after those for the converters are the bitmasks' bool fields, in order.
+fn convert_row(
+    row: Row,
+    parquet_col_nums: &[ColPath],
+    converters: &[&converters::ConverterFn],
+    fills: &[Option<FillVal>],
+    bitmasks: &[Bitmask],
+    null_substitutions: &mut [u64], // per converter, added to for each value it nulls
+) -> Vec<Box<dyn ToSql + Sync>> {
+    let all_fields = row.into_columns();
+    let desired_fields: Vec<_> = parquet_col_nums
+        .iter()
+        .map(|col_path| field_at(&all_fields, col_path))
+        .collect();
+
+    // TODO: type data can come from pq_type_data
+    let mut converted: Vec<Box<dyn ToSql + Sync>> = converters
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match fills.get(i) {
+            Some(Some(fill)) if parquet_col_nums[i].is_empty() => {
+                Box::new(fill.clone()) as Box<dyn ToSql + Sync>
+            }
+            _ => {
+                let value = f(&desired_fields[i]);
+                if converters::take_null_substituted() {
+                    null_substitutions[i] += 1;
+                }
+                value
             }
+        })
+        .collect();
+    let mut bits = desired_fields[converters.len()..].iter();
+    for bitmask in bitmasks {
+        converted.push(bitmask.value(bits.by_ref().take(bitmask.fields.len())));
+    }
+    converted
+}
+
+// A db.bitmask_columns col: bool fields packed into one int col, the first as bit 0.
+// A true field sets its bit, a false or null one leaves it unset.
+// The sign bit is never used, so the value isn't negative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitmask {
+    pub col: String,
+    pub col_type: PgType, // INT2, INT4 or INT8
+    pub fields: Vec<String>,
+}
+
+impl Bitmask {
+    fn max_fields(col_type: &PgType) -> Option<usize> {
+        match *col_type {
+            PgType::INT2 => Some(15),
+            PgType::INT4 => Some(31),
+            PgType::INT8 => Some(63),
+            _ => None,
         }
+    }
 
-        Ok(Db {
-            client,
-            db_cols,
-            db_col_types,
-            table_name: table_name.to_string(),
+    fn value<'a>(&self, bits: impl Iterator<Item = &'a Field>) -> Box<dyn ToSql + Sync> {
+        let mask = bits
+            .enumerate()
+            .filter(|(_, field)| matches!(field, Field::Bool(true)))
+            .fold(0_i64, |mask, (bit, _)| mask | 1 << bit);
+        match self.col_type {
+            PgType::INT2 => Box::new(mask as i16),
+            PgType::INT4 => Box::new(mask as i32),
+            _ => Box::new(mask),
+        }
+    }
+}
+
+// For parquet.row_filter: keeps the rows whose field compares true with the value, as the
+// field's type. A null field never does. col_path is the field's, in the file being loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowFilter {
+    pub column: String,
+    pub col_path: ColPath,
+    op: FilterOp,
+    value: String,
+}
+
+impl RowFilter {
+    fn keeps(&self, row: &Row) -> Result<bool> {
+        let field = match self.col_path.split_first() {
+            Some((col_num, rest)) => match row.get_column_iter().nth(*col_num) {
+                Some((_, field)) => field_under(field, rest),
+                None => Field::Null,
+            },
+            None => Field::Null,
+        };
+        let ordering = match &field {
+            Field::Null => return Ok(false),
+            Field::Str(v) => v.as_str().cmp(self.value.as_str()),
+            Field::Bool(v) => v.cmp(&self.parse::<bool>(&field)?),
+            Field::Byte(_)
+            | Field::Short(_)
+            | Field::Int(_)
+            | Field::Long(_)
+            | Field::UByte(_)
+            | Field::UShort(_)
+            | Field::UInt(_)
+            | Field::ULong(_) => {
+                let v: i128 = field.to_string().parse()?;
+                v.cmp(&self.parse::<i128>(&field)?)
+            }
+            Field::Float(_) | Field::Double(_) => {
+                let v: f64 = field.to_string().parse()?;
+                match v.partial_cmp(&self.parse::<f64>(&field)?) {
+                    Some(ordering) => ordering,
+                    None => return Ok(false), // NaN
+                }
+            }
+            _ => bail!(
+                "parquet.row_filter column {} is {}, but only string, bool and number fields can be filtered on",
+                self.column,
+                field
+            ),
+        };
+        Ok(match self.op {
+            FilterOp::Eq => ordering.is_eq(),
+            FilterOp::Ne => ordering.is_ne(),
+            FilterOp::Gt => ordering.is_gt(),
+            FilterOp::Lt => ordering.is_lt(),
         })
     }
 
-    // We want the safety provided by pin_mut!, so we create the following in the same scope:
-    // * sink (filehandle) for copy in
-    // * writer object
-    // * pin_mut'ed writer (fixed mem address for its lifetime, but rust will still allow mutability)
-    // You have to also call writer.as_mut().write() in the same scope
-    // as any abstraction involves borrowing the writer, which is complicated by the Pin
-    pub async fn write_rows(
-        &self,
-        iter: parquet::record::reader::RowIter<'_>,
-        parquet_col_nums: &[usize],
-        pq_type_data: &[PqTypeData],
-    ) -> Result<u64> {
-        let copy_in_sql = format!(
-            "COPY {} ({}) FROM STDIN BINARY",
-            self.table_name.clone(),
-            self.db_cols.join(","),
-        );
-        let pg_types = &self.db_col_types;
+    fn parse<T: std::str::FromStr>(&self, field: &Field) -> Result<T> {
+        match self.value.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => bail!(
+                "parquet.row_filter value {} can't be compared with column {}, as it holds {}",
+                self.value,
+                self.column,
+                field
+            ),
+        }
+    }
+}
+
+// A row, with its place in the file, from 1, so a failed write can name it
+type NumberedRow = (u64, parquet::errors::Result<Row>);
 
-        let converters = converters::build(pq_type_data, &self.db_col_types)?;
+// The places in the file of the rows of the row groups given, in the order they're read.
+// row_group_sizes is the number of rows in each of the file's row groups.
+fn row_numbers(row_group_sizes: &[u64], row_groups: &[usize]) -> impl Iterator<Item = u64> {
+    let mut firsts: Vec<u64> = Vec::with_capacity(row_group_sizes.len());
+    let mut rows_before: u64 = 0;
+    for size in row_group_sizes {
+        firsts.push(rows_before + 1);
+        rows_before += size;
+    }
+    let ranges: Vec<std::ops::Range<u64>> = row_groups
+        .iter()
+        .map(|&i| firsts[i]..firsts[i] + row_group_sizes[i])
+        .collect();
+    ranges.into_iter().flatten()
+}
+
+// The rows row_filter keeps, if there is one, adding those it doesn't to skipped.
+// They're numbered before, so a row keeps its place in the file.
+fn filter_rows<'a>(
+    iter: impl Iterator<Item = NumberedRow> + 'a,
+    row_filter: Option<&'a RowFilter>,
+    skipped: &'a AtomicU64,
+) -> impl Iterator<Item = NumberedRow> + 'a {
+    iter.filter_map(move |(row_number, row_result)| {
+        let (Some(row_filter), Ok(row)) = (row_filter, &row_result) else {
+            return Some((row_number, row_result));
+        };
+        match row_filter.keeps(row) {
+            Ok(true) => Some((row_number, row_result)),
+            Ok(false) => {
+                skipped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(e) => Some((row_number, Err(ParquetError::General(e.to_string())))),
+        }
+    })
+}
 
-        let sink = self.client.copy_in(copy_in_sql.as_str()).await?;
-        let writer = BinaryCopyInWriter::new(sink, pg_types);
-        pin_mut!(writer);
+// Rows read ahead of the COPY on a thread of their own, so with db.copy_parallelism each
+// connection's share is read at the same time as the others. At most this many wait.
+const READ_AHEAD_ROWS: usize = 1000;
 
+fn read_ahead(iter: RowIter<'static>) -> impl Iterator<Item = parquet::errors::Result<Row>> {
+    let (sender, receiver) = std::sync::mpsc::sync_channel(READ_AHEAD_ROWS);
+    std::thread::spawn(move || {
         for row_result in iter {
-            let row: Row = row_result?;
-            let all_fields = row.into_columns();
-            let desired_fields: Vec<_> = parquet_col_nums
-                .iter()
-                .map(|index| all_fields[*index].1.clone())
-                .collect();
+            if sender.send(row_result).is_err() {
+                break; // the COPY's given up
+            }
+        }
+    });
+    receiver.into_iter()
+}
 
-            // TODO: type data can come from pq_type_data
-            let converted: Vec<Box<dyn ToSql + Sync>> = converters
-                .iter()
-                .enumerate()
-                .map(|(i, f)| f(&desired_fields[i]))
-                .collect();
+// Of copies run at the same time, the error of the first in order to fail, not the first
+// in time, so a file fails the same way each run. The others' errors are only logged.
+fn first_error(results: Vec<Result<RowsWritten>>) -> Result<Vec<RowsWritten>> {
+    for other in results.iter().filter_map(|r| r.as_ref().err()).skip(1) {
+        error!(
+            "another copy of the file's row groups failed too: {}",
+            other
+        );
+    }
+    results.into_iter().collect()
+}
 
-            debug!("converted data:<<{:?}>>", converted);
+// What writing rows did. null_substitutions has, per col, how many values a converter
+// didn't expect, so wrote as NULL instead. Cols without any are left out. Parquet nulls
+// aren't counted, nor NaN/Infinity nulled for db.on_non_finite_float null.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RowsWritten {
+    pub rows: u64,
+    pub null_substitutions: HashMap<String, u64>,
+    pub rows_skipped: u64, // by parquet.row_filter
+}
 
-            let mut row_data: Vec<&(dyn ToSql + Sync)> =
-                converted.iter().map(|x| x.as_ref()).collect();
+impl RowsWritten {
+    // null_substitutions has the count for each of the first db_cols
+    fn new(rows: u64, db_cols: &[String], null_substitutions: &[u64]) -> Self {
+        let null_substitutions = db_cols
+            .iter()
+            .zip(null_substitutions)
+            .filter(|(_, count)| **count > 0)
+            .map(|(col, count)| (col.clone(), *count))
+            .collect();
+        Self {
+            rows,
+            null_substitutions,
+            rows_skipped: 0,
+        }
+    }
 
-            debug!("SELECTED ROW DATA: {:?}", &row_data);
-            debug!("RUST DATA TYPES: {:?}", &pq_type_data);
-            match writer.as_mut().write(&row_data).await {
-                Ok(_) => debug!("row written to db"),
-                Err(e) => {
-                    let msg = format!(
-                        "\
-                       Issue writing row to db: \n\
-                       {}\n\
-                       column names are: {:?}\n\
-                       db col types are: {:?}\n\
-                       rust types of data: {:?}\n\
-                    ",
-                        e, &self.db_cols, &self.db_col_types, pq_type_data,
-                    );
-                    bail!(MultiLineError { msg });
-                }
-            };
-            row_data.clear();
+    fn add(mut self, other: RowsWritten) -> Self {
+        self.rows += other.rows;
+        self.rows_skipped += other.rows_skipped;
+        for (col, count) in other.null_substitutions {
+            *self.null_substitutions.entry(col).or_default() += count;
         }
+        self
+    }
 
-        // Some issues may only present when the COPY executes - on writer.finish()
-        match writer.finish().await {
-            Ok(num_rows_added) => Ok(num_rows_added),
-            Err(e) => {
-                let msg = format!("\
-                    Issue flushing data to db: \n\
-                    {}\n\
-                    column names are: {:?}\n\
-                    db col types are: {:?}\n\
-                    Check the types of the corresponding parquet row data:\n\
-                    Either run with RUST_LOG=db=debug, or use a parquet inspector to check the metadata\n\
-                ",
-                    e,
-                    &self.db_cols,
-                    &self.db_col_types,
+    // Logs a warning for each col with values nulled, as each is data lost.
+    // Returns the lines logged.
+    pub fn warn_null_substitutions(&self, context: &str) -> Vec<String> {
+        let mut cols: Vec<(&String, &u64)> = self.null_substitutions.iter().collect();
+        cols.sort();
+        cols.into_iter()
+            .map(|(col, count)| {
+                let line = format!(
+                    "{}: {} values for col {} didn't match the parquet type, so were written as NULL",
+                    context, count, col
                 );
-                bail!(MultiLineError { msg });
-            }
+                warn!("{}", line);
+                line
+            })
+            .collect()
+    }
+}
+
+// For db.on_type_mismatch error: fails on a row with any value a converter didn't expect.
+// null_substitutions are the counts so far, so any at all means this row or one before.
+fn check_type_mismatch(
+    on_type_mismatch: &TypeMismatch,
+    db_cols: &[String],
+    null_substitutions: &[u64],
+) -> Result<()> {
+    if *on_type_mismatch != TypeMismatch::Error {
+        return Ok(());
+    }
+    if let Some(i) = null_substitutions.iter().position(|count| *count > 0) {
+        bail!(
+            "db.on_type_mismatch is error, but a value for col {} doesn't match the parquet type",
+            db_cols[i]
+        );
+    }
+    Ok(())
+}
+
+// For runner.quarantine_dir: why a converted row can't be written, if it can't. Either a
+// converter didn't expect a value (so nulled it), or a value won't encode as its col's type.
+fn reject_reason(
+    converted: &[Box<dyn ToSql + Sync>],
+    db_cols: &[String],
+    col_types: &[PgType],
+    nulled_before: &[u64],
+    null_substitutions: &[u64],
+) -> Option<String> {
+    if let Some(i) = (0..nulled_before.len()).find(|i| null_substitutions[*i] > nulled_before[*i]) {
+        return Some(format!(
+            "a value for col {} doesn't match the parquet type",
+            db_cols[i]
+        ));
+    }
+    let mut buf = BytesMut::new();
+    for ((value, col), col_type) in converted.iter().zip(db_cols).zip(col_types) {
+        buf.clear();
+        if let Err(e) = value.to_sql_checked(col_type, &mut buf) {
+            return Some(format!("a value for col {} won't encode: {}", col, e));
         }
     }
+    None
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::test_setup::tests::{
-        create_table_return_client, get_rows_as_csv_string, parquet_cars_reader,
-        parquet_iris_reader, setup_docker, GOOD_DB_CONN_STR,
+// For runner.quarantine_dir: writes the row to rejects if it can't be written to the db,
+// taking back the nulls its conversion added. Returns whether it was rejected.
+#[allow(clippy::too_many_arguments)]
+fn reject_row(
+    rejects: &Rejects,
+    row: Row, // as it was before converting
+    converted: &[Box<dyn ToSql + Sync>],
+    parquet_col_nums: &[ColPath],
+    db_cols: &[String],
+    col_types: &[PgType],
+    nulled_before: &[u64],
+    null_substitutions: &mut [u64],
+) -> Result<bool> {
+    let Some(reason) = reject_reason(
+        converted,
+        db_cols,
+        col_types,
+        nulled_before,
+        null_substitutions,
+    ) else {
+        return Ok(false);
     };
-    use anyhow::Result;
-    use parquet::basic::{ConvertedType, Type as PqType};
-    use parquet::file::reader::FileReader;
-    use std::collections::HashMap;
-    use tokio_postgres::types::Type as PgType;
+    null_substitutions.copy_from_slice(nulled_before);
+    let cols = row.into_columns();
+    let fields: Vec<Field> = parquet_col_nums
+        .iter()
+        .map(|col_path| field_at(&cols, col_path))
+        .collect();
+    rejects.write(&fields, &reason)?;
+    Ok(true)
+}
 
-    macro_rules! vec_stringify {
-        ($($x:expr),*) => (vec![$($x.to_string()),*]);
+// How many of the fields are desired fields, the rest being the bitmasks' bool fields.
+// Checks each of those is a BOOLEAN.
+fn num_desired_fields(pq_type_data: &[PqTypeData], bitmasks: &[Bitmask]) -> Result<usize> {
+    let bitmask_fields: Vec<&String> = bitmasks.iter().flat_map(|b| &b.fields).collect();
+    let num_desired = pq_type_data.len() - bitmask_fields.len();
+    for (i, field) in bitmask_fields.iter().enumerate() {
+        let (physical, _, _) = &pq_type_data[num_desired + i];
+        if *physical != PqType::BOOLEAN {
+            bail!(
+                "db.bitmask_columns field {} is a {}, not a BOOLEAN",
+                field,
+                physical
+            );
+        }
     }
+    Ok(num_desired)
+}
 
-    pub async fn default_db_struct_for_cars_table(
-        table_name: &str,
-        schema_type: &str,
-    ) -> Result<Db> {
-        let client = create_table_return_client(table_name.to_string(), schema_type).await?;
-        match schema_type {
-            "car" => Ok(Db {
-                client, // do connection as simply as possible.
-                db_cols: vec_stringify!["model", "num_of_cyl", "miles_per_gallon", "gear"],
-                db_col_types: vec![PgType::VARCHAR, PgType::INT4, PgType::FLOAT8, PgType::INT4],
-                table_name: table_name.to_string(),
-            }),
-            // the _ case provides incorrect db types to force failure
-            _ => Ok(Db {
-                client, // do connection as simply as possible.
-                db_cols: vec_stringify!["model", "num_of_cyl", "miles_per_gallon", "gear"],
-                db_col_types: vec![PgType::INT2, PgType::INT2, PgType::FLOAT8, PgType::INT4],
-                table_name: table_name.to_string(),
-            }),
+// How many times to retry connecting to the db, and the initial delay between attempts.
+// The delay doubles after each failed attempt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectRetry {
+    pub retries: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for ConnectRetry {
+    fn default() -> Self {
+        ConnectRetry {
+            retries: 0,
+            backoff_ms: 500,
         }
     }
+}
 
-    pub async fn default_db_struct_for_iris_table(table_name: &str) -> Result<Db> {
-        let client = create_table_return_client(table_name.to_string(), "iris").await?;
-        Ok(Db {
-            client, // do connection as simply as possible.
-            db_cols: vec_stringify!["variety", "\"sepal.length\"", "\"sepal.width\""],
-            db_col_types: vec![PgType::VARCHAR, PgType::FLOAT8, PgType::FLOAT8],
-            table_name: table_name.to_string(),
-        })
+// What a db.load_metadata or db.extra_columns column is filled with
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadMetadataValue {
+    SourceKey,
+    LoadedAt,
+    RunId,
+    FileStartedAt,   // now(), as it'd be in the file's transaction
+    Literal(String), // the same text for every row
+}
+
+impl LoadMetadataValue {
+    // A db.extra_columns value: now(), source_file, or else a literal. One in single quotes,
+    // as in sql, is always a literal, e.g. 'now()'.
+    pub fn from_extra_column(value: &str) -> Self {
+        if let Some(quoted) = value
+            .strip_prefix('\'')
+            .and_then(|rest| rest.strip_suffix('\''))
+        {
+            return LoadMetadataValue::Literal(quoted.replace("''", "'"));
+        }
+        match value {
+            "now()" => LoadMetadataValue::FileStartedAt,
+            "source_file" => LoadMetadataValue::SourceKey,
+            _ => LoadMetadataValue::Literal(value.to_string()),
+        }
     }
 
-    #[tokio::test]
-    async fn test_connect_success() -> Result<()> {
-        setup_docker();
-        let table_name = "test_connect_success";
-        let _ = create_table_return_client(table_name.to_string(), "car").await;
+    fn allowed_types(&self) -> &'static [PgType] {
+        match self {
+            LoadMetadataValue::LoadedAt | LoadMetadataValue::FileStartedAt => {
+                &[PgType::TIMESTAMPTZ, PgType::TIMESTAMP]
+            }
+            _ => &[PgType::TEXT, PgType::VARCHAR, PgType::BPCHAR],
+        }
+    }
+}
 
-        // Attempt to connect.
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            table_name,
-            vec_stringify!["model", "gear"],
-            None,
+// The db.load_metadata and db.extra_columns columns, written after the desired fields' cols,
+// with the same values for every row of a file.
+#[derive(Debug, Default)]
+pub struct LoadMetadata {
+    pub cols: Vec<(String, PgType, LoadMetadataValue)>,
+    pub run_id: String,
+    pub loaded_at: DateTime<Utc>,
+    pub source_key: String,             // of the file being loaded
+    pub file_started_at: DateTime<Utc>, // when it started loading
+}
+
+impl LoadMetadata {
+    fn values(&self) -> Vec<Box<dyn ToSql + Sync>> {
+        self.cols
+            .iter()
+            .map(|(_, col_type, value)| -> Box<dyn ToSql + Sync> {
+                match value {
+                    LoadMetadataValue::SourceKey => Box::new(self.source_key.clone()),
+                    LoadMetadataValue::RunId => Box::new(self.run_id.clone()),
+                    // a timestamp without zone gets the UTC time
+                    LoadMetadataValue::LoadedAt if *col_type == PgType::TIMESTAMP => {
+                        Box::new(self.loaded_at.naive_utc())
+                    }
+                    LoadMetadataValue::LoadedAt => Box::new(self.loaded_at),
+                    LoadMetadataValue::FileStartedAt if *col_type == PgType::TIMESTAMP => {
+                        Box::new(self.file_started_at.naive_utc())
+                    }
+                    LoadMetadataValue::FileStartedAt => Box::new(self.file_started_at),
+                    LoadMetadataValue::Literal(text) => Box::new(text.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+// Errors from the server (bad password, no such db) won't go away by retrying,
+// except for those that mean the server is starting up, shutting down or busy.
+// Anything else (refused, reset, timed out) is a connection issue, so worth retrying.
+fn is_retryable(e: &tokio_postgres::Error) -> bool {
+    use tokio_postgres::error::SqlState;
+
+    match e.code() {
+        Some(code) => matches!(
+            *code,
+            SqlState::CANNOT_CONNECT_NOW
+                | SqlState::TOO_MANY_CONNECTIONS
+                | SqlState::ADMIN_SHUTDOWN
+                | SqlState::CRASH_SHUTDOWN
+        ),
+        None => e.as_db_error().is_none(),
+    }
+}
+
+async fn connect_with_tls<T>(
+    pg_config: &tokio_postgres::Config,
+    tls: T,
+    notices: UnboundedSender<String>,
+) -> Result<Client, tokio_postgres::Error>
+where
+    T: MakeTlsConnect<Socket>,
+    T::Stream: Send + 'static,
+{
+    let (client, connection) = pg_config.connect(tls).await?;
+    spawn_connection(connection, notices);
+    Ok(client)
+}
+
+async fn connect_client(
+    conn_str: &str,
+    sslmode: &SslMode,
+    ca_cert: Option<&str>,
+    retry: &ConnectRetry,
+) -> Result<(Client, UnboundedReceiver<String>)> {
+    use tokio_postgres::config::SslMode as PgSslMode;
+    use tokio_postgres::{Config, NoTls};
+
+    // a bad conn_str or tls config is never worth retrying, so check those first
+    let mut pg_config: Config = conn_str.parse()?;
+    let tls = match sslmode {
+        SslMode::Disable => None,
+        SslMode::Require | SslMode::VerifyFull => {
+            pg_config.ssl_mode(PgSslMode::Require); // fail if server won't do TLS
+            Some(tls::make_tls_connect(sslmode, ca_cert)?)
+        }
+    };
+
+    let (notices_tx, notices_rx) = mpsc::unbounded_channel();
+    let mut attempt: u32 = 0;
+    loop {
+        let result = match &tls {
+            None => connect_with_tls(&pg_config, NoTls, notices_tx.clone()).await,
+            Some(tls) => connect_with_tls(&pg_config, tls.clone(), notices_tx.clone()).await,
+        };
+
+        match result {
+            Ok(client) => return Ok((client, notices_rx)),
+            Err(e) if attempt < retry.retries && is_retryable(&e) => {
+                let delay_ms = retry
+                    .backoff_ms
+                    .saturating_mul(2_u64.saturating_pow(attempt));
+                attempt += 1;
+                warn!(
+                    "db connection failed: {} ... retry {} of {} in {}ms",
+                    e, attempt, retry.retries, delay_ms
+                );
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// For db.run_report_table: a run's totals, as in its run summary
+pub struct RunReport<'a> {
+    pub run_id: &'a str,
+    pub files_processed: u64,
+    pub rows_written: u64,
+    pub files_failed: u64,
+    pub elapsed_secs: f64,
+}
+
+// Inserts one row for the run into table_name, on a connection of its own. The table must
+// have these cols, of any types they cast to, and may have others e.g. reported_at default now().
+pub async fn insert_run_report(
+    conn_str: &str,
+    sslmode: &SslMode,
+    ca_cert: Option<&str>,
+    retry: &ConnectRetry,
+    table_name: &str,
+    audit_sql: &AuditSql,
+    report: &RunReport<'_>,
+) -> Result<()> {
+    let (client, _) = connect_client(conn_str, sslmode, ca_cert, retry).await?;
+    let sql = format!(
+        "INSERT INTO {} (run_id, files_processed, rows_written, files_failed, elapsed_secs) \
+         VALUES ($1::text, $2::bigint, $3::bigint, $4::bigint, $5::float8)",
+        quote_table_name(table_name)
+    );
+    audit_sql.record(&sql)?;
+    client
+        .execute(
+            sql.as_str(),
+            &[
+                &report.run_id,
+                &(report.files_processed as i64),
+                &(report.rows_written as i64),
+                &(report.files_failed as i64),
+                &report.elapsed_secs,
+            ],
         )
-        .await;
+        .await?;
+    Ok(())
+}
 
-        // Ensure the connection was successful by asserting it is Ok.
-        assert!(
-            db.is_ok(),
-            "Connection should succeed with a valid connection string"
-        );
+// ANALYZEs each table, or VACUUM ANALYZEs, so the planner's stats cover the rows just loaded.
+// On a connection of its own, outside any transaction, as VACUUM can't run in one.
+pub async fn analyze_tables(
+    conn_str: &str,
+    sslmode: &SslMode,
+    ca_cert: Option<&str>,
+    retry: &ConnectRetry,
+    table_names: &[String],
+    vacuum: bool,
+    audit_sql: &AuditSql,
+) -> Result<()> {
+    let (client, _) = connect_client(conn_str, sslmode, ca_cert, retry).await?;
+    let command = match vacuum {
+        true => "VACUUM ANALYZE",
+        false => "ANALYZE",
+    };
+    for table_name in table_names {
+        let sql = format!("{} {}", command, quote_table_name(table_name));
+        debug!("{}", sql);
+        audit_sql.record(&sql)?;
+        if let Err(e) = client.batch_execute(sql.as_str()).await {
+            bail!("failed to {} {}: {}", command, table_name, e);
+        }
+    }
+    Ok(())
+}
+
+// Rows are COPYed into a temp table first, as COPY has no ON CONFLICT,
+// then inserted from there into the real table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upsert {
+    pub on_conflict: OnConflict,
+    pub conflict_columns: Vec<String>, // db col names, empty means any unique constraint (do_nothing only)
+}
+
+fn upsert_sql(
+    table_name: &str,
+    tmp_table_name: &str,
+    db_cols: &[String],
+    upsert: &Upsert,
+) -> String {
+    let cols = db_cols
+        .iter()
+        .map(|col| quote_ident(col))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let conflict_target = match upsert.conflict_columns.is_empty() {
+        true => String::new(),
+        false => format!(
+            " ({})",
+            upsert
+                .conflict_columns
+                .iter()
+                .map(|col| quote_ident(col))
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+    };
+
+    let action = match upsert.on_conflict {
+        OnConflict::DoNothing => String::from("DO NOTHING"),
+        OnConflict::DoUpdate => format!(
+            "DO UPDATE SET {}",
+            db_cols
+                .iter()
+                .filter(|col| !upsert.conflict_columns.contains(col))
+                .map(|col| format!("{} = EXCLUDED.{}", quote_ident(col), quote_ident(col)))
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+    };
+
+    format!(
+        "INSERT INTO {} ({}) SELECT {} FROM {} ON CONFLICT{} {}",
+        table_name, cols, cols, tmp_table_name, conflict_target, action
+    )
+}
+
+// The connection only hands NOTICEs and WARNINGs from the server (e.g. raised by a
+// trigger during COPY) to its own info logging. So we poll it ourselves,
+// and pass them on, to be logged as warnings for the file being loaded.
+fn spawn_connection<S, T>(mut connection: Connection<S, T>, notices: UnboundedSender<String>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notice(notice)) => {
+                    // receiver only goes when the Db does, so no one left to tell
+                    let _ = notices.send(format!("{}: {}", notice.severity(), notice.message()));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let msg = format!("db connection error: {}", e);
+                    error!("{}", msg);
+                    bail!("{}", msg);
+                }
+            }
+        }
+        Ok(())
+    });
+}
+
+// The most params postgres takes for one statement, so caps the rows per INSERT
+const MAX_INSERT_PARAMS: usize = 65535;
+
+// For db.copy_parallelism: each share but the first is prepared as <prefix><txid>:<share>,
+// where txid is the first share's transaction. Whether that committed says what to do with
+// the rest, so shares left prepared by a run that stopped part way can be settled.
+const PREPARED_GID_PREFIX: &str = "s3_parquet_to_postgres:";
+
+// A tsvector col's param is the text, which postgres turns into one. COPY can't do that.
+fn insert_param(param: usize, db_col_type: &PgType) -> String {
+    match *db_col_type {
+        PgType::TS_VECTOR => format!("to_tsvector(${})", param),
+        _ => format!("${}", param),
+    }
+}
+
+// The type each col's param is sent as: a tsvector col's is its text
+fn insert_param_type(db_col_type: &PgType) -> PgType {
+    match *db_col_type {
+        PgType::TS_VECTOR => PgType::TEXT,
+        _ => db_col_type.clone(),
+    }
+}
+
+// "INSERT INTO t (a,b) VALUES ($1,$2),($3,$4)" for num_rows rows
+fn insert_sql(
+    table_name: &str,
+    db_cols: &[String],
+    db_col_types: &[PgType],
+    num_rows: usize,
+) -> String {
+    let values = (0..num_rows)
+        .map(|row| {
+            let params = db_col_types
+                .iter()
+                .enumerate()
+                .map(|(col, db_col_type)| insert_param(row * db_cols.len() + col + 1, db_col_type))
+                .collect::<Vec<String>>()
+                .join(",");
+            format!("({})", params)
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+    format!(
+        "INSERT INTO {} ({}) VALUES {}",
+        table_name,
+        db_cols
+            .iter()
+            .map(|col| quote_ident(col))
+            .collect::<Vec<String>>()
+            .join(","),
+        values
+    )
+}
+
+// For db.write_mode insert: as copy_rows, but batch_size rows at a time go in one INSERT.
+// The statement is prepared once for a full batch, and again for any smaller last one.
+#[allow(clippy::too_many_arguments)]
+async fn insert_rows(
+    tx: &Transaction<'_>,
+    table_name: &str,
+    db_cols: &[String],
+    db_col_types: &[PgType],
+    iter: impl Iterator<Item = parquet::errors::Result<Row>>,
+    parquet_col_nums: &[ColPath],
+    converters: &[&converters::ConverterFn],
+    fills: &[Option<FillVal>],
+    bitmasks: &[Bitmask],
+    on_type_mismatch: &TypeMismatch,
+    load_metadata: &[Box<dyn ToSql + Sync>],
+    audit_sql: &AuditSql,
+    batch_size: usize,
+    rejects: Option<&Rejects>,
+) -> Result<RowsWritten> {
+    let mut full_batch_stmt: Option<Statement> = None;
+    let mut num_rows_added: u64 = 0;
+    let mut null_substitutions = vec![0; converters.len()];
+    let mut batch: Vec<Vec<Box<dyn ToSql + Sync>>> = Vec::with_capacity(batch_size);
+    let mut rows = iter.peekable();
+
+    let col_param_types: Vec<PgType> = db_col_types.iter().map(insert_param_type).collect();
+
+    while let Some(row_result) = rows.next() {
+        let row: Row = row_result?;
+        // for runner.quarantine_dir, the row and nulls as they were, in case it's rejected
+        let kept = rejects.map(|_| (row.clone(), null_substitutions.clone()));
+        let converted = convert_row(
+            row,
+            parquet_col_nums,
+            converters,
+            fills,
+            bitmasks,
+            &mut null_substitutions,
+        );
+        let rejected = match (rejects, kept) {
+            (Some(rejects), Some((row, nulled_before))) => reject_row(
+                rejects,
+                row,
+                &converted,
+                parquet_col_nums,
+                db_cols,
+                &col_param_types,
+                &nulled_before,
+                &mut null_substitutions,
+            )?,
+            _ => false,
+        };
+        if !rejected {
+            batch.push(converted);
+        }
+        check_type_mismatch(on_type_mismatch, db_cols, &null_substitutions)?;
+        // a rejected last row can leave an empty batch, or one still to insert
+        if batch.is_empty() || (batch.len() < batch_size && rows.peek().is_some()) {
+            continue;
+        }
+
+        let stmt = match &full_batch_stmt {
+            Some(stmt) if batch.len() == batch_size => stmt.clone(),
+            _ => {
+                let sql = insert_sql(table_name, db_cols, db_col_types, batch.len());
+                let param_types: Vec<PgType> = (0..batch.len())
+                    .flat_map(|_| db_col_types.iter().map(insert_param_type))
+                    .collect();
+                audit_sql.record(&sql)?;
+                let stmt = tx.prepare_typed(sql.as_str(), &param_types).await?;
+                if batch.len() == batch_size {
+                    full_batch_stmt = Some(stmt.clone());
+                }
+                stmt
+            }
+        };
+        let params: Vec<&(dyn ToSql + Sync)> = batch
+            .iter()
+            .flat_map(|converted| converted.iter().chain(load_metadata))
+            .map(|x| x.as_ref())
+            .collect();
+        debug!("inserting {} rows", batch.len());
+        match tx.execute(&stmt, &params).await {
+            Ok(num_rows) => num_rows_added += num_rows,
+            Err(e) => {
+                let msg = format!(
+                    "\
+                   Issue inserting rows to db: \n\
+                   {}\n\
+                   column names are: {:?}\n\
+                   db col types are: {:?}\n\
+                ",
+                    e, db_cols, db_col_types,
+                );
+                bail!(MultiLineError { msg });
+            }
+        }
+        batch.clear();
+    }
+
+    Ok(RowsWritten::new(
+        num_rows_added,
+        db_cols,
+        &null_substitutions,
+    ))
+}
+
+// Writes the rows over COPY ... FROM STDIN BINARY, or with insert_rows for db.write_mode insert.
+// With max_rows_per_copy, each COPY takes at most that many, then another is started,
+// all in the caller's transaction. There's always at least one COPY, even for no rows.
+#[allow(clippy::too_many_arguments)]
+async fn copy_rows(
+    tx: &Transaction<'_>,
+    table_name: &str,
+    db_cols: &[String],
+    db_col_types: &[PgType],
+    iter: impl Iterator<Item = NumberedRow>,
+    parquet_col_nums: &[ColPath],
+    pq_type_data: &[PqTypeData],
+    base64_decode: &[bool],
+    decrypt: &[Option<DecryptKey>],
+    assume_timezone: &[Option<Tz>],
+    field_types: &[Option<FieldType>],
+    date_formats: Option<&[String]>,
+    on_non_finite_float: &NonFiniteFloat,
+    on_type_mismatch: &TypeMismatch,
+    fills: &[Option<FillVal>],
+    bitmasks: &[Bitmask], // cols after the desired fields' cols, their fields after the desired
+    load_metadata: &[Box<dyn ToSql + Sync>], // values of the cols after those
+    audit_sql: &AuditSql,
+    insert_batch_size: Option<usize>, // for db.write_mode insert, None is COPY
+    max_rows_per_copy: Option<usize>, // for db.max_rows_per_copy, None is all rows in one
+    copy_timeout: Option<Duration>,   // for db.copy_timeout_ms, the longest each COPY may take
+    rejects: Option<&Rejects>,        // for runner.quarantine_dir, None fails on a bad row
+) -> Result<RowsWritten> {
+    let copy_in_sql = format!(
+        "COPY {} ({}) FROM STDIN BINARY",
+        table_name,
+        db_cols
+            .iter()
+            .map(|col| quote_ident(col))
+            .collect::<Vec<String>>()
+            .join(","),
+    );
+
+    let num_desired = num_desired_fields(pq_type_data, bitmasks)?;
+    let converters = converters::build(
+        &pq_type_data[..num_desired],
+        &db_col_types[..num_desired],
+        base64_decode,
+        decrypt,
+        assume_timezone,
+        field_types,
+        date_formats,
+        on_non_finite_float,
+    )?;
+
+    if let Some(batch_size) = insert_batch_size {
+        return insert_rows(
+            tx,
+            table_name,
+            db_cols,
+            db_col_types,
+            iter.map(|(_, row_result)| row_result),
+            parquet_col_nums,
+            &converters,
+            fills,
+            bitmasks,
+            on_type_mismatch,
+            load_metadata,
+            audit_sql,
+            batch_size,
+            rejects,
+        )
+        .await;
+    }
+
+    let mut null_substitutions = vec![0; converters.len()];
+    let mut num_rows_added: u64 = 0;
+    let mut rows = iter.peekable();
+    loop {
+        let copy = copy_in(
+            tx,
+            &copy_in_sql,
+            db_cols,
+            db_col_types,
+            rows.by_ref().take(max_rows_per_copy.unwrap_or(usize::MAX)),
+            parquet_col_nums,
+            pq_type_data,
+            &converters,
+            fills,
+            bitmasks,
+            on_type_mismatch,
+            load_metadata,
+            audit_sql,
+            &mut null_substitutions,
+            rejects,
+        );
+        // only gives up while waiting on the db, e.g. a stalled connection or a lock
+        num_rows_added += match copy_timeout {
+            None => copy.await?,
+            Some(limit) => match tokio::time::timeout(limit, copy).await {
+                Ok(copied) => copied?,
+                Err(_) => bail!(
+                    "COPY into {} timed out after {}ms (db.copy_timeout_ms)",
+                    table_name,
+                    limit.as_millis()
+                ),
+            },
+        };
+        if rows.peek().is_none() {
+            break;
+        }
+    }
+
+    Ok(RowsWritten::new(
+        num_rows_added,
+        db_cols,
+        &null_substitutions,
+    ))
+}
+
+// After a failed write, so only logs its own failure. With db.copy_timeout_ms, it gets as
+// long again, as a connection stalled on a COPY won't answer the ROLLBACK either.
+async fn rollback(tx: Transaction<'_>, copy_timeout: Option<Duration>) {
+    let rolled_back = match copy_timeout {
+        None => tx.rollback().await,
+        Some(limit) => match tokio::time::timeout(limit, tx.rollback()).await {
+            Ok(rolled_back) => rolled_back,
+            Err(_) => {
+                error!(
+                    "no answer to ROLLBACK after {}ms, so the connection may be stuck",
+                    limit.as_millis()
+                );
+                return;
+            }
+        },
+    };
+    if let Err(rollback_err) = rolled_back {
+        error!("failed to rollback: {}", rollback_err);
+    }
+}
+
+// For db.copy_parallelism: commits, or rolls back, each share left prepared whose gid starts
+// with gid_prefix, as its first share did. Those whose first share is still going, e.g. in
+// another run, are left be. Returns how many were settled.
+async fn settle_prepared<C: GenericClient>(
+    client: &C,
+    audit_sql: &AuditSql,
+    gid_prefix: &str,
+) -> Result<usize> {
+    let rows = client
+        .query(
+            "SELECT gid FROM pg_prepared_xacts WHERE database = current_database() AND starts_with(gid, $1) ORDER BY gid",
+            &[&gid_prefix],
+        )
+        .await?;
+    let mut settled: usize = 0;
+    for row in rows {
+        let gid: String = row.get(0);
+        let txid = gid
+            .strip_prefix(PREPARED_GID_PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .and_then(|(txid, _)| txid.parse::<i64>().ok());
+        let Some(txid) = txid else {
+            warn!(
+                "prepared transaction {} has no txid in its gid, so leaving it",
+                gid
+            );
+            continue;
+        };
+        let status: Option<String> = client
+            .query_one("SELECT txid_status($1)", &[&txid])
+            .await?
+            .get(0);
+        let sql = match status.as_deref() {
+            Some("committed") => format!("COMMIT PREPARED '{}'", gid),
+            Some("aborted") => format!("ROLLBACK PREPARED '{}'", gid),
+            Some(_) => {
+                debug!(
+                    "prepared transaction {} isn't settled yet, so leaving it",
+                    gid
+                );
+                continue;
+            }
+            None => {
+                warn!(
+                    "prepared transaction {}: txid {} is too old to know if it committed, so leaving it",
+                    gid, txid
+                );
+                continue;
+            }
+        };
+        debug!("settling a share of a file: {}", sql);
+        audit_sql.record(&sql)?;
+        client.batch_execute(&sql).await?;
+        settled += 1;
+    }
+    Ok(settled)
+}
+
+// For db.copy_parallelism: commits a file's shares as one. All but the first are prepared,
+// then the first is committed: that's the point at which the file's loaded. Then the rest
+// are committed. A share failing to prepare rolls them all back. If we stop after the first
+// commits, the rest are left prepared, named for the first's txid, so Db::settle_prepared
+// commits them when next run. Until then, they hold their locks, and their rows aren't seen.
+// So does one that can't be committed on any share's connection: that's SharesLeftPrepared.
+async fn commit_shares(
+    txs: Vec<Transaction<'_>>,
+    audit_sql: &AuditSql,
+    copy_timeout: Option<Duration>,
+) -> Result<()> {
+    let mut txs = txs.into_iter();
+    let Some(first) = txs.next() else {
+        return Ok(());
+    };
+    let txid: i64 = first.query_one("SELECT txid_current()", &[]).await?.get(0);
+    let gid_prefix = format!("{}{}:", PREPARED_GID_PREFIX, txid);
+
+    // Once prepared, a transaction is no longer its session's, so is forgotten, not dropped,
+    // as that sends a ROLLBACK. Its connection still takes the COMMIT PREPARED.
+    let mut prepared: Vec<(String, Transaction<'_>)> = Vec::new();
+    let mut prepare_err: Option<tokio_postgres::Error> = None;
+    for (share, tx) in txs.by_ref().enumerate() {
+        let gid = format!("{}{}", gid_prefix, share + 1);
+        let sql = format!("PREPARE TRANSACTION '{}'", gid);
+        audit_sql.record(&sql)?;
+        match tx.batch_execute(&sql).await {
+            Ok(()) => prepared.push((gid, tx)),
+            Err(e) => {
+                audit_sql.record("ROLLBACK")?;
+                rollback(tx, copy_timeout).await;
+                prepare_err = Some(e);
+                break;
+            }
+        }
+    }
+    if let Some(e) = prepare_err {
+        for tx in std::iter::once(first).chain(txs) {
+            audit_sql.record("ROLLBACK")?;
+            rollback(tx, copy_timeout).await;
+        }
+        for (gid, tx) in prepared {
+            let sql = format!("ROLLBACK PREPARED '{}'", gid);
+            audit_sql.record(&sql)?;
+            if let Err(rollback_err) = tx.batch_execute(&sql).await {
+                error!("failed to {}: {}", sql, rollback_err);
+            }
+            std::mem::forget(tx);
+        }
+        return Err(anyhow::Error::new(e).context("failed to prepare a share of the file"));
+    }
+
+    audit_sql.record("COMMIT")?;
+    if let Err(e) = first.commit().await {
+        // it may have committed before its connection went, so settle the rest as it did
+        let (_, tx) = &prepared[0];
+        settle_prepared(tx, audit_sql, &gid_prefix).await?;
+        let status: Option<String> = tx
+            .query_one("SELECT txid_status($1)", &[&txid])
+            .await?
+            .get(0);
+        prepared
+            .into_iter()
+            .for_each(|(_, tx)| std::mem::forget(tx));
+        if status.as_deref() != Some("committed") {
+            return Err(e.into());
+        }
+        warn!("the file's first share committed, despite: {}", e);
+        return Ok(());
+    }
+
+    // the file's loaded, so a share failing to commit now is tried again on the other
+    // shares' connections, as any session can commit a prepared transaction
+    let mut uncommitted: Vec<String> = Vec::new();
+    for (gid, tx) in &prepared {
+        let sql = format!("COMMIT PREPARED '{}'", gid);
+        audit_sql.record(&sql)?;
+        if let Err(e) = tx.batch_execute(&sql).await {
+            warn!("failed to {}: {}", sql, e);
+            uncommitted.push(gid.clone());
+        }
+    }
+    if !uncommitted.is_empty() {
+        for (gid, tx) in &prepared {
+            match settle_prepared(tx, audit_sql, &gid_prefix).await {
+                Ok(_) => {
+                    uncommitted.clear();
+                    break;
+                }
+                Err(e) => warn!(
+                    "failed to settle the file's shares on {}'s connection: {}",
+                    gid, e
+                ),
+            }
+        }
+    }
+    prepared
+        .into_iter()
+        .for_each(|(_, tx)| std::mem::forget(tx));
+    match uncommitted.is_empty() {
+        true => Ok(()),
+        false => Err(SharesLeftPrepared { gids: uncommitted }.into()),
+    }
+}
+
+// One COPY statement, for all the rows given. Returns the number of rows added.
+// A failed write names its row by its place in the file.
+//
+// We want the safety provided by pin_mut!, so we create the following in the same scope:
+// * sink (filehandle) for copy in
+// * writer object
+// * pin_mut'ed writer (fixed mem address for its lifetime, but rust will still allow mutability)
+// You have to also call writer.as_mut().write() in the same scope
+// as any abstraction involves borrowing the writer, which is complicated by the Pin
+#[allow(clippy::too_many_arguments)]
+async fn copy_in(
+    tx: &Transaction<'_>,
+    copy_in_sql: &str,
+    db_cols: &[String],
+    db_col_types: &[PgType],
+    iter: impl Iterator<Item = NumberedRow>,
+    parquet_col_nums: &[ColPath],
+    pq_type_data: &[PqTypeData],
+    converters: &[&converters::ConverterFn],
+    fills: &[Option<FillVal>],
+    bitmasks: &[Bitmask],
+    on_type_mismatch: &TypeMismatch,
+    load_metadata: &[Box<dyn ToSql + Sync>],
+    audit_sql: &AuditSql,
+    null_substitutions: &mut [u64],
+    rejects: Option<&Rejects>,
+) -> Result<u64> {
+    let pg_types = db_col_types;
+    audit_sql.record(copy_in_sql)?;
+    let sink = tx.copy_in(copy_in_sql).await?;
+    let writer = BinaryCopyInWriter::new(sink, pg_types);
+    pin_mut!(writer);
+
+    for (row_number, row_result) in iter {
+        let row: Row = row_result?;
+        // for runner.quarantine_dir, the row and nulls as they were, in case it's rejected
+        let kept = rejects.map(|_| (row.clone(), null_substitutions.to_vec()));
+        let converted = convert_row(
+            row,
+            parquet_col_nums,
+            converters,
+            fills,
+            bitmasks,
+            null_substitutions,
+        );
+        if let (Some(rejects), Some((row, nulled_before))) = (rejects, kept) {
+            if reject_row(
+                rejects,
+                row,
+                &converted,
+                parquet_col_nums,
+                db_cols,
+                pg_types,
+                &nulled_before,
+                null_substitutions,
+            )? {
+                continue;
+            }
+        }
+        check_type_mismatch(on_type_mismatch, db_cols, null_substitutions)?;
+
+        debug!("converted data:<<{:?}>>", converted);
+
+        let mut row_data: Vec<&(dyn ToSql + Sync)> = converted
+            .iter()
+            .chain(load_metadata)
+            .map(|x| x.as_ref())
+            .collect();
+
+        debug!("SELECTED ROW DATA: {:?}", &row_data);
+        debug!("RUST DATA TYPES: {:?}", &pq_type_data);
+        match writer.as_mut().write(&row_data).await {
+            Ok(_) => debug!("row written to db"),
+            Err(e) => {
+                let msg = format!(
+                    "\
+                   Issue writing row {} to db: \n\
+                   {}\n\
+                   row values are: {:?}\n\
+                   column names are: {:?}\n\
+                   db col types are: {:?}\n\
+                   rust types of data: {:?}\n\
+                ",
+                    row_number, e, row_data, db_cols, db_col_types, pq_type_data,
+                );
+                bail!(MultiLineError { msg });
+            }
+        };
+        row_data.clear();
+    }
+
+    // Some issues may only present when the COPY executes - on writer.finish()
+    match writer.finish().await {
+        Ok(num_rows_added) => Ok(num_rows_added),
+        Err(e) => {
+            let msg = format!("\
+                Issue flushing data to db: \n\
+                {}\n\
+                column names are: {:?}\n\
+                db col types are: {:?}\n\
+                Check the types of the corresponding parquet row data:\n\
+                Either run with RUST_LOG=db=debug, or use a parquet inspector to check the metadata\n\
+            ",
+                e,
+                db_cols,
+                db_col_types,
+            );
+            bail!(MultiLineError { msg });
+        }
+    }
+}
+
+// The db cols, and their types, for parquet_fields. For each use its alias if defined, or else
+// name the db col after the field, per the naming strategy (by default the same name).
+// Errors if the table doesn't have a col, or has it with a type we don't know.
+fn cols_for_fields(
+    table_name: &str,
+    parquet_fields: &[String],
+    field_aliases: &HashMap<String, Option<String>>,
+    naming_strategy: &NamingStrategy,
+    schema: &TableSchema,
+) -> Result<(Vec<String>, Vec<PgType>)> {
+    let (db_col_to_type, unknown_type_oids) = schema;
+    let db_cols: Vec<String> = parquet_fields
+        .iter()
+        .map(|f| match field_aliases.get(f.as_str()) {
+            Some(Some(alias)) => alias.clone(),
+            _ => db_col_name(f, naming_strategy),
+        })
+        .collect();
+
+    // Check each user specified db col exists or error, going by the table's name for it.
+    // For each that exists, collect its postgres column data type.
+    let table_cols: Vec<&String> = db_col_to_type
+        .keys()
+        .chain(unknown_type_oids.keys())
+        .collect();
+    let db_cols: Vec<String> = db_cols
+        .iter()
+        .map(|col| Ok(table_col_name(col, &table_cols)?.unwrap_or_else(|| col.clone())))
+        .collect::<Result<_>>()?;
+    let mut db_col_types: Vec<PgType> = Vec::with_capacity(db_cols.len());
+    for col in &db_cols {
+        if let Some(col_type) = db_col_to_type.get(col.as_str()) {
+            db_col_types.push(col_type.clone());
+        } else if let Some(type_oid) = unknown_type_oids.get(col.as_str()) {
+            let msg = format!(
+                "Table {} column {} has unknown type OID: {}",
+                table_name, col, type_oid
+            );
+            error!("{}", msg);
+            bail!("{}", msg);
+        } else {
+            let msg = format!("Table {} does not have column {}", table_name, col);
+            error!("{}", msg);
+            bail!("{}", msg);
+        }
+    }
+    Ok((db_cols, db_col_types))
+}
+
+// For parquet.desired_fields *: what's needed to find a file's fields' cols, once it's read
+#[derive(Debug)]
+pub struct AllFields {
+    field_aliases: HashMap<String, Option<String>>,
+    naming_strategy: NamingStrategy,
+    schema: TableSchema,
+}
+
+#[derive(Debug)]
+pub struct Db {
+    pub client: Client,
+    pub db_cols: Vec<String>,
+    pub db_col_types: Vec<PgType>,
+    pub table_name: String,
+    pub upsert: Option<Upsert>,
+    pub load_mode: LoadMode,
+    pub notices: Mutex<UnboundedReceiver<String>>,
+    pub on_non_finite_float: NonFiniteFloat,
+    pub on_type_mismatch: TypeMismatch,
+    pub base64_decode: Vec<bool>, // per db col, empty if none are
+    pub decrypt: Vec<Option<DecryptKey>>, // per db col, empty if none are
+    pub assume_timezone: Vec<Option<Tz>>, // per db col, empty if none are
+    pub field_types: Vec<Option<FieldType>>, // per db col, empty if none are
+    pub date_formats: Option<Vec<String>>, // for parquet.parse_dates. None leaves it to postgres
+    pub fills: Vec<Option<FillVal>>, // per db col, for optional fields. Empty if none are
+    pub load_metadata: LoadMetadata,
+    // for db.copy_parallelism, the connections besides client that COPY a file's
+    // row groups at the same time. Each with its notices. Empty means just client.
+    pub copy_clients: Vec<(Client, Mutex<UnboundedReceiver<String>>)>,
+    pub audit_sql: AuditSql,
+    pub insert_batch_size: Option<usize>, // for db.write_mode insert. None is COPY
+    pub bitmasks: Vec<Bitmask>,           // written after the desired fields' cols
+    pub max_rows_per_copy: Option<usize>, // for db.max_rows_per_copy. None is one COPY a file
+    pub copy_timeout: Option<Duration>,   // for db.copy_timeout_ms. None waits for ever
+    pub row_filter: Option<RowFilter>,    // for parquet.row_filter. None writes every row
+    pub all_fields: Option<AllFields>,    // for parquet.desired_fields *. None if they're listed
+    pub rejects: Option<Rejects>,         // for runner.quarantine_dir. None fails on a bad row
+    // the table's cols and their types, as connect found them, so the other builders
+    // needn't query the catalog again. None for a Db not made by connect.
+    pub schema: Option<TableSchema>,
+}
+
+impl Db {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn connect(
+        conn_str: &str,
+        sslmode: &SslMode,
+        ca_cert: Option<&str>,
+        retry: &ConnectRetry,
+        table_name: &str,
+        parquet_fields: Vec<String>,
+        parquet_to_db: Option<HashMap<String, Option<String>>>,
+        naming_strategy: &NamingStrategy,
+        schema_cache: Option<&SchemaCache>,
+    ) -> Result<Self> {
+        let (client, notices) = connect_client(conn_str, sslmode, ca_cert, retry).await?;
+
+        // query db table to get types for each column, unless another connection just did
+        let (db_col_to_type, unknown_type_oids) = match schema_cache {
+            Some(cache) => cache.db_col_to_type(&client, conn_str, table_name).await?,
+            None => db_col_to_type(&client, table_name).await?,
+        };
+        debug!("db_col_to_type: {:?}", db_col_to_type);
+
+        // parquet_to_db: HashMap of parquet field name to the destination db col.
+        // It's useful when the db col name differs from the parquet field name.
+        // e.g. when parquet field name has characters not allowed in a db column name.
+        let field_aliases = parquet_to_db.unwrap_or_default();
+        // for "*", the fields are only known once a file's read, so their cols are found then
+        let all_fields = parquet_fields.iter().any(|f| f == ALL_FIELDS);
+        let parquet_fields = match all_fields {
+            true => vec![],
+            false => parquet_fields,
+        };
+        let schema: TableSchema = (db_col_to_type, unknown_type_oids);
+        let (db_cols, db_col_types) = cols_for_fields(
+            table_name,
+            &parquet_fields,
+            &field_aliases,
+            naming_strategy,
+            &schema,
+        )?;
+        let (_, unknown_type_oids) = &schema;
+
+        // Columns we don't write to can be any type, so just the one line for all of them
+        if !unknown_type_oids.is_empty() {
+            let mut skipped: Vec<String> = unknown_type_oids
+                .iter()
+                .map(|(col, type_oid)| format!("{} ({})", col, type_oid))
+                .collect();
+            skipped.sort();
+            debug!(
+                "Table {} columns not written to, with unknown type OIDs: {}",
+                table_name,
+                skipped.join(", ")
+            );
+        }
+
+        Ok(Db {
+            client,
+            db_cols,
+            db_col_types,
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: Mutex::new(notices),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: all_fields.then(|| AllFields {
+                field_aliases,
+                naming_strategy: naming_strategy.clone(),
+                schema: schema.clone(),
+            }),
+            rejects: None,
+            schema: Some(schema),
+        })
+    }
+
+    // For parquet.desired_fields *: the cols for the fields a file has, found once it's read.
+    // Does nothing when the fields are listed, as their cols were found on connect.
+    pub fn set_fields(&mut self, parquet_fields: &[String]) -> Result<()> {
+        let Some(all) = &self.all_fields else {
+            return Ok(());
+        };
+        let (db_cols, db_col_types) = cols_for_fields(
+            &self.table_name,
+            parquet_fields,
+            &all.field_aliases,
+            &all.naming_strategy,
+            &all.schema,
+        )?;
+        // connect's checks ran before the cols were known, so they're done again here
+        for col in &db_cols {
+            if self.load_metadata.cols.iter().any(|(c, _, _)| c == col) {
+                bail!(
+                    "db.load_metadata or db.extra_columns column {} is already written to, so can't be filled in too",
+                    col
+                );
+            }
+            if self.bitmasks.iter().any(|b| &b.col == col) {
+                bail!(
+                    "db.bitmask_columns column {} is already written to, so can't be a bitmask too",
+                    col
+                );
+            }
+        }
+        self.db_cols = db_cols;
+        self.db_col_types = db_col_types;
+        Ok(())
+    }
+
+    // Logs, as warnings, any notices the server has sent since last called.
+    // context says what we were doing e.g. the file being written.
+    // Returns the logged lines.
+    pub fn warn_notices(&self, context: &str) -> Vec<String> {
+        let mut logged: Vec<String> = Vec::new();
+        let copy_notices = self.copy_clients.iter().map(|(_, notices)| notices);
+        for notices in std::iter::once(&self.notices).chain(copy_notices) {
+            let mut notices = notices.lock().unwrap();
+            while let Ok(notice) = notices.try_recv() {
+                let line = format!("{}: db {}", context, notice);
+                warn!("{}", line);
+                logged.push(line);
+            }
+        }
+        logged
+    }
+
+    // For db.enforce_column_order: errors unless db_cols are in the same order as the
+    // table's columns. Table columns not in db_cols are skipped, as COPY leaves them be.
+    pub async fn check_column_order(&self) -> Result<()> {
+        let table_cols = db_col_order(&self.client, &self.table_name).await?;
+        let expected: Vec<&String> = table_cols
+            .iter()
+            .filter(|col| self.db_cols.contains(col))
+            .collect();
+
+        if expected != self.db_cols.iter().collect::<Vec<&String>>() {
+            bail!(
+                "db.enforce_column_order is set, but the columns to write are not in table {}'s column order.\n\
+                 columns to write: {:?}\n\
+                 table's order:    {:?}\n\
+                 Reorder parquet.desired_fields to match",
+                self.table_name,
+                self.db_cols,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    // For reconcile: which of keys have at least one row in the table, going by key_column.
+    // Compared as text, so key_column can be any type the keys are written as.
+    pub async fn keys_with_rows(
+        &self,
+        key_column: &str,
+        keys: &[String],
+    ) -> Result<HashSet<String>> {
+        let sql = format!(
+            "SELECT DISTINCT {col}::text FROM {table} WHERE {col}::text = ANY($1)",
+            col = quote_ident(key_column),
+            table = quote_table_name(&self.table_name),
+        );
+        debug!("keys with rows: {}", sql);
+        let rows = self.client.query(sql.as_str(), &[&keys]).await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    pub fn with_load_mode(mut self, load_mode: LoadMode) -> Self {
+        self.load_mode = load_mode;
+        self
+    }
+
+    pub fn with_non_finite_float(mut self, on_non_finite_float: NonFiniteFloat) -> Self {
+        self.on_non_finite_float = on_non_finite_float;
+        self
+    }
+
+    pub fn with_type_mismatch(mut self, on_type_mismatch: TypeMismatch) -> Self {
+        self.on_type_mismatch = on_type_mismatch;
+        self
+    }
+
+    // For parquet_to_db.base64_decode. desired_fields are the parquet fields, in db_cols order.
+    // Each base64 field must be one we write, into a BYTEA col.
+    pub fn with_base64_decode(
+        mut self,
+        desired_fields: &[String],
+        base64_fields: &[String],
+    ) -> Result<Self> {
+        for field in base64_fields {
+            let Some(i) = desired_fields.iter().position(|f| f == field) else {
+                bail!(
+                    "parquet_to_db.base64_decode field {} is not in parquet.desired_fields",
+                    field
+                );
+            };
+            if self.db_col_types[i] != PgType::BYTEA {
+                bail!(
+                    "parquet_to_db.base64_decode field {} must go into a bytea col, but {} is {}",
+                    field,
+                    self.db_cols[i],
+                    self.db_col_types[i]
+                );
+            }
+        }
+        self.base64_decode = desired_fields
+            .iter()
+            .map(|f| base64_fields.contains(f))
+            .collect();
+        Ok(self)
+    }
+
+    // For parquet_to_db.decrypt. Call after with_base64_decode, as a field can't be both.
+    // Each field must be one we write, into a bytea or text col, with its key in its env var.
+    pub fn with_decrypt(
+        mut self,
+        desired_fields: &[String],
+        decrypt_cfgs: &[DecryptConfig],
+    ) -> Result<Self> {
+        let mut decrypt: Vec<Option<DecryptKey>> = vec![None; desired_fields.len()];
+        for decrypt_cfg in decrypt_cfgs {
+            let field = &decrypt_cfg.col;
+            let Some(i) = desired_fields.iter().position(|f| f == field) else {
+                bail!(
+                    "parquet_to_db.decrypt col {} is not in parquet.desired_fields",
+                    field
+                );
+            };
+            let allowed = [PgType::BYTEA, PgType::TEXT, PgType::VARCHAR, PgType::BPCHAR];
+            if !allowed.contains(&self.db_col_types[i]) {
+                bail!(
+                    "parquet_to_db.decrypt col {} must go into a bytea or text col, but {} is {}",
+                    field,
+                    self.db_cols[i],
+                    self.db_col_types[i]
+                );
+            }
+            if self.base64_decode.get(i) == Some(&true) || decrypt[i].is_some() {
+                bail!(
+                    "parquet_to_db.decrypt col {} is already decoded or decrypted",
+                    field
+                );
+            }
+            decrypt[i] = Some(DecryptKey::from_env(&decrypt_cfg.key_env)?);
+        }
+        self.decrypt = decrypt;
+        Ok(self)
+    }
+
+    // For parquet_to_db.assume_timezone, a map of parquet field to zone name e.g. America/New_York.
+    // Each field must be one we write, into a TIMESTAMPTZ col, and each zone in the tz database.
+    pub fn with_assume_timezone(
+        mut self,
+        desired_fields: &[String],
+        zones: &HashMap<String, String>,
+    ) -> Result<Self> {
+        let mut assume_timezone: Vec<Option<Tz>> = vec![None; desired_fields.len()];
+        for (field, zone) in zones {
+            let Some(i) = desired_fields.iter().position(|f| f == field) else {
+                bail!(
+                    "parquet_to_db.assume_timezone field {} is not in parquet.desired_fields",
+                    field
+                );
+            };
+            if self.db_col_types[i] != PgType::TIMESTAMPTZ {
+                bail!(
+                    "parquet_to_db.assume_timezone field {} must go into a timestamptz col, but {} is {}",
+                    field,
+                    self.db_cols[i],
+                    self.db_col_types[i]
+                );
+            }
+            let Ok(tz) = zone.parse::<Tz>() else {
+                bail!(
+                    "parquet_to_db.assume_timezone zone {} for field {} is not a valid timezone name",
+                    zone,
+                    field
+                );
+            };
+            assume_timezone[i] = Some(tz);
+        }
+        self.assume_timezone = assume_timezone;
+        Ok(self)
+    }
+
+    // For parquet.field_types. Each field must be one we write.
+    // Whether the type fits the field is only known once a file is read.
+    pub fn with_field_types(
+        mut self,
+        desired_fields: &[String],
+        field_types: &HashMap<String, FieldType>,
+    ) -> Result<Self> {
+        let mut types: Vec<Option<FieldType>> = vec![None; desired_fields.len()];
+        for (field, field_type) in field_types {
+            let Some(i) = desired_fields.iter().position(|f| f == field) else {
+                bail!(
+                    "parquet.field_types field {} is not in parquet.desired_fields",
+                    field
+                );
+            };
+            types[i] = Some(*field_type);
+        }
+        self.field_types = types;
+        Ok(self)
+    }
+
+    // For parquet.parse_dates, validated with the config
+    pub fn with_parse_dates(mut self, parse_dates: Option<ParseDates>) -> Self {
+        self.date_formats = parse_dates.map(|p| p.formats());
+        self
+    }
+
+    // For parquet.optional_fields. desired_fields are the parquet fields, in db_cols order.
+    // Each fill value must parse for its field's db col.
+    pub fn with_optional_fields(
+        mut self,
+        desired_fields: &[String],
+        optional_fields: &[OptionalField],
+    ) -> Result<Self> {
+        let mut fills: Vec<Option<FillVal>> = vec![None; desired_fields.len()];
+        for optional_field in optional_fields {
+            let field = optional_field.name();
+            let Some(i) = desired_fields.iter().position(|f| f == field) else {
+                bail!(
+                    "parquet.optional_fields field {} is not in parquet.desired_fields",
+                    field
+                );
+            };
+            if let Some(fill) = optional_field.fill() {
+                match converters::parse_fill(fill, &self.db_col_types[i]) {
+                    Ok(fill_val) => fills[i] = Some(fill_val),
+                    Err(e) => bail!(
+                        "parquet.optional_fields field {} into col {}: {}",
+                        field,
+                        self.db_cols[i],
+                        e
+                    ),
+                }
+            }
+        }
+        self.fills = fills;
+        Ok(self)
+    }
+
+    // For db.load_metadata. Each col must be in the table, and not one of the desired fields'.
+    // The s3 key and run id go into text cols, and loaded_at into a timestamptz or timestamp col.
+    pub async fn with_load_metadata(
+        mut self,
+        load_metadata: &LoadMetadataConfig,
+        run_id: &str,
+        loaded_at: DateTime<Utc>,
+    ) -> Result<Self> {
+        let wanted = [
+            (
+                &load_metadata.source_key_column,
+                LoadMetadataValue::SourceKey,
+            ),
+            (&load_metadata.loaded_at_column, LoadMetadataValue::LoadedAt),
+            (&load_metadata.run_id_column, LoadMetadataValue::RunId),
+        ];
+        let mut cols: Vec<(String, PgType, LoadMetadataValue)> = Vec::new();
+        for (col, value) in wanted {
+            let Some(col) = col else {
+                continue;
+            };
+            if self.db_cols.contains(col) || cols.iter().any(|(c, _, _)| c == col) {
+                bail!(
+                    "db.load_metadata column {} is already written to, so can't be filled in too",
+                    col
+                );
+            }
+            cols.push((col.clone(), PgType::UNKNOWN, value));
+        }
+        if cols.is_empty() {
+            return Ok(self);
+        }
+
+        self.set_load_metadata_col_types(&mut cols, "db.load_metadata")
+            .await?;
+
+        self.load_metadata = LoadMetadata {
+            cols,
+            run_id: run_id.to_string(),
+            loaded_at,
+            ..Default::default()
+        };
+        Ok(self)
+    }
+
+    // For db.extra_columns, db col to what it's filled with. Call after with_load_metadata,
+    // as they're written after those cols, and can't be any already written to.
+    pub async fn with_extra_columns(
+        mut self,
+        extra_columns: &HashMap<String, String>,
+    ) -> Result<Self> {
+        if extra_columns.is_empty() {
+            return Ok(self);
+        }
+        // sorted, so the cols are always written in the same order
+        let mut wanted: Vec<(&String, &String)> = extra_columns.iter().collect();
+        wanted.sort();
+
+        let mut cols: Vec<(String, PgType, LoadMetadataValue)> = Vec::new();
+        for (col, value) in wanted {
+            let already_written = self.db_cols.contains(col)
+                || self.load_metadata.cols.iter().any(|(c, _, _)| c == col);
+            if already_written {
+                bail!(
+                    "db.extra_columns column {} is already written to, so can't be filled in too",
+                    col
+                );
+            }
+            cols.push((
+                col.clone(),
+                PgType::UNKNOWN,
+                LoadMetadataValue::from_extra_column(value),
+            ));
+        }
+        self.set_load_metadata_col_types(&mut cols, "db.extra_columns")
+            .await?;
+
+        self.load_metadata.cols.extend(cols);
+        Ok(self)
+    }
+
+    // The table's cols and their types, as connect found them, or queried if it didn't
+    async fn table_schema(&self) -> Result<TableSchema> {
+        match &self.schema {
+            Some(schema) => Ok(schema.clone()),
+            None => db_col_to_type(&self.client, &self.table_name).await,
+        }
+    }
+
+    // Each col must be in the table, with a type its value can go in
+    async fn set_load_metadata_col_types(
+        &self,
+        cols: &mut [(String, PgType, LoadMetadataValue)],
+        setting: &str,
+    ) -> Result<()> {
+        let (db_col_to_type, _) = self.table_schema().await?;
+        for (col, col_type, value) in cols.iter_mut() {
+            let Some(found_type) = db_col_to_type.get(col.as_str()) else {
+                bail!(
+                    "Table {} does not have {} column {}",
+                    self.table_name,
+                    setting,
+                    col
+                );
+            };
+            let allowed = value.allowed_types();
+            if !allowed.contains(found_type) {
+                bail!(
+                    "{} column {} must be one of {}, but is {}",
+                    setting,
+                    col,
+                    allowed
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    found_type
+                );
+            }
+            *col_type = found_type.clone();
+        }
+        Ok(())
+    }
+
+    // For db.bitmask_columns, target col to its bool fields. Call after with_extra_columns,
+    // as a bitmask col can't be one that's already written to.
+    pub async fn with_bitmask_columns(
+        mut self,
+        bitmask_columns: &HashMap<String, Vec<String>>,
+    ) -> Result<Self> {
+        if bitmask_columns.is_empty() {
+            return Ok(self);
+        }
+        // sorted, so the cols are always written in the same order
+        let mut cols: Vec<(&String, &Vec<String>)> = bitmask_columns.iter().collect();
+        cols.sort();
+
+        let (db_col_to_type, _) = self.table_schema().await?;
+        for (col, fields) in cols {
+            let already_written = self.db_cols.contains(col)
+                || self.load_metadata.cols.iter().any(|(c, _, _)| c == col);
+            if already_written {
+                bail!(
+                    "db.bitmask_columns column {} is already written to, so can't be a bitmask too",
+                    col
+                );
+            }
+            let Some(col_type) = db_col_to_type.get(col.as_str()) else {
+                bail!(
+                    "Table {} does not have db.bitmask_columns column {}",
+                    self.table_name,
+                    col
+                );
+            };
+            let Some(max_fields) = Bitmask::max_fields(col_type) else {
+                bail!(
+                    "db.bitmask_columns column {} must be a smallint, int or bigint, but is {}",
+                    col,
+                    col_type
+                );
+            };
+            if fields.is_empty() || fields.len() > max_fields {
+                bail!(
+                    "db.bitmask_columns column {} is a {}, so takes 1 to {} fields, not {}",
+                    col,
+                    col_type,
+                    max_fields,
+                    fields.len()
+                );
+            }
+            self.bitmasks.push(Bitmask {
+                col: col.clone(),
+                col_type: col_type.clone(),
+                fields: fields.clone(),
+            });
+        }
+        Ok(self)
+    }
+
+    // The parquet fields the bitmask cols are made from, to read after the desired fields
+    pub fn bitmask_fields(&self) -> Vec<String> {
+        self.bitmasks
+            .iter()
+            .flat_map(|bitmask| bitmask.fields.iter().cloned())
+            .collect()
+    }
+
+    // Every col a row is written to, in order: the desired fields' cols, the bitmask cols,
+    // then the load metadata and extra cols.
+    fn written_cols(&self) -> (Vec<String>, Vec<PgType>) {
+        let cols = self
+            .db_cols
+            .iter()
+            .cloned()
+            .chain(self.bitmasks.iter().map(|bitmask| bitmask.col.clone()))
+            .chain(
+                self.load_metadata
+                    .cols
+                    .iter()
+                    .map(|(col, _, _)| col.clone()),
+            )
+            .collect();
+        let col_types = self
+            .db_col_types
+            .iter()
+            .cloned()
+            .chain(self.bitmasks.iter().map(|bitmask| bitmask.col_type.clone()))
+            .chain(self.load_metadata.cols.iter().map(|(_, t, _)| t.clone()))
+            .collect();
+        (cols, col_types)
+    }
+
+    // The s3 key of the file about to be written, for a db.load_metadata source_key_column
+    // or a db.extra_columns source_file. It's starting now, for a db.extra_columns now().
+    pub fn set_source_key(&mut self, source_key: &str) {
+        self.load_metadata.source_key = source_key.to_string();
+        self.load_metadata.file_started_at = Utc::now();
+    }
+
+    // The table rows are written to. With swap, that's the copy of the table
+    // that will replace it once the run is done, in the same schema.
+    fn load_table_name(&self) -> String {
+        match self.load_mode {
+            LoadMode::Append => self.table_name.clone(),
+            LoadMode::Swap => format!("{}_new", self.table_name),
+        }
+    }
+
+    // Creates the copy of the table to load into, with the same keys, indexes,
+    // defaults and constraints. If it's already there, a previous run failed part way.
+    // It already has the rows of the files marked completed, so we carry on loading into it.
+    // A view on the table would follow it when it's renamed, so stop the old one being
+    // dropped, hence those tables can't be swapped.
+    pub async fn start_swap_load(&self) -> Result<()> {
+        let table = quote_table_name(&self.table_name);
+        let rows = self.client.query(DEPENDENT_VIEWS_SQL, &[&table]).await?;
+        if !rows.is_empty() {
+            let views: Vec<String> = rows.iter().map(|row| row.get("view_name")).collect();
+            bail!(
+                "table {} can't be loaded with db.load_mode swap, as views depend on it: {}",
+                self.table_name,
+                views.join(", ")
+            );
+        }
+        let sql = self.start_swap_load_sql();
+        debug!("start swap load: {}", sql);
+        self.audit_sql.record(&sql)?;
+        self.client.batch_execute(sql.as_str()).await?;
+        Ok(())
+    }
+
+    fn start_swap_load_sql(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (LIKE {} INCLUDING ALL)",
+            quote_table_name(&self.load_table_name()),
+            quote_table_name(&self.table_name),
+        )
+    }
+
+    // Whether a copy of the table is there to swap in, e.g. left by a run that loaded every
+    // file but failed or was shut down before swapping, so there's nothing in todo.
+    pub async fn swap_load_started(&self) -> Result<bool> {
+        let copy = quote_table_name(&self.load_table_name());
+        let row = self
+            .client
+            .query_one(
+                "SELECT to_regclass($1::text) IS NOT NULL AS found",
+                &[&copy],
+            )
+            .await?;
+        Ok(row.get("found"))
+    }
+
+    // Statements sent together run in one transaction, so readers of the table
+    // see either the old rows or the new ones, and any failure leaves the old table in place.
+    // The copy's serial cols default to the old table's sequences, so those are handed
+    // over to the copy before the old table's dropped, as they'd go with it otherwise.
+    pub async fn finish_swap_load(&self) -> Result<()> {
+        // RENAME TO takes a name without the schema, as the table stays in its schema
+        let (_, table) = split_table_name(&self.table_name);
+        let old_table_name = format!("{}_old", self.table_name);
+        let rows = self
+            .client
+            .query(OWNED_SEQUENCES_SQL, &[&quote_table_name(&self.table_name)])
+            .await?;
+        let hand_over: String = rows
+            .iter()
+            .map(|row| {
+                format!(
+                    " ALTER SEQUENCE {} OWNED BY {}.{};",
+                    row.get::<_, String>("sequence_name"),
+                    quote_table_name(&self.table_name),
+                    quote_ident(row.get("column_name")),
+                )
+            })
+            .collect();
+        let sql = format!(
+            "ALTER TABLE {} RENAME TO {}; ALTER TABLE {} RENAME TO {};{} DROP TABLE {};",
+            quote_table_name(&self.table_name),
+            quote_ident(&format!("{}_old", table)),
+            quote_table_name(&self.load_table_name()),
+            quote_ident(table),
+            hand_over,
+            quote_table_name(&old_table_name),
+        );
+        debug!("finish swap load: {}", sql);
+        self.audit_sql.record(&sql)?;
+        self.client.batch_execute(sql.as_str()).await?;
+        Ok(())
+    }
+
+    // Checks the conflict columns are among the cols we write, as they're all
+    // the temp table has. do_update needs a conflict target, and something to update.
+    pub fn with_upsert(mut self, upsert: Option<Upsert>) -> Result<Self> {
+        if let Some(upsert) = &upsert {
+            for col in &upsert.conflict_columns {
+                if !self.db_cols.contains(col) {
+                    let msg = format!(
+                        "conflict column {} is not one of the cols written to table {}",
+                        col, self.table_name
+                    );
+                    error!("{}", msg);
+                    bail!("{}", msg);
+                }
+            }
+            if upsert.on_conflict == OnConflict::DoUpdate {
+                if upsert.conflict_columns.is_empty() {
+                    bail!("on_conflict do_update needs conflict_columns");
+                }
+                if upsert.conflict_columns.len() == self.db_cols.len() {
+                    bail!("on_conflict do_update needs at least one col that is not a conflict column to update");
+                }
+            }
+        }
+        self.upsert = upsert;
+        Ok(self)
+    }
+
+    // For parquet.row_filter. Its col path is only known once a file's opened,
+    // so is set for each with set_row_filter_col_path.
+    pub fn with_row_filter(mut self, row_filter: Option<crate::config::RowFilter>) -> Self {
+        self.row_filter = row_filter.map(|f| RowFilter {
+            column: f.column,
+            col_path: vec![],
+            op: f.op,
+            value: f.value,
+        });
+        self
+    }
+
+    pub fn set_row_filter_col_path(&mut self, col_path: ColPath) {
+        if let Some(row_filter) = &mut self.row_filter {
+            row_filter.col_path = col_path;
+        }
+    }
+
+    // For runner.quarantine_dir, per file. None fails the file on a row that won't convert.
+    pub fn set_rejects(&mut self, rejects: Option<Rejects>) {
+        self.rejects = rejects;
+    }
+
+    // For db.audit_sql_file. run_id goes on each line, to tell runs apart.
+    pub fn with_audit_sql(mut self, audit_sql_file: Option<&str>, run_id: &str) -> Result<Self> {
+        self.audit_sql = AuditSql::new(audit_sql_file, run_id)?;
+        Ok(self)
+    }
+
+    // For db.write_mode. Call after with_load_metadata and with_bitmask_columns, as a batch of
+    // rows needs a param for each of the cols written, and they must fit in one INSERT.
+    pub fn with_write_mode(
+        mut self,
+        write_mode: WriteMode,
+        insert_batch_size: usize,
+    ) -> Result<Self> {
+        self.insert_batch_size = match write_mode {
+            WriteMode::Copy => {
+                let (db_cols, db_col_types) = self.written_cols();
+                if let Some(i) = db_col_types.iter().position(|t| *t == PgType::TS_VECTOR) {
+                    bail!(
+                        "col {} is a tsvector, so needs db.write_mode: insert, as COPY can't call to_tsvector",
+                        db_cols[i]
+                    );
+                }
+                None
+            }
+            WriteMode::Insert => {
+                let num_cols = self.written_cols().0.len();
+                if insert_batch_size < 1 {
+                    bail!("db.insert_batch_size must be at least 1");
+                }
+                if insert_batch_size * num_cols > MAX_INSERT_PARAMS {
+                    bail!(
+                        "db.insert_batch_size {} is too big for {} cols, as an INSERT takes at most {} params. Use at most {}",
+                        insert_batch_size,
+                        num_cols,
+                        MAX_INSERT_PARAMS,
+                        MAX_INSERT_PARAMS / num_cols
+                    );
+                }
+                Some(insert_batch_size)
+            }
+        };
+        Ok(self)
+    }
+
+    // For db.max_rows_per_copy. Each COPY of a file's rows is in the file's one transaction,
+    // so this bounds how long a COPY runs, not how much is committed at once.
+    pub fn with_max_rows_per_copy(mut self, max_rows_per_copy: Option<usize>) -> Result<Self> {
+        if max_rows_per_copy == Some(0) {
+            bail!("db.max_rows_per_copy must be at least 1");
+        }
+        self.max_rows_per_copy = max_rows_per_copy;
+        Ok(self)
+    }
+
+    // For db.copy_timeout_ms: a COPY still going after this long fails the file. Each COPY
+    // gets the whole limit, so with max_rows_per_copy, a file can take longer in all.
+    pub fn with_copy_timeout(mut self, copy_timeout_ms: Option<u64>) -> Result<Self> {
+        if copy_timeout_ms == Some(0) {
+            bail!("db.copy_timeout_ms must be at least 1, or left unset for no timeout");
+        }
+        self.copy_timeout = copy_timeout_ms.map(Duration::from_millis);
+        Ok(self)
+    }
+
+    // For db.statement_timeout_ms: sets statement_timeout for the session on every
+    // connection, so postgres itself cancels any statement that runs longer.
+    // After with_copy_parallelism, so its connections get it too.
+    pub async fn with_statement_timeout(self, statement_timeout_ms: Option<u64>) -> Result<Self> {
+        let Some(ms) = statement_timeout_ms else {
+            return Ok(self);
+        };
+        if ms == 0 {
+            bail!("db.statement_timeout_ms must be at least 1, or left unset for no timeout");
+        }
+        let sql = format!("SET statement_timeout = {}", ms);
+        let copy_clients = self.copy_clients.iter().map(|(client, _)| client);
+        for client in std::iter::once(&self.client).chain(copy_clients) {
+            self.audit_sql.record(&sql)?;
+            client.batch_execute(sql.as_str()).await?;
+        }
+        Ok(self)
+    }
+
+    // For db.copy_parallelism: settles any shares of a file left prepared, as a run stopped
+    // between committing its first share and the rest. Call once, before loading, as it
+    // could settle those of a file being loaded on another connection.
+    pub async fn settle_prepared(&self) -> Result<usize> {
+        settle_prepared(&self.client, &self.audit_sql, PREPARED_GID_PREFIX).await
+    }
+
+    // For db.copy_parallelism: opens the connections, besides client, to COPY a file's
+    // row groups over at once. Upserts go through a temp table per transaction, so can't.
+    // The shares are committed together by preparing all but one, so the server needs
+    // max_prepared_transactions for them, for each of the files_at_once loading together.
+    pub async fn with_copy_parallelism(
+        mut self,
+        copy_parallelism: usize,
+        files_at_once: usize,
+        conn_str: &str,
+        sslmode: &SslMode,
+        ca_cert: Option<&str>,
+        retry: &ConnectRetry,
+    ) -> Result<Self> {
+        if copy_parallelism < 1 {
+            bail!("db.copy_parallelism must be at least 1");
+        }
+        if copy_parallelism > 1 && self.upsert.is_some() {
+            bail!("db.copy_parallelism is only for COPY straight into the table, so can't be used with db.on_conflict");
+        }
+        if copy_parallelism > 1 {
+            let max_prepared: String = self
+                .client
+                .query_one("SHOW max_prepared_transactions", &[])
+                .await?
+                .get(0);
+            let needed = files_at_once * (copy_parallelism - 1);
+            if max_prepared.parse::<usize>().unwrap_or(0) < needed {
+                bail!(
+                    "db.copy_parallelism {} commits a file's shares together with PREPARE TRANSACTION, \
+                     so with {} file(s) at once needs the server's max_prepared_transactions at least {}, not {}",
+                    copy_parallelism,
+                    files_at_once,
+                    needed,
+                    max_prepared
+                );
+            }
+        }
+        for _ in 1..copy_parallelism {
+            let (client, notices) = connect_client(conn_str, sslmode, ca_cert, retry).await?;
+            self.copy_clients.push((client, Mutex::new(notices)));
+        }
+        Ok(self)
+    }
+
+    // As write_rows, but with db.copy_parallelism the file's row groups are split across
+    // the connections, each reading its share on its own thread, and COPYing it in its own
+    // transaction, at the same time. So the rows land in no particular order. Once every
+    // COPY has finished, the transactions are committed all or nothing, by commit_shares,
+    // so a failed file leaves no rows, as with write_rows.
+    pub async fn write_row_groups(
+        &mut self,
+        parquet: &Parquet,
+        parquet_col_nums: &[ColPath],
+        pq_type_data: &[PqTypeData],
+    ) -> Result<RowsWritten> {
+        let file_reader = parquet.file_reader()?;
+        let num_row_groups = file_reader.num_row_groups();
+        if self.copy_clients.is_empty() || num_row_groups < 2 {
+            return self
+                .write_rows(parquet.row_iter()?, parquet_col_nums, pq_type_data)
+                .await;
+        }
+
+        // row groups dealt out in turn, so each connection gets a similar share
+        let num_connections = num_row_groups.min(self.copy_clients.len() + 1);
+        let mut shares: Vec<Vec<usize>> = (0..num_connections).map(|_| vec![]).collect();
+        for i in 0..num_row_groups {
+            shares[i % num_connections].push(i);
+        }
+        // so each share's rows are numbered by their place in the file
+        let row_group_sizes: Vec<u64> = file_reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|row_group| row_group.num_rows() as u64)
+            .collect();
+        debug!(
+            "copying {} row groups over {} connections",
+            num_row_groups, num_connections
+        );
+
+        let load_table_name = quote_table_name(&self.load_table_name());
+        let (db_cols, db_col_types) = &self.written_cols();
+        let load_metadata = &self.load_metadata.values();
+
+        let mut txs: Vec<Transaction<'_>> = Vec::with_capacity(num_connections);
+        let copy_clients = self.copy_clients.iter_mut().map(|(client, _)| client);
+        for client in std::iter::once(&mut self.client)
+            .chain(copy_clients)
+            .take(num_connections)
+        {
+            self.audit_sql.record("BEGIN")?;
+            txs.push(client.transaction().await?);
+        }
+
+        let skipped = AtomicU64::new(0);
+        let copies = txs.iter().zip(shares).map(|(tx, share)| async {
+            copy_rows(
+                tx,
+                &load_table_name,
+                db_cols,
+                db_col_types,
+                filter_rows(
+                    row_numbers(&row_group_sizes, &share)
+                        .zip(read_ahead(parquet.row_groups_iter(share)?)),
+                    self.row_filter.as_ref(),
+                    &skipped,
+                ),
+                parquet_col_nums,
+                pq_type_data,
+                &self.base64_decode,
+                &self.decrypt,
+                &self.assume_timezone,
+                &self.field_types,
+                self.date_formats.as_deref(),
+                &self.on_non_finite_float,
+                &self.on_type_mismatch,
+                &self.fills,
+                &self.bitmasks,
+                load_metadata,
+                &self.audit_sql,
+                self.insert_batch_size,
+                self.max_rows_per_copy,
+                self.copy_timeout,
+                self.rejects.as_ref(),
+            )
+            .await
+        });
+
+        match first_error(join_all(copies).await) {
+            Ok(rows_written) => {
+                commit_shares(txs, &self.audit_sql, self.copy_timeout).await?;
+                let written = rows_written
+                    .into_iter()
+                    .fold(RowsWritten::default(), RowsWritten::add);
+                Ok(RowsWritten {
+                    rows_skipped: skipped.into_inner(),
+                    ..written
+                })
+            }
+            Err(e) => {
+                for tx in txs {
+                    self.audit_sql.record("ROLLBACK")?;
+                    rollback(tx, self.copy_timeout).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // COPY straight into the table, unless configured to upsert.
+    // Each file is loaded in its own transaction, so either all its rows land or none do.
+    pub async fn write_rows(
+        &mut self,
+        iter: parquet::record::reader::RowIter<'_>,
+        parquet_col_nums: &[ColPath],
+        pq_type_data: &[PqTypeData],
+    ) -> Result<RowsWritten> {
+        self.load_rows(iter, parquet_col_nums, pq_type_data, true)
+            .await
+    }
+
+    // For --validate-copy: as write_rows, but the transaction is always rolled back.
+    // So postgres checks every row against the table's types and constraints, and keeps none.
+    // With swap, the copy of the table is created in the transaction if needed, so is rolled
+    // back too. Constraints deferred until commit are not checked.
+    pub async fn validate_rows(
+        &mut self,
+        iter: parquet::record::reader::RowIter<'_>,
+        parquet_col_nums: &[ColPath],
+        pq_type_data: &[PqTypeData],
+    ) -> Result<RowsWritten> {
+        self.load_rows(iter, parquet_col_nums, pq_type_data, false)
+            .await
+    }
+
+    async fn load_rows(
+        &mut self,
+        iter: parquet::record::reader::RowIter<'_>,
+        parquet_col_nums: &[ColPath],
+        pq_type_data: &[PqTypeData],
+        commit: bool,
+    ) -> Result<RowsWritten> {
+        let start_swap_load_sql = match (commit, &self.load_mode) {
+            (false, LoadMode::Swap) => Some(self.start_swap_load_sql()),
+            _ => None,
+        };
+        let load_table_name = self.load_table_name();
+        // temp tables have their own schema, so no schema in the name
+        let tmp_table_name =
+            quote_ident(&format!("{}_upsert", split_table_name(&load_table_name).1));
+        let load_table_name = quote_table_name(&load_table_name);
+        let (db_cols, db_col_types) = &self.written_cols();
+        let load_metadata = &self.load_metadata.values();
+        let upsert = &self.upsert;
+        let on_non_finite_float = &self.on_non_finite_float;
+        let on_type_mismatch = &self.on_type_mismatch;
+        let base64_decode = &self.base64_decode;
+        let decrypt = &self.decrypt;
+        let assume_timezone = &self.assume_timezone;
+        let fills = &self.fills;
+        let bitmasks = &self.bitmasks;
+        let field_types = &self.field_types;
+        let date_formats = self.date_formats.as_deref();
+        let audit_sql = &self.audit_sql;
+        let insert_batch_size = self.insert_batch_size;
+        let max_rows_per_copy = self.max_rows_per_copy;
+        let copy_timeout = self.copy_timeout;
+        let rejects = self.rejects.as_ref();
+        let skipped = AtomicU64::new(0);
+        let iter = filter_rows((1..).zip(iter), self.row_filter.as_ref(), &skipped);
+        audit_sql.record("BEGIN")?;
+        let tx = self.client.transaction().await?;
+
+        let result: Result<RowsWritten> = async {
+            if let Some(sql) = start_swap_load_sql {
+                audit_sql.record(&sql)?;
+                tx.batch_execute(sql.as_str()).await?;
+            }
+            let Some(upsert) = upsert else {
+                return copy_rows(
+                    &tx,
+                    &load_table_name,
+                    db_cols,
+                    db_col_types,
+                    iter,
+                    parquet_col_nums,
+                    pq_type_data,
+                    base64_decode,
+                    decrypt,
+                    assume_timezone,
+                    field_types,
+                    date_formats,
+                    on_non_finite_float,
+                    on_type_mismatch,
+                    fills,
+                    bitmasks,
+                    load_metadata,
+                    audit_sql,
+                    insert_batch_size,
+                    max_rows_per_copy,
+                    copy_timeout,
+                    rejects,
+                )
+                .await;
+            };
+
+            // COPY has no ON CONFLICT, so rows go into a temp table first, then are
+            // inserted from there. The temp table only lives for the transaction.
+            // Returns the number of rows inserted or updated, not those skipped.
+            let create_tmp_sql = format!(
+                "CREATE TEMP TABLE {} ON COMMIT DROP AS SELECT {} FROM {} WITH NO DATA",
+                tmp_table_name,
+                db_cols
+                    .iter()
+                    .map(|col| quote_ident(col))
+                    .collect::<Vec<String>>()
+                    .join(","),
+                load_table_name,
+            );
+            audit_sql.record(&create_tmp_sql)?;
+            tx.batch_execute(create_tmp_sql.as_str()).await?;
+
+            let copied = copy_rows(
+                &tx,
+                &tmp_table_name,
+                db_cols,
+                db_col_types,
+                iter,
+                parquet_col_nums,
+                pq_type_data,
+                base64_decode,
+                decrypt,
+                assume_timezone,
+                field_types,
+                date_formats,
+                on_non_finite_float,
+                on_type_mismatch,
+                fills,
+                bitmasks,
+                load_metadata,
+                audit_sql,
+                insert_batch_size,
+                max_rows_per_copy,
+                copy_timeout,
+                rejects,
+            )
+            .await?;
+
+            let insert_sql = upsert_sql(&load_table_name, &tmp_table_name, db_cols, upsert);
+            debug!("upsert sql: {}", insert_sql);
+            audit_sql.record(&insert_sql)?;
+            let num_rows_upserted = tx.execute(insert_sql.as_str(), &[]).await?;
+            debug!(
+                "{} rows copied, {} inserted or updated",
+                copied.rows, num_rows_upserted
+            );
+            Ok(RowsWritten {
+                rows: num_rows_upserted,
+                ..copied
+            })
+        }
+        .await
+        .map(|written| RowsWritten {
+            rows_skipped: skipped.load(Ordering::Relaxed),
+            ..written
+        });
+
+        match result {
+            Ok(rows_written) if commit => {
+                audit_sql.record("COMMIT")?;
+                tx.commit().await?;
+                Ok(rows_written)
+            }
+            Ok(rows_written) => {
+                audit_sql.record("ROLLBACK")?;
+                tx.rollback().await?;
+                Ok(rows_written)
+            }
+            Err(e) => {
+                audit_sql.record("ROLLBACK")?;
+                rollback(tx, copy_timeout).await;
+                Err(e)
+            }
+        }
+    }
+
+    // Same as write_rows, but never starts a COPY. Each converted value is still
+    // serialised for its db column type, so type mismatches are caught as they
+    // would be by the writer. Returns the number of rows that would have been added.
+    pub async fn check_rows(
+        &self,
+        iter: parquet::record::reader::RowIter<'_>,
+        parquet_col_nums: &[ColPath],
+        pq_type_data: &[PqTypeData],
+    ) -> Result<RowsWritten> {
+        let num_desired = num_desired_fields(pq_type_data, &self.bitmasks)?;
+        let converters = converters::build(
+            &pq_type_data[..num_desired],
+            &self.db_col_types,
+            &self.base64_decode,
+            &self.decrypt,
+            &self.assume_timezone,
+            &self.field_types,
+            self.date_formats.as_deref(),
+            &self.on_non_finite_float,
+        )?;
+        // converted values are for the desired fields' cols, then the bitmask cols
+        let (db_cols, db_col_types) = self.written_cols();
+        let mut buf = BytesMut::new();
+        let mut num_rows: u64 = 0;
+        let mut null_substitutions = vec![0; converters.len()];
+        let skipped = AtomicU64::new(0);
+
+        for (_, row_result) in filter_rows((1..).zip(iter), self.row_filter.as_ref(), &skipped) {
+            let row: Row = row_result?;
+            let converted = convert_row(
+                row,
+                parquet_col_nums,
+                &converters,
+                &self.fills,
+                &self.bitmasks,
+                &mut null_substitutions,
+            );
+            check_type_mismatch(&self.on_type_mismatch, &db_cols, &null_substitutions)?;
+
+            for (i, value) in converted.iter().enumerate() {
+                buf.clear();
+                // as insert_rows sends it, e.g. a tsvector col's text
+                let param_type = insert_param_type(&db_col_types[i]);
+                if let Err(e) = value.to_sql_checked(&param_type, &mut buf) {
+                    let msg = format!(
+                        "\
+                       Issue converting row for db: \n\
+                       {}\n\
+                       column name is: {}\n\
+                       db col type is: {}\n\
+                       rust types of data: {:?}\n\
+                    ",
+                        e, &db_cols[i], &db_col_types[i], pq_type_data,
+                    );
+                    bail!(MultiLineError { msg });
+                }
+            }
+            num_rows += 1;
+        }
+
+        Ok(RowsWritten {
+            rows_skipped: skipped.into_inner(),
+            ..RowsWritten::new(num_rows, &db_cols, &null_substitutions)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parquet_ops::Parquet;
+    use crate::test_setup::tests::{
+        aes_gcm_seal, bools_parquet_reader, create_table_return_client, double_parquet_reader,
+        duration_parquet_reader, fixed_16_parquet_reader, get_rows_as_csv_string,
+        int32_decimal_parquet_reader, int32_parquet_reader, int32_row_groups_parquet_reader,
+        int_8_parquet_reader, nested_parquet_reader, parquet_cars_reader, parquet_iris_reader,
+        repeated_parquet_reader, setup_docker, special_field_names_reader,
+        time_micros_parquet_reader, time_millis_parquet_reader, timestamp_nanos_parquet_reader,
+        utf8_parquet_reader, xy_parquet_reader, GOOD_DB_CONN_STR,
+    };
+    use anyhow::Result;
+    use arrow_schema::TimeUnit;
+    use assert_fs::fixture::TempDir;
+    use base64::prelude::{Engine, BASE64_STANDARD};
+    use parquet::basic::{ConvertedType, Type as PqType};
+    use parquet::file::reader::FileReader;
+    use std::collections::HashMap;
+    use tokio_postgres::types::Type as PgType;
+
+    macro_rules! vec_stringify {
+        ($($x:expr),*) => (vec![$($x.to_string()),*]);
+    }
+
+    // for a Db whose client wasn't made by Db::connect, so no notices are passed on
+    fn no_notices() -> Mutex<UnboundedReceiver<String>> {
+        Mutex::new(mpsc::unbounded_channel().1)
+    }
+
+    pub async fn default_db_struct_for_cars_table(
+        table_name: &str,
+        schema_type: &str,
+    ) -> Result<Db> {
+        let client = create_table_return_client(table_name.to_string(), schema_type).await?;
+        match schema_type {
+            "car" | "car_pk" | "car_check" | "car_load_metadata" => Ok(Db {
+                client, // do connection as simply as possible.
+                db_cols: vec_stringify!["model", "num_of_cyl", "miles_per_gallon", "gear"],
+                db_col_types: vec![PgType::VARCHAR, PgType::INT4, PgType::FLOAT8, PgType::INT4],
+                table_name: table_name.to_string(),
+                upsert: None,
+                load_mode: LoadMode::Append,
+                notices: no_notices(),
+                on_non_finite_float: NonFiniteFloat::Pass,
+                on_type_mismatch: TypeMismatch::Warn,
+                base64_decode: vec![],
+                decrypt: vec![],
+                assume_timezone: vec![],
+                field_types: vec![],
+                date_formats: None,
+                fills: vec![],
+                load_metadata: LoadMetadata::default(),
+                copy_clients: vec![],
+                audit_sql: AuditSql::default(),
+                insert_batch_size: None,
+                bitmasks: vec![],
+                max_rows_per_copy: None,
+                copy_timeout: None,
+                row_filter: None,
+                all_fields: None,
+                rejects: None,
+                schema: None,
+            }),
+            // the _ case provides incorrect db types to force failure
+            _ => Ok(Db {
+                client, // do connection as simply as possible.
+                db_cols: vec_stringify!["model", "num_of_cyl", "miles_per_gallon", "gear"],
+                db_col_types: vec![PgType::INT2, PgType::INT2, PgType::FLOAT8, PgType::INT4],
+                table_name: table_name.to_string(),
+                upsert: None,
+                load_mode: LoadMode::Append,
+                notices: no_notices(),
+                on_non_finite_float: NonFiniteFloat::Pass,
+                on_type_mismatch: TypeMismatch::Warn,
+                base64_decode: vec![],
+                decrypt: vec![],
+                assume_timezone: vec![],
+                field_types: vec![],
+                date_formats: None,
+                fills: vec![],
+                load_metadata: LoadMetadata::default(),
+                copy_clients: vec![],
+                audit_sql: AuditSql::default(),
+                insert_batch_size: None,
+                bitmasks: vec![],
+                max_rows_per_copy: None,
+                copy_timeout: None,
+                row_filter: None,
+                all_fields: None,
+                rejects: None,
+                schema: None,
+            }),
+        }
+    }
+
+    pub async fn default_db_struct_for_iris_table(table_name: &str) -> Result<Db> {
+        let client = create_table_return_client(table_name.to_string(), "iris").await?;
+        Ok(Db {
+            client, // do connection as simply as possible.
+            db_cols: vec_stringify!["variety", "sepal.length", "sepal.width"],
+            db_col_types: vec![PgType::VARCHAR, PgType::FLOAT8, PgType::FLOAT8],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_connect_success() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_success";
+        let _ = create_table_return_client(table_name.to_string(), "car").await;
+
+        // Attempt to connect.
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        // Ensure the connection was successful by asserting it is Ok.
+        assert!(
+            db.is_ok(),
+            "Connection should succeed with a valid connection string"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_sslmode_require_fails_against_non_tls_db() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_sslmode_require_fails_against_non_tls_db";
+        let _ = create_table_return_client(table_name.to_string(), "car").await;
+
+        // local docker db has no TLS, so require should refuse to fall back to plaintext
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Require,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        assert!(db.is_err(), "Should fail as local db does not support TLS");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_retries_respected_when_db_unreachable() -> Result<()> {
+        // nothing listens on port 1, so every attempt is refused
+        let unreachable_conn_str = "host=127.0.0.1 port=1 user=postgres dbname=testing";
+        let retry = ConnectRetry {
+            retries: 3,
+            backoff_ms: 50,
+        };
+
+        let start = std::time::Instant::now();
+        let db = Db::connect(
+            unreachable_conn_str,
+            &SslMode::Disable,
+            None,
+            &retry,
+            "test_connect_retries_respected_when_db_unreachable",
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+        let elapsed = start.elapsed();
+
+        assert!(db.is_err(), "Should fail as db is unreachable");
+        // 3 retries waiting 50, 100 then 200ms, before giving up on the 4th attempt
+        assert!(
+            elapsed >= Duration::from_millis(350),
+            "Should have backed off between retries, only took {:?}",
+            elapsed
+        );
+        assert!(
+            elapsed < Duration::from_millis(5000),
+            "Should have given up after 3 retries, took {:?}",
+            elapsed
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_no_retry_for_invalid_conn_str() -> Result<()> {
+        let retry = ConnectRetry {
+            retries: 5,
+            backoff_ms: 10_000,
+        };
+
+        let start = std::time::Instant::now();
+        let db = Db::connect(
+            "apples and pears",
+            &SslMode::Disable,
+            None,
+            &retry,
+            "test_connect_no_retry_for_invalid_conn_str",
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        assert!(db.is_err(), "Should fail with an invalid connection string");
+        assert!(
+            start.elapsed() < Duration::from_millis(10_000),
+            "Should not retry as the connection string will never be valid"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_failure";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+                                                                                 // Set up an invalid connection string.
+        let invalid_conn_str = "apples and pears";
+
+        // Attempt to connect.
+        let db = Db::connect(
+            invalid_conn_str,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        // Ensure the connection fails by asserting it is an Err.
+        assert!(
+            db.is_err(),
+            "Connection should fail with an invalid connection string"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_invalid_tablename() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_invalid_tablename";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+
+        // Attempt to connect.
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            "not_a_real_table",
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        // Ensure the connection was successful by asserting it is Ok.
+        assert!(db.is_err(), "Should fail as table not in connected db");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_col_name_not_exist() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_col_name_not_exist";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+
+        // Attempt to connect.
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "gear", "not_a_col"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        // Ensure the connection was successful by asserting it is Ok.
+        assert!(db.is_err(), "Should fail as col not in table");
+
+        Ok(())
+    }
+
+    // enum, domain and composite typed cols, with types only this test uses
+    async fn add_custom_type_cols(client: &Client, table_name: &str) -> Result<()> {
+        client
+            .batch_execute(
+                format!(
+                    "DROP TYPE IF EXISTS {t}_mood CASCADE;
+                     DROP DOMAIN IF EXISTS {t}_posint CASCADE;
+                     DROP TYPE IF EXISTS {t}_pair CASCADE;
+                     CREATE TYPE {t}_mood AS ENUM ('happy', 'sad');
+                     CREATE DOMAIN {t}_posint AS INT CHECK (VALUE > 0);
+                     CREATE TYPE {t}_pair AS (a INT, b TEXT);
+                     ALTER TABLE {t} ADD COLUMN mood {t}_mood,
+                         ADD COLUMN miles {t}_posint,
+                         ADD COLUMN pair {t}_pair;",
+                    t = table_name
+                )
+                .as_str(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_unknown_types_in_cols_not_written_to() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_unknown_types_in_cols_not_written_to";
+        let client = create_table_return_client(table_name.to_string(), "car").await?;
+        add_custom_type_cols(&client, table_name).await?;
+
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+
+        assert_eq!(db.db_col_types, [PgType::VARCHAR, PgType::INT4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_citext() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_citext";
+        let client = create_table_return_client(table_name.to_string(), "car").await?;
+        client
+            .batch_execute(
+                format!(
+                    "CREATE EXTENSION IF NOT EXISTS citext;
+                     ALTER TABLE {} ALTER COLUMN model TYPE citext;",
+                    table_name
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+        assert_eq!(db.db_col_types[0].name(), "citext");
+
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let written = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(written.rows, 32);
+
+        // stored as is, but compared without regard to case
+        let sql = format!(
+            "SELECT model::text AS model FROM {} WHERE model = 'MAZDA rx4 WAG'",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "model\nMazda RX4 Wag\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_unknown_type_in_col_written_to() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_unknown_type_in_col_written_to";
+        let client = create_table_return_client(table_name.to_string(), "car").await?;
+        add_custom_type_cols(&client, table_name).await?;
+
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "mood"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        let Err(e) = db else {
+            panic!("should fail as mood is an enum, unknown to tokio_postgres")
+        };
+        assert!(
+            e.to_string().contains("column mood has unknown type OID"),
+            "unexpected error: {}",
+            e
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_col_name_has_alias() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_col_name_has_alias";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+
+        let aliases: HashMap<String, Option<String>> = HashMap::from([
+            ("i.model".to_string(), Some("model".to_string())),
+            ("num_of_gears".to_string(), Some("gear".to_string())),
+        ]);
+
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["i.model", "num_of_gears"], // desired cols from parquet
+            Some(aliases),                             // map of parquet col names to db table cols,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        let res = db.as_ref().unwrap();
+
+        assert!(db.is_ok(), "Should pass as aliases are all cols in db");
+
+        let exp_db_col_names_for_desired_fields = vec_stringify!["model", "gear"];
+        assert_eq!(res.db_cols, exp_db_col_names_for_desired_fields);
+
+        let exp_db_types = [PgType::VARCHAR, PgType::INT4];
+        assert_eq!(res.db_col_types, exp_db_types);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_naming_strategy_with_alias_override() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_naming_strategy_with_alias_override";
+        let _ = create_table_return_client(table_name.to_string(), "car").await?;
+
+        // the alias wins over the strategy, which would make it num_of_gears
+        let aliases: HashMap<String, Option<String>> =
+            HashMap::from([("NumOfGears".to_string(), Some("gear".to_string()))]);
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["Model", "MilesPerGallon", "NumOfGears"],
+            Some(aliases),
+            &NamingStrategy::SnakeCase,
+            None,
+        )
+        .await?;
+
+        assert_eq!(
+            db.db_cols,
+            vec_stringify!["model", "miles_per_gallon", "gear"]
+        );
+        assert_eq!(
+            db.db_col_types,
+            [PgType::VARCHAR, PgType::FLOAT8, PgType::INT4]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_schema_cache() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_schema_cache";
+        let _ = create_table_return_client(table_name.to_string(), "car").await?;
+
+        let cache = SchemaCache::default();
+        let mut dbs = vec![];
+        for _ in 0..2 {
+            let db = Db::connect(
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+                table_name,
+                vec_stringify!["miles_per_gallon", "gear"],
+                None,
+                &NamingStrategy::Identity,
+                Some(&cache),
+            )
+            .await?
+            // these look up their cols' types in what connect found, not the catalog
+            .with_extra_columns(&HashMap::from([(
+                "model".to_string(),
+                "Mazda RX4".to_string(),
+            )]))
+            .await?
+            .with_bitmask_columns(&HashMap::from([(
+                "carb".to_string(),
+                vec_stringify!["a", "b"],
+            )]))
+            .await?;
+            dbs.push(db);
+        }
+
+        // the second connection's types came from the first's query
+        assert_eq!(cache.queries.load(Ordering::Relaxed), 1);
+        assert_eq!(dbs[0].db_col_types, [PgType::FLOAT8, PgType::INT4]);
+        assert_eq!(dbs[1].load_metadata.cols[0].1, PgType::VARCHAR);
+        assert_eq!(dbs[1].bitmasks[0].col_type, PgType::INT2);
+        assert_eq!(dbs[1].db_col_types, dbs[0].db_col_types);
+        let (cached, _) = cache
+            .db_col_to_type(&dbs[1].client, GOOD_DB_CONN_STR, table_name)
+            .await?;
+        let (queried, _) = db_col_to_type(&dbs[1].client, table_name).await?;
+        assert_eq!(cached, queried);
+        assert_eq!(cache.queries.load(Ordering::Relaxed), 1);
+
+        // a missing table isn't cached, so still errors
+        let missing = cache
+            .db_col_to_type(&dbs[0].client, GOOD_DB_CONN_STR, "no_such_table")
+            .await;
+        assert!(missing.is_err());
+        assert_eq!(cache.queries.load(Ordering::Relaxed), 2);
+
+        // nor do the builders query it again, so don't see a col dropped since connect
+        let db = dbs.pop().unwrap();
+        db.client
+            .batch_execute(&format!("ALTER TABLE {} DROP COLUMN hp", table_name))
+            .await?;
+        let db = db
+            .with_bitmask_columns(&HashMap::from([("hp".to_string(), vec_stringify!["c"])]))
+            .await?;
+        assert_eq!(db.bitmasks[1].col_type, PgType::INT4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_all_fields() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_all_fields";
+        let _ = create_table_return_client(table_name.to_string(), "iris").await?;
+
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["*"],
+            Some(HashMap::from([(
+                "kind".to_string(),
+                Some("variety".to_string()),
+            )])),
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+        // no cols till a file's fields are known
+        assert!(db.db_cols.is_empty());
+
+        let file_fields = vec_stringify!["sepal.length", "kind"];
+        db.set_fields(&file_fields)?;
+        assert_eq!(db.db_cols, vec_stringify!["sepal.length", "variety"]);
+        assert_eq!(db.db_col_types, [PgType::FLOAT8, PgType::VARCHAR]);
+
+        // a file with a field the table has no col for
+        let file_fields = vec_stringify!["sepal.length", "stem.length"];
+        let err = db.set_fields(&file_fields);
+        assert!(err.is_err());
+
+        // a file with a field whose col is filled in by db.extra_columns
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["*"],
+            Some(HashMap::from([(
+                "kind".to_string(),
+                Some("variety".to_string()),
+            )])),
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?
+        .with_extra_columns(&HashMap::from([(
+            "variety".to_string(),
+            "Setosa".to_string(),
+        )]))
+        .await?;
+        let file_fields = vec_stringify!["sepal.length", "kind"];
+        let err = db.set_fields(&file_fields).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "db.load_metadata or db.extra_columns column variety is already written to, so can't be filled in too"
+        );
+
+        // or is a bitmask
+        db.load_metadata = LoadMetadata::default();
+        db.bitmasks = vec![Bitmask {
+            col: "variety".to_string(),
+            col_type: PgType::INT4,
+            fields: vec_stringify!["is_big"],
+        }];
+        let err = db.set_fields(&file_fields).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "db.bitmask_columns column variety is already written to, so can't be a bitmask too"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_db_col_name_has_none_alias_but_parquet_name_is_same() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_db_col_name_has_none_alias_but_parquet_name_is_same";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+
+        let aliases: HashMap<String, Option<String>> = HashMap::from([
+            ("model".to_string(), None), // should use delivery_id
+            ("num_of_gears".to_string(), Some("gear".to_string())), // will use alias even if same
+        ]);
+
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "num_of_gears"], // desired cols from parquet
+            Some(aliases),                           // map of parquet col names to db table cols,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        let res = db.as_ref().unwrap();
+
+        assert!(db.is_ok(), "Should pass as aliases are all cols in db");
+
+        let exp_db_col_names_for_desired_fields = vec_stringify!["model", "gear"];
+        assert_eq!(res.db_cols, exp_db_col_names_for_desired_fields);
+
+        let exp_db_types = [PgType::VARCHAR, PgType::INT4];
+        assert_eq!(res.db_col_types, exp_db_types);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_db_col_name_has_none_alias_but_parquet_name_is_not_same_as_db_col(
+    ) -> Result<()> {
+        setup_docker();
+        let table_name =
+            "test_connect_db_col_name_has_none_alias_but_parquet_name_is_not_same_as_db_col";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+
+        let aliases: HashMap<String, Option<String>> = HashMap::from([
+            ("model".to_string(), None),
+            ("num_of_gears".to_string(), None), // the db col is actually gear, so should have had alias
+        ]);
+
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "num_of_gears"], // desired cols from parquet
+            Some(aliases),                           // map of parquet col names to db table cols,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        assert!(
+            db.is_err(),
+            "Should fail as no db col alias given for parquet field d_id, and no col with same name as parquet field"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_given_db_col_alias_does_not_exist() -> Result<()> {
+        setup_docker();
+        let table_name = "test_connect_given_db_col_alias_does_not_exist";
+        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+
+        let aliases: HashMap<String, Option<String>> = HashMap::from([
+            ("model".to_string(), Some("not_a_col".to_string())), // not_a_col doesn't exist in db
+            ("num_of_gears".to_string(), Some("gear".to_string())),
+        ]);
+
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "num_of_gears"],
+            Some(aliases),
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await;
+
+        assert!(
+            db.is_err(),
+            "Should fail as alias 'not_a_col' is not a col in the db"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_happy_path() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_happy_path";
+        let mut db = default_db_struct_for_cars_table(table_name, "car")
+            .await
+            .unwrap();
+        let (tmp_dir, reader) = parquet_cars_reader().await.unwrap();
+
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        // PQ cols:     model       cyl         mpg         gear
+        // col no.      0           2           1           10
+        // types:       BYTE_ARRAY  INT32       DOUBLE      INT32
+        // converted:   UTF8        NONE|INT_32 NONE        NONE|INT_32
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let num_rows_added = db.write_rows(row_iter, &col_nums, pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 32);
+        let sql = format!("SELECT * from {} ORDER by model DESC LIMIT 2", table_name);
+        let exp_string = "\
+            model,miles_per_gallon,num_of_cyl,disp,hp,drat,wt,qsec,vs,am,gear,carb\n\
+            Volvo 142E,21.4,4,,,,,,,,4,\n\
+            Valiant,18.1,6,,,,,,,,3,\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str())
+            .await
+            .unwrap();
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_insert_mode_matches_copy() -> Result<()> {
+        setup_docker();
+        let copy_table_name = "test_write_rows_insert_mode_matches_copy";
+        let insert_table_name = "test_write_rows_insert_mode_matches_copy_insert";
+        let mut copy_db = default_db_struct_for_cars_table(copy_table_name, "car").await?;
+        // 32 rows in batches of 5, so a smaller last batch
+        let mut insert_db = default_db_struct_for_cars_table(insert_table_name, "car")
+            .await?
+            .with_write_mode(WriteMode::Insert, 5)?;
+        let (tmp_dir, reader) = parquet_cars_reader().await.unwrap();
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let num_rows_copied = copy_db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        let num_rows_inserted = insert_db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_copied.rows, 32);
+        assert_eq!(num_rows_inserted.rows, 32);
+        let sql = "SELECT * from {} ORDER by model";
+        let copied =
+            get_rows_as_csv_string(&copy_db.client, sql.replace("{}", copy_table_name).as_str())
+                .await?;
+        let inserted = get_rows_as_csv_string(
+            &insert_db.client,
+            sql.replace("{}", insert_table_name).as_str(),
+        )
+        .await?;
+        assert_eq!(copied.lines().count(), 33);
+        assert_eq!(inserted, copied);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_row_filter() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_row_filter";
+        let row_filter = |op: FilterOp, value: &str| {
+            Some(crate::config::RowFilter {
+                column: "gear".to_string(),
+                op,
+                value: value.to_string(),
+            })
+        };
+        let mut db = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_row_filter(row_filter(FilterOp::Eq, "4"));
+        db.set_row_filter_col_path(vec![10]);
+        let (tmp_dir, reader) = parquet_cars_reader().await.unwrap();
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let written = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        // a dry run skips the same way
+        db = db.with_row_filter(row_filter(FilterOp::Gt, "4"));
+        db.set_row_filter_col_path(vec![10]);
+        let checked = db
+            .check_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        // gear is an int, so the value must be one too
+        db = db.with_row_filter(row_filter(FilterOp::Ne, "four"));
+        db.set_row_filter_col_path(vec![10]);
+        let not_an_int = db
+            .check_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!((written.rows, written.rows_skipped), (12, 20));
+        let sql = format!(
+            "SELECT count(*) AS total, count(*) FILTER (WHERE gear = 4) AS gear_4 FROM {}",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total,gear_4\n12,12\n");
+        assert_eq!((checked.rows, checked.rows_skipped), (5, 27));
+        assert!(not_an_int
+            .unwrap_err()
+            .to_string()
+            .contains("parquet.row_filter value four can't be compared with column gear"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_write_mode_insert_batch_size() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_write_mode_insert_batch_size";
+
+        let db = default_db_struct_for_cars_table(table_name, "car").await?;
+        let Err(e) = db.with_write_mode(WriteMode::Insert, 0) else {
+            panic!("a batch needs rows")
+        };
+        assert_eq!(e.to_string(), "db.insert_batch_size must be at least 1");
+
+        // 4 cols, so at most 65535 / 4 rows fit
+        let db = default_db_struct_for_cars_table(table_name, "car").await?;
+        let Err(e) = db.with_write_mode(WriteMode::Insert, 16384) else {
+            panic!("too many params for one INSERT")
+        };
+        assert!(e.to_string().ends_with("Use at most 16383"), "{}", e);
+
+        let db = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_write_mode(WriteMode::Insert, 16383)?;
+        assert_eq!(db.insert_batch_size, Some(16383));
+        // the batch size is only for inserts
+        let db = db.with_write_mode(WriteMode::Copy, 16384)?;
+        assert_eq!(db.insert_batch_size, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_sql() {
+        assert_eq!(
+            insert_sql(
+                "\"t\"",
+                &["a".to_string(), "b".to_string()],
+                &[PgType::INT4, PgType::TEXT],
+                2
+            ),
+            "INSERT INTO \"t\" (\"a\",\"b\") VALUES ($1,$2),($3,$4)"
+        );
+        assert_eq!(
+            insert_sql(
+                "\"t\"",
+                &["a".to_string(), "doc".to_string()],
+                &[PgType::INT4, PgType::TS_VECTOR],
+                2
+            ),
+            "INSERT INTO \"t\" (\"a\",\"doc\") VALUES ($1,to_tsvector($2)),($3,to_tsvector($4))"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_load_metadata() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_load_metadata";
+        let loaded_at = DateTime::parse_from_rfc3339("2024-09-24T12:00:00Z")?.to_utc();
+        let load_metadata = LoadMetadataConfig {
+            source_key_column: Some("source_key".to_string()),
+            loaded_at_column: Some("loaded_at".to_string()),
+            run_id_column: Some("run_id".to_string()),
+        };
+        let mut db = default_db_struct_for_cars_table(table_name, "car_load_metadata")
+            .await?
+            .with_load_metadata(&load_metadata, "run-1", loaded_at)
+            .await?;
+        db.set_source_key("cars/cars.parquet");
+        let (tmp_dir, reader) = parquet_cars_reader().await.unwrap();
+
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let num_rows_added = db.write_rows(row_iter, &col_nums, pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 32);
+        // every row has the same metadata
+        let sql = format!(
+            "SELECT count(*) AS total, source_key, extract(epoch from loaded_at)::bigint AS loaded_at, run_id
+             FROM {} GROUP BY source_key, loaded_at, run_id",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string,
+            "total,source_key,loaded_at,run_id\n32,cars/cars.parquet,1727179200,run-1\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_load_metadata_invalid_cols() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_load_metadata_invalid_cols";
+        let cases = [
+            (
+                LoadMetadataConfig {
+                    loaded_at_column: Some("source_key".to_string()),
+                    ..Default::default()
+                },
+                "db.load_metadata column source_key must be one of timestamptz, timestamp, but is text",
+            ),
+            (
+                LoadMetadataConfig {
+                    run_id_column: Some("no_such_col".to_string()),
+                    ..Default::default()
+                },
+                "Table test_with_load_metadata_invalid_cols does not have db.load_metadata column no_such_col",
+            ),
+            (
+                LoadMetadataConfig {
+                    source_key_column: Some("model".to_string()),
+                    ..Default::default()
+                },
+                "db.load_metadata column model is already written to, so can't be filled in too",
+            ),
+        ];
+        for (load_metadata, expected) in cases {
+            let result = default_db_struct_for_cars_table(table_name, "car_load_metadata")
+                .await?
+                .with_load_metadata(&load_metadata, "run-1", Utc::now())
+                .await;
+            assert_eq!(result.unwrap_err().to_string(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_extra_columns() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_extra_columns";
+        let extra_columns: HashMap<String, String> = HashMap::from([
+            ("source_key".to_string(), "source_file".to_string()),
+            ("loaded_at".to_string(), "now()".to_string()),
+            ("run_id".to_string(), "backfill".to_string()),
+        ]);
+        let mut db = default_db_struct_for_cars_table(table_name, "car_load_metadata")
+            .await?
+            .with_extra_columns(&extra_columns)
+            .await?;
+        let before = Utc::now();
+        db.set_source_key("cars/cars.parquet");
+        let after = Utc::now();
+        let (tmp_dir, reader) = parquet_cars_reader().await.unwrap();
+
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let num_rows_added = db.write_rows(row_iter, &col_nums, pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 32);
+        // every row has the file's key, the literal, and when the file started loading
+        let sql = format!(
+            "SELECT count(*) AS total, source_key, run_id,
+                 min(loaded_at) = max(loaded_at) AND min(loaded_at) BETWEEN $1 AND $2 AS loaded_at
+             FROM {} GROUP BY source_key, run_id",
+            table_name
+        );
+        let rows = db.client.query(sql.as_str(), &[&before, &after]).await?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<_, i64>("total"), 32);
+        assert_eq!(rows[0].get::<_, &str>("source_key"), "cars/cars.parquet");
+        assert_eq!(rows[0].get::<_, &str>("run_id"), "backfill");
+        assert!(rows[0].get::<_, bool>("loaded_at"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_extra_columns_invalid_cols() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_extra_columns_invalid_cols";
+        let cases = [
+            (
+                ("source_key", "now()"),
+                "db.extra_columns column source_key must be one of timestamptz, timestamp, but is text",
+            ),
+            (
+                ("gear", "3"),
+                "db.extra_columns column gear is already written to, so can't be filled in too",
+            ),
+            (
+                ("run_id", "x"),
+                "db.extra_columns column run_id is already written to, so can't be filled in too",
+            ),
+            (
+                ("no_such_col", "x"),
+                "Table test_with_extra_columns_invalid_cols does not have db.extra_columns column no_such_col",
+            ),
+        ];
+        // run_id's filled in by db.load_metadata
+        let load_metadata = LoadMetadataConfig {
+            run_id_column: Some("run_id".to_string()),
+            ..Default::default()
+        };
+        for ((col, value), expected) in cases {
+            let extra_columns = HashMap::from([(col.to_string(), value.to_string())]);
+            let result = default_db_struct_for_cars_table(table_name, "car_load_metadata")
+                .await?
+                .with_load_metadata(&load_metadata, "run-1", Utc::now())
+                .await?
+                .with_extra_columns(&extra_columns)
+                .await;
+            assert_eq!(result.unwrap_err().to_string(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_metadata_value_from_extra_column() {
+        let cases = [
+            ("now()", LoadMetadataValue::FileStartedAt),
+            ("source_file", LoadMetadataValue::SourceKey),
+            ("eu-west", LoadMetadataValue::Literal("eu-west".to_string())),
+            ("'now()'", LoadMetadataValue::Literal("now()".to_string())),
+            ("'it''s'", LoadMetadataValue::Literal("it's".to_string())),
+            ("", LoadMetadataValue::Literal(String::new())),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                LoadMetadataValue::from_extra_column(value),
+                expected,
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_constraint_violation_leaves_no_rows() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_constraint_violation_leaves_no_rows";
+        let mut db = default_db_struct_for_cars_table(table_name, "car_check").await?;
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let result = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(result.is_err(), "5 gear cars violate the check constraint");
+        let sql = format!("SELECT COUNT(*) AS n FROM {}", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "n\n0\n",
+            "none of the file's rows should remain"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_field_at_matches_row_iter_for_multi_group_schema() -> Result<()> {
+        let (tmp_dir, reader) = nested_parquet_reader()?;
+        let mut pq = Parquet {
+            filename: String::from("nested.parquet"),
+            desired_fields: vec_stringify!["billing.city", "address.zip", "id", "address.city"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: crate::config::SchemaPolicy::Superset,
+        };
+        let (col_nums, _) = pq.get_desired_cols(&reader)?;
+        let rows: Vec<Vec<Field>> = reader
+            .get_row_iter(None)?
+            .map(|row| {
+                let all_fields = row.unwrap().into_columns();
+                col_nums
+                    .iter()
+                    .map(|col_path| field_at(&all_fields, col_path))
+                    .collect()
+            })
+            .collect();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        // into_columns() has the top level fields, each group as one field,
+        // so a field in the second group is found by its number within that group
+        let str_field = |v: &str| Field::Str(v.to_string());
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    str_field("Hull"),
+                    str_field("LS1"),
+                    Field::Int(1),
+                    str_field("Leeds")
+                ],
+                vec![Field::Null, Field::Null, Field::Int(2), Field::Null],
+                vec![Field::Null, Field::Null, Field::Int(3), str_field("York")],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("model"), "\"model\"");
+        assert_eq!(quote_ident("full name"), "\"full name\"");
+        assert_eq!(quote_ident("order-id"), "\"order-id\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn test_db_col_name() {
+        let iris: &[&str] = &["sepal.length", "petal.width", "variety"];
+        let cars: &[&str] = &["model", "miles_per_gallon", "num_of_cyl"];
+        let others: &[&str] = &[
+            "orderId",
+            "OrderID",
+            "IDNumber",
+            "order-id",
+            "Full Name",
+            "raw_v2Total",
+        ];
+        let cases = [
+            (
+                NamingStrategy::Identity,
+                [iris, cars].concat(),
+                [iris, cars].concat(),
+            ),
+            (
+                NamingStrategy::DotsToUnderscores,
+                iris.to_vec(),
+                vec!["sepal_length", "petal_width", "variety"],
+            ),
+            (
+                NamingStrategy::Lowercase,
+                others.to_vec(),
+                vec![
+                    "orderid",
+                    "orderid",
+                    "idnumber",
+                    "order-id",
+                    "full name",
+                    "raw_v2total",
+                ],
+            ),
+            (
+                NamingStrategy::SnakeCase,
+                [iris, cars, others].concat(),
+                vec![
+                    "sepal_length",
+                    "petal_width",
+                    "variety",
+                    "model",
+                    "miles_per_gallon",
+                    "num_of_cyl",
+                    "order_id",
+                    "order_id",
+                    "id_number",
+                    "order_id",
+                    "full_name",
+                    "raw_v2_total",
+                ],
+            ),
+            (
+                NamingStrategy::StripPrefix("num_of_".to_string()),
+                cars.to_vec(),
+                vec!["model", "miles_per_gallon", "cyl"],
+            ),
+        ];
+        for (strategy, fields, expected) in cases {
+            let names: Vec<String> = fields.iter().map(|f| db_col_name(f, &strategy)).collect();
+            assert_eq!(names, expected, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_table_col_name() -> Result<()> {
+        let cols = vec_stringify!["MixedCase", "model", "Gear", "gear", "a\"b"];
+        let table_cols: Vec<&String> = cols.iter().collect();
+        let found = |name: &str| table_col_name(name, &table_cols);
+
+        assert_eq!(found("MixedCase")?.as_deref(), Some("MixedCase"));
+        assert_eq!(found("mixedcase")?.as_deref(), Some("MixedCase"));
+        assert_eq!(found("\"MixedCase\"")?.as_deref(), Some("MixedCase"));
+        assert_eq!(found("MODEL")?.as_deref(), Some("model"));
+        assert_eq!(found("\"a\"\"b\"")?.as_deref(), Some("a\"b"));
+        // an exact match wins over the others differing in case
+        assert_eq!(found("gear")?.as_deref(), Some("gear"));
+        assert_eq!(found("\"Gear\"")?.as_deref(), Some("Gear"));
+        // quoted, so the case must match
+        assert_eq!(found("\"mixedcase\"")?, None);
+        assert_eq!(found("not_a_col")?, None);
+
+        let Err(e) = found("GEAR") else {
+            panic!("should fail as GEAR could be Gear or gear")
+        };
+        assert!(
+            e.to_string().contains("\"Gear\", \"gear\""),
+            "unexpected error: {}",
+            e
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_and_quote_table_name() {
+        assert_eq!(split_table_name("orders"), (None, "orders"));
+        assert_eq!(
+            split_table_name("analytics.orders"),
+            (Some("analytics"), "orders")
+        );
+        assert_eq!(quote_table_name("orders"), "\"orders\"");
+        assert_eq!(
+            quote_table_name("analytics.orders"),
+            "\"analytics\".\"orders\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_schema_qualified_table() -> Result<()> {
+        setup_docker();
+        let table = "test_write_rows_schema_qualified_table";
+        let table_name = format!("test_schema.{}", table);
+
+        // same table name in public, but with other cols, so we'd notice if it was used
+        let client = create_table_return_client(table.to_string(), "xml").await?;
+        client
+            .batch_execute("CREATE SCHEMA IF NOT EXISTS test_schema")
+            .await?;
+        let _ = create_table_return_client(table_name.clone(), "car").await?;
+
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name.as_str(),
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+        assert_eq!(db.db_col_types, vec![PgType::VARCHAR, PgType::INT4]);
+
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let num_rows_added = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0], vec![10]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(num_rows_added.rows, 32);
+
+        let sql = format!(
+            "SELECT (SELECT COUNT(*) FROM {}) AS in_schema, (SELECT COUNT(*) FROM public.{}) AS in_public",
+            table_name, table
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "in_schema,in_public\n32,0\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_special_chars_in_names() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_special_chars_in_names";
+        let _ = create_table_return_client(table_name.to_string(), "special_names").await?;
+
+        // parquet field names have a hyphen and a space, as do the db cols
+        let aliases: HashMap<String, Option<String>> =
+            HashMap::from([("order-id".to_string(), Some("order id".to_string()))]);
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["order-id", "full name"],
+            Some(aliases),
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+        assert_eq!(db.db_cols, vec_stringify!["order id", "full name"]);
+
+        let (tmp_dir, reader) = special_field_names_reader().await.unwrap();
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[
+            (PqType::INT64, ConvertedType::NONE, None),
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+        ];
+        let num_rows_added = db
+            .write_rows(row_iter, &[vec![0], vec![1]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 2);
+        let sql = format!("SELECT * from {} ORDER BY 1", table_name);
+        let exp_string = "\
+            order id,full name\n\
+            1,Ada Lovelace\n\
+            2,Alan Turing\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str())
+            .await
+            .unwrap();
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_mixed_case_col() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_mixed_case_col";
+        let _ = create_table_return_client(table_name.to_string(), "mixed_case").await?;
+
+        // not quoted, nor in the col's case
+        let aliases: HashMap<String, Option<String>> =
+            HashMap::from([("order-id".to_string(), Some("mixedcase".to_string()))]);
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["order-id", "full name"],
+            Some(aliases),
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+        assert_eq!(db.db_cols, vec_stringify!["MixedCase", "full name"]);
+
+        let (tmp_dir, reader) = special_field_names_reader().await.unwrap();
+        let pq_data: &[PqTypeData] = &[
+            (PqType::INT64, ConvertedType::NONE, None),
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+        ];
+        let num_rows_added = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0], vec![1]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 2);
+        let sql = format!("SELECT * from {} ORDER BY 1", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string,
+            "MixedCase,full name\n1,Ada Lovelace\n2,Alan Turing\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_quoted_db_cols_happy_path() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_quoted_db_cols_happy_path";
+        let mut db = default_db_struct_for_iris_table(table_name).await?;
+        let (tmp_dir, reader) = parquet_iris_reader().await?;
+        // the iris cols have dots in their names, e.g. sepal.length, so the COPY only
+        // finds them if it quotes them, as it does any col whose name it matched
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        // col numbers in parquet, in the order we write the data
+        let col_paths = [vec![4], vec![0], vec![1]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+        ];
+        let num_rows_added = db.write_rows(row_iter, &col_paths, pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 150);
+        let sql = format!(
+            "SELECT * from {} ORDER by \"sepal.length\" DESC, \"sepal.width\" DESC LIMIT 2",
+            table_name
+        );
+        let exp_string = "\
+            sepal.length,sepal.width,petal.length,petal.width,variety\n\
+            7.9,3.8,,,Virginica\n\
+            7.7,3.8,,,Virginica\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_upsert_do_nothing_same_file_twice() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_upsert_do_nothing_same_file_twice";
+        let mut db = default_db_struct_for_cars_table(table_name, "car_pk")
+            .await?
+            .with_upsert(Some(Upsert {
+                on_conflict: OnConflict::DoNothing,
+                conflict_columns: vec_stringify!["model"],
+            }))?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        let first = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await;
+        let second = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(first?.rows, 32);
+        assert_eq!(second?.rows, 0, "every row should have been skipped");
+
+        let sql = format!(
+            "SELECT COUNT(*) AS n, COUNT(DISTINCT model) AS models FROM {}",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "n,models\n32,32\n", "no duplicate rows");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_notices_logged_with_context() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_notices_logged_with_context";
+        let client = create_table_return_client(table_name.to_string(), "car").await?;
+        client
+            .batch_execute(
+                format!(
+                    "CREATE OR REPLACE FUNCTION {}_notice() RETURNS trigger AS $$
+                     BEGIN
+                        IF NEW.gear > 4 THEN
+                            RAISE NOTICE 'car % has % gears', NEW.model, NEW.gear;
+                        END IF;
+                        RETURN NEW;
+                     END $$ LANGUAGE plpgsql;
+                     CREATE TRIGGER notice BEFORE INSERT ON {} FOR EACH ROW EXECUTE FUNCTION {}_notice();",
+                    table_name, table_name, table_name
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["model", "gear"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        let col_nums = vec![vec![0], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        db.write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        let logged = db.warn_notices("cars.parquet");
+        assert_eq!(
+            logged.len(),
+            5,
+            "one notice per car with 5 gears: {:?}",
+            logged
+        );
+        assert!(
+            logged.contains(&"cars.parquet: db NOTICE: car Lotus Europa has 5 gears".to_string()),
+            "notice should be logged with the file: {:?}",
+            logged
+        );
+        assert!(db.warn_notices("cars.parquet").is_empty(), "already logged");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_rows_swap_keeps_nothing() -> Result<()> {
+        setup_docker();
+        let table_name = "test_validate_rows_swap_keeps_nothing";
+        let mut db = default_db_struct_for_cars_table(table_name, "car_pk")
+            .await?
+            .with_load_mode(LoadMode::Swap);
+        db.client
+            .batch_execute(format!("DROP TABLE IF EXISTS {}_new", table_name).as_str())
+            .await?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+
+        // no start_swap_load(), as validating makes the copy of the table itself
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        let num_rows_copied = db
+            .validate_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(num_rows_copied.rows, 32);
+
+        let sql = format!(
+            "SELECT COUNT(*) AS n, to_regclass('{}_new') IS NULL AS no_copy FROM {}",
+            table_name, table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "n,no_copy\n0,true\n",
+            "the rows and the copy of the table should be rolled back"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_swap_load() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_swap_load";
+        let mut db = default_db_struct_for_cars_table(table_name, "car_pk")
+            .await?
+            .with_load_mode(LoadMode::Swap);
+        db.client
+            .batch_execute(
+                format!(
+                    "DROP TABLE IF EXISTS {t}_new; INSERT INTO {t} VALUES ('Old Banger', 9.9, 4, 3); \
+                     ALTER TABLE {t} ADD COLUMN row_id serial",
+                    t = table_name
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let count_sql = format!(
+            "SELECT COUNT(*) AS n, MIN(model) AS first FROM {}",
+            table_name
+        );
+
+        db.start_swap_load().await?;
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        let num_rows_added = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(num_rows_added.rows, 32);
+
+        let csv_string = get_rows_as_csv_string(&db.client, count_sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "n,first\n1,Old Banger\n",
+            "old table should still serve the old data during the load"
+        );
+
+        db.finish_swap_load().await?;
+
+        let csv_string = get_rows_as_csv_string(&db.client, count_sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "n,first\n32,AMC Javelin\n",
+            "new data should be live after the swap"
+        );
+
+        // primary key comes from the old table
+        let pk_sql = format!(
+            "SELECT COUNT(*) AS n FROM pg_index i JOIN pg_class c ON i.indrelid = c.oid \
+             WHERE c.relname = '{}' AND i.indisprimary",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, pk_sql.as_str()).await?;
+        assert_eq!(csv_string, "n\n1\n");
+
+        // and nothing left behind
+        let leftovers_sql = format!(
+            "SELECT COUNT(*) AS n FROM pg_class WHERE relname IN ('{}_new', '{}_old')",
+            table_name, table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, leftovers_sql.as_str()).await?;
+        assert_eq!(csv_string, "n\n0\n");
+
+        // the serial col's sequence went over to the new table, so a second swap can drop
+        // the first's table, and carries on numbering from where the first left off
+        db.start_swap_load().await?;
+        let (tmp_dir, reader) = parquet_cars_reader().await?;
+        db.write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        db.finish_swap_load().await?;
+        let row_ids_sql = format!(
+            "SELECT COUNT(*) AS n, MIN(row_id) AS first, MAX(row_id) AS last FROM {}",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, row_ids_sql.as_str()).await?;
+        assert_eq!(csv_string, "n,first,last\n32,34,65\n");
+        let csv_string = get_rows_as_csv_string(&db.client, leftovers_sql.as_str()).await?;
+        assert_eq!(csv_string, "n\n0\n");
+
+        // a view would go with the old table, so can't be swapped under
+        db.client
+            .batch_execute(
+                format!(
+                    "CREATE VIEW {t}_view AS SELECT model FROM {t}",
+                    t = table_name
+                )
+                .as_str(),
+            )
+            .await?;
+        let result = db.start_swap_load().await;
+        db.client
+            .batch_execute(format!("DROP VIEW {}_view", table_name).as_str())
+            .await?;
+        let Err(e) = result else {
+            panic!("should fail as a view depends on the table")
+        };
+        assert!(
+            e.to_string()
+                .contains(&format!("views depend on it: {}_view", table_name)),
+            "{}",
+            e
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_audit_sql() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_audit_sql";
+        let tmp_dir = TempDir::new().unwrap();
+        let audit_sql_file = format!("{}/audit.sql.log", tmp_dir.path().display());
+        let mut db = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_load_mode(LoadMode::Swap)
+            .with_audit_sql(Some(&audit_sql_file), "test-run")?;
+        db.client
+            .batch_execute(format!("DROP TABLE IF EXISTS {}_new", table_name).as_str())
+            .await?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        db.start_swap_load().await?;
+        let (parquet_dir, reader) = parquet_cars_reader().await?;
+        db.write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        parquet_dir.close().unwrap(); // can be deleted as read what we need
+        db.finish_swap_load().await?;
+
+        let recorded: Vec<(String, String)> = std::fs::read_to_string(&audit_sql_file)?
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[1].to_string(), fields[2].to_string())
+            })
+            .collect();
+        tmp_dir.close().unwrap();
+
+        let t = table_name;
+        let exp: Vec<(String, String)> = [
+            format!(r#"CREATE TABLE IF NOT EXISTS "{t}_new" (LIKE "{t}" INCLUDING ALL)"#),
+            "BEGIN".to_string(),
+            format!(
+                r#"COPY "{t}_new" ("model","num_of_cyl","miles_per_gallon","gear") FROM STDIN BINARY"#
+            ),
+            "COMMIT".to_string(),
+            format!(
+                r#"ALTER TABLE "{t}" RENAME TO "{t}_old"; ALTER TABLE "{t}_new" RENAME TO "{t}"; DROP TABLE "{t}_old";"#
+            ),
+        ]
+        .into_iter()
+        .map(|sql| ("test-run".to_string(), sql))
+        .collect();
+        assert_eq!(recorded, exp);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_max_rows_per_copy() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_max_rows_per_copy";
+        let tmp_dir = TempDir::new().unwrap();
+        let audit_sql_file = format!("{}/audit.sql.log", tmp_dir.path().display());
+        let db = default_db_struct_for_cars_table(table_name, "car").await?;
+        let Err(e) = db.with_max_rows_per_copy(Some(0)) else {
+            panic!("a COPY needs rows")
+        };
+        assert_eq!(e.to_string(), "db.max_rows_per_copy must be at least 1");
+
+        let mut db = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_max_rows_per_copy(Some(10))?
+            .with_audit_sql(Some(&audit_sql_file), "test-run")?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let (parquet_dir, reader) = parquet_cars_reader().await?;
+        let num_rows_added = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await?;
+        parquet_dir.close().unwrap(); // can be deleted as read what we need
+
+        let recorded: Vec<String> = std::fs::read_to_string(&audit_sql_file)?
+            .lines()
+            .map(|line| line.split('\t').nth(2).unwrap().to_string())
+            .collect();
+        tmp_dir.close().unwrap();
+
+        // 32 cars, so 4 COPYs, between the one BEGIN and COMMIT
+        assert_eq!(num_rows_added.rows, 32);
+        let copy_sql = format!(
+            r#"COPY "{}" ("model","num_of_cyl","miles_per_gallon","gear") FROM STDIN BINARY"#,
+            table_name
+        );
+        let mut exp = vec!["BEGIN".to_string()];
+        exp.extend(vec![copy_sql; 4]);
+        exp.push("COMMIT".to_string());
+        assert_eq!(recorded, exp);
+
+        // all written by the one transaction
+        let sql = format!(
+            "SELECT count(*)::text AS total, count(DISTINCT xmin::text)::text AS txs from {}",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total,txs\n32,1\n");
+
+        Ok(())
+    }
+
+    // Another connection holding an ACCESS EXCLUSIVE lock on the table, so a COPY into it
+    // waits until the lock's let go, as if the db had stalled. ROLLBACK on it to let go.
+    async fn lock_table(table_name: &str) -> Result<Client> {
+        let (locker, _notices) = connect_client(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+        )
+        .await?;
+        let sql = format!(
+            "BEGIN; LOCK TABLE {} IN ACCESS EXCLUSIVE MODE",
+            quote_table_name(table_name)
+        );
+        locker.batch_execute(sql.as_str()).await?;
+        Ok(locker)
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_copy_timeout() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_copy_timeout";
+        let db = default_db_struct_for_cars_table(table_name, "car").await?;
+        let Err(e) = db.with_copy_timeout(Some(0)) else {
+            panic!("a zero timeout would fail every COPY")
+        };
+        assert_eq!(
+            e.to_string(),
+            "db.copy_timeout_ms must be at least 1, or left unset for no timeout"
+        );
+
+        let mut db = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_copy_timeout(Some(200))?;
+        let locker = lock_table(table_name).await?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let (parquet_dir, reader) = parquet_cars_reader().await?;
+        let result = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await;
+        parquet_dir.close().unwrap(); // can be deleted as read what we need
+        locker.batch_execute("ROLLBACK").await?;
+
+        let Err(e) = result else {
+            panic!("the COPY can't get past the lock, so should time out")
+        };
+        assert_eq!(
+            e.to_string(),
+            r#"COPY into "test_write_rows_copy_timeout" timed out after 200ms (db.copy_timeout_ms)"#
+        );
+        let sql = format!("SELECT count(*)::text AS total from {}", table_name);
+        let csv_string = get_rows_as_csv_string(&locker, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n0\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_statement_timeout() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_statement_timeout";
+        let db = default_db_struct_for_cars_table(table_name, "car").await?;
+        let Err(e) = db.with_statement_timeout(Some(0)).await else {
+            panic!("0 is postgres's no timeout, so would be confusing")
+        };
+        assert_eq!(
+            e.to_string(),
+            "db.statement_timeout_ms must be at least 1, or left unset for no timeout"
+        );
+
+        let mut db = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_statement_timeout(Some(200))
+            .await?;
+        let statement_timeout = db.client.query_one("SHOW statement_timeout", &[]).await?;
+        assert_eq!(statement_timeout.get::<_, String>(0), "200ms");
+        let locker = lock_table(table_name).await?;
+
+        let col_nums = vec![vec![0], vec![2], vec![1], vec![10]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::INT32, ConvertedType::INT_32, None),
+            (PqType::DOUBLE, ConvertedType::NONE, None),
+            (PqType::INT32, ConvertedType::NONE, None),
+        ];
+        let (parquet_dir, reader) = parquet_cars_reader().await?;
+        let result = db
+            .write_rows(reader.get_row_iter(None)?, &col_nums, pq_data)
+            .await;
+        parquet_dir.close().unwrap(); // can be deleted as read what we need
+        locker.batch_execute("ROLLBACK").await?;
+
+        // postgres cancelled it, so no copy_timeout needed
+        let Err(e) = result else {
+            panic!("the COPY can't get past the lock, so should be cancelled")
+        };
+        assert!(
+            format!("{:#}", e).contains("canceling statement due to statement timeout"),
+            "unexpected error: {:#}",
+            e
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_upsert_invalid_conflict_columns() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_upsert_invalid_conflict_columns";
+
+        let db = default_db_struct_for_cars_table(table_name, "car_pk").await?;
+        let result = db.with_upsert(Some(Upsert {
+            on_conflict: OnConflict::DoNothing,
+            conflict_columns: vec_stringify!["hp"],
+        }));
+        assert!(result.is_err(), "hp is not one of the cols written");
+
+        let db = default_db_struct_for_cars_table(table_name, "car_pk").await?;
+        let result = db.with_upsert(Some(Upsert {
+            on_conflict: OnConflict::DoUpdate,
+            conflict_columns: vec![],
+        }));
+        assert!(result.is_err(), "do_update needs a conflict target");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_sql_do_nothing() {
+        let upsert = Upsert {
+            on_conflict: OnConflict::DoNothing,
+            conflict_columns: vec_stringify!["id"],
+        };
+        let db_cols = vec_stringify!["id", "name"];
+        assert_eq!(
+            upsert_sql("orders", "orders_upsert", &db_cols, &upsert),
+            "INSERT INTO orders (\"id\",\"name\") SELECT \"id\",\"name\" FROM orders_upsert \
+             ON CONFLICT (\"id\") DO NOTHING"
+        );
+
+        let upsert = Upsert {
+            on_conflict: OnConflict::DoNothing,
+            conflict_columns: vec![],
+        };
+        let db_cols = vec_stringify!["id"];
+        assert_eq!(
+            upsert_sql("orders", "orders_upsert", &db_cols, &upsert),
+            "INSERT INTO orders (\"id\") SELECT \"id\" FROM orders_upsert ON CONFLICT DO NOTHING"
+        );
+    }
+
+    #[test]
+    fn test_upsert_sql_do_update() {
+        let upsert = Upsert {
+            on_conflict: OnConflict::DoUpdate,
+            conflict_columns: vec_stringify!["id", "full name"],
+        };
+        let db_cols = vec_stringify!["id", "full name", "qty", "price"];
+        assert_eq!(
+            upsert_sql("orders", "orders_upsert", &db_cols, &upsert),
+            "INSERT INTO orders (\"id\",\"full name\",\"qty\",\"price\") \
+             SELECT \"id\",\"full name\",\"qty\",\"price\" FROM orders_upsert \
+             ON CONFLICT (\"id\",\"full name\") \
+             DO UPDATE SET \"qty\" = EXCLUDED.\"qty\",\"price\" = EXCLUDED.\"price\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_str_to_timestamptz() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_str_to_timestamptz";
+        let client = create_table_return_client(table_name.to_string(), "timestamptz").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["happened_at"],
+            db_col_types: vec![PgType::TIMESTAMPTZ],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "happened_at",
+            vec![
+                Some("2024-09-24T12:00:00+02:00"),
+                Some("2024-09-24T23:30:00Z"),
+                Some("2024-09-24T20:15:00-05:00"),
+                None,
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 4);
+        let sql = format!(
+            "SELECT happened_at from {} ORDER BY 1 NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            happened_at\n\
+            2024-09-24 10:00:00 UTC\n\
+            2024-09-24 23:30:00 UTC\n\
+            2024-09-25 01:15:00 UTC\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_naive_str_to_timestamptz_in_assumed_zone() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_naive_str_to_timestamptz_in_assumed_zone";
+        let client = create_table_return_client(table_name.to_string(), "timestamptz").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["happened_at"],
+            db_col_types: vec![PgType::TIMESTAMPTZ],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_assume_timezone(
+            &["happened_at".to_string()],
+            &HashMap::from([("happened_at".to_string(), "America/New_York".to_string())]),
+        )?;
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "happened_at",
+            vec![
+                Some("2024-09-24T12:00:00"), // EDT, UTC-4
+                Some("2024-12-24 12:00:00"), // EST, UTC-5
+                Some("2024-09-24T12:00:00Z"),
+                None,
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 4);
+        let sql = format!(
+            "SELECT happened_at from {} ORDER BY 1 NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            happened_at\n\
+            2024-09-24 12:00:00 UTC\n\
+            2024-09-24 16:00:00 UTC\n\
+            2024-12-24 17:00:00 UTC\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_assume_timezone_invalid_zone() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_assume_timezone_invalid_zone";
+        let client = create_table_return_client(table_name.to_string(), "timestamptz").await?;
+        let db = Db {
+            client,
+            db_cols: vec_stringify!["happened_at"],
+            db_col_types: vec![PgType::TIMESTAMPTZ],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+
+        let result = db.with_assume_timezone(
+            &["happened_at".to_string()],
+            &HashMap::from([("happened_at".to_string(), "America/Gotham".to_string())]),
+        );
+        let Err(e) = result else {
+            panic!("should reject a zone not in the tz database")
+        };
+        assert!(
+            e.to_string().contains("not a valid timezone name"),
+            "unexpected error: {}",
+            e
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_nested_fields() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_nested_fields";
+        let client = create_table_return_client(table_name.to_string(), "address").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["id", "city", "billing_city"],
+            db_col_types: vec![PgType::INT4, PgType::VARCHAR, PgType::VARCHAR],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = nested_parquet_reader()?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        // id, address.city, billing.city
+        let col_nums = vec![vec![0], vec![1, 0], vec![2, 0]];
+        let pq_data: &[PqTypeData] = &[
+            (PqType::INT32, ConvertedType::NONE, None),
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+        ];
+        let num_rows_added = db.write_rows(row_iter, &col_nums, pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 3);
+        // fields of a null group are null
+        let sql = format!("SELECT * from {} ORDER BY id", table_name);
+        let exp_string = "\
+            id,city,billing_city\n\
+            1,Leeds,Hull\n\
+            2,,\n\
+            3,York,\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_optional_fields_absent() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_optional_fields_absent";
+        let client = create_table_return_client(table_name.to_string(), "optional_fields").await?;
+        let desired_fields = vec_stringify!["id", "address.city", "country", "region"];
+        let optional_fields = vec![
+            OptionalField::WithFill {
+                name: "country".to_string(),
+                fill: "UK".to_string(),
+            },
+            OptionalField::Name("region".to_string()),
+        ];
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["id", "city", "country", "region"],
+            db_col_types: vec![
+                PgType::INT4,
+                PgType::VARCHAR,
+                PgType::VARCHAR,
+                PgType::VARCHAR,
+            ],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_optional_fields(&desired_fields, &optional_fields)?;
+        let (tmp_dir, reader) = nested_parquet_reader()?;
+        // the file has neither country nor region
+        let mut pq = Parquet::new("nested.parquet".to_string(), desired_fields)?
+            .with_optional_fields(vec_stringify!["country", "region"]);
+        let (col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let num_rows_added = db.write_rows(row_iter, &col_nums, &pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 3);
+        // country is NOT NULL, so would fail without the fill
+        let sql = format!("SELECT * from {} ORDER BY id", table_name);
+        let exp_string = "\
+            id,city,country,region\n\
+            1,Leeds,UK,\n\
+            2,,UK,\n\
+            3,York,UK,\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_optional_fields_invalid_fill() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_optional_fields_invalid_fill";
+        let desired_fields = vec_stringify!["model", "cyl", "mpg", "gear"];
+        let result = default_db_struct_for_cars_table(table_name, "car")
+            .await?
+            .with_optional_fields(
+                &desired_fields,
+                &[OptionalField::WithFill {
+                    name: "gear".to_string(),
+                    fill: "four".to_string(),
+                }],
+            );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "parquet.optional_fields field gear into col gear: fill value four is not a valid int4"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_base64_str_to_bytea() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_base64_str_to_bytea";
+        let client = create_table_return_client(table_name.to_string(), "bytea").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["payload"],
+            db_col_types: vec![PgType::BYTEA],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_base64_decode(&["payload".to_string()], &["payload".to_string()])?;
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "payload",
+            vec![Some("AP8Q"), Some("aGVsbG8="), Some(""), None],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 4);
+        // bytea isn't rendered as csv, so compare as hex text
+        let sql = format!(
+            "SELECT encode(payload, 'hex') AS payload from {} ORDER BY 1 NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            payload\n\
+            \"\"\n\
+            00ff10\n\
+            68656c6c6f\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_decrypt() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_decrypt";
+        let client = create_table_return_client(table_name.to_string(), "delivery").await?;
+        let key = [7u8; 32];
+        let key_env = "TEST_WRITE_ROWS_DECRYPT_KEY";
+        std::env::set_var(key_env, BASE64_STANDARD.encode(key));
+        let db = Db {
+            client,
+            db_cols: vec_stringify!["body"],
+            db_col_types: vec![PgType::TEXT],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_decrypt(
+            &["body".to_string()],
+            &[DecryptConfig {
+                col: "body".to_string(),
+                key_env: key_env.to_string(),
+            }],
+        );
+        std::env::remove_var(key_env);
+        let mut db = db?;
+
+        let sealed: Vec<String> = ["hello", "it's a secret"]
+            .iter()
+            .map(|plaintext| BASE64_STANDARD.encode(aes_gcm_seal(&key, plaintext.as_bytes())))
+            .collect();
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "body",
+            vec![Some(sealed[0].as_str()), Some(sealed[1].as_str()), None],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!("SELECT body from {} ORDER BY 1 NULLS LAST", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "body\nhello\nit's a secret\n\"\"\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_decrypt_invalid() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_decrypt_invalid";
+        let key_env = "TEST_WITH_DECRYPT_INVALID_KEY";
+        std::env::set_var(key_env, BASE64_STANDARD.encode([7u8; 16]));
+        let cases = [
+            ("model", "TEST_WITH_DECRYPT_INVALID_UNSET", "env var TEST_WITH_DECRYPT_INVALID_UNSET for a decrypt key is not set"),
+            ("model", key_env, "env var TEST_WITH_DECRYPT_INVALID_KEY must be a 32 byte AES-256 key, as base64, not 16 bytes"),
+            ("gear", key_env, "parquet_to_db.decrypt col gear must go into a bytea or text col, but gear is int4"),
+            ("nope", key_env, "parquet_to_db.decrypt col nope is not in parquet.desired_fields"),
+        ];
+        let desired_fields = vec_stringify!["model", "num_of_cyl", "miles_per_gallon", "gear"];
+        let mut errors: Vec<String> = vec![];
+        for (col, env_name, _) in &cases {
+            let result = default_db_struct_for_cars_table(table_name, "car")
+                .await?
+                .with_decrypt(
+                    &desired_fields,
+                    &[DecryptConfig {
+                        col: col.to_string(),
+                        key_env: env_name.to_string(),
+                    }],
+                );
+            errors.push(result.unwrap_err().to_string());
+        }
+        std::env::remove_var(key_env);
+        for ((_, _, expected), error) in cases.iter().zip(errors) {
+            assert_eq!(&error, expected);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_str_to_timestamptz_unparseable() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_str_to_timestamptz_unparseable";
+        let client = create_table_return_client(table_name.to_string(), "timestamptz").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["happened_at"],
+            db_col_types: vec![PgType::TIMESTAMPTZ],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader("happened_at", vec![Some("24/09/2024 12:00")])?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let result = db.write_rows(row_iter, &[vec![0]], pq_data).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(result.is_err(), "should fail on an unparseable timestamp");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("not a timestamp with a timezone offset"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_names_failing_row() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_names_failing_row";
+        let client = create_table_return_client(table_name.to_string(), "timestamptz").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["happened_at"],
+            db_col_types: vec![PgType::TIMESTAMPTZ],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            // the count carries on across COPYs
+            max_rows_per_copy: Some(2),
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "happened_at",
+            vec![
+                Some("2024-09-24T12:00:00+00:00"),
+                Some("2024-09-24T13:00:00+00:00"),
+                None,
+                Some("24/09/2024 15:00"),
+                Some("2024-09-24T16:00:00+00:00"),
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let result = db.write_rows(row_iter, &[vec![0]], pq_data).await;
+        // rows a row_filter skips still count, so the row keeps its place in the file
+        db = db.with_row_filter(Some(crate::config::RowFilter {
+            column: "happened_at".to_string(),
+            op: FilterOp::Ne,
+            value: "2024-09-24T12:00:00+00:00".to_string(),
+        }));
+        db.set_row_filter_col_path(vec![0]);
+        let filtered = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        for result in [result, filtered] {
+            let Err(e) = result else {
+                panic!("should fail on the unparseable timestamp")
+            };
+            let msg = e.to_string();
+            assert!(msg.contains("Issue writing row 4 to db"), "{}", msg);
+            assert!(msg.contains("24/09/2024 15:00"), "{}", msg);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_int32_as_date_per_field_types() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_int32_as_date_per_field_types";
+        let client = create_table_return_client(table_name.to_string(), "date").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["sold_on"],
+            db_col_types: vec![PgType::DATE],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_field_types(
+            &["sold_on".to_string()],
+            &HashMap::from([("sold_on".to_string(), FieldType::Date)]),
+        )?;
+        // days since 1970-01-01, but with no DATE converted type
+        let (tmp_dir, reader) = int32_parquet_reader("sold_on", vec![Some(19_990), Some(0), None])?;
+        let mut pq = Parquet::new("int32.parquet".to_string(), vec_stringify!["sold_on"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        assert_eq!(pq_data[0], (PqType::INT32, ConvertedType::NONE, None));
+        let num_rows_added = db.write_rows(row_iter, &parquet_col_nums, &pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!(
+            "SELECT sold_on::text AS sold_on from {} ORDER BY sold_on NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            sold_on\n\
+            1970-01-01\n\
+            2024-09-24\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_int32_time_millis() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_int32_time_millis";
+        let client = create_table_return_client(table_name.to_string(), "time").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["sold_at", "sold_at_text"],
+            db_col_types: vec![PgType::TIME, PgType::TEXT],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        // milliseconds since midnight
+        let (tmp_dir, _) =
+            time_millis_parquet_reader("sold_at", vec![Some(45_296_789), Some(0), None])?;
+        let parquet_file = format!("{}/int32.parquet", tmp_dir.path().display());
+        // the same field into both cols
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["sold_at", "sold_at"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&pq.file_reader()?)?;
+
+        assert_eq!(
+            pq_data[0],
+            (PqType::INT32, ConvertedType::TIME_MILLIS, None)
+        );
+        let num_rows_added = db
+            .write_rows(pq.row_iter()?, &parquet_col_nums, &pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!(
+            "SELECT sold_at::text AS sold_at, sold_at_text from {} ORDER BY sold_at NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            sold_at,sold_at_text\n\
+            00:00:00,00:00:00.000\n\
+            12:34:56.789,12:34:56.789\n\
+            ,\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_int64_time_micros() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_int64_time_micros";
+        let client = create_table_return_client(table_name.to_string(), "time").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["sold_at", "sold_at_text"],
+            db_col_types: vec![PgType::TIME, PgType::TEXT],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        // microseconds since midnight, the last just before the next one
+        let (tmp_dir, _) = time_micros_parquet_reader(
+            "sold_at",
+            vec![Some(45_296_789_012), Some(0), Some(86_399_999_999), None],
+        )?;
+        let parquet_file = format!("{}/int64.parquet", tmp_dir.path().display());
+        // the same field into both cols
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["sold_at", "sold_at"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&pq.file_reader()?)?;
+
+        assert_eq!(
+            pq_data[0],
+            (PqType::INT64, ConvertedType::TIME_MICROS, None)
+        );
+        let num_rows_added = db
+            .write_rows(pq.row_iter()?, &parquet_col_nums, &pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 4);
+        let sql = format!(
+            "SELECT sold_at::text AS sold_at, sold_at_text from {} ORDER BY sold_at NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            sold_at,sold_at_text\n\
+            00:00:00,00:00:00.000000\n\
+            12:34:56.789012,12:34:56.789012\n\
+            23:59:59.999999,23:59:59.999999\n\
+            ,\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_int_8_to_smallint() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_int_8_to_smallint";
+        let client = create_table_return_client(table_name.to_string(), "int2").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["n"],
+            db_col_types: vec![PgType::INT2],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Error,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = int_8_parquet_reader("n", vec![Some(-128), Some(127), None])?;
+        let pq_data: &[PqTypeData] = &[(PqType::INT32, ConvertedType::INT_8, None)];
+        let written = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        // not nulled as an unknown converted type, which on_type_mismatch error would fail
+        assert_eq!(written.rows, 3);
+        let sql = format!("SELECT n from {} ORDER BY 1 NULLS LAST", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "n\n-128\n127\n\"\"\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_counts_null_substitutions() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_counts_null_substitutions";
+        let client = create_table_return_client(table_name.to_string(), "int4").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["n"],
+            db_col_types: vec![PgType::INT4],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = int32_parquet_reader("n", vec![Some(1), None, Some(3)])?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        // deliberately mismatched: the converter for an INT_16 expects shorts, not ints
+        let pq_data: &[PqTypeData] = &[(PqType::INT32, ConvertedType::INT_16, None)];
+        let written = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        // the parquet null isn't a substitution
+        assert_eq!(
+            written,
+            RowsWritten {
+                rows: 3,
+                null_substitutions: HashMap::from([("n".to_string(), 2)]),
+                rows_skipped: 0,
+            }
+        );
+        assert_eq!(
+            written.warn_null_substitutions("int32.parquet"),
+            vec!["int32.parquet: 2 values for col n didn't match the parquet type, so were written as NULL"]
+        );
+        let sql = format!(
+            "SELECT count(*) AS nulls from {} WHERE n IS NULL",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "nulls\n3\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_on_type_mismatch_error() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_on_type_mismatch_error";
+        let client = create_table_return_client(table_name.to_string(), "int4").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["n"],
+            db_col_types: vec![PgType::INT4],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_type_mismatch(TypeMismatch::Error);
+        // deliberately mismatched: the converter for an INT_16 expects shorts, not ints
+        let pq_data: &[PqTypeData] = &[(PqType::INT32, ConvertedType::INT_16, None)];
+
+        // real nulls are fine
+        let (tmp_dir, reader) = int32_parquet_reader("n", vec![None, None])?;
+        let written = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(written.rows, 2);
+        assert!(written.null_substitutions.is_empty());
+
+        let (tmp_dir, reader) = int32_parquet_reader("n", vec![None, Some(2)])?;
+        let result = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        let Err(e) = result else {
+            panic!("an Int isn't what the converter expects")
+        };
+        assert_eq!(
+            e.to_string(),
+            "db.on_type_mismatch is error, but a value for col n doesn't match the parquet type"
+        );
+
+        // the failed file's transaction left nothing
+        let sql = format!("SELECT count(*) AS total from {}", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n2\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_quarantines_rejects() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_quarantines_rejects";
+        let client = create_table_return_client(table_name.to_string(), "int4").await?;
+        let quarantine_dir = TempDir::new().unwrap();
+        let field_names = vec_stringify!["n"];
+        let rejects = Rejects::new(
+            &quarantine_dir.path().display().to_string(),
+            "2024/n.parquet",
+            &field_names,
+        )?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["n"],
+            db_col_types: vec![PgType::INT4],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: Some(rejects),
+            schema: None,
+        }
+        .with_type_mismatch(TypeMismatch::Error); // a rejected row doesn't fail the file
+                                                  // deliberately mismatched: the converter for an INT_16 expects shorts, not ints
+        let pq_data: &[PqTypeData] = &[(PqType::INT32, ConvertedType::INT_16, None)];
+
+        let (tmp_dir, reader) = int32_parquet_reader("n", vec![None, Some(2), None])?;
+        let written = db
+            .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(written.rows, 2);
+        assert!(written.null_substitutions.is_empty());
+        assert_eq!(db.rejects.as_ref().map(Rejects::count), Some(1));
+
+        let rejected = std::fs::read_to_string(quarantine_dir.path().join("2024/n.parquet.jsonl"))?;
+        quarantine_dir.close().unwrap();
+        assert_eq!(
+            rejected,
+            "{\"error\":\"a value for col n doesn't match the parquet type\",\"row\":{\"n\":2}}\n"
+        );
+
+        let sql = format!("SELECT count(*) AS total from {}", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n2\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_bitmask_columns() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_bitmask_columns";
+        let _ = create_table_return_client(table_name.to_string(), "bitmask").await?;
+        let bitmask_columns = HashMap::from([("flags".to_string(), vec_stringify!["a", "b", "c"])]);
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["id"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?
+        .with_bitmask_columns(&bitmask_columns)
+        .await?;
+        assert_eq!(db.bitmask_fields(), vec_stringify!["a", "b", "c"]);
+
+        let (tmp_dir, reader) = bools_parquet_reader(vec![
+            [Some(true), Some(false), Some(false)],
+            [Some(false), Some(true), Some(true)],
+            [Some(true), None, Some(true)], // a null leaves its bit unset
+            [Some(true), Some(true), Some(true)],
+            [None, None, None],
+        ])?;
+        let parquet_file = format!("{}/bools.parquet", tmp_dir.path().display());
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["id", "a", "b", "c"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+
+        let num_rows_added = db
+            .write_rows(pq.row_iter()?, &parquet_col_nums, &pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 5);
+        let sql = format!(
+            "SELECT id, flags, (flags & 1 > 0)::text AS a, (flags & 2 > 0)::text AS b, \
+             (flags & 4 > 0)::text AS c from {} ORDER BY id",
+            table_name
+        );
+        let exp_string = "\
+            id,flags,a,b,c\n\
+            1,1,true,false,false\n\
+            2,6,false,true,true\n\
+            3,5,true,false,true\n\
+            4,7,true,true,true\n\
+            5,0,false,false,false\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_bitmask_columns_invalid() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_bitmask_columns_invalid";
+        let _ = create_table_return_client(table_name.to_string(), "bitmask").await?;
+        let with_bitmask = |col: &'static str, fields: Vec<String>| async move {
+            Db::connect(
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+                table_name,
+                vec_stringify!["id"],
+                None,
+                &NamingStrategy::Identity,
+                None,
+            )
+            .await?
+            .with_bitmask_columns(&HashMap::from([(col.to_string(), fields)]))
+            .await
+        };
+
+        let errors = [
+            (
+                with_bitmask("id", vec_stringify!["a"]).await,
+                "db.bitmask_columns column id is already written to, so can't be a bitmask too",
+            ),
+            (
+                with_bitmask("note", vec_stringify!["a"]).await,
+                "db.bitmask_columns column note must be a smallint, int or bigint, but is text",
+            ),
+            (
+                with_bitmask("nope", vec_stringify!["a"]).await,
+                "Table test_with_bitmask_columns_invalid does not have db.bitmask_columns column nope",
+            ),
+            (
+                with_bitmask("flags", (0..16).map(|i| format!("f{}", i)).collect()).await,
+                "db.bitmask_columns column flags is a int2, so takes 1 to 15 fields, not 16",
+            ),
+        ];
+        for (result, exp_err) in errors {
+            let Err(e) = result else {
+                panic!("should fail with: {}", exp_err)
+            };
+            assert_eq!(e.to_string(), exp_err);
+        }
+
+        // the fields must be BOOLEANs, checked once the file's types are known
+        let (tmp_dir, reader) = bools_parquet_reader(vec![[Some(true), None, None]])?;
+        let mut db = with_bitmask("flags", vec_stringify!["id"]).await?;
+        let parquet_file = format!("{}/bools.parquet", tmp_dir.path().display());
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["id", "id"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+        let result = db
+            .write_rows(pq.row_iter()?, &parquet_col_nums, &pq_data)
+            .await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        let Err(e) = result else {
+            panic!("an INT32 isn't a bit")
+        };
+        assert_eq!(
+            e.to_string(),
+            "db.bitmask_columns field id is a INT32, not a BOOLEAN"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_repeated_primitive() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_repeated_primitive";
+        let client = create_table_return_client(table_name.to_string(), "scores").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["id", "scores"],
+            db_col_types: vec![PgType::INT4, PgType::INT4_ARRAY],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, _) = repeated_parquet_reader()?;
+        let parquet_file = format!("{}/repeated.parquet", tmp_dir.path().display());
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["id", "scores"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&pq.file_reader()?)?;
+
+        let num_rows_added = db
+            .write_rows(pq.row_iter()?, &parquet_col_nums, &pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!(
+            "SELECT id, scores::text AS scores from {} ORDER BY id",
+            table_name
+        );
+        let exp_string = "\
+            id,scores\n\
+            1,\"{1,2,3}\"\n\
+            2,{}\n\
+            3,{4}\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_row_groups_copy_parallelism() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_row_groups_copy_parallelism";
+        let _ = create_table_return_client(table_name.to_string(), "int4").await?;
+        let mut db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["n"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?
+        .with_copy_parallelism(
+            3,
+            1,
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+        )
+        .await?;
+        assert_eq!(db.copy_clients.len(), 2);
+
+        // 5 row groups of 1000 rows, so one connection gets 1 fewer than the others
+        let row_groups: Vec<Vec<i32>> = (0..5)
+            .map(|rg| (rg * 1000..(rg + 1) * 1000).collect())
+            .collect();
+        let (tmp_dir, reader) = int32_row_groups_parquet_reader("n", &row_groups)?;
+        assert_eq!(reader.num_row_groups(), 5);
+        let parquet_file = format!("{}/int32_row_groups.parquet", tmp_dir.path().display());
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["n"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+
+        let num_rows_added = db
+            .write_row_groups(&pq, &parquet_col_nums, &pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 5000);
+        let sql = format!(
+            "SELECT count(*)::text AS count, count(DISTINCT n)::text AS distinct_n, max(n)::text AS max_n from {}",
+            table_name
+        );
+        let exp_string = "\
+            count,distinct_n,max_n\n\
+            5000,5000,4999\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_row_groups_copy_parallelism_same_as_sequential() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_row_groups_copy_parallelism_same_as_sequential";
+        let sequential_table_name = format!("{}_seq", table_name);
+        let _ = create_table_return_client(table_name.to_string(), "int4").await?;
+        let _ = create_table_return_client(sequential_table_name.clone(), "int4").await?;
+        let connect = |table_name: String| async move {
+            Db::connect(
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+                &table_name,
+                vec_stringify!["n"],
+                None,
+                &NamingStrategy::Identity,
+                None,
+            )
+            .await
+        };
+        let mut sequential_db = connect(sequential_table_name.clone()).await?;
+        let mut parallel_db = connect(table_name.to_string())
+            .await?
+            .with_copy_parallelism(
+                4,
+                1,
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+            )
+            .await?;
+
+        // row groups of different sizes, more of them than connections
+        let row_groups: Vec<Vec<i32>> = (0..7)
+            .map(|rg| (rg * 1000..rg * 1000 + 100 * (rg + 1)).collect())
+            .collect();
+        let (tmp_dir, _) = int32_row_groups_parquet_reader("n", &row_groups)?;
+        let parquet_file = format!("{}/int32_row_groups.parquet", tmp_dir.path().display());
+        let mut pq = Parquet::new(parquet_file, vec_stringify!["n"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&pq.file_reader()?)?;
+
+        let sequential = sequential_db
+            .write_row_groups(&pq, &parquet_col_nums, &pq_data)
+            .await?;
+        let parallel = parallel_db
+            .write_row_groups(&pq, &parquet_col_nums, &pq_data)
+            .await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(sequential.rows, 2800);
+        assert_eq!(parallel, sequential);
+        let sql = "SELECT count(*)::text AS count, sum(n)::text AS sum_n from {}";
+        let sequential_rows = get_rows_as_csv_string(
+            &sequential_db.client,
+            sql.replace("{}", &sequential_table_name).as_str(),
+        )
+        .await?;
+        let parallel_rows =
+            get_rows_as_csv_string(&parallel_db.client, sql.replace("{}", table_name).as_str())
+                .await?;
+        assert_eq!(parallel_rows, sequential_rows);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_settle_prepared() -> Result<()> {
+        setup_docker();
+        let table_name = "test_settle_prepared";
+        let client = create_table_return_client(table_name.to_string(), "int4").await?;
+        let db = Db::connect(
+            GOOD_DB_CONN_STR,
+            &SslMode::Disable,
+            None,
+            &ConnectRetry::default(),
+            table_name,
+            vec_stringify!["n"],
+            None,
+            &NamingStrategy::Identity,
+            None,
+        )
+        .await?;
+
+        // the first shares of two files, one committed, one rolled back
+        let mut txids: Vec<i64> = Vec::new();
+        for end in ["COMMIT", "ROLLBACK"] {
+            client.batch_execute("BEGIN").await?;
+            txids.push(client.query_one("SELECT txid_current()", &[]).await?.get(0));
+            client.batch_execute(end).await?;
+        }
+        // and the rest of each, left prepared by a run that stopped
+        for (n, txid) in txids.iter().enumerate() {
+            client
+                .batch_execute(&format!(
+                    "BEGIN; INSERT INTO {} VALUES ({}); PREPARE TRANSACTION '{}{}:1'",
+                    table_name, n, PREPARED_GID_PREFIX, txid
+                ))
+                .await?;
+        }
+
+        assert!(db.settle_prepared().await? >= 2);
+        let sql = format!("SELECT n::text AS n FROM {}", table_name);
+        let csv_string = get_rows_as_csv_string(&client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "n\n0\n",
+            "only the committed file's share should be in"
+        );
+        for txid in txids {
+            let gid = format!("{}{}:1", PREPARED_GID_PREFIX, txid);
+            let left = client
+                .query("SELECT gid FROM pg_prepared_xacts WHERE gid = $1", &[&gid])
+                .await?;
+            assert!(left.is_empty(), "{} should be settled", gid);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_numbers() {
+        let row_group_sizes = [3, 2, 4];
+        assert_eq!(
+            row_numbers(&row_group_sizes, &[0, 1, 2]).collect::<Vec<u64>>(),
+            (1..=9).collect::<Vec<u64>>()
+        );
+        // a copy_parallelism share's row groups aren't next to each other
+        assert_eq!(
+            row_numbers(&row_group_sizes, &[0, 2]).collect::<Vec<u64>>(),
+            vec![1, 2, 3, 6, 7, 8, 9]
+        );
+        assert_eq!(
+            row_numbers(&row_group_sizes, &[1]).collect::<Vec<u64>>(),
+            vec![4, 5]
+        );
+    }
+
+    #[test]
+    fn test_first_error() {
+        let written = |rows: u64| -> Result<RowsWritten> {
+            Ok(RowsWritten {
+                rows,
+                ..Default::default()
+            })
+        };
+        let all_written = first_error(vec![written(1), written(2)]).unwrap();
+        assert_eq!(all_written.iter().map(|w| w.rows).sum::<u64>(), 3);
+
+        // the first in order, whichever failed first
+        let failed = first_error(vec![
+            written(1),
+            Err(anyhow::anyhow!("second failed")),
+            Err(anyhow::anyhow!("third failed")),
+        ]);
+        assert_eq!(failed.unwrap_err().to_string(), "second failed");
+    }
+
+    #[tokio::test]
+    async fn test_with_copy_parallelism_max_prepared() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_copy_parallelism_max_prepared";
+        let db = default_db_struct_for_cars_table(table_name, "car_pk").await?;
+
+        // each file loading at once holds copy_parallelism - 1 prepared shares
+        let err = db
+            .with_copy_parallelism(
+                2,
+                1000,
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "with 1000 file(s) at once needs the server's max_prepared_transactions at least 1000"
+            ),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_copy_parallelism_not_with_upsert() -> Result<()> {
+        setup_docker();
+        let table_name = "test_with_copy_parallelism_not_with_upsert";
+        let db = default_db_struct_for_cars_table(table_name, "car_pk")
+            .await?
+            .with_upsert(Some(Upsert {
+                on_conflict: OnConflict::DoNothing,
+                conflict_columns: vec![],
+            }))?;
+
+        let err = db
+            .with_copy_parallelism(
+                2,
+                1,
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "db.copy_parallelism is only for COPY straight into the table, so can't be used with db.on_conflict"
+        );
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_write_rows_duration_to_interval() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_duration_to_interval";
+        let client = create_table_return_client(table_name.to_string(), "interval").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["took"],
+            db_col_types: vec![PgType::INTERVAL],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = duration_parquet_reader(
+            "took",
+            TimeUnit::Millisecond,
+            vec![Some(1_500), Some(129_600_000), Some(-250), None],
+        )?;
+        let mut pq = Parquet::new("duration.parquet".to_string(), vec_stringify!["took"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let num_rows_added = db.write_rows(row_iter, &parquet_col_nums, &pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 4);
+        let sql = format!(
+            "SELECT took::text AS took from {} ORDER BY took NULLS LAST",
+            table_name
+        );
+        // 36 hours stays as hours, it's not turned into a day
+        let exp_string = "\
+            took\n\
+            -00:00:00.25\n\
+            00:00:01.5\n\
+            36:00:00\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_timestamp_nanos() -> Result<()> {
+        setup_docker();
+        // 2024-09-24 12:00:00.123456789 UTC, then just before the epoch
+        let nanos = vec![Some(1_727_179_200_123_456_789), Some(-1), None];
+        let tables = [
+            (
+                PgType::TIMESTAMPTZ,
+                "timestamptz",
+                "2024-09-24 12:00:00.123456+00\n1969-12-31 23:59:59.999999+00\n\"\"\n",
+            ),
+            (
+                PgType::TIMESTAMP,
+                "timestamp",
+                "2024-09-24 12:00:00.123456\n1969-12-31 23:59:59.999999\n\"\"\n",
+            ),
+        ];
+
+        for (db_col_type, schema_type, exp_rows) in tables {
+            let table_name = format!("test_write_rows_timestamp_nanos_{}", schema_type);
+            let client = create_table_return_client(table_name.clone(), schema_type).await?;
+            client.batch_execute("SET TIME ZONE 'UTC'").await?;
+            let mut db = Db {
+                client,
+                db_cols: vec_stringify!["happened_at"],
+                db_col_types: vec![db_col_type],
+                table_name: table_name.clone(),
+                upsert: None,
+                load_mode: LoadMode::Append,
+                notices: no_notices(),
+                on_non_finite_float: NonFiniteFloat::Pass,
+                on_type_mismatch: TypeMismatch::Warn,
+                base64_decode: vec![],
+                decrypt: vec![],
+                assume_timezone: vec![],
+                field_types: vec![],
+                date_formats: None,
+                fills: vec![],
+                load_metadata: LoadMetadata::default(),
+                copy_clients: vec![],
+                audit_sql: AuditSql::default(),
+                insert_batch_size: None,
+                bitmasks: vec![],
+                max_rows_per_copy: None,
+                copy_timeout: None,
+                row_filter: None,
+                all_fields: None,
+                rejects: None,
+                schema: None,
+            };
+            let (tmp_dir, reader) = timestamp_nanos_parquet_reader("happened_at", nanos.clone())?;
+            let mut pq = Parquet::new(
+                "timestamp_nanos.parquet".to_string(),
+                vec_stringify!["happened_at"],
+            )?;
+            let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+            let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+            let num_rows_added = db.write_rows(row_iter, &parquet_col_nums, &pq_data).await?;
+            tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+            assert_eq!(num_rows_added.rows, 3);
+            let sql = format!(
+                "SELECT happened_at::text AS happened_at from {} ORDER BY happened_at DESC NULLS LAST",
+                table_name
+            );
+            // postgres keeps microseconds, so the nanoseconds are truncated
+            let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+            assert_eq!(csv_string, format!("happened_at\n{}", exp_rows));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_int32_decimal_to_numeric() -> Result<()> {
+        setup_docker();
+        let table_name = "test_write_rows_int32_decimal_to_numeric";
+        let client = create_table_return_client(table_name.to_string(), "numeric").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["price"],
+            db_col_types: vec![PgType::NUMERIC],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) =
+            int32_decimal_parquet_reader("price", 2, vec![Some(1234), Some(100), Some(-5), None])?;
+        let mut pq = Parquet::new("int32.parquet".to_string(), vec_stringify!["price"])?;
+        let (parquet_col_nums, pq_data) = pq.get_desired_cols(&reader)?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+        let num_rows_added = db.write_rows(row_iter, &parquet_col_nums, &pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(num_rows_added.rows, 4);
+        let sql = format!(
+            "SELECT price::text AS price from {} ORDER BY price DESC NULLS LAST",
+            table_name
+        );
+        // exactly, with the scale kept, not via a float
+        let exp_string = "\
+            price\n\
+            12.34\n\
+            1.00\n\
+            -0.05\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_column_order() -> Result<()> {
+        setup_docker();
+        let table_name = "test_check_column_order";
+        // table is model, miles_per_gallon, num_of_cyl ... gear
+        // db_cols are model, num_of_cyl, miles_per_gallon, gear
+        let mut db = default_db_struct_for_cars_table(table_name, "car").await?;
+
+        let result = db.check_column_order().await;
+        assert!(result.is_err(), "num_of_cyl comes after miles_per_gallon");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("not in table test_check_column_order's column order"));
+
+        // columns left out are fine, as long as the rest are in order
+        db.db_cols = vec_stringify!["model", "miles_per_gallon", "gear"];
+        db.check_column_order().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rows_non_finite_floats() -> Result<()> {
+        setup_docker();
+        let modes = [
+            (NonFiniteFloat::Pass, "-inf\n1.5\ninf\nNaN\n\"\"\n"),
+            (NonFiniteFloat::Null, "1.5\n\"\"\n\"\"\n\"\"\n\"\"\n"),
+            (NonFiniteFloat::Error, "0\n"), // a count, as nothing written
+        ];
+
+        for (on_non_finite_float, exp_rows) in modes {
+            let table_name = format!(
+                "test_write_rows_non_finite_floats_{:?}",
+                on_non_finite_float
+            )
+            .to_lowercase();
+            let client = create_table_return_client(table_name.clone(), "float8").await?;
+            let mut db = Db {
+                client,
+                db_cols: vec_stringify!["measured"],
+                db_col_types: vec![PgType::FLOAT8],
+                table_name: table_name.clone(),
+                upsert: None,
+                load_mode: LoadMode::Append,
+                notices: no_notices(),
+                on_non_finite_float: on_non_finite_float.clone(),
+                on_type_mismatch: TypeMismatch::Warn,
+                base64_decode: vec![],
+                decrypt: vec![],
+                assume_timezone: vec![],
+                field_types: vec![],
+                date_formats: None,
+                fills: vec![],
+                load_metadata: LoadMetadata::default(),
+                copy_clients: vec![],
+                audit_sql: AuditSql::default(),
+                insert_batch_size: None,
+                bitmasks: vec![],
+                max_rows_per_copy: None,
+                copy_timeout: None,
+                row_filter: None,
+                all_fields: None,
+                rejects: None,
+                schema: None,
+            };
+            let (tmp_dir, reader) = double_parquet_reader(
+                "measured",
+                vec![
+                    Some(1.5),
+                    Some(f64::NAN),
+                    Some(f64::INFINITY),
+                    Some(f64::NEG_INFINITY),
+                    None,
+                ],
+            )?;
+            let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+
+            let pq_data: &[PqTypeData] = &[(PqType::DOUBLE, ConvertedType::NONE, None)];
+            let result = db.write_rows(row_iter, &[vec![0]], pq_data).await;
+            tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+            match on_non_finite_float {
+                NonFiniteFloat::Error => {
+                    let err = result.expect_err("should fail on NaN");
+                    assert!(err.to_string().contains("float value is NaN"), "{}", err);
+                }
+                _ => assert_eq!(result?.rows, 5, "{:?}", on_non_finite_float),
+            }
+
+            let sql = match on_non_finite_float {
+                NonFiniteFloat::Error => format!("SELECT count(*) AS measured from {}", table_name),
+                _ => format!("SELECT measured from {} ORDER BY 1 NULLS LAST", table_name),
+            };
+            let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+            assert_eq!(
+                csv_string,
+                format!("measured\n{}", exp_rows),
+                "{:?}",
+                on_non_finite_float
+            );
+        }
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_failure() -> Result<()> {
+    async fn test_write_rows_str_to_xml() -> Result<()> {
         setup_docker();
-        let table_name = "test_connect_failure";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
-                                                                                 // Set up an invalid connection string.
-        let invalid_conn_str = "apples and pears";
+        let table_name = "test_write_rows_str_to_xml";
+        let client = create_table_return_client(table_name.to_string(), "xml").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["body"],
+            db_col_types: vec![PgType::XML],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "body",
+            vec![
+                Some("<order id=\"1\"><item>apple</item></order>"),
+                // xml content, not a document, which postgres takes by default
+                Some("<a/><b>text</b>"),
+                None,
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
 
-        // Attempt to connect.
-        let db = Db::connect(
-            invalid_conn_str,
-            table_name,
-            vec_stringify!["model", "gear"],
-            None,
-        )
-        .await;
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
 
-        // Ensure the connection fails by asserting it is an Err.
-        assert!(
-            db.is_err(),
-            "Connection should fail with an invalid connection string"
-        );
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!("SELECT body::text AS body from {} ORDER BY 1", table_name);
+        let exp_string = "\
+            body\n\
+            <a/><b>text</b>\n\
+            \"<order id=\"\"1\"\"><item>apple</item></order>\"\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str())
+            .await
+            .unwrap();
+        assert_eq!(csv_string, exp_string.to_string());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_invalid_tablename() -> Result<()> {
+    async fn test_write_rows_str_to_jsonb() -> Result<()> {
         setup_docker();
-        let table_name = "test_connect_invalid_tablename";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+        let table_name = "test_write_rows_str_to_jsonb";
+        let client = create_table_return_client(table_name.to_string(), "jsonb").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["doc"],
+            db_col_types: vec![PgType::JSONB],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "doc",
+            vec![
+                Some(r#"{"id": 1, "customer": {"name": "Ann"}}"#),
+                Some(r#"{"id": 2, "customer": {}}"#),
+                None,
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
 
-        // Attempt to connect.
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            "not_a_real_table",
-            vec_stringify!["model", "gear"],
-            None,
-        )
-        .await;
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::JSON, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
 
-        // Ensure the connection was successful by asserting it is Ok.
-        assert!(db.is_err(), "Should fail as table not in connected db");
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!(
+            "SELECT doc->>'id' AS id, doc->'customer'->>'name' AS name from {} ORDER BY 1",
+            table_name
+        );
+        let exp_string = "\
+            id,name\n\
+            1,Ann\n\
+            2,\n\
+            ,\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
+
+        // invalid json fails the file before the COPY's sent
+        let (tmp_dir, reader) = utf8_parquet_reader("doc", vec![Some(r#"{"id": 3,}"#)])?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let res = db.write_rows(row_iter, &[vec![0]], pq_data).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        let Err(e) = res else {
+            panic!("a trailing comma isn't json")
+        };
+        assert!(
+            format!("{:#}", e).contains(r#"value is not valid json: {"id": 3,}"#),
+            "unexpected error: {:#}",
+            e
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_col_name_not_exist() -> Result<()> {
+    async fn test_write_rows_xy_group_to_point() -> Result<()> {
         setup_docker();
-        let table_name = "test_connect_col_name_not_exist";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
+        let table_name = "test_write_rows_xy_group_to_point";
+        let client = create_table_return_client(table_name.to_string(), "point").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["id", "loc"],
+            db_col_types: vec![PgType::INT4, PgType::POINT],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
 
-        // Attempt to connect.
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            table_name,
-            vec_stringify!["model", "gear", "not_a_col"],
-            None,
-        )
-        .await;
+        let (tmp_dir, reader) =
+            xy_parquet_reader(vec![Some((1.5, -2.0)), None, Some((0.0, 51.25))])?;
+        let mut parquet = Parquet::new(
+            format!("{}/xy.parquet", tmp_dir.path().display()),
+            vec_stringify!["id", "loc"],
+        )?;
+        let (parquet_col_nums, pq_data) = parquet.get_desired_cols(&reader)?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let num_rows_added = db.write_rows(row_iter, &parquet_col_nums, &pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(num_rows_added.rows, 3);
+        assert!(num_rows_added.null_substitutions.is_empty());
 
-        // Ensure the connection was successful by asserting it is Ok.
-        assert!(db.is_err(), "Should fail as col not in table");
+        let sql = format!(
+            "SELECT id, loc[0] AS x, loc[1] AS y FROM {} ORDER BY id",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "id,x,y\n1,1.5,-2\n2,,\n3,0,51.25\n");
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_col_name_has_alias() -> Result<()> {
+    async fn test_write_rows_str_to_tsvector() -> Result<()> {
         setup_docker();
-        let table_name = "test_connect_col_name_has_alias";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
-
-        let aliases: HashMap<String, Option<String>> = HashMap::from([
-            ("i.model".to_string(), Some("model".to_string())),
-            ("num_of_gears".to_string(), Some("gear".to_string())),
-        ]);
-
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            table_name,
-            vec_stringify!["i.model", "num_of_gears"], // desired cols from parquet
-            Some(aliases),                             // map of parquet col names to db table cols
-        )
-        .await;
+        let table_name = "test_write_rows_str_to_tsvector";
+        let client = create_table_return_client(table_name.to_string(), "tsvector").await?;
+        let db = Db {
+            client,
+            db_cols: vec_stringify!["doc"],
+            db_col_types: vec![PgType::TS_VECTOR],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let Err(e) = db.with_write_mode(WriteMode::Copy, 1000) else {
+            panic!("COPY can't write a tsvector")
+        };
+        assert_eq!(
+            e.to_string(),
+            "col doc is a tsvector, so needs db.write_mode: insert, as COPY can't call to_tsvector"
+        );
 
-        let res = db.as_ref().unwrap();
+        let client = create_table_return_client(table_name.to_string(), "tsvector").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["doc"],
+            db_col_types: vec![PgType::TS_VECTOR],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        }
+        .with_write_mode(WriteMode::Insert, 2)?;
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "doc",
+            vec![
+                Some("The quick brown fox jumps"),
+                Some("over the lazy dogs"),
+                None,
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
 
-        assert!(db.is_ok(), "Should pass as aliases are all cols in db");
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        // a dry run takes the text, as the insert does
+        let checked = db
+            .check_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+            .await?;
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
 
-        let exp_db_col_names_for_desired_fields = vec_stringify!["model", "gear"];
-        assert_eq!(res.db_cols, exp_db_col_names_for_desired_fields);
+        assert_eq!(checked.rows, 3);
+        assert_eq!(num_rows_added.rows, 3);
+        // stemmed by the default text search config, so "dog" finds "dogs"
+        let sql = format!(
+            "SELECT doc::text AS doc from {} WHERE doc @@ to_tsquery('dog')",
+            table_name
+        );
+        let exp_string = "\
+            doc\n\
+            'dog':4 'lazi':3\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
 
-        let exp_db_types = [PgType::VARCHAR, PgType::INT4];
-        assert_eq!(res.db_col_types, exp_db_types);
+        let sql = format!(
+            "SELECT count(*)::text AS n from {} WHERE doc IS NULL",
+            table_name
+        );
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, "n\n1\n");
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_db_col_name_has_none_alias_but_parquet_name_is_same() -> Result<()> {
+    async fn test_write_rows_str_and_bytes_to_uuid() -> Result<()> {
         setup_docker();
-        let table_name = "test_connect_db_col_name_has_none_alias_but_parquet_name_is_same";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
-
-        let aliases: HashMap<String, Option<String>> = HashMap::from([
-            ("model".to_string(), None), // should use delivery_id
-            ("num_of_gears".to_string(), Some("gear".to_string())), // will use alias even if same
-        ]);
-
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            table_name,
-            vec_stringify!["model", "num_of_gears"], // desired cols from parquet
-            Some(aliases),                           // map of parquet col names to db table cols
-        )
-        .await;
+        let table_name = "test_write_rows_str_and_bytes_to_uuid";
+        let client = create_table_return_client(table_name.to_string(), "uuid").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["id"],
+            db_col_types: vec![PgType::UUID],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
 
-        let res = db.as_ref().unwrap();
+        // as strings
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "id",
+            vec![
+                Some("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                Some("A0EEBC999C0B4EF8BB6D6BB9BD380A11"),
+                None,
+            ],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(num_rows_added.rows, 3);
 
-        assert!(db.is_ok(), "Should pass as aliases are all cols in db");
+        // as 16 raw bytes
+        let (tmp_dir, reader) = fixed_16_parquet_reader(
+            "id",
+            vec![Some([
+                0xf8, 0x1d, 0x4f, 0xae, 0x7d, 0xec, 0x11, 0xd0, 0xa7, 0x65, 0x00, 0xa0, 0xc9, 0x1e,
+                0x6b, 0xf6,
+            ])],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let pq_data: &[PqTypeData] = &[(PqType::FIXED_LEN_BYTE_ARRAY, ConvertedType::NONE, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        assert_eq!(num_rows_added.rows, 1);
 
-        let exp_db_col_names_for_desired_fields = vec_stringify!["model", "gear"];
-        assert_eq!(res.db_cols, exp_db_col_names_for_desired_fields);
+        let sql = format!(
+            "SELECT id::text AS id from {} ORDER BY 1 NULLS LAST",
+            table_name
+        );
+        let exp_string = "\
+            id\n\
+            67e55044-10b1-426f-9247-bb680e5fe0c8\n\
+            a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11\n\
+            f81d4fae-7dec-11d0-a765-00a0c91e6bf6\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
 
-        let exp_db_types = [PgType::VARCHAR, PgType::INT4];
-        assert_eq!(res.db_col_types, exp_db_types);
+        // a malformed one fails the file
+        let (tmp_dir, reader) = utf8_parquet_reader("id", vec![Some("not-a-uuid")])?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let res = db.write_rows(row_iter, &[vec![0]], pq_data).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        let Err(e) = res else {
+            panic!("not-a-uuid isn't a uuid")
+        };
+        assert!(
+            format!("{:#}", e).contains("value is not a uuid: not-a-uuid"),
+            "unexpected error: {:#}",
+            e
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_db_col_name_has_none_alias_but_parquet_name_is_not_same_as_db_col(
-    ) -> Result<()> {
+    async fn test_write_rows_str_to_macaddr() -> Result<()> {
         setup_docker();
-        let table_name =
-            "test_connect_db_col_name_has_none_alias_but_parquet_name_is_not_same_as_db_col";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
-
-        let aliases: HashMap<String, Option<String>> = HashMap::from([
-            ("model".to_string(), None),
-            ("num_of_gears".to_string(), None), // the db col is actually gear, so should have had alias
-        ]);
+        let table_name = "test_write_rows_str_to_macaddr";
+        let client = create_table_return_client(table_name.to_string(), "macaddr").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["mac"],
+            db_col_types: vec![PgType::MACADDR],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader(
+            "mac",
+            vec![Some("08:00:2b:01:02:03"), Some("AA-BB-CC-DD-EE-FF"), None],
+        )?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
 
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            table_name,
-            vec_stringify!["model", "num_of_gears"], // desired cols from parquet
-            Some(aliases),                           // map of parquet col names to db table cols
-        )
-        .await;
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let num_rows_added = db.write_rows(row_iter, &[vec![0]], pq_data).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
 
-        assert!(
-            db.is_err(),
-            "Should fail as no db col alias given for parquet field d_id, and no col with same name as parquet field"
+        assert_eq!(num_rows_added.rows, 3);
+        let sql = format!(
+            "SELECT mac::text AS mac from {} ORDER BY 1 NULLS LAST",
+            table_name
         );
+        let exp_string = "\
+            mac\n\
+            08:00:2b:01:02:03\n\
+            aa:bb:cc:dd:ee:ff\n\
+            \"\"\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        assert_eq!(csv_string, exp_string.to_string());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_connect_given_db_col_alias_does_not_exist() -> Result<()> {
+    async fn test_write_rows_str_to_macaddr_malformed() -> Result<()> {
         setup_docker();
-        let table_name = "test_connect_given_db_col_alias_does_not_exist";
-        let _ = create_table_return_client(table_name.to_string(), "car").await; // create table
-
-        let aliases: HashMap<String, Option<String>> = HashMap::from([
-            ("model".to_string(), Some("not_a_col".to_string())), // not_a_col doesn't exist in db
-            ("num_of_gears".to_string(), Some("gear".to_string())),
-        ]);
+        let table_name = "test_write_rows_str_to_macaddr_malformed";
+        let client = create_table_return_client(table_name.to_string(), "macaddr").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["mac"],
+            db_col_types: vec![PgType::MACADDR],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) =
+            utf8_parquet_reader("mac", vec![Some("08:00:2b:01:02:03"), Some("08:00:2b")])?;
+        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
 
-        let db = Db::connect(
-            GOOD_DB_CONN_STR,
-            table_name,
-            vec_stringify!["model", "num_of_gears"],
-            Some(aliases),
-        )
-        .await;
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let res = db.write_rows(row_iter, &[vec![0]], pq_data).await;
+        let sql = format!("SELECT count(*) AS total from {}", table_name);
+        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str()).await?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
 
+        let Err(e) = res else {
+            panic!("a malformed MAC address should be rejected")
+        };
         assert!(
-            db.is_err(),
-            "Should fail as alias 'not_a_col' is not a col in the db"
+            format!("{:#}", e).contains("not a MAC address"),
+            "unexpected error: {:#}",
+            e
         );
+        assert_eq!(csv_string, "total\n0\n", "the file's rows are rolled back");
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_write_rows_happy_path() -> Result<()> {
+    async fn test_write_rows_str_to_xml_malformed() -> Result<()> {
         setup_docker();
-        let table_name = "test_write_rows_happy_path";
-        let db = default_db_struct_for_cars_table(table_name, "car")
-            .await
-            .unwrap();
-        let (tmp_dir, reader) = parquet_cars_reader().await.unwrap();
-
+        let table_name = "test_write_rows_str_to_xml_malformed";
+        let client = create_table_return_client(table_name.to_string(), "xml").await?;
+        let mut db = Db {
+            client,
+            db_cols: vec_stringify!["body"],
+            db_col_types: vec![PgType::XML],
+            table_name: table_name.to_string(),
+            upsert: None,
+            load_mode: LoadMode::Append,
+            notices: no_notices(),
+            on_non_finite_float: NonFiniteFloat::Pass,
+            on_type_mismatch: TypeMismatch::Warn,
+            base64_decode: vec![],
+            decrypt: vec![],
+            assume_timezone: vec![],
+            field_types: vec![],
+            date_formats: None,
+            fills: vec![],
+            load_metadata: LoadMetadata::default(),
+            copy_clients: vec![],
+            audit_sql: AuditSql::default(),
+            insert_batch_size: None,
+            bitmasks: vec![],
+            max_rows_per_copy: None,
+            copy_timeout: None,
+            row_filter: None,
+            all_fields: None,
+            rejects: None,
+            schema: None,
+        };
+        let (tmp_dir, reader) = utf8_parquet_reader("body", vec![Some("<order><item></order>")])?;
         let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
 
-        // PQ cols:     model       cyl         mpg         gear
-        // col no.      0           2           1           10
-        // types:       BYTE_ARRAY  INT32       DOUBLE      INT32
-        // converted:   UTF8        NONE|INT_32 NONE        NONE|INT_32
-        let col_nums = vec![0, 2, 1, 10];
-        let pq_data: &[PqTypeData] = &[
-            (PqType::BYTE_ARRAY, ConvertedType::UTF8),
-            (PqType::INT32, ConvertedType::INT_32),
-            (PqType::DOUBLE, ConvertedType::NONE),
-            (PqType::INT32, ConvertedType::NONE),
-        ];
-        let num_rows_added = db.write_rows(row_iter, &col_nums, pq_data).await?;
+        let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+        let res = db.write_rows(row_iter, &[vec![0]], pq_data).await;
         tmp_dir.close().unwrap(); // can be deleted as read what we need
 
-        assert_eq!(num_rows_added, 32);
-        let sql = format!("SELECT * from {} ORDER by model DESC LIMIT 2", table_name);
-        let exp_string = "\
-            model,miles_per_gallon,num_of_cyl,disp,hp,drat,wt,qsec,vs,am,gear,carb\n\
-            Volvo 142E,21.4,4,,,,,,,,4,\n\
-            Valiant,18.1,6,,,,,,,,3,\n\
-        ";
-        let csv_string = get_rows_as_csv_string(&db.client, sql.as_str())
-            .await
-            .unwrap();
-        assert_eq!(csv_string, exp_string.to_string());
+        assert!(res.is_err(), "postgres should reject unbalanced xml tags");
+
+        Ok(())
+    }
+
+    // Not run by default, as it's a benchmark, not a test. In a release build, with:
+    //   cargo test --release -- --ignored bench_string_reads --nocapture
+    // For PERFORMANCE in README.md: how long a string col's reads take, i.e. the record
+    // reader's String per value, and our clone of it, next to the whole load, COPY and all.
+    // Once with few distinct values, so dictionary encoded throughout, and once all distinct,
+    // as writers fall back to plain encoding once a row group's dictionary is over 1MB.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_string_reads() -> Result<()> {
+        setup_docker();
+        const ROWS: usize = 500_000;
+        for (fixture, distinct) in [("low cardinality", 16), ("high cardinality", ROWS)] {
+            let table_name = "bench_string_reads";
+            let client = create_table_return_client(table_name.to_string(), "delivery").await?;
+            let mut db = Db::connect(
+                GOOD_DB_CONN_STR,
+                &SslMode::Disable,
+                None,
+                &ConnectRetry::default(),
+                table_name,
+                vec_stringify!["body"],
+                None,
+                &NamingStrategy::Identity,
+                None,
+            )
+            .await?;
+            let values: Vec<String> = (0..ROWS)
+                .map(|i| format!("customer-{:08}", i % distinct))
+                .collect();
+            let (tmp_dir, reader) =
+                utf8_parquet_reader("body", values.iter().map(|v| Some(v.as_str())).collect())?;
+
+            let started = std::time::Instant::now();
+            let mut read_bytes: usize = 0;
+            for row in reader.get_row_iter(None)? {
+                if let Field::Str(value) = row?.get_column_iter().next().unwrap().1 {
+                    read_bytes += value.clone().len();
+                }
+            }
+            let reads = started.elapsed();
+
+            let started = std::time::Instant::now();
+            let pq_data: &[PqTypeData] = &[(PqType::BYTE_ARRAY, ConvertedType::UTF8, None)];
+            let written = db
+                .write_rows(reader.get_row_iter(None)?, &[vec![0]], pq_data)
+                .await?;
+            let load = started.elapsed();
+            tmp_dir.close().unwrap(); // can be deleted as read what we need
+            client
+                .batch_execute(format!("DROP TABLE {}", table_name).as_str())
+                .await?;
+
+            assert_eq!(written.rows, ROWS as u64);
+            assert_eq!(read_bytes, ROWS * "customer-00000000".len());
+            println!(
+                "{} ({} distinct), {} rows: reads {:?}, whole load {:?}, so reads are {:.0}% of it",
+                fixture,
+                distinct,
+                ROWS,
+                reads,
+                load,
+                100.0 * reads.as_secs_f64() / load.as_secs_f64()
+            );
+        }
 
         Ok(())
     }
@@ -563,32 +7440,5 @@ mod tests {
 
             Ok(())
         }
-
-        #[tokio::test]
-        async fn test_write_rows_quoted_db_cols_happy_path() -> Result<()> {
-            setup_docker();
-            let table_name = "test_write_rows_quoted_db_cols_happy_path";
-            let db = default_db_struct_for_iris_table(table_name).await.unwrap();
-            let (tmp_dir, reader) = parquet_iris_reader().await.unwrap();
-            let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None).unwrap();
-
-            let col_nums = vec![4, 0, 1]; // col numbers in parquet, in the order we write the data
-            let num_rows_added = db.write_rows(row_iter, &col_nums).await?;
-            tmp_dir.close().unwrap(); // can be deleted as read what we need
-
-            assert_eq!(num_rows_added, 150);
-            let sql = format!("SELECT * from {} ORDER by variety DESC LIMIT 2", table_name);
-            let exp_string = "\
-                sepal.length,sepal.width,petal.length,petal.width,variety\n\
-                6.3,3.3,,,Virginica\n\
-                5.8,2.7,,,Virginica\n\
-            ";
-            let csv_string = get_rows_as_csv_string(&db.client, sql.as_str())
-                .await
-                .unwrap();
-            assert_eq!(csv_string, exp_string.to_string());
-
-            Ok(())
-        }
     */
 }
@@ -1,12 +1,16 @@
 use anyhow::Result;
 
+mod audit_sql;
 mod cmd_args;
 mod config;
 mod converters;
 mod db;
 mod parquet_ops;
+mod rejects;
 mod runner;
 mod s3_download;
+mod sqs_source;
+mod tls;
 mod work_lists;
 
 #[cfg(test)]
@@ -15,9 +19,37 @@ mod test_setup; // for docker-compose setup
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
+    // checked before anything else, as these need no other args
+    if cmd_args::version(|| std::env::args().collect::<Vec<String>>()) {
+        println!("{}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if cmd_args::help(|| std::env::args().collect::<Vec<String>>()) {
+        println!("{}", cmd_args::USAGE);
+        return Ok(());
+    }
+    // schema subcommand needs no config, so check for it before asking for one
+    if let Some(parquet_file) = cmd_args::schema_file(|| std::env::args().collect::<Vec<String>>())?
+    {
+        print!(
+            "{}",
+            parquet_ops::schema_with_converted_types(&parquet_file)?
+        );
+        return Ok(());
+    }
     // do this in main as command line arg collection only relevant to binary
     let cfg_file: String = cmd_args::config_yaml(|| std::env::args().collect::<Vec<String>>())?;
+    let dry_run: bool = cmd_args::dry_run(|| std::env::args().collect::<Vec<String>>());
 
-    runner::run(cfg_file.as_str()).await?;
+    if cmd_args::reconcile(|| std::env::args().collect::<Vec<String>>()) {
+        runner::reconcile(cfg_file.as_str()).await?;
+        return Ok(());
+    }
+    // validating the copy checks all a dry run does, and more
+    if cmd_args::validate_copy(|| std::env::args().collect::<Vec<String>>()) {
+        runner::validate_copy(cfg_file.as_str()).await?;
+        return Ok(());
+    }
+    runner::run(cfg_file.as_str(), dry_run, None).await?;
     Ok(())
 }
@@ -1,16 +1,81 @@
 use anyhow::{anyhow, Result};
-use parquet::basic::{ConvertedType, Type as PqType};
-use parquet::file::reader::{FileReader, SerializedFileReader};
-use parquet::schema::types::Type;
+use arrow_schema::{DataType, Schema as ArrowSchema, TimeUnit};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use parquet::arrow::ARROW_SCHEMA_META_KEY;
+use parquet::basic::{
+    Compression, ConvertedType, LogicalType, Repetition, TimeUnit as PqTimeUnit, Type as PqType,
+};
+use parquet::file::metadata::{FileMetaData, ParquetMetaData, RowGroupMetaData};
+use parquet::file::properties::{ReaderProperties, ReaderPropertiesPtr};
+use parquet::file::reader::{FileReader, RowGroupReader, SerializedFileReader};
+use parquet::file::serialized_reader::SerializedRowGroupReader;
+use parquet::format::KeyValue;
+use parquet::record::reader::RowIter;
+use parquet::schema::types::{SchemaDescriptor, Type, TypePtr};
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Write};
 use std::fs::File;
 use std::path::Path;
+use std::sync::Arc;
 
-pub type PqTypeData = (PqType, ConvertedType);
+use crate::config::{Codec, SchemaPolicy, ALL_FIELDS};
+
+// The Int64Unit is only set for INT64 columns whose converted type doesn't say what they count
+pub type PqTypeData = (PqType, ConvertedType, Option<Int64Unit>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Int64Unit {
+    Duration(TimeUnit), // an arrow duration, see duration_unit()
+    TimestampNanos,     // a TIMESTAMP(NANOS), which has no converted type
+}
+
+// Column numbers from the top level down to a field, e.g. [2, 0] for the first field of the
+// group that's the third top level column. A top level field's is just its column number.
+pub type ColPath = Vec<usize>;
+
+// A primitive (leaf) field, found at its dotted path e.g. address.city
+#[derive(Debug, Clone)]
+struct LeafField {
+    path: String,
+    col_path: ColPath,
+    physical_type: PqType,
+    converted_type: ConvertedType,
+    timestamp_nanos: bool,
+}
+
+// Leaf fields by dotted path, and by bare name. More than one under a name means it's ambiguous.
+type FieldMap = HashMap<String, Vec<LeafField>>;
+
+// An absent optional field's col path. There's no column to read, so it's always null.
+pub const ABSENT: ColPath = Vec::new();
+
+// A file with a column compressed by a codec not in parquet.allowed_codecs.
+// The runner quarantines the file, rather than failing it, as no retry will help.
+#[derive(Debug)]
+pub struct DisallowedCodec {
+    pub column: String,
+    pub codec: Compression,
+}
+
+impl fmt::Display for DisallowedCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "column {} is compressed with {}, which parquet.allowed_codecs doesn't allow",
+            self.column, self.codec
+        )
+    }
+}
+
+impl Error for DisallowedCodec {}
 
 pub struct Parquet {
     pub filename: String,
     pub desired_fields: Vec<String>,
+    pub optional_fields: Vec<String>, // desired fields the file may not have
+    pub allowed_codecs: Option<Vec<Codec>>, // any codec if None
+    pub schema_policy: SchemaPolicy,
 }
 
 impl Parquet {
@@ -19,66 +84,567 @@ impl Parquet {
         Ok(Parquet {
             filename,
             desired_fields,
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
         })
     }
 
+    pub fn with_optional_fields(mut self, optional_fields: Vec<String>) -> Self {
+        self.optional_fields = optional_fields;
+        self
+    }
+
+    pub fn with_allowed_codecs(mut self, allowed_codecs: Option<Vec<Codec>>) -> Self {
+        self.allowed_codecs = allowed_codecs;
+        self
+    }
+
+    pub fn with_schema_policy(mut self, schema_policy: SchemaPolicy) -> Self {
+        self.schema_policy = schema_policy;
+        self
+    }
+
+    // For parquet.schema_policy strict and lenient: errors if the file has fields that aren't
+    // read, i.e. at or under one of the col paths read. Lists and maps included.
+    pub fn check_unread_fields(
+        &self,
+        reader: &SerializedFileReader<File>,
+        read: &[ColPath],
+    ) -> Result<()> {
+        if self.schema_policy == SchemaPolicy::Superset {
+            return Ok(());
+        }
+        let mut leaves: Vec<(String, ColPath)> = Vec::new();
+        leaf_fields(
+            reader.metadata().file_metadata().schema(),
+            "",
+            &[],
+            &mut leaves,
+        );
+        let unread: Vec<String> = leaves
+            .into_iter()
+            .filter(|(_, col_path)| {
+                !read
+                    .iter()
+                    .any(|r| !r.is_empty() && col_path.starts_with(r))
+            })
+            .map(|(path, _)| path)
+            .collect();
+        if !unread.is_empty() {
+            let policy = format!("{:?}", self.schema_policy).to_lowercase();
+            return Err(anyhow!(
+                "file has fields that aren't read, which parquet.schema_policy {} doesn't allow: {}",
+                policy,
+                unread.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    // For parquet.allowed_codecs: errors with DisallowedCodec on the first column chunk,
+    // in any row group, compressed with a codec not allowed. Reads only the metadata.
+    pub fn check_codecs(&self, reader: &SerializedFileReader<File>) -> Result<()> {
+        let Some(allowed_codecs) = &self.allowed_codecs else {
+            return Ok(());
+        };
+        for row_group in reader.metadata().row_groups() {
+            for column in row_group.columns() {
+                if !allowed_codecs.contains(&codec_of(column.compression())) {
+                    return Err(DisallowedCodec {
+                        column: column.column_path().string(),
+                        codec: column.compression(),
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn file_reader(&self) -> Result<SerializedFileReader<File>> {
         let file = File::open(Path::new(&self.filename))?;
         let reader = SerializedFileReader::new(file)?;
         Ok(reader)
     }
 
+    // The file's rows. Read through RowGroups, rather than file_reader()'s get_row_iter.
+    pub fn row_iter(&self) -> Result<RowIter<'static>> {
+        let num_row_groups = self.file_reader()?.num_row_groups();
+        self.row_groups_iter((0..num_row_groups).collect())
+    }
+
+    // The rows of just the row groups given, by their index in the file
+    pub fn row_groups_iter(&self, row_groups: Vec<usize>) -> Result<RowIter<'static>> {
+        let reader = RowGroups::new(&self.filename, &row_groups)?;
+        Ok(RowIter::from_file_into(Box::new(reader)))
+    }
+
+    // Each desired field is a dotted path from the top level e.g. address.city, or a bare
+    // field name e.g. city, as long as only one group has a field of that name.
+    // An optional field the file doesn't have gets the ABSENT col path.
     pub fn get_desired_cols(
         &mut self,
         reader: &SerializedFileReader<File>,
-    ) -> Result<(Vec<usize>, Vec<PqTypeData>)> {
-        let schema: &Type = reader.metadata().file_metadata().schema();
+    ) -> Result<(Vec<ColPath>, Vec<PqTypeData>)> {
+        let file_metadata = reader.metadata().file_metadata();
+        let schema: &Type = file_metadata.schema();
+        let arrow_schema: Option<ArrowSchema> =
+            embedded_arrow_schema(file_metadata.key_value_metadata());
 
-        let mut desired_cols: Vec<usize> = Vec::with_capacity(self.desired_fields.len());
+        let mut desired_cols: Vec<ColPath> = Vec::with_capacity(self.desired_fields.len());
         let mut pq_type_data: Vec<PqTypeData> = Vec::with_capacity(self.desired_fields.len());
 
-        let field_map: &mut HashMap<String, (usize, parquet::basic::Type, ConvertedType)> =
-            &mut HashMap::new();
-        Self::map_fields_to_parquet_metadata(field_map, schema, 0, 0); // populate field_map with parquet metadata
+        let (by_path, by_name) = Self::field_maps(schema);
+        if self.desired_fields.iter().any(|f| f == ALL_FIELDS) {
+            if self.desired_fields.len() > 1 {
+                return Err(anyhow!(
+                    "desired field * is every field, so is ambiguous with others: {}",
+                    self.desired_fields.join(", ")
+                ));
+            }
+            self.desired_fields = all_fields(&by_path);
+        }
 
         for field in self.desired_fields.clone() {
-            let (col_num, physical_type, converted_type) = field_map
-                .get(&field)
-                .ok_or_else(|| anyhow!("Field '{}' not found in field_map", field))?;
+            let absent = !by_path.contains_key(&field) && !by_name.contains_key(&field);
+            let may_be_absent = self.schema_policy != SchemaPolicy::Strict;
+            if absent && may_be_absent && self.optional_fields.contains(&field) {
+                desired_cols.push(ABSENT);
+                // BOOLEAN's converter takes any field as is, so writes the nulls into any col type
+                pq_type_data.push((PqType::BOOLEAN, ConvertedType::NONE, None));
+                continue;
+            }
+            let leaf = Self::find_field(&by_path, &by_name, &field)?;
 
-            desired_cols.push(*col_num);
-            pq_type_data.push((*physical_type, *converted_type));
+            desired_cols.push(leaf.col_path.clone());
+            let int64_unit = match leaf.timestamp_nanos {
+                true => Some(Int64Unit::TimestampNanos),
+                false => duration_unit(arrow_schema.as_ref(), &leaf.path).map(Int64Unit::Duration),
+            };
+            pq_type_data.push((leaf.physical_type, leaf.converted_type, int64_unit));
         }
 
         Ok((desired_cols, pq_type_data))
     }
 
+    // The col path of one field, found as a desired field is, e.g. for parquet.row_filter
+    pub fn find_col(&self, reader: &SerializedFileReader<File>, field: &str) -> Result<ColPath> {
+        let (by_path, by_name) = Self::field_maps(reader.metadata().file_metadata().schema());
+        Ok(Self::find_field(&by_path, &by_name, field)?
+            .col_path
+            .clone())
+    }
+
+    fn field_maps(schema: &Type) -> (FieldMap, FieldMap) {
+        let mut by_path: FieldMap = HashMap::new();
+        let mut by_name: FieldMap = HashMap::new();
+        // populate the maps with parquet metadata
+        Self::map_fields_to_parquet_metadata(&mut by_path, &mut by_name, schema, "", &[]);
+        (by_path, by_name)
+    }
+
     fn map_fields_to_parquet_metadata(
-        field_map: &mut HashMap<String, (usize, parquet::basic::Type, ConvertedType)>,
-        schema: &Type,
-        _depth: usize,
-        col_num: usize,
+        by_path: &mut FieldMap,
+        by_name: &mut FieldMap,
+        group: &Type,
+        group_path: &str,
+        group_col_path: &[usize],
     ) {
-        let name = schema.name();
-
-        match schema {
-            Type::PrimitiveType {
-                basic_info,
-                physical_type,
-                ..
-            } => {
-                field_map.insert(
-                    String::from(name),
-                    (col_num, *physical_type, basic_info.converted_type()),
-                );
+        for (col_num, field) in group.get_fields().iter().enumerate() {
+            let name = field.name();
+            let path = match group_path {
+                "" => String::from(name),
+                _ => format!("{}.{}", group_path, name),
+            };
+            let mut col_path = group_col_path.to_vec();
+            col_path.push(col_num);
+
+            match field.as_ref() {
+                Type::PrimitiveType {
+                    basic_info,
+                    physical_type,
+                    ..
+                } => {
+                    // a repeated primitive (not in a list group) is read as a list of its values.
+                    // LIST is only ever a group's converted type, so marks it for the converter.
+                    let converted_type = match basic_info.repetition() {
+                        Repetition::REPEATED => ConvertedType::LIST,
+                        _ => basic_info.converted_type(),
+                    };
+                    let timestamp_nanos = matches!(
+                        basic_info.logical_type(),
+                        Some(LogicalType::Timestamp {
+                            unit: PqTimeUnit::NANOS(_),
+                            ..
+                        })
+                    );
+                    let leaf = LeafField {
+                        path: path.clone(),
+                        col_path,
+                        physical_type: *physical_type,
+                        converted_type,
+                        timestamp_nanos,
+                    };
+                    by_path.entry(path).or_default().push(leaf.clone());
+                    by_name.entry(String::from(name)).or_default().push(leaf);
+                }
+                // a list or map is read as one value, so there are no fields to go down to
+                Type::GroupType { basic_info, .. }
+                    if matches!(
+                        basic_info.converted_type(),
+                        ConvertedType::LIST | ConvertedType::MAP | ConvertedType::MAP_KEY_VALUE
+                    ) || (basic_info.has_repetition()
+                        && basic_info.repetition() == Repetition::REPEATED) => {}
+                Type::GroupType { .. } => {
+                    // picked whole, its converter takes the group apart
+                    if Self::is_xy_group(field) {
+                        let leaf = LeafField {
+                            path: path.clone(),
+                            col_path: col_path.clone(),
+                            physical_type: PqType::DOUBLE,
+                            converted_type: ConvertedType::NONE,
+                            timestamp_nanos: false,
+                        };
+                        by_path.entry(path.clone()).or_default().push(leaf.clone());
+                        by_name.entry(String::from(name)).or_default().push(leaf);
+                    }
+                    Self::map_fields_to_parquet_metadata(by_path, by_name, field, &path, &col_path);
+                }
+            };
+        }
+    }
+
+    // A group of just x and y FLOAT or DOUBLE fields, in any order, e.g. for a POINT col.
+    // It can be picked as a field, as well as its x and y.
+    fn is_xy_group(group: &Type) -> bool {
+        let fields = group.get_fields();
+        let mut names: Vec<&str> = fields.iter().map(|f| f.name()).collect();
+        names.sort_unstable();
+        names == ["x", "y"]
+            && fields.iter().all(|f| {
+                f.is_primitive()
+                    && matches!(f.get_physical_type(), PqType::FLOAT | PqType::DOUBLE)
+                    && !(f.get_basic_info().has_repetition()
+                        && f.get_basic_info().repetition() == Repetition::REPEATED)
+            })
+    }
+
+    // A dotted path wins over a bare name, so a top level field can always be picked,
+    // even if named like a path, e.g. sepal.length
+    fn find_field<'a>(
+        by_path: &'a FieldMap,
+        by_name: &'a FieldMap,
+        field: &str,
+    ) -> Result<&'a LeafField> {
+        let found = by_path
+            .get(field)
+            .or_else(|| by_name.get(field))
+            .ok_or_else(|| anyhow!("Field '{}' not found in field_map", field))?;
+
+        match found.as_slice() {
+            [leaf] => Ok(leaf),
+            _ => {
+                let paths: Vec<&str> = found.iter().map(|leaf| leaf.path.as_str()).collect();
+                Err(anyhow!(
+                    "Field '{}' is ambiguous, as it matches {}. Use a dotted path from the top level, that only one field has",
+                    field,
+                    paths.join(", ")
+                ))
             }
-            Type::GroupType { .. } => {}
+        }
+    }
+}
+
+// For desired field *: every field that can be desired, in schema order. A group that can be
+// picked whole, e.g. x and y for a POINT, is left to its own fields.
+fn all_fields(by_path: &FieldMap) -> Vec<String> {
+    let mut leaves: Vec<&LeafField> = by_path.values().flatten().collect();
+    leaves.sort_by(|a, b| a.col_path.cmp(&b.col_path));
+    leaves
+        .iter()
+        .filter(|leaf| {
+            !leaves.iter().any(|other| {
+                other.col_path.len() > leaf.col_path.len()
+                    && other.col_path.starts_with(&leaf.col_path)
+            })
+        })
+        .map(|leaf| leaf.path.clone())
+        .collect()
+}
+
+// Every primitive field under group, with its dotted path and col path, even in a list or map
+fn leaf_fields(
+    group: &Type,
+    group_path: &str,
+    group_col_path: &[usize],
+    leaves: &mut Vec<(String, ColPath)>,
+) {
+    for (col_num, field) in group.get_fields().iter().enumerate() {
+        let path = match group_path {
+            "" => String::from(field.name()),
+            _ => format!("{}.{}", group_path, field.name()),
         };
+        let mut col_path = group_col_path.to_vec();
+        col_path.push(col_num);
+        match field.is_primitive() {
+            true => leaves.push((path, col_path)),
+            false => leaf_fields(field, &path, &col_path, leaves),
+        }
+    }
+}
+
+// Some of a file's row groups, with the schema as parquet's record reader can read it.
+// It has no conversion for INT32 TIME_MILLIS or INT64 TIME_MICROS, and panics on one. So
+// those fields have their converted type dropped here, to come through as Ints or Longs.
+// get_desired_cols reads the file's own schema, so their converters still go by it.
+// Repeated primitives outside a list group are wrapped in one, see as_list.
+struct RowGroups {
+    file: Arc<File>,
+    metadata: ParquetMetaData,
+    props: ReaderPropertiesPtr,
+}
+
+impl RowGroups {
+    fn new(filename: &str, row_groups: &[usize]) -> Result<Self> {
+        let file = File::open(Path::new(filename))?;
+        let reader = SerializedFileReader::new(file.try_clone()?)?;
+        let file_metadata = reader.metadata().file_metadata();
 
-        if schema.is_group() {
-            for (column_num, column) in schema.get_fields().iter().enumerate() {
-                Self::map_fields_to_parquet_metadata(field_map, column, _depth + 1, column_num);
+        let schema_descr = Arc::new(SchemaDescriptor::new(readable_schema(
+            &file_metadata.schema_descr().root_schema_ptr(),
+        )?));
+        let mut metadata: Vec<RowGroupMetaData> = Vec::with_capacity(row_groups.len());
+        for i in row_groups {
+            let Some(row_group) = reader.metadata().row_groups().get(*i) else {
+                return Err(anyhow!("{} has no row group {}", filename, i));
+            };
+            metadata.push(RowGroupMetaData::from_thrift(
+                schema_descr.clone(),
+                row_group.to_thrift(),
+            )?);
+        }
+        let file_metadata = FileMetaData::new(
+            file_metadata.version(),
+            metadata.iter().map(|row_group| row_group.num_rows()).sum(),
+            file_metadata.created_by().map(String::from),
+            file_metadata.key_value_metadata().cloned(),
+            schema_descr,
+            file_metadata.column_orders().cloned(),
+        );
+
+        Ok(RowGroups {
+            file: Arc::new(file),
+            metadata: ParquetMetaData::new(file_metadata, metadata),
+            props: Arc::new(ReaderProperties::builder().build()),
+        })
+    }
+}
+
+impl FileReader for RowGroups {
+    fn metadata(&self) -> &ParquetMetaData {
+        &self.metadata
+    }
+
+    fn num_row_groups(&self) -> usize {
+        self.metadata.num_row_groups()
+    }
+
+    fn get_row_group(&self, i: usize) -> parquet::errors::Result<Box<dyn RowGroupReader + '_>> {
+        Ok(Box::new(SerializedRowGroupReader::new(
+            self.file.clone(),
+            self.metadata.row_group(i),
+            None,
+            self.props.clone(),
+        )?))
+    }
+
+    fn get_row_iter(&self, projection: Option<Type>) -> parquet::errors::Result<RowIter<'_>> {
+        RowIter::from_file(projection, self)
+    }
+}
+
+// The schema, with the converted type of any TIME_MILLIS or TIME_MICROS field dropped,
+// and repeated primitives wrapped as lists, see RowGroups
+fn readable_schema(schema: &TypePtr) -> Result<TypePtr> {
+    let info = schema.get_basic_info();
+    let id = info.has_id().then(|| info.id());
+    let readable = match schema.as_ref() {
+        Type::PrimitiveType { physical_type, .. }
+            if matches!(
+                info.converted_type(),
+                ConvertedType::TIME_MILLIS | ConvertedType::TIME_MICROS
+            ) =>
+        {
+            Type::primitive_type_builder(schema.name(), *physical_type)
+                .with_repetition(info.repetition())
+                .with_id(id)
+                .build()?
+        }
+        Type::PrimitiveType { .. } => return Ok(schema.clone()),
+        Type::GroupType { fields, .. } => {
+            let in_list_or_map = info.converted_type() != ConvertedType::NONE;
+            let fields = fields
+                .iter()
+                .map(|field| match field.get_basic_info().repetition() {
+                    Repetition::REPEATED if field.is_primitive() && !in_list_or_map => {
+                        as_list(field)
+                    }
+                    _ => readable_schema(field),
+                })
+                .collect::<Result<Vec<TypePtr>>>()?;
+            let mut group = Type::group_type_builder(schema.name())
+                .with_converted_type(info.converted_type())
+                .with_logical_type(info.logical_type())
+                .with_id(id)
+                .with_fields(fields);
+            if info.has_repetition() {
+                group = group.with_repetition(info.repetition());
             }
+            group.build()?
+        }
+    };
+    Ok(Arc::new(readable))
+}
+
+// A repeated primitive outside a list group, e.g. `repeated int32 scores;`, wrapped in one:
+// `required group scores (LIST) { repeated int32 scores; }`. The record reader reads it
+// bare as a single value, taking values from the rows after. Wrapped, it's read as a list.
+// The levels are the same, as a required group adds none.
+fn as_list(field: &TypePtr) -> Result<TypePtr> {
+    let list = Type::group_type_builder(field.name())
+        .with_repetition(Repetition::REQUIRED)
+        .with_converted_type(ConvertedType::LIST)
+        .with_fields(vec![readable_schema(field)?])
+        .build()?;
+    Ok(Arc::new(list))
+}
+
+// The codec as parquet.allowed_codecs names it, without any level
+fn codec_of(compression: Compression) -> Codec {
+    match compression {
+        Compression::UNCOMPRESSED => Codec::Uncompressed,
+        Compression::SNAPPY => Codec::Snappy,
+        Compression::GZIP(_) => Codec::Gzip,
+        Compression::LZO => Codec::Lzo,
+        Compression::BROTLI(_) => Codec::Brotli,
+        Compression::LZ4 => Codec::Lz4,
+        Compression::ZSTD(_) => Codec::Zstd,
+        Compression::LZ4_RAW => Codec::Lz4Raw,
+    }
+}
+
+// Whether the file opens as parquet, i.e. has a valid footer. A truncated download won't.
+pub fn is_parquet(filename: &str) -> bool {
+    File::open(filename)
+        .ok()
+        .and_then(|file| SerializedFileReader::new(file).ok())
+        .is_some()
+}
+
+// Arrow writers (pyarrow, arrow-rs, polars ...) embed their schema in the file's
+// key-value metadata, as a base64 encoded arrow IPC schema message.
+// It's only used to find durations, so a missing or unreadable schema just means there are none.
+fn embedded_arrow_schema(key_value_metadata: Option<&Vec<KeyValue>>) -> Option<ArrowSchema> {
+    let encoded = key_value_metadata?
+        .iter()
+        .find(|kv| kv.key == ARROW_SCHEMA_META_KEY)?
+        .value
+        .as_ref()?;
+    let bytes = BASE64_STANDARD.decode(encoded).ok()?;
+
+    // newer writers prefix the message with a continuation marker and its length
+    let message = match bytes.get(..4) {
+        Some([255, 255, 255, 255]) => bytes.get(8..)?,
+        _ => bytes.as_slice(),
+    };
+    let schema = arrow_ipc::root_as_message(message)
+        .ok()?
+        .header_as_schema()?;
+    Some(arrow_ipc::convert::fb_to_schema(schema))
+}
+
+// Parquet has no duration type of its own. Arrow writers store a duration as a plain INT64,
+// keeping the unit in their embedded schema, so that's where we look for it.
+// (parquet's own arrow reader ignores that hint, so reads them back as Int64)
+fn duration_unit(arrow_schema: Option<&ArrowSchema>, field_name: &str) -> Option<TimeUnit> {
+    match arrow_schema?.field_with_name(field_name).ok()?.data_type() {
+        DataType::Duration(unit) => Some(*unit),
+        _ => None,
+    }
+}
+
+// Returns the parquet file's schema as an indented tree, one field per line.
+// Leaf fields show their column number within their group and their physical type.
+pub fn schema_as_string(reader: &SerializedFileReader<File>) -> String {
+    let mut schema_str = String::new();
+    write_schema(
+        &mut schema_str,
+        reader.metadata().file_metadata().schema(),
+        0,
+        0,
+        false,
+    );
+    schema_str
+}
+
+// As schema_as_string, but leaf fields also show their converted type.
+// For the schema subcommand, to help write desired_fields and parquet_to_db.
+pub fn schema_with_converted_types(filename: &str) -> Result<String> {
+    let pq = Parquet::new(filename.to_string(), vec![])?;
+    let reader = pq.file_reader()?;
+    let mut schema_str = String::new();
+    write_schema(
+        &mut schema_str,
+        reader.metadata().file_metadata().schema(),
+        0,
+        0,
+        true,
+    );
+    Ok(schema_str)
+}
+
+fn write_schema(
+    schema_str: &mut String,
+    schema: &Type,
+    depth: usize,
+    col_num: usize,
+    with_converted_type: bool,
+) {
+    let name = schema.name();
+    let indent = " ".repeat(4 * depth);
+
+    // writeln! to a String can't fail, so ignore the fmt::Result
+    let _ = match schema {
+        Type::PrimitiveType { physical_type, .. } if with_converted_type => writeln!(
+            schema_str,
+            "{}{}) {} : {} / {}",
+            indent,
+            col_num,
+            name,
+            physical_type,
+            schema.get_basic_info().converted_type()
+        ),
+        Type::PrimitiveType { physical_type, .. } => {
+            writeln!(
+                schema_str,
+                "{}{}) {} : {}",
+                indent, col_num, name, physical_type
+            )
+        }
+        Type::GroupType { .. } => writeln!(schema_str, "{}{} is a group type", indent, name),
+    };
+
+    // for nested schema, where is list of other types
+    if schema.is_group() {
+        for (column_num, column) in schema.get_fields().iter().enumerate() {
+            write_schema(
+                schema_str,
+                column,
+                depth + 1,
+                column_num,
+                with_converted_type,
+            );
         }
     }
 }
@@ -94,7 +660,10 @@ mod tests {
     use std::env;
     use std::fs::File;
 
-    use crate::test_setup::tests::LOCALSTACK_PARQUET_DIR_IRIS;
+    use crate::test_setup::tests::{
+        duration_parquet_reader, nested_parquet_reader, repeated_parquet_reader,
+        timestamp_nanos_parquet_reader, xy_parquet_reader, LOCALSTACK_PARQUET_DIR_IRIS,
+    };
 
     static TESTDATA_DIR: &str = formatcp!(
         "{}/{}",
@@ -110,6 +679,41 @@ mod tests {
         type_name::<T>()
     }
 
+    #[test]
+    fn test_check_codecs() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir
+            .copy_from(TESTDATA_DIR, &["gzip_codec.parquet"])
+            .unwrap();
+        let parquet_file = format!("{}/gzip_codec.parquet", tmp_dir.path().display());
+        let reader = SerializedFileReader::new(File::open(&parquet_file)?)?;
+        let with_allowed = |allowed_codecs: Option<Vec<Codec>>| {
+            Parquet::new(parquet_file.clone(), vec_stringify!["id", "name"])
+                .unwrap()
+                .with_allowed_codecs(allowed_codecs)
+        };
+
+        let result = with_allowed(Some(vec![Codec::Snappy, Codec::Zstd])).check_codecs(&reader);
+        let allowed_gzip = with_allowed(Some(vec![Codec::Gzip])).check_codecs(&reader);
+        let allowed_any = with_allowed(None).check_codecs(&reader);
+        tmp_dir.close().unwrap();
+
+        let Err(e) = result else {
+            panic!("gzip isn't allowed")
+        };
+        assert!(e.downcast_ref::<DisallowedCodec>().is_some());
+        assert!(
+            e.to_string()
+                .starts_with("column id is compressed with GZIP"),
+            "unexpected error: {}",
+            e
+        );
+        assert!(allowed_gzip.is_ok());
+        assert!(allowed_any.is_ok());
+
+        Ok(())
+    }
+
     fn test_reader_iris_file(
         desired_fields: Vec<String>,
     ) -> Result<(TempDir, Parquet, SerializedFileReader<File>)> {
@@ -122,6 +726,9 @@ mod tests {
         let pq = Parquet {
             filename: parquet_file,
             desired_fields,
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
         };
         let reader = pq.file_reader().unwrap();
 
@@ -147,6 +754,9 @@ mod tests {
         let pq = Parquet {
             filename: "/no/such/file".to_string(),
             desired_fields: vec_stringify!["field_A"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
         };
         let result = pq.file_reader();
         assert!(result.is_err(), "Should fail as file does not exist");
@@ -164,6 +774,9 @@ mod tests {
         let pq = Parquet {
             filename: parquet_file.clone(),
             desired_fields: vec_stringify!["field_A"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
         };
 
         {
@@ -185,6 +798,9 @@ mod tests {
         let pq = Parquet {
             filename: parquet_file.clone(),
             desired_fields: vec_stringify!["field_A"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
         };
 
         {
@@ -206,6 +822,9 @@ mod tests {
         let pq = Parquet {
             filename: parquet_file.clone(),
             desired_fields: vec_stringify!["field_A"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
         };
 
         {
@@ -226,7 +845,93 @@ mod tests {
 
         assert!(result.is_ok(), "should find variety field in iris.parquet");
         let (col_nums, _) = result.unwrap();
-        assert_eq!(col_nums, vec![4, 0]); // can see col order in PARQUET META at end of file
+        assert_eq!(col_nums, vec![vec![4], vec![0]]); // can see col order in PARQUET META at end of file
+    }
+
+    #[test]
+    fn test_check_unread_fields_schema_policy() {
+        let desired_fields = vec_stringify!["variety", "sepal.length"];
+        let (tmp_dir, mut pq, reader) = test_reader_iris_file(desired_fields).unwrap();
+        let (col_nums, _) = pq.get_desired_cols(&reader).unwrap();
+        // reading them all leaves nothing unread
+        let all = vec![vec![0], vec![1], vec![2], vec![3], vec![4]];
+
+        let superset = pq.check_unread_fields(&reader, &col_nums);
+        pq.schema_policy = SchemaPolicy::Lenient;
+        let lenient = pq.check_unread_fields(&reader, &col_nums);
+        pq.schema_policy = SchemaPolicy::Strict;
+        let strict = pq.check_unread_fields(&reader, &col_nums);
+        let strict_all = pq.check_unread_fields(&reader, &all);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(superset.is_ok());
+        let err = lenient.unwrap_err().to_string();
+        assert!(err.contains("schema_policy lenient"), "{}", err);
+        assert!(
+            err.contains("sepal.width, petal.length, petal.width"),
+            "{}",
+            err
+        );
+        assert!(strict.is_err());
+        assert!(strict_all.is_ok());
+    }
+
+    #[test]
+    fn test_get_desired_cols_absent_optional_field_schema_policy() {
+        let desired_fields = vec_stringify!["variety", "does.not.exist"];
+        let (tmp_dir, pq, reader) = test_reader_iris_file(desired_fields).unwrap();
+        let mut pq = pq
+            .with_optional_fields(vec_stringify!["does.not.exist"])
+            .with_schema_policy(SchemaPolicy::Lenient);
+
+        let lenient = pq.get_desired_cols(&reader);
+        pq.schema_policy = SchemaPolicy::Strict;
+        let strict = pq.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(lenient.is_ok());
+        assert!(strict.is_err(), "strict reads optional fields too");
+    }
+
+    #[test]
+    fn test_get_desired_cols_all_fields() {
+        let (tmp_dir, mut pq, reader) = test_reader_iris_file(vec_stringify!["*"]).unwrap();
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+
+        let (col_nums, pq_type_data) = result.unwrap();
+        assert_eq!(col_nums, vec![vec![0], vec![1], vec![2], vec![3], vec![4]]);
+        assert_eq!(pq_type_data.len(), 5);
+        assert_eq!(
+            pq.desired_fields,
+            vec_stringify![
+                "sepal.length",
+                "sepal.width",
+                "petal.length",
+                "petal.width",
+                "variety"
+            ]
+        );
+
+        // * with another field is ambiguous
+        let mut ambiguous =
+            Parquet::new(pq.filename.clone(), vec_stringify!["*", "variety"]).unwrap();
+        assert!(ambiguous.get_desired_cols(&reader).is_err());
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+    }
+
+    #[test]
+    fn test_find_col() {
+        let (tmp_dir, pq, reader) = test_reader_iris_file(vec_stringify!["variety"]).unwrap();
+
+        // needn't be a desired field
+        let found = pq.find_col(&reader, "sepal.length");
+        let missing = pq.find_col(&reader, "does.not.exist");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert_eq!(found.unwrap(), vec![0]);
+        assert!(missing.is_err());
     }
 
     #[test]
@@ -244,6 +949,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_desired_cols_absent_optional_field() {
+        let desired_fields = vec_stringify!["variety", "does.not.exist", "sepal.length"];
+        let (tmp_dir, pq, reader) = test_reader_iris_file(desired_fields).unwrap();
+        let mut pq = pq.with_optional_fields(vec_stringify!["does.not.exist", "variety"]);
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        // variety is optional, but there, so is read as usual
+        let (col_nums, _) = result.unwrap();
+        assert_eq!(col_nums, vec![vec![4], ABSENT, vec![0]]);
+    }
+
     #[test]
     fn test_get_desired_cols_same_field_duplicated_is_fine() {
         let desired_fields = vec_stringify!["variety", "sepal.length", "variety"];
@@ -255,7 +975,259 @@ mod tests {
 
         assert!(result.is_ok(), "should find variety field in iris.parquet");
         let (col_nums, _) = result.unwrap();
-        assert_eq!(col_nums, vec![4, 0, 4]); // can see col order in PARQUET META at end of file
+        assert_eq!(col_nums, vec![vec![4], vec![0], vec![4]]); // can see col order in PARQUET META at end of file
+    }
+
+    #[test]
+    fn test_get_desired_cols_special_chars_in_field_names() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir
+            .copy_from(TESTDATA_DIR, &["special_field_names.parquet"])
+            .unwrap();
+        let parquet_file = format!("{}/special_field_names.parquet", tmp_dir.path().display());
+
+        let mut pq = Parquet {
+            filename: parquet_file,
+            desired_fields: vec_stringify!["full name", "order-id"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+        let reader = pq.file_reader().unwrap();
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(
+            result.is_ok(),
+            "should find fields with a space and a hyphen in the name"
+        );
+        let (col_nums, pq_type_data) = result.unwrap();
+        assert_eq!(col_nums, vec![vec![1], vec![0]]);
+        assert_eq!(
+            pq_type_data,
+            vec![
+                (PqType::BYTE_ARRAY, ConvertedType::UTF8, None),
+                (PqType::INT64, ConvertedType::NONE, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_desired_cols_nested_fields() {
+        let (tmp_dir, reader) = nested_parquet_reader().unwrap();
+        let mut pq = Parquet {
+            filename: String::from("nested.parquet"),
+            desired_fields: vec_stringify!["address.city", "zip", "billing.city", "id"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+
+        let mut pq_ambiguous = Parquet {
+            filename: String::from("nested.parquet"),
+            desired_fields: vec_stringify!["city"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+        let result_ambiguous = pq_ambiguous.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        let (col_nums, pq_type_data) = result.unwrap();
+        assert_eq!(col_nums, vec![vec![1, 0], vec![1, 1], vec![2, 0], vec![0]]);
+        assert_eq!(
+            pq_type_data[0],
+            (PqType::BYTE_ARRAY, ConvertedType::UTF8, None)
+        );
+        assert_eq!(pq_type_data[3], (PqType::INT32, ConvertedType::NONE, None));
+
+        let Err(e) = result_ambiguous else {
+            panic!("city is in both address and billing, so should be ambiguous")
+        };
+        assert!(
+            e.to_string().contains("matches address.city, billing.city"),
+            "unexpected error: {}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_get_desired_cols_xy_group() -> Result<()> {
+        let (tmp_dir, reader) = xy_parquet_reader(vec![Some((1.5, -2.0))])?;
+        let mut pq = Parquet {
+            filename: String::from("xy.parquet"),
+            desired_fields: vec_stringify!["loc", "loc.x"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+
+        // a group with anything besides x and y can't be picked whole
+        let (nested_tmp_dir, nested_reader) = nested_parquet_reader()?;
+        let mut pq_not_xy = Parquet {
+            filename: String::from("nested.parquet"),
+            desired_fields: vec_stringify!["address"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+        let result_not_xy = pq_not_xy.get_desired_cols(&nested_reader);
+        tmp_dir.close()?; // can be deleted as read what we need
+        nested_tmp_dir.close()?;
+
+        // the group is loc itself, and x is its second field
+        let (col_nums, pq_type_data) = result?;
+        assert_eq!(col_nums, vec![vec![1], vec![1, 1]]);
+        assert_eq!(
+            pq_type_data,
+            vec![
+                (PqType::DOUBLE, ConvertedType::NONE, None),
+                (PqType::DOUBLE, ConvertedType::NONE, None)
+            ]
+        );
+        assert!(
+            result_not_xy.is_err(),
+            "address has city and zip, not x and y"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_desired_cols_repeated_primitive() {
+        let (tmp_dir, reader) = repeated_parquet_reader().unwrap();
+        let mut pq = Parquet {
+            filename: String::from("repeated.parquet"),
+            desired_fields: vec_stringify!["id", "scores"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        let (col_nums, pq_type_data) = result.unwrap();
+        assert_eq!(col_nums, vec![vec![0], vec![1]]);
+        assert_eq!(pq_type_data[0], (PqType::INT32, ConvertedType::NONE, None));
+        assert_eq!(
+            pq_type_data[1],
+            (PqType::INT32, ConvertedType::LIST, None),
+            "repeated primitive should be marked as a list"
+        );
+    }
+
+    #[test]
+    fn test_get_desired_cols_arrow_duration_has_unit() {
+        let (tmp_dir, reader) =
+            duration_parquet_reader("took", TimeUnit::Millisecond, vec![Some(1500)]).unwrap();
+        let mut pq = Parquet {
+            filename: String::from("duration.parquet"),
+            desired_fields: vec_stringify!["took"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        let (_, pq_type_data) = result.unwrap();
+        assert_eq!(
+            pq_type_data,
+            vec![(
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::Duration(TimeUnit::Millisecond))
+            )],
+            "the unit should come from the embedded arrow schema"
+        );
+    }
+
+    #[test]
+    fn test_get_desired_cols_timestamp_nanos() {
+        let (tmp_dir, reader) =
+            timestamp_nanos_parquet_reader("happened_at", vec![Some(1_500)]).unwrap();
+        let mut pq = Parquet {
+            filename: String::from("timestamp_nanos.parquet"),
+            desired_fields: vec_stringify!["happened_at"],
+            optional_fields: vec![],
+            allowed_codecs: None,
+            schema_policy: SchemaPolicy::Superset,
+        };
+
+        // test method
+        let result = pq.get_desired_cols(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        let (_, pq_type_data) = result.unwrap();
+        assert_eq!(
+            pq_type_data,
+            vec![(
+                PqType::INT64,
+                ConvertedType::NONE,
+                Some(Int64Unit::TimestampNanos)
+            )],
+            "nanos have no converted type, so should come from the logical type"
+        );
+    }
+
+    #[test]
+    fn test_schema_as_string_iris() {
+        let (tmp_dir, _, reader) = test_reader_iris_file(vec_stringify!["variety"]).unwrap();
+
+        // test function
+        let schema_str = schema_as_string(&reader);
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(
+            schema_str.starts_with("duckdb_schema is a group type\n"),
+            "root of schema should be the message group"
+        );
+        assert!(
+            schema_str.contains("    0) sepal.length : DOUBLE\n"),
+            "should show sepal.length as DOUBLE, got:\n{}",
+            schema_str
+        );
+        assert!(
+            schema_str.contains("    4) variety : BYTE_ARRAY\n"),
+            "should show variety as BYTE_ARRAY, got:\n{}",
+            schema_str
+        );
+    }
+
+    #[test]
+    fn test_schema_with_converted_types_iris() {
+        let (tmp_dir, pq, _) = test_reader_iris_file(vec_stringify!["variety"]).unwrap();
+
+        // test function
+        let schema_str = schema_with_converted_types(&pq.filename).unwrap();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+
+        assert!(
+            schema_str.contains("    0) sepal.length : DOUBLE / NONE\n"),
+            "should show sepal.length as DOUBLE with no converted type, got:\n{}",
+            schema_str
+        );
+        assert!(
+            schema_str.contains("    4) variety : BYTE_ARRAY / UTF8\n"),
+            "should show variety as BYTE_ARRAY UTF8, got:\n{}",
+            schema_str
+        );
+    }
+
+    #[test]
+    fn test_schema_with_converted_types_no_such_file() {
+        assert!(schema_with_converted_types("/no/such/file.parquet").is_err());
     }
 }
 /*
@@ -0,0 +1,122 @@
+use anyhow::{bail, Context, Result};
+use parquet::record::Field;
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// For runner.quarantine_dir: a file's rows that won't convert, written to <dir>/<key>.jsonl
+// instead of failing the file. One json object per row, with why it was rejected and its
+// fields as read from the parquet. The file's only created once there's a row for it, and
+// one left by an earlier attempt at the key is removed, so it's never stale.
+// Every connection copying the file's rows writes to the same one, each line written whole.
+#[derive(Debug)]
+pub struct Rejects {
+    path: PathBuf,
+    field_names: Vec<String>, // of the fields in each row written, in order
+    file: Mutex<Option<File>>,
+    count: AtomicU64,
+}
+
+impl Rejects {
+    pub fn new(dir: &str, key: &str, field_names: &[String]) -> Result<Self> {
+        // the key's own dirs are kept under dir, so it mustn't lead out of it
+        if !Path::new(key)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!(
+                "runner.quarantine_dir can't hold rows of {}, as it's not a plain relative path",
+                key
+            );
+        }
+        let path = Path::new(dir).join(format!("{}.jsonl", key));
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("can't remove {}", path.display()))
+            }
+            _ => {}
+        }
+        Ok(Rejects {
+            path,
+            field_names: field_names.to_vec(),
+            file: Mutex::new(None),
+            count: AtomicU64::new(0),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    // fields are the row's, in the order of field_names
+    pub fn write(&self, fields: &[Field], error: &str) -> Result<()> {
+        let row: Map<String, Value> = self
+            .field_names
+            .iter()
+            .cloned()
+            .zip(fields.iter().map(Field::to_json_value))
+            .collect();
+        let line = format!("{}\n", json!({ "error": error, "row": row }));
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("can't create quarantine dir {}", dir.display()))?;
+            }
+            *file = Some(
+                File::create(&self.path)
+                    .with_context(|| format!("can't create {}", self.path.display()))?,
+            );
+        }
+        if let Some(file) = file.as_mut() {
+            file.write_all(line.as_bytes())
+                .with_context(|| format!("can't write to {}", self.path.display()))?;
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::fixture::TempDir;
+
+    #[test]
+    fn test_write() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = format!("{}/rejects", tmp_dir.path().display());
+        let field_names = vec!["id".to_string(), "name".to_string()];
+
+        let rejects = Rejects::new(&dir, "2024/orders.parquet", &field_names)?;
+        assert!(!rejects.path().exists(), "not created until there's a row");
+        rejects.write(&[Field::Int(7), Field::Null], "bad row")?;
+        rejects.write(&[Field::Int(8), Field::Str("b".to_string())], "another")?;
+
+        let written = fs::read_to_string(format!("{}/2024/orders.parquet.jsonl", dir))?;
+        assert_eq!(
+            written,
+            "{\"error\":\"bad row\",\"row\":{\"id\":7,\"name\":null}}\n\
+             {\"error\":\"another\",\"row\":{\"id\":8,\"name\":\"b\"}}\n"
+        );
+        assert_eq!(rejects.count(), 2);
+
+        // a retry of the key that rejects no rows leaves none behind
+        let retry = Rejects::new(&dir, "2024/orders.parquet", &field_names)?;
+        assert!(!retry.path().exists(), "earlier attempt's file removed");
+        tmp_dir.close().unwrap();
+
+        for key in ["../orders.parquet", "/orders.parquet"] {
+            assert!(Rejects::new(&dir, key, &field_names).is_err(), "{}", key);
+        }
+        Ok(())
+    }
+}
@@ -1,163 +1,2160 @@
-use anyhow::Result;
-use log::{debug, info};
+use anyhow::{bail, Context, Result};
+use futures::future::join_all;
+use log::{debug, error, info, warn};
 use parquet::file::reader::FileReader;
+use serde::{Serialize, Serializer};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
 
 // don't need crate::cmd_args, as only handles things for binary
+use crate::audit_sql::AuditSql;
 use crate::config;
 use crate::db;
 use crate::parquet_ops;
+use crate::rejects::Rejects;
 use crate::s3_download;
+use crate::sqs_source;
 use crate::work_lists;
 
 fn cfg(cfg_file: &str) -> Result<config::Config> {
-    let cfg: config::Config = config::Config::from_yaml_file(cfg_file)?;
+    let cfg: config::Config = match cfg_file {
+        config::STDIN => config::Config::from_reader(std::io::stdin())?,
+        _ => config::Config::from_yaml_file(cfg_file)?,
+    };
     Ok(cfg)
 }
 
+// Both checks download and convert every pending file, but leave the db and work lists be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunMode {
+    Load,
+    DryRun,       // serialise every value for its db col, but never start a COPY
+    ValidateCopy, // COPY every row in a transaction that's always rolled back
+}
+
+// For anything run from another program, e.g. to show a progress bar.
+// Sent as each file is started, then again once its rows are written. Counts are for the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub file: String,      // s3 key of the file
+    pub rows_written: u64, // rows committed to the db
+    pub total_rows: u64,   // rows in the files started, from their parquet metadata
+}
+
+// The run's totals, logged once it's done, whether it succeeded or not.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub files_processed: u64, // loaded, or checked on a dry run or validate copy
+    pub rows_written: u64,    // rows committed to the db
+    pub files_failed: u64,    // skipped, or the one(s) that stopped the run
+    #[serde(rename = "elapsed_secs", serialize_with = "secs_to_millis")]
+    pub elapsed: Duration,
+}
+
+// A duration as seconds, to the millisecond
+fn secs_to_millis<S: Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(elapsed.as_millis() as f64 / 1000.0)
+}
+
+impl RunSummary {
+    // for runner.summary_json
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+// Keeps the run's counts for the progress events, if anyone wants them, and the summary.
+// Unbounded, so a slow receiver never holds up a load.
+struct RunProgress {
+    sender: Option<UnboundedSender<Progress>>,
+    rows_written: Cell<u64>,
+    total_rows: Cell<u64>,
+    files_processed: Cell<u64>,
+    files_failed: Cell<u64>,
+    started: Instant,
+}
+
+impl RunProgress {
+    fn new(sender: Option<UnboundedSender<Progress>>) -> Self {
+        RunProgress {
+            sender,
+            rows_written: Cell::new(0),
+            total_rows: Cell::new(0),
+            files_processed: Cell::new(0),
+            files_failed: Cell::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    fn file_processed(&self) {
+        self.files_processed.set(self.files_processed.get() + 1);
+    }
+
+    fn file_failed(&self) {
+        self.files_failed.set(self.files_failed.get() + 1);
+    }
+
+    fn summary(&self) -> RunSummary {
+        RunSummary {
+            files_processed: self.files_processed.get(),
+            rows_written: self.rows_written.get(),
+            files_failed: self.files_failed.get(),
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    fn add(&self, file: &str, rows_written: u64, total_rows: u64) {
+        self.rows_written
+            .set(self.rows_written.get() + rows_written);
+        self.total_rows.set(self.total_rows.get() + total_rows);
+        if let Some(sender) = &self.sender {
+            // receiver gone means no one is watching, which needn't stop the load
+            let _ = sender.send(Progress {
+                file: file.to_string(),
+                rows_written: self.rows_written.get(),
+                total_rows: self.total_rows.get(),
+            });
+        }
+    }
+}
+
+// Requested on SIGINT or SIGTERM. The run stops pulling batches and starting files,
+// but each file already loading finishes, and is marked completed, so nothing's left half done.
+#[derive(Debug, Clone, Default)]
+struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// How often a poll's sleep checks for shutdown, so a stop needn't wait out the interval
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+// What a load does once todo's done
+enum WhenIdle {
+    Exit,                       // the run's over
+    Poll(Duration),             // sleep, then look in todo again
+    Sqs(sqs_source::SqsSource), // wait on the queue for more
+}
+
+impl WhenIdle {
+    // Ok(true) to look for another batch, Ok(false) to end the run
+    async fn more_work(
+        &mut self,
+        work_lists: &work_lists::WorkLists,
+        shutdown: &Shutdown,
+    ) -> Result<bool> {
+        match self {
+            WhenIdle::Exit => Ok(false),
+            WhenIdle::Poll(interval) => {
+                debug!("todo's done, checking again in {}s", interval.as_secs());
+                let until = Instant::now() + *interval;
+                while !shutdown.is_requested() && Instant::now() < until {
+                    let left = until.saturating_duration_since(Instant::now());
+                    tokio::time::sleep(left.min(SHUTDOWN_CHECK_INTERVAL)).await;
+                }
+                Ok(true)
+            }
+            // None means the queue's drained
+            WhenIdle::Sqs(sqs_source) => match sqs_source.receive().await? {
+                None => Ok(false),
+                Some(keys) => {
+                    let (added, already_done) = work_lists.add_todo(&keys)?;
+                    info!("{} new file(s) to load from sqs", added);
+                    // a message received again for keys done with has nothing left to wait on
+                    sqs_source.delete_done(&already_done).await?;
+                    Ok(true)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn sigterm() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(e) => {
+            warn!(
+                "can't listen for SIGTERM, so only SIGINT stops the run cleanly: {}",
+                e
+            );
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn sigterm() {
+    std::future::pending::<()>().await;
+}
+
+// Requests shutdown on the first SIGINT or SIGTERM. Once listening, neither kills the
+// process any more, so a second one exits straight away, for a run that won't stop.
+fn shutdown_on_signal(shutdown: Shutdown) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        for _ in 0..2 {
+            let signal_name = tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm() => "SIGTERM",
+            };
+            if shutdown.is_requested() {
+                error!(
+                    "{} received again, so exiting without finishing",
+                    signal_name
+                );
+                std::process::exit(130);
+            }
+            warn!(
+                "{} received, so stopping once the file(s) loading now are done. Again to exit at once",
+                signal_name
+            );
+            shutdown.request();
+        }
+    })
+}
+
+// For parquet.row_filter, so a file's rows add up to those written and those skipped
+fn log_rows_skipped(file: &str, written: &db::RowsWritten) {
+    if written.rows_skipped > 0 {
+        info!(
+            "{}: {} rows skipped, as parquet.row_filter doesn't keep them",
+            file, written.rows_skipped
+        );
+    }
+}
+
+// source_key is the file's s3 key, for a db.load_metadata source_key_column
+// or a db.extra_columns source_file.
+// Returns the rows added, or copied or checked for the other modes.
+#[allow(clippy::too_many_arguments)]
 async fn parquet_rows_to_db(
+    source_key: &str,
     downloaded_file: String,
     desired_fields: Vec<String>,
-    db: &db::Db,
-) -> Result<()> {
-    let mut parquet = parquet_ops::Parquet::new(downloaded_file.clone(), desired_fields.clone())?;
+    optional_fields: Vec<String>,
+    allowed_codecs: Option<Vec<config::Codec>>,
+    schema_policy: config::SchemaPolicy,
+    quarantine_dir: Option<&str>,
+    db: &mut db::Db,
+    mode: RunMode,
+    progress: &RunProgress,
+) -> Result<u64> {
+    db.set_source_key(source_key);
+    // the bitmask cols' bool fields are read after the desired fields
+    let parquet_fields: Vec<String> = desired_fields
+        .into_iter()
+        .chain(db.bitmask_fields())
+        .collect();
+    let mut parquet = parquet_ops::Parquet::new(downloaded_file.clone(), parquet_fields)?
+        .with_optional_fields(optional_fields)
+        .with_allowed_codecs(allowed_codecs)
+        .with_schema_policy(schema_policy);
 
     // block controls that parquet file (dowloaded_file) is guaranteed closed at end of this scope
     {
         let reader = parquet.file_reader()?;
+        parquet.check_codecs(&reader)?; // before reading any rows
+        debug!(
+            "{}: parquet schema:\n{}",
+            downloaded_file,
+            parquet_ops::schema_as_string(&reader)
+        );
 
         debug!("{}: ... finding desired columns positions", downloaded_file);
         let (parquet_col_nums, pq_type_data) = parquet.get_desired_cols(&reader)?;
+        // for desired field *, the cols are those of the fields this file has
+        db.set_fields(&parquet.desired_fields)?;
+        // parquet.row_filter's field needn't be a desired field, so is found on its own
+        if let Some(column) = db.row_filter.as_ref().map(|f| f.column.clone()) {
+            db.set_row_filter_col_path(parquet.find_col(&reader, &column)?);
+        }
+        let read: Vec<parquet_ops::ColPath> = parquet_col_nums
+            .iter()
+            .cloned()
+            .chain(db.row_filter.as_ref().map(|f| f.col_path.clone()))
+            .collect();
+        parquet.check_unread_fields(&reader, &read)?;
+
+        debug!("{}: ... reading parquet rows", downloaded_file);
+        let row_iter: parquet::record::reader::RowIter = parquet.row_iter()?;
+
+        if mode == RunMode::ValidateCopy {
+            info!(
+                "{}: ... copying rows to db, to be rolled back",
+                downloaded_file
+            );
+            let result = db
+                .validate_rows(row_iter, &parquet_col_nums, &pq_type_data)
+                .await;
+            db.warn_notices(&downloaded_file); // may explain a failure too
+            let copied = result?;
+            copied.warn_null_substitutions(&downloaded_file);
+            log_rows_skipped(&downloaded_file, &copied);
+
+            info!(
+                "{}: VALIDATE COPY - {} rows copied, then rolled back",
+                downloaded_file, copied.rows
+            );
+            return Ok(copied.rows);
+        }
+
+        if mode == RunMode::DryRun {
+            info!("{}: ... checking rows convert for db", downloaded_file);
+            let checked = db
+                .check_rows(row_iter, &parquet_col_nums, &pq_type_data)
+                .await?;
+            checked.warn_null_substitutions(&downloaded_file);
+            log_rows_skipped(&downloaded_file, &checked);
+
+            info!(
+                "{}: DRY RUN - {} rows would have been added to db",
+                downloaded_file, checked.rows
+            );
+            return Ok(checked.rows);
+        }
+
+        // for runner.quarantine_dir, a file of this one's rejected rows, replaced each attempt
+        let rejects = match quarantine_dir {
+            Some(dir) => Some(Rejects::new(dir, source_key, &parquet.desired_fields)?),
+            None => None,
+        };
+        db.set_rejects(rejects);
+
+        progress.add(
+            source_key,
+            0,
+            reader.metadata().file_metadata().num_rows() as u64,
+        );
+        info!("{}: ... writing rows to db", downloaded_file);
+        let result = db
+            .write_row_groups(&parquet, &parquet_col_nums, &pq_type_data)
+            .await;
+        db.warn_notices(&downloaded_file); // may explain a failure too
+        let added = result?;
+        added.warn_null_substitutions(&downloaded_file);
+        log_rows_skipped(&downloaded_file, &added);
+        if let Some(rejects) = db.rejects.as_ref().filter(|r| r.count() > 0) {
+            warn!(
+                "{}: {} rows couldn't be written, so went to {}",
+                downloaded_file,
+                rejects.count(),
+                rejects.path().display()
+            );
+        }
+        progress.add(source_key, added.rows, 0);
+
+        info!(
+            "{}: {} rows added to db successfully",
+            downloaded_file, added.rows
+        );
+        Ok(added.rows)
+    } // shouldn't be anything still keeping the downloaded file open now - free to delete
+}
+
+// A dry run downloads and converts every pending file, but writes nothing to the db
+// and leaves the work lists untouched (bar a todo generated from s3, if there was none).
+// progress, if given, is sent the run's progress as each file loads. Not sent on a dry run.
+// Returns the run's totals, also logged at the end.
+pub async fn run(
+    cfg_file: &str,
+    dry_run: bool,
+    progress: Option<UnboundedSender<Progress>>,
+) -> Result<RunSummary> {
+    match dry_run {
+        true => run_mode(cfg_file, RunMode::DryRun, None).await,
+        false => run_mode(cfg_file, RunMode::Load, progress).await,
+    }
+}
+
+// Like a dry run, but each file's rows are COPYed into the table and rolled back,
+// so postgres itself checks them. Fails on the first file it rejects.
+pub async fn validate_copy(cfg_file: &str) -> Result<RunSummary> {
+    run_mode(cfg_file, RunMode::ValidateCopy, None).await
+}
+
+async fn run_mode(
+    cfg_file: &str,
+    mode: RunMode,
+    progress: Option<UnboundedSender<Progress>>,
+) -> Result<RunSummary> {
+    let shutdown = Shutdown::default();
+    let signal_listener = shutdown_on_signal(shutdown.clone());
+    let result = run_mode_until(cfg_file, mode, progress, shutdown).await;
+    signal_listener.abort();
+    result
+}
+
+// As run_mode, but stops early once shutdown is requested
+async fn run_mode_until(
+    cfg_file: &str,
+    mode: RunMode,
+    progress: Option<UnboundedSender<Progress>>,
+    shutdown: Shutdown,
+) -> Result<RunSummary> {
+    info!("reading cfg file: [{}]", cfg_file);
+    let mut cfg = cfg(cfg_file)?;
+    let summary_json: bool = cfg.runner.summary_json;
+    let progress = RunProgress::new(progress);
+    // for db.load_metadata, the same for every row the run writes
+    let loaded_at = chrono::Utc::now();
+    let run_id = format!(
+        "{}-{}",
+        loaded_at.format("%Y%m%dT%H%M%S%.6fZ"),
+        std::process::id()
+    );
+
+    // so another run sharing downloads_dir can't overwrite or delete this one's files
+    let run_downloads_dir: Option<String> = match cfg.s3.downloads_per_run {
+        true => Some(format!("{}/{}", cfg.s3.downloads_dir, run_id)),
+        false => None,
+    };
+    if let Some(dir) = &run_downloads_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create downloads dir {}", dir))?;
+        cfg.s3.downloads_dir = dir.clone();
+    }
+
+    // for db.run_report_table, whatever the run's outcome. Jobs share the db config.
+    let report_db: config::DbConfig = cfg.db.clone();
+    // for db.analyze_after_load, each table once, though jobs may share one
+    let mut loaded_tables: Vec<String> = match cfg.jobs.is_empty() {
+        true => vec![cfg.db.table_name.clone()],
+        false => cfg.jobs.iter().map(|job| job.table_name.clone()).collect(),
+    };
+    loaded_tables.sort();
+    loaded_tables.dedup();
+    let result = process_jobs(cfg, mode, &progress, &shutdown, &run_id, loaded_at).await;
+    // only once every file's loaded, as a failed run's retried anyway
+    let result = match (result, mode, report_db.analyze_after_load) {
+        (Ok(()), RunMode::Load, true) => analyze_loaded(&report_db, &loaded_tables, &run_id)
+            .await
+            .context("failed to analyze the loaded tables, with db.analyze_after_load"),
+        (result, _, _) => result,
+    };
+    if shutdown.is_requested() {
+        warn!("run stopped early, on shutdown. The next run carries on from here");
+    }
+
+    // whether or not the run succeeded, as the next run won't look in here
+    if let Some(dir) = &run_downloads_dir {
+        debug!("removing this run's downloads dir {}", dir);
+        if let Err(e) = std::fs::remove_dir_all(dir) {
+            warn!("failed to remove this run's downloads dir {}: {}", dir, e);
+        }
+    }
+
+    let summary = progress.summary();
+    info!(
+        "run summary: {} file(s) processed, {} rows written, {} file(s) failed, in {:.3}s",
+        summary.files_processed,
+        summary.rows_written,
+        summary.files_failed,
+        summary.elapsed.as_secs_f64()
+    );
+    // to stdout, so it can be picked out from the logs
+    if summary_json {
+        match summary.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => warn!("Failed to write the run summary as json: {:?}", e),
+        }
+    }
+    // a dry run or validate copy writes nothing to the db, so isn't reported either
+    if let (Some(table), RunMode::Load) = (&report_db.run_report_table, mode) {
+        match (
+            &result,
+            report_run(&report_db, table, &run_id, &summary).await,
+        ) {
+            (_, Ok(())) => info!("run reported in {}", table),
+            (Ok(()), Err(e)) => {
+                return Err(e.context(format!("failed to report the run in {}", table)))
+            }
+            // the run's own error is the one to return
+            (Err(_), Err(e)) => error!("failed to report the run in {}: {:#}", table, e),
+        }
+    }
+    result.map(|()| summary)
+}
+
+// For db.analyze_after_load, once the run's succeeded
+async fn analyze_loaded(db_cfg: &config::DbConfig, tables: &[String], run_id: &str) -> Result<()> {
+    let connect_retry = db::ConnectRetry {
+        retries: db_cfg.connect_retries,
+        backoff_ms: db_cfg.connect_backoff_ms,
+    };
+    let audit_sql = AuditSql::new(db_cfg.audit_sql_file.as_deref(), run_id)?;
+    db::analyze_tables(
+        db_cfg.conn_str.as_str(),
+        &db_cfg.sslmode,
+        db_cfg.ca_cert.as_deref(),
+        &connect_retry,
+        tables,
+        db_cfg.vacuum_after_load,
+        &audit_sql,
+    )
+    .await?;
+    info!("analyzed {}", tables.join(", "));
+    Ok(())
+}
+
+// For db.run_report_table: a row with the run's totals, on a connection of its own
+async fn report_run(
+    db_cfg: &config::DbConfig,
+    table: &str,
+    run_id: &str,
+    summary: &RunSummary,
+) -> Result<()> {
+    let connect_retry = db::ConnectRetry {
+        retries: db_cfg.connect_retries,
+        backoff_ms: db_cfg.connect_backoff_ms,
+    };
+    let audit_sql = AuditSql::new(db_cfg.audit_sql_file.as_deref(), run_id)?;
+    let report = db::RunReport {
+        run_id,
+        files_processed: summary.files_processed,
+        rows_written: summary.rows_written,
+        files_failed: summary.files_failed,
+        elapsed_secs: summary.elapsed.as_secs_f64(),
+    };
+    db::insert_run_report(
+        db_cfg.conn_str.as_str(),
+        &db_cfg.sslmode,
+        db_cfg.ca_cert.as_deref(),
+        &connect_retry,
+        table,
+        &audit_sql,
+        &report,
+    )
+    .await
+}
+
+// Each of the config's jobs in turn, stopping at the first to fail. Without jobs, just the one.
+async fn process_jobs(
+    cfg: config::Config,
+    mode: RunMode,
+    progress: &RunProgress,
+    shutdown: &Shutdown,
+    run_id: &str,
+    loaded_at: chrono::DateTime<chrono::Utc>,
+) -> Result<()> {
+    let jobs: Vec<String> = cfg.jobs.iter().map(|job| job.name.clone()).collect();
+    if jobs.is_empty() {
+        return process_files(cfg, mode, progress, shutdown, run_id, loaded_at).await;
+    }
+    for (name, job_cfg) in jobs.iter().zip(cfg.job_configs()) {
+        if shutdown.is_requested() {
+            break;
+        }
+        info!("job {}: loading into table {}", name, job_cfg.db.table_name);
+        // a job's work lists go in their own dir, which may be new
+        let dir = job_cfg.work_lists.dir.clone();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create work lists dir {}", dir))?;
+        process_files(job_cfg, mode, progress, shutdown, run_id, loaded_at)
+            .await
+            .with_context(|| format!("job {} failed", name))?;
+    }
+    Ok(())
+}
+
+async fn process_files(
+    cfg: config::Config,
+    mode: RunMode,
+    progress: &RunProgress,
+    shutdown: &Shutdown,
+    run_id: &str,
+    loaded_at: chrono::DateTime<chrono::Utc>,
+) -> Result<()> {
+    // files to process
+    let work_lists_dir: &str = cfg.work_lists.dir.as_str();
+    let completed_flush_every: usize = cfg.work_lists.completed_flush_every;
+    let generate_from_s3: bool = cfg.work_lists.generate_from_s3;
+    let max_attempts: Option<u32> = cfg.work_lists.max_attempts;
+
+    // s3 downloads
+    let batch_size: usize = cfg.s3.download_batch_size;
+    let bucket_name = cfg.s3.bucket;
+    let output_dir = cfg.s3.downloads_dir;
+    let s3_prefix: String = cfg.s3.prefix.unwrap_or_default();
+    let receipt_prefix: Option<String> = cfg.s3.receipt_prefix;
+    let require_tags: HashMap<String, String> = cfg.s3.require_tags;
+    // deterministic runs also download one file at a time, in todo order
+    let deterministic: bool = cfg.runner.deterministic;
+    let download_concurrency: usize = match deterministic {
+        true => 1,
+        false => cfg.s3.download_concurrency,
+    };
+    // and load one file at a time
+    let parallel_files: usize = match deterministic {
+        true => 1,
+        false => cfg.runner.parallel_files,
+    };
+    if parallel_files < 1 {
+        bail!("runner.parallel_files must be at least 1");
+    }
+    let on_file_error = cfg.runner.on_file_error;
+    let parse_error_redownloads: u32 = cfg.runner.parse_error_redownloads;
+    let poll_interval: Option<Duration> = cfg.runner.poll_interval_secs.map(Duration::from_secs);
+    let quarantine_dir: Option<String> = cfg.runner.quarantine_dir.clone();
+    let max_inflight_bytes: Option<u64> = cfg.s3.max_inflight_bytes;
+    let get_retry = s3_download::GetRetry {
+        retries: cfg.s3.get_retries,
+        backoff_ms: cfg.s3.get_backoff_ms,
+    };
+    let sdk_settings = s3_download::SdkSettings {
+        max_attempts: cfg.s3.sdk_max_attempts,
+        operation_timeout_ms: cfg.s3.sdk_operation_timeout_ms,
+        endpoint_url: cfg.s3.endpoint_url.clone(),
+        region: cfg.s3.region.clone(),
+        assume_role_arn: cfg.s3.assume_role_arn.clone(),
+        external_id: cfg.s3.external_id.clone(),
+    };
+
+    // parquet
+    let desired_fields: Vec<String> = cfg.parquet.desired_fields;
+    let optional_fields: Vec<config::OptionalField> = cfg.parquet.optional_fields;
+    let field_types: HashMap<String, config::FieldType> = cfg.parquet.field_types;
+    let allowed_codecs: Option<Vec<config::Codec>> = cfg.parquet.allowed_codecs;
+    let schema_policy: config::SchemaPolicy = cfg.parquet.schema_policy;
+    let row_filter: Option<config::RowFilter> = cfg.parquet.row_filter;
+    let parse_dates: Option<config::ParseDates> = cfg.parquet.parse_dates;
+    let optional_field_names: Vec<String> = optional_fields
+        .iter()
+        .map(|f| f.name().to_string())
+        .collect();
+
+    // db
+    let table_name: String = cfg.db.table_name;
+    let conn_str: &str = cfg.db.conn_str.as_str();
+    let sslmode = cfg.db.sslmode;
+    let ca_cert: Option<String> = cfg.db.ca_cert;
+    let load_mode = cfg.db.load_mode;
+    let on_non_finite_float = cfg.db.on_non_finite_float;
+    let on_type_mismatch = cfg.db.on_type_mismatch;
+    let enforce_column_order: bool = cfg.db.enforce_column_order;
+    let load_metadata = cfg.db.load_metadata;
+    let extra_columns: HashMap<String, String> = cfg.db.extra_columns;
+    let audit_sql_file: Option<String> = cfg.db.audit_sql_file;
+    let bitmask_columns: HashMap<String, Vec<String>> = cfg.db.bitmask_columns;
+    let write_mode = cfg.db.write_mode;
+    let insert_batch_size: usize = cfg.db.insert_batch_size;
+    let max_rows_per_copy: Option<usize> = cfg.db.max_rows_per_copy;
+    let statement_timeout_ms: Option<u64> = cfg.db.statement_timeout_ms;
+    let copy_timeout_ms: Option<u64> = cfg.db.copy_timeout_ms;
+    let connect_retry = db::ConnectRetry {
+        retries: cfg.db.connect_retries,
+        backoff_ms: cfg.db.connect_backoff_ms,
+    };
+
+    let upsert: Option<db::Upsert> = match cfg.db.on_conflict {
+        None if !cfg.db.conflict_columns.is_empty() => {
+            bail!("db.conflict_columns is set, but db.on_conflict is not")
+        }
+        None => None,
+        Some(on_conflict) => Some(db::Upsert {
+            on_conflict,
+            conflict_columns: cfg.db.conflict_columns,
+        }),
+    };
+
+    let (parquet_to_db, naming_strategy, base64_fields, decrypt, assume_timezone) =
+        match cfg.parquet_to_db {
+            None => (None, Default::default(), vec![], vec![], HashMap::new()),
+            Some(p) => (
+                Some(p.aliases),
+                p.strategy,
+                p.base64_decode,
+                p.decrypt,
+                p.assume_timezone,
+            ),
+        };
+    // Each file loading at once needs its own connection, as each is loaded in a transaction.
+    // The first does everything else e.g. the swap. The checks keep nothing, so only need one.
+    let num_connections: usize = match mode {
+        RunMode::Load => parallel_files,
+        RunMode::DryRun | RunMode::ValidateCopy => 1,
+    };
+    // and with copy_parallelism, each of those has more to COPY a file over
+    let copy_parallelism: usize = match mode {
+        RunMode::Load => cfg.db.copy_parallelism,
+        RunMode::DryRun | RunMode::ValidateCopy => 1,
+    };
+    info!("connecting to db with {} connection(s)", num_connections);
+    let mut dbs: Vec<db::Db> = Vec::with_capacity(num_connections);
+    // every connection is to the same table, so only the first need look up its cols
+    let schema_cache = db::SchemaCache::default();
+    for _ in 0..num_connections {
+        let db = db::Db::connect(
+            conn_str,
+            &sslmode,
+            ca_cert.as_deref(),
+            &connect_retry,
+            table_name.as_str(),
+            desired_fields.clone(),
+            parquet_to_db.clone(),
+            &naming_strategy,
+            Some(&schema_cache),
+        )
+        .await?
+        .with_upsert(upsert.clone())?
+        .with_load_mode(load_mode.clone())
+        .with_non_finite_float(on_non_finite_float.clone())
+        .with_type_mismatch(on_type_mismatch.clone())
+        .with_base64_decode(&desired_fields, &base64_fields)?
+        .with_decrypt(&desired_fields, &decrypt)?
+        .with_assume_timezone(&desired_fields, &assume_timezone)?
+        .with_optional_fields(&desired_fields, &optional_fields)?
+        .with_field_types(&desired_fields, &field_types)?
+        .with_parse_dates(parse_dates.clone())
+        .with_load_metadata(&load_metadata, run_id, loaded_at)
+        .await?
+        .with_extra_columns(&extra_columns)
+        .await?
+        .with_bitmask_columns(&bitmask_columns)
+        .await?
+        .with_write_mode(write_mode.clone(), insert_batch_size)?
+        .with_max_rows_per_copy(max_rows_per_copy)?
+        .with_copy_timeout(copy_timeout_ms)?
+        .with_row_filter(row_filter.clone())
+        .with_audit_sql(audit_sql_file.as_deref(), run_id)?
+        .with_copy_parallelism(
+            copy_parallelism,
+            num_connections,
+            conn_str,
+            &sslmode,
+            ca_cert.as_deref(),
+            &connect_retry,
+        )
+        .await?
+        .with_statement_timeout(statement_timeout_ms)
+        .await?;
+        dbs.push(db);
+    }
+
+    if enforce_column_order {
+        dbs[0].check_column_order().await?;
+    }
+
+    // before any loads, so none of this run's shares are mistaken for a stopped run's
+    if mode == RunMode::Load {
+        let settled = dbs[0].settle_prepared().await?;
+        if settled > 0 {
+            info!(
+                "settled {} share(s) of files a stopped run left prepared",
+                settled
+            );
+        }
+    }
+
+    info!(
+        "Will write fields {} to database table {}",
+        desired_fields.join(", "),
+        table_name
+    );
+
+    if generate_from_s3 && !work_lists::todo_exists(work_lists_dir) {
+        info!(
+            "no todo file, so listing parquet files in bucket {} under prefix {:?}",
+            bucket_name, s3_prefix
+        );
+        let keys =
+            s3_download::list_keys(bucket_name.as_str(), s3_prefix.as_str(), &sdk_settings).await?;
+        work_lists::write_todo(work_lists_dir, &keys)?;
+    }
+
+    // for source sqs, the keys come from the queue, so todo starts empty if there's none
+    let source_sqs = cfg.source == config::Source::Sqs;
+    if source_sqs {
+        if mode != RunMode::Load {
+            bail!("source sqs only loads, as a check would leave the queue's messages to load");
+        }
+        if load_mode == config::LoadMode::Swap {
+            bail!("source sqs can't load with db.load_mode swap, as there's no end to swap in at");
+        }
+        if !work_lists::todo_exists(work_lists_dir) {
+            work_lists::write_todo(work_lists_dir, &[])?;
+        }
+    }
+    if poll_interval.is_some() {
+        if mode != RunMode::Load {
+            bail!("runner.poll_interval_secs only loads, as a check would never end");
+        }
+        if load_mode == config::LoadMode::Swap {
+            bail!("runner.poll_interval_secs can't load with db.load_mode swap, as there's no end to swap in at");
+        }
+    }
+
+    let mut work_lists =
+        work_lists::WorkLists::new(work_lists_dir, batch_size, completed_flush_every)?
+            .with_max_attempts(max_attempts)?
+            .with_trim_keys(cfg.work_lists.trim_keys)
+            .with_on_startup_wip(&cfg.work_lists.on_startup_wip)?;
+
+    if deterministic {
+        info!("deterministic run: files downloaded and loaded one at a time, in todo order");
+    } else if parallel_files > 1 {
+        info!("loading up to {} files at once", parallel_files);
+    }
+
+    if mode != RunMode::Load {
+        match mode {
+            RunMode::ValidateCopy => info!("VALIDATE COPY: all rows will be rolled back"),
+            _ => info!("DRY RUN: no rows will be written to the db"),
+        }
+        let pending_items = work_lists.pending_items()?;
+        for batch in pending_items.chunks(batch_size) {
+            if shutdown.is_requested() {
+                break;
+            }
+            let mut batch: Vec<String> = batch.to_vec();
+            if !require_tags.is_empty() {
+                let not_ready: Vec<String>;
+                (batch, not_ready) = s3_download::split_by_tags(
+                    &bucket_name,
+                    batch,
+                    &require_tags,
+                    download_concurrency,
+                    &sdk_settings,
+                )
+                .await?;
+                for key in not_ready {
+                    info!(
+                        "{}: not checked, as its tags don't match s3.require_tags",
+                        key
+                    );
+                }
+            }
+            let map_ids_to_downloads = s3_download::get(
+                bucket_name.clone(),
+                batch.clone(),
+                output_dir.clone(),
+                download_concurrency,
+                max_inflight_bytes,
+                &get_retry,
+                &sdk_settings,
+            )
+            .await?;
+            for id in &batch {
+                let downloaded_file = map_ids_to_downloads.get(id.as_str()).unwrap();
+                if shutdown.is_requested() {
+                    s3_download::delete(downloaded_file.clone())?;
+                    continue;
+                }
+                parquet_rows_to_db(
+                    id,
+                    downloaded_file.to_string(),
+                    desired_fields.clone(),
+                    optional_field_names.clone(),
+                    allowed_codecs.clone(),
+                    schema_policy,
+                    quarantine_dir.as_deref(),
+                    &mut dbs[0],
+                    mode,
+                    progress,
+                )
+                .await
+                .inspect_err(|_| progress.file_failed())?;
+                progress.file_processed();
+                s3_download::delete(downloaded_file.clone())?;
+            }
+        }
+        return Ok(());
+    }
+
+    // one client for every receipt, only needed once a file's loaded
+    let receipt_client = match &receipt_prefix {
+        Some(_) => Some(s3_download::s3_client(&sdk_settings).await?),
+        None => None,
+    };
+
+    let mut when_idle = match (&cfg.sqs, source_sqs, poll_interval) {
+        (Some(sqs_cfg), true, _) => WhenIdle::Sqs(
+            sqs_source::SqsSource::new(sqs_cfg, sdk_settings.region.as_deref(), &bucket_name)
+                .await?,
+        ),
+        (_, _, Some(interval)) => {
+            info!(
+                "polling: once todo's done, checking it again every {}s until stopped",
+                interval.as_secs()
+            );
+            WhenIdle::Poll(interval)
+        }
+        _ => WhenIdle::Exit,
+    };
+
+    // A swap only makes sense if there's something to load, else we'd swap in an empty table,
+    // or a copy's left loaded by an earlier run, that stopped before swapping it in
+    let swap = load_mode == config::LoadMode::Swap
+        && (!work_lists.pending_items()?.is_empty() || dbs[0].swap_load_started().await?);
+    if swap {
+        info!(
+            "loading into a copy of table {}, swapped in at the end",
+            table_name
+        );
+        dbs[0].start_swap_load().await?;
+    }
+
+    // for s3.require_tags, keys found not ready since last waiting on more work
+    let mut not_ready: HashSet<String> = HashSet::new();
+    loop {
+        if shutdown.is_requested() {
+            info!("shutting down, so not starting another batch");
+            break;
+        }
+        let mut wip_list = work_lists.next_batch()?.wip_list.clone();
+
+        // keys not ready go to the end of todo, so are looked at again once the rest have been
+        if !require_tags.is_empty() && !wip_list.is_empty() {
+            let later: Vec<String>;
+            (wip_list, later) = s3_download::split_by_tags(
+                &bucket_name,
+                wip_list,
+                &require_tags,
+                download_concurrency,
+                &sdk_settings,
+            )
+            .await?;
+            for key in &later {
+                info!(
+                    "{}: not loaded yet, as its tags don't match s3.require_tags",
+                    key
+                );
+            }
+            work_lists.mark_not_ready(&later)?;
+            not_ready.extend(later);
+        }
+
+        if wip_list.is_empty() {
+            // todo may still have keys not looked at, behind those not ready
+            let pending_items = work_lists.pending_items()?;
+            if !pending_items.iter().all(|key| not_ready.contains(key)) {
+                continue;
+            }
+            match when_idle.more_work(&work_lists, shutdown).await? {
+                true => {
+                    not_ready.clear();
+                    continue;
+                }
+                false => {
+                    if !pending_items.is_empty() {
+                        info!(
+                            "{} file(s) left in todo, as their tags don't match s3.require_tags",
+                            pending_items.len()
+                        );
+                    }
+                    break;
+                }
+            }
+        }
+
+        let map_ids_to_downloads = s3_download::get(
+            bucket_name.clone(),
+            wip_list.clone(),
+            output_dir.clone(),
+            download_concurrency,
+            max_inflight_bytes,
+            &get_retry,
+            &sdk_settings,
+        )
+        .await?;
+        info!("... downloaded files:");
+        for file_id in &wip_list {
+            info!("\t{}", map_ids_to_downloads.get(file_id).unwrap());
+        }
+
+        // Files are dealt out in turn to one lane per connection. Each lane loads its
+        // files one after another, stopping at its first error. The other lanes carry on,
+        // so every file that did load is marked completed before we give up.
+        let mut lanes: Vec<Vec<&String>> = vec![vec![]; dbs.len()];
+        for (i, id) in wip_list.iter().enumerate() {
+            lanes[i % dbs.len()].push(id);
+        }
+        // the files that left wip, loaded or not, so their sqs messages can go
+        let batch_done: RefCell<Vec<String>> = RefCell::new(vec![]);
+        let results = {
+            // lanes run on this task, and never hold the borrow over an await
+            let work_lists = RefCell::new(&mut work_lists);
+            let redownload = |id: &String| {
+                s3_download::get(
+                    bucket_name.clone(),
+                    vec![id.to_string()],
+                    output_dir.clone(),
+                    1,
+                    max_inflight_bytes,
+                    &get_retry,
+                    &sdk_settings,
+                )
+            };
+            let lane_loads = dbs.iter_mut().zip(lanes).map(|(db, lane)| {
+                let work_lists = &work_lists;
+                let map_ids_to_downloads = &map_ids_to_downloads;
+                let desired_fields = &desired_fields;
+                let optional_field_names = &optional_field_names;
+                let allowed_codecs = &allowed_codecs;
+                let quarantine_dir = &quarantine_dir;
+                let on_file_error = &on_file_error;
+                let redownload = &redownload;
+                let bucket_name = &bucket_name;
+                let receipt_prefix = &receipt_prefix;
+                let receipt_client = &receipt_client;
+                let batch_done = &batch_done;
+                async move {
+                    // parquet filename has the output_dir
+                    for id in lane {
+                        let downloaded_file = map_ids_to_downloads.get(id.as_str()).unwrap();
+                        // left in wip, so the next run loads it first
+                        if shutdown.is_requested() {
+                            info!("{}: not started, as shutting down", downloaded_file);
+                            s3_download::delete(downloaded_file.clone())?;
+                            continue;
+                        }
+                        info!("{}: handling downloaded parquet file", downloaded_file);
+
+                        let result: Result<u64> = async {
+                            // A file that won't open as parquet, e.g. truncated, may have been
+                            // mangled on the way, so is downloaded again before giving up on it
+                            let mut redownloads: u32 = 0;
+                            while redownloads < parse_error_redownloads
+                                && !parquet_ops::is_parquet(downloaded_file)
+                            {
+                                redownloads += 1;
+                                warn!(
+                                    "{}: not a valid parquet file, downloading it again ({} of {})",
+                                    downloaded_file, redownloads, parse_error_redownloads
+                                );
+                                redownload(id).await?;
+                            }
+                            let result = parquet_rows_to_db(
+                                id,
+                                downloaded_file.to_string(),
+                                desired_fields.clone(),
+                                optional_field_names.clone(),
+                                allowed_codecs.clone(),
+                                schema_policy,
+                                quarantine_dir.as_deref(),
+                                db,
+                                mode,
+                                progress,
+                            )
+                            .await;
+                            match redownloads {
+                                0 => result,
+                                _ => result.with_context(|| {
+                                    format!("after downloading it {} more time(s)", redownloads)
+                                }),
+                            }
+                        }
+                        .await;
+                        // a file is loaded in one transaction, or with copy_parallelism, its
+                        // shares are committed all or nothing, so a failed one left no rows,
+                        // bar one whose shares couldn't all be committed once the first was
+                        let num_rows_added = match result {
+                            Ok(num_rows_added) => num_rows_added,
+                            // its rows are in, so loading it again would double them, but
+                            // only a run starting commits the shares left, so this one ends
+                            Err(e) if e.downcast_ref::<db::SharesLeftPrepared>().is_some() => {
+                                error!("{}: {:#}", downloaded_file, e);
+                                work_lists.borrow_mut().mark_completed(id.to_string())?;
+                                batch_done.borrow_mut().push(id.to_string());
+                                progress.file_processed();
+                                s3_download::delete(downloaded_file.clone())?;
+                                return Err(e);
+                            }
+                            Err(e) => {
+                                progress.file_failed();
+                                // a codec that's not allowed never will be, so no point retrying
+                                if e.downcast_ref::<parquet_ops::DisallowedCodec>().is_some() {
+                                    warn!("{}: quarantining, as {:#}", downloaded_file, e);
+                                    work_lists
+                                        .borrow_mut()
+                                        .mark_quarantined(id.to_string(), &format!("{:#}", e))?;
+                                    batch_done.borrow_mut().push(id.to_string());
+                                    s3_download::delete(downloaded_file.clone())?;
+                                    continue;
+                                }
+                                if *on_file_error == config::OnFileError::Abort {
+                                    // counted, so a file that keeps failing is dead-lettered
+                                    let dead_lettered = work_lists
+                                        .borrow_mut()
+                                        .mark_failed(id.to_string(), &format!("{:#}", e))?;
+                                    if dead_lettered {
+                                        batch_done.borrow_mut().push(id.to_string());
+                                        s3_download::delete(downloaded_file.clone())?;
+                                    }
+                                    return Err(e);
+                                }
+                                error!("{}: skipping, as failed to load: {:#}", downloaded_file, e);
+                                work_lists
+                                    .borrow_mut()
+                                    .mark_skipped(id.to_string(), &format!("{:#}", e))?;
+                                batch_done.borrow_mut().push(id.to_string());
+                                s3_download::delete(downloaded_file.clone())?;
+                                continue;
+                            }
+                        };
+
+                        debug!("{}: will mark {} as completed", downloaded_file, id);
+                        work_lists.borrow_mut().mark_completed(id.to_string())?;
+                        batch_done.borrow_mut().push(id.to_string());
+                        progress.file_processed();
+
+                        // the rows are in, so a receipt that can't be written doesn't undo that
+                        if let (Some(prefix), Some(client)) = (receipt_prefix, receipt_client) {
+                            let put = async {
+                                let receipt =
+                                    s3_download::Receipt::new(id, num_rows_added, downloaded_file)?;
+                                s3_download::put_receipt(client, bucket_name, prefix, &receipt)
+                                    .await
+                            };
+                            if let Err(e) = put.await {
+                                warn!("{}: no receipt written: {:#}", downloaded_file, e);
+                            }
+                        }
+
+                        debug!("{}: deleting downloaded file", downloaded_file);
+                        s3_download::delete(downloaded_file.clone())?;
+                    }
+                    Ok::<(), anyhow::Error>(())
+                }
+            });
+            join_all(lane_loads).await
+        };
+
+        // record what we did finish, before bailing out on any error
+        work_lists.flush_completed()?;
+        // only once completed is on file, so a message never goes before its load is recorded
+        if let WhenIdle::Sqs(sqs_source) = &mut when_idle {
+            sqs_source.delete_done(&batch_done.into_inner()).await?;
+        }
+        if let Some(e) = results.into_iter().find_map(Result::err) {
+            return Err(e);
+        }
+    }
+
+    if swap && shutdown.is_requested() {
+        // as after a failed run, the next one carries on loading into the copy
+        warn!(
+            "shut down part way through loading, so not swapping in table {}",
+            table_name
+        );
+    } else if swap {
+        info!("swapping in the newly loaded table {}", table_name);
+        dbs[0].finish_swap_load().await?;
+    }
+
+    Ok(())
+}
+
+// After the db is restored from a backup, completed may list files whose rows are gone.
+// Moves any completed item without rows in the table, going by work_lists.reconcile_key_column,
+// back to todo so the next run loads it again. Returns the items moved.
+pub async fn reconcile(cfg_file: &str) -> Result<Vec<String>> {
+    info!("reading cfg file: [{}]", cfg_file);
+    let cfg = cfg(cfg_file)?;
+
+    // each job has its own table and work lists
+    let mut moved: Vec<String> = Vec::new();
+    for job_cfg in cfg.job_configs() {
+        moved.extend(reconcile_table(job_cfg).await?);
+    }
+    Ok(moved)
+}
+
+async fn reconcile_table(cfg: config::Config) -> Result<Vec<String>> {
+    let Some(key_column) = cfg.work_lists.reconcile_key_column else {
+        bail!("reconcile needs work_lists.reconcile_key_column, the db column holding each row's s3 key")
+    };
+    let work_lists_dir: &str = cfg.work_lists.dir.as_str();
+    let connect_retry = db::ConnectRetry {
+        retries: cfg.db.connect_retries,
+        backoff_ms: cfg.db.connect_backoff_ms,
+    };
+
+    // so a run can't start on the work lists while we rewrite them
+    let _lock = work_lists::lock_dir(work_lists_dir)?;
+    let completed = work_lists::completed_items(work_lists_dir)?;
+    if completed.is_empty() {
+        info!("nothing completed, so nothing to reconcile");
+        return Ok(vec![]);
+    }
+
+    info!("connecting to db");
+    // the key column is the only one we need, and connect checks it exists
+    let db = db::Db::connect(
+        cfg.db.conn_str.as_str(),
+        &cfg.db.sslmode,
+        cfg.db.ca_cert.as_deref(),
+        &connect_retry,
+        cfg.db.table_name.as_str(),
+        vec![key_column.clone()],
+        None,
+        &config::NamingStrategy::Identity,
+        None,
+    )
+    .await?;
+
+    let keys_with_rows = db.keys_with_rows(key_column.as_str(), &completed).await?;
+    let missing: Vec<String> = completed
+        .into_iter()
+        .filter(|item| !keys_with_rows.contains(item))
+        .collect();
+
+    for item in &missing {
+        info!(
+            "{}: completed, but has no rows in {}",
+            item, cfg.db.table_name
+        );
+    }
+    work_lists::requeue(work_lists_dir, &missing)?;
+    info!("{} completed items moved back to todo", missing.len());
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use assert_fs::{fixture::TempDir, prelude::*};
+    use aws_sdk_s3::types::{Tag, Tagging};
+    use const_format::formatcp;
+    use once_cell::sync::Lazy;
+    use std::collections::HashMap;
+    use std::env;
+    use std::path::Path;
+    use tokio::sync::Mutex;
+    use tokio_postgres::Client;
+
+    #[allow(unused_imports)]
+    use crate::test_setup::tests::{
+        create_table_return_client, get_rows_as_csv_string, render_tmpl_str, restore_env,
+        set_good_aws_vars, setup_docker, LOCALSTACK_PARQUET_DIR_CUSTOMERS,
+    };
+
+    static RUNNER_TESTDATA: &str = formatcp!(
+        "{}/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        "tests/testdata/unit-tests/runner"
+    );
+
+    static LOCK_ENV_RUNNER_TESTS: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+    // test with orders parquet files
+    // yaml file should use relative paths (as will be in a temp dir)
+    // create a temp dir and cd to it
+    // test run.rs creates expected csv
+
+    async fn runner_tests_setup(test_name: &str, table_columns: &str) -> Result<(TempDir, Client)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+
+        let src_dir = format!("{}/{}", RUNNER_TESTDATA, test_name);
+        tmp_dir.copy_from(src_dir, &["*", "todo"])?;
+
+        setup_docker(); // do this before creating test table!
+                        // create expected db table
+        let db_client = create_table_return_client(test_name.to_string(), table_columns).await?;
+
+        set_good_aws_vars();
+        env::set_current_dir(Path::new(&tmp_dir_path)).expect("Failed to cd to temp dir for test");
+
+        Ok((tmp_dir, db_client))
+    }
+
+    #[tokio::test]
+    async fn test_run_progress() -> Result<()> {
+        let test_name = "test_run_progress";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let result = run("config.yml", false, Some(sender)).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        result?;
+
+        // the sender went with the run, so this gets every event then stops
+        let mut events: Vec<Progress> = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+        // 6 files, each started then written
+        assert_eq!(events.len(), 12);
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].rows_written <= pair[1].rows_written));
+        assert_eq!(
+            events[0],
+            Progress {
+                file: "order_00.parquet".to_string(),
+                rows_written: 0,
+                total_rows: 10,
+            }
+        );
+
+        let last = events.last().unwrap();
+        assert_eq!(last.file, "order_05.parquet");
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, format!("total\n{}\n", last.rows_written));
+        assert_eq!(last.total_rows, last.rows_written);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_shutdown_finishes_current_file() -> Result<()> {
+        let test_name = "test_run_shutdown";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // as if a signal came in once the first file started loading
+        let shutdown = Shutdown::default();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let requester = {
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if receiver.recv().await.is_some() {
+                    shutdown.request();
+                }
+            })
+        };
+        let result = run_mode_until("config.yml", RunMode::Load, Some(sender), shutdown).await;
+        requester.await?;
+        let completed = std::fs::read_to_string("work/completed");
+        let wip = std::fs::read_to_string("work/wip");
+        let todo = std::fs::read_to_string("work/todo");
+        let downloads_left = std::fs::read_dir("out")?.count();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+
+        let summary = result?;
+        assert_eq!(summary.files_processed, 1);
+        // the rest of the batch is left in wip, and the next batch never pulled from todo
+        assert_eq!(completed?, "order_00.parquet\n");
+        assert_eq!(
+            wip?,
+            "order_01.parquet\norder_02.parquet\norder_03.parquet\n"
+        );
+        assert_eq!(todo?, "order_04.parquet\norder_05.parquet\n");
+        assert_eq!(downloads_left, 0, "downloads not started should be deleted");
+
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_swap_load_resume() -> Result<()> {
+        let test_name = "test_run_swap_load_resume";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        // the copy an earlier run loaded every file into, then stopped before swapping in
+        db_client
+            .batch_execute(
+                format!(
+                    "DROP TABLE IF EXISTS {t}_new; INSERT INTO {t} (id) VALUES (0); \
+                     CREATE TABLE {t}_new (LIKE {t} INCLUDING ALL); \
+                     INSERT INTO {t}_new (id) SELECT generate_series(1, 60)",
+                    t = test_name
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let result = run("config.yml", false, None).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        assert_eq!(result?.files_processed, 0);
+
+        // todo's empty, but the copy's still swapped in, rather than left stranded
+        let sql = format!(
+            "SELECT COUNT(*) AS n, to_regclass('{t}_new') IS NULL AS no_copy FROM {t}",
+            t = test_name
+        );
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "n,no_copy\n60,true\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_exits_on_empty_todo() -> Result<()> {
+        let test_name = "test_run_empty_todo";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, _db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // without runner.poll_interval_secs, nothing to do means the run's over
+        let result =
+            tokio::time::timeout(Duration::from_secs(30), run("config.yml", false, None)).await;
+        tmp_dir.close().unwrap();
+        restore_env(original_env);
+
+        let summary = result.expect("run should end once todo's done")?;
+        assert_eq!(summary.files_processed, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_poll_picks_up_new_todo() -> Result<()> {
+        let test_name = "test_run_poll";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // todo starts empty, so the run sleeps. A file added while it does is loaded next time
+        // it looks, then the run's stopped, as polling never ends by itself
+        let shutdown = Shutdown::default();
+        let add_then_stop = async {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            let mut todo = std::fs::OpenOptions::new().append(true).open("work/todo")?;
+            std::io::Write::write_all(&mut todo, b"order_00.parquet\n")?;
+            let until = Instant::now() + Duration::from_secs(30);
+            while std::fs::read_to_string("work/completed")
+                .unwrap_or_default()
+                .is_empty()
+                && Instant::now() < until
+            {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            shutdown.request();
+            Ok::<(), anyhow::Error>(())
+        };
+        let (result, added) = tokio::join!(
+            run_mode_until("config.yml", RunMode::Load, None, shutdown.clone()),
+            add_then_stop
+        );
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        added?;
+
+        let summary = result?;
+        assert_eq!(summary.files_processed, 1);
+        assert_eq!(completed?, "order_00.parquet\n");
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_receipts() -> Result<()> {
+        let test_name = "test_run_receipts";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        let result = run("config.yml", false, None).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+                                  // the receipts and what they should say, for the files loaded
+        let receipts: Result<Vec<(String, String)>> = async {
+            let client = s3_download::s3_client(&s3_download::SdkSettings::default()).await?;
+            let mut receipts = Vec::new();
+            for key in ["order_00.parquet", "order_01.parquet"] {
+                let expected = s3_download::Receipt::new(
+                    key,
+                    10,
+                    &format!("{}/{}", LOCALSTACK_PARQUET_DIR_CUSTOMERS, key),
+                )?;
+                let output = client
+                    .get_object()
+                    .bucket("customer-orders-parquet")
+                    .key(expected.receipt_key("receipts/test_run_receipts/"))
+                    .send()
+                    .await?;
+                let body = output.body.collect().await?.into_bytes();
+                receipts.push((String::from_utf8(body.to_vec())?, expected.to_json()?));
+            }
+            Ok(receipts)
+        }
+        .await;
+        restore_env(original_env);
+        let summary = result?;
+        assert_eq!(summary.rows_written, 20);
+
+        // a receipt per file, with the rows and checksum of the file loaded
+        let receipts = receipts?;
+        for (receipt, expected) in &receipts {
+            assert_eq!(receipt, expected);
+        }
+        assert!(receipts[0]
+            .0
+            .starts_with(r#"{"key":"order_00.parquet","rows":10,"crc32":""#));
+
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n20\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_require_tags() -> Result<()> {
+        let test_name = "test_run_require_tags";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        // only order_00 is ready: order_01's still uploading, and order_02 has no tags
+        let tagged: Result<()> = async {
+            let client = s3_download::s3_client(&s3_download::SdkSettings::default()).await?;
+            for (key, status) in [
+                ("order_00.parquet", "ready"),
+                ("order_01.parquet", "uploading"),
+            ] {
+                let tag = Tag::builder().key(test_name).value(status).build()?;
+                client
+                    .put_object_tagging()
+                    .bucket("customer-orders-parquet")
+                    .key(key)
+                    .tagging(Tagging::builder().tag_set(tag).build()?)
+                    .send()
+                    .await?;
+            }
+            client
+                .delete_object_tagging()
+                .bucket("customer-orders-parquet")
+                .key("order_02.parquet")
+                .send()
+                .await?;
+            Ok(())
+        }
+        .await;
+
+        let result = match tagged {
+            Ok(()) => run("config.yml", false, None).await,
+            Err(e) => Err(e),
+        };
+        let todo = std::fs::read_to_string("work/todo");
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        let summary = result?;
+        assert_eq!(summary.rows_written, 10);
+
+        // those not ready are left in todo for a later run
+        assert_eq!(completed?, "order_00.parquet\n");
+        assert_eq!(todo?, "order_02.parquet\norder_01.parquet\n");
+
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_jobs() -> Result<()> {
+        let test_name = "test_run_jobs";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        let amounts_table = format!("{}_amounts", test_name);
+        let _ = create_table_return_client(amounts_table.clone(), "customer_order").await?;
+
+        // orders loads 2 files into one table, then amounts 1 into the other
+        let result = run("config.yml", false, None).await;
+        let completed_orders = std::fs::read_to_string("work/orders/completed");
+        let completed_amounts = std::fs::read_to_string("work/amounts/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+
+        let summary = result?;
+        assert_eq!(summary.files_processed, 3);
+        assert_eq!(completed_orders?, "order_00.parquet\norder_01.parquet\n");
+        assert_eq!(completed_amounts?, "order_02.parquet\n");
+
+        let sql = format!(
+            "SELECT count(description) AS described, count(some_positive_int) AS amounts FROM {}",
+            test_name
+        );
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "described,amounts\n20,0\n");
+        let sql = format!(
+            "SELECT count(id) AS ids, count(description) AS described FROM {}",
+            amounts_table
+        );
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "ids,described\n10,0\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_summary() -> Result<()> {
+        let test_name = "test_run_summary";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // config has summary_json: true, which only adds a line to stdout
+        let result = run("config.yml", false, None).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        let summary = result?;
+
+        // 6 files of 10 rows, over 2 batches
+        assert_eq!(summary.files_processed, 6);
+        assert_eq!(summary.files_failed, 0);
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, format!("total\n{}\n", summary.rows_written));
+        assert_eq!(summary.rows_written, 60);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_report_table() -> Result<()> {
+        let test_name = "test_run_report_table";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        db_client
+            .batch_execute(
+                "DROP TABLE IF EXISTS test_run_report_table_runs;
+                 CREATE TABLE test_run_report_table_runs (
+                     run_id TEXT PRIMARY KEY,
+                     files_processed INT,
+                     rows_written BIGINT,
+                     files_failed INT,
+                     elapsed_secs NUMERIC,
+                     reported_at TIMESTAMPTZ DEFAULT now()
+                 )",
+            )
+            .await?;
+
+        let result = run("config.yml", false, None).await;
+        // a dry run writes nothing to the db, so isn't reported
+        let dry_run = run("config.yml", true, None).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        let summary = result?;
+        dry_run?;
+
+        let sql =
+            "SELECT files_processed::text AS files_processed, rows_written::text AS rows_written, \
+                   files_failed::text AS files_failed, (elapsed_secs > 0)::text AS took_time, \
+                   (run_id <> '')::text AS has_run_id \
+                   FROM test_run_report_table_runs";
+        let csv_string = get_rows_as_csv_string(&db_client, sql).await?;
+        assert_eq!(
+            csv_string,
+            "files_processed,rows_written,files_failed,took_time,has_run_id\n6,60,0,true,true\n"
+        );
+        assert_eq!(summary.rows_written, 60);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_analyze_after_load() -> Result<()> {
+        let test_name = "test_run_analyze_after_load";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        let result = run("config.yml", false, None).await;
+        let audited = std::fs::read_to_string("audit.sql")?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        assert_eq!(result?.rows_written, 60);
+
+        // recorded like any other statement the run sends
+        let last = audited.lines().last().unwrap_or_default();
+        assert!(
+            last.ends_with("\tVACUUM ANALYZE \"test_run_analyze_after_load\""),
+            "{}",
+            last
+        );
+
+        // only ANALYZE sets the planner's row count, which COPY leaves as it was
+        let sql = "SELECT reltuples::bigint::text AS reltuples FROM pg_class \
+                   WHERE relname = 'test_run_analyze_after_load'";
+        let csv_string = get_rows_as_csv_string(&db_client, sql).await?;
+        assert_eq!(csv_string, "reltuples\n60\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_summary_to_json() {
+        let summary = RunSummary {
+            files_processed: 6,
+            rows_written: 60,
+            files_failed: 1,
+            elapsed: Duration::from_millis(1234),
+        };
+        assert_eq!(
+            summary.to_json().unwrap(),
+            r#"{"files_processed":6,"rows_written":60,"files_failed":1,"elapsed_secs":1.234}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_downloads_per_run() -> Result<()> {
+        let test_name = "test_run_downloads_per_run";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // both runs download the same keys into the same downloads_dir, at the same time
+        let (result_a, result_b) = tokio::join!(
+            run("config_a.yml", false, None),
+            run("config_b.yml", false, None)
+        );
+        let completed_a = std::fs::read_to_string("work_a/completed");
+        let completed_b = std::fs::read_to_string("work_b/completed");
+        let leftovers: Vec<String> = std::fs::read_dir("out")?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().to_string()))
+            .collect::<Result<_, _>>()?;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        result_a?;
+        result_b?;
+
+        let all_files = "order_00.parquet\norder_01.parquet\norder_02.parquet\n\
+             order_03.parquet\norder_04.parquet\norder_05.parquet\n";
+        assert_eq!(completed_a?, all_files);
+        assert_eq!(completed_b?, all_files);
+        assert!(
+            leftovers.is_empty(),
+            "each run's downloads dir should be removed, found {:?}",
+            leftovers
+        );
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "total\n120\n",
+            "each run should load every file"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_download_concurrency() -> Result<()> {
+        let test_name = "test_run_download_concurrency";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // config has download_concurrency: 8, more than the batch of 6 files
+        let cfg = cfg("config.yml")?;
+        assert_eq!(cfg.s3.download_concurrency, 8);
+        let result = run("config.yml", false, None).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        result?;
+
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "total\n60\n",
+            "all files should be downloaded and loaded"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_generate_todo_from_s3() -> Result<()> {
+        let test_name = "test_run_generate_todo_from_s3";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // no todo file, so one is listed from s3 under prefix order_0
+        let todo_existed = Path::new("work/todo").exists();
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        result?;
+
+        assert!(!todo_existed);
+        assert_eq!(
+            completed?,
+            "order_00.parquet\norder_01.parquet\norder_02.parquet\n\
+             order_03.parquet\norder_04.parquet\norder_05.parquet\n"
+        );
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "total\n60\n",
+            "all listed files should be loaded"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_deterministic_completed_in_todo_order() -> Result<()> {
+        let test_name = "test_run_deterministic_completed_in_todo_order";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, _) = runner_tests_setup(test_name, "customer_order").await?;
+
+        let todo = std::fs::read_to_string("work/todo")?;
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+        result?;
+
+        // todo isn't in alphabetical order, so would be noticed if sorted
+        assert_eq!(completed?, todo, "files should complete in todo order");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_requeues_completed_without_rows() -> Result<()> {
+        let test_name = "test_run_reconcile";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "source_key").await?;
+
+        // all 3 completed files were loaded, then order_01's rows were lost in a restore
+        std::fs::write(
+            "work/completed",
+            "order_00.parquet\norder_01.parquet\norder_02.parquet\n",
+        )?;
+        db_client
+            .batch_execute(
+                "INSERT INTO test_run_reconcile VALUES
+                    ('order_00.parquet', 1), ('order_01.parquet', 2), ('order_02.parquet', 3);
+                 DELETE FROM test_run_reconcile WHERE source_key = 'order_01.parquet';",
+            )
+            .await?;
+
+        let result = reconcile("config.yml").await;
+        let todo = std::fs::read_to_string("work/todo");
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+
+        assert_eq!(result?, vec!["order_01.parquet".to_string()]);
+        assert_eq!(todo?, "order_01.parquet\norder_03.parquet\n");
+        assert_eq!(completed?, "order_00.parquet\norder_02.parquet\n");
 
-        debug!("{}: ... reading parquet rows", downloaded_file);
-        let row_iter: parquet::record::reader::RowIter = reader.get_row_iter(None)?;
+        Ok(())
+    }
 
-        info!("{}: ... writing rows to db", downloaded_file);
-        let num_rows_added = db
-            .write_rows(row_iter, &parquet_col_nums, &pq_type_data)
-            .await?;
+    #[tokio::test]
+    async fn test_run_parallel_files() -> Result<()> {
+        let test_name = "test_run_parallel_files";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
 
-        info!(
-            "{}: {} rows added to db successfully",
-            downloaded_file, num_rows_added
-        );
-    } // shouldn't be anything still keeping the downloaded file open now - free to delete
-    Ok(())
-}
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
 
-pub async fn run(cfg_file: &str) -> Result<()> {
-    info!("reading cfg file: [{}]", cfg_file);
-    let cfg = cfg(cfg_file)?;
+        let todo = std::fs::read_to_string("work/todo")?;
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+        result?;
 
-    // files to process
-    let work_lists_dir: &str = cfg.work_lists.dir.as_str();
+        // files in a batch may complete in any order
+        let completed = completed?;
+        let mut completed: Vec<&str> = completed.lines().collect();
+        completed.sort();
+        assert_eq!(completed, todo.lines().collect::<Vec<&str>>());
 
-    // s3 downloads
-    let batch_size: usize = cfg.s3.download_batch_size;
-    let bucket_name = cfg.s3.bucket;
-    let output_dir = cfg.s3.downloads_dir;
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(
+            csv_string, "total\n60\n",
+            "all files should be loaded, each once"
+        );
 
-    // parquet
-    let desired_fields: Vec<String> = cfg.parquet.desired_fields;
+        Ok(())
+    }
 
-    // db
-    let table_name: String = cfg.db.table_name;
-    let conn_str: &str = cfg.db.conn_str.as_str();
+    #[tokio::test]
+    async fn test_run_s3_endpoint_from_config() -> Result<()> {
+        let test_name = "test_run_s3_endpoint_from_config";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
 
-    let parquet_to_db = cfg.parquet_to_db;
-    info!("connecting to db");
-    let db = db::Db::connect(
-        conn_str,
-        table_name.as_str(),
-        desired_fields.clone(),
-        parquet_to_db,
-    )
-    .await?;
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        // only the creds come from env. Its endpoint goes nowhere, so the config's must win.
+        env::set_var("AWS_ENDPOINT_URL", "http://127.0.0.1:1");
+        env::remove_var("AWS_DEFAULT_REGION");
 
-    info!(
-        "Will write fields {} to database table {}",
-        desired_fields.join(", "),
-        table_name
-    );
+        let result = run("config.yml", false, None).await;
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+        result?;
 
-    let mut work_lists = work_lists::WorkLists::new(work_lists_dir, batch_size)?;
-    loop {
-        let wip_list = work_lists.next_batch()?.wip_list.clone();
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n20\n");
 
-        if wip_list.is_empty() {
-            break;
-        }
+        Ok(())
+    }
 
-        let map_ids_to_downloads =
-            s3_download::get(bucket_name.clone(), wip_list.clone(), output_dir.clone()).await?;
-        info!("... downloaded files:");
-        for file_id in &wip_list {
-            info!("\t{}", map_ids_to_downloads.get(file_id).unwrap());
+    #[tokio::test]
+    async fn test_run_sqs_source() -> Result<()> {
+        let test_name = "test_run_sqs_source";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        let sqs_client = aws_sdk_sqs::Client::new(&aws_config::from_env().load().await);
+        let queue_url = sqs_client
+            .create_queue()
+            .queue_name(test_name)
+            .send()
+            .await?
+            .queue_url
+            .unwrap();
+        env::set_var("TEST_RUN_SQS_QUEUE_URL", &queue_url);
+
+        // the test event s3 sends when notifications are set up, then one new file
+        let bodies = [
+            r#"{"Service":"Amazon S3","Event":"s3:TestEvent","Bucket":"customer-orders-parquet"}"#,
+            r#"{"Records":[{"eventVersion":"2.1","eventSource":"aws:s3","eventName":"ObjectCreated:Put",
+                "s3":{"bucket":{"name":"customer-orders-parquet"},"object":{"key":"order_00.parquet"}}}]}"#,
+        ];
+        for body in bodies {
+            sqs_client
+                .send_message()
+                .queue_url(&queue_url)
+                .message_body(body)
+                .send()
+                .await?;
         }
-        // parquet filename has the output_dir
-        for id in &wip_list {
-            let downloaded_file = map_ids_to_downloads.get(id.as_str()).unwrap();
-            info!("{}: handling downloaded parquet file", downloaded_file);
 
-            parquet_rows_to_db(downloaded_file.to_string(), desired_fields.clone(), &db).await?;
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
 
-            debug!("{}: will mark {} as completed", downloaded_file, id);
-            work_lists.mark_completed(id.to_string())?;
+        let attributes = sqs_client
+            .get_queue_attributes()
+            .queue_url(&queue_url)
+            .attribute_names(aws_sdk_sqs::types::QueueAttributeName::All)
+            .send()
+            .await?
+            .attributes
+            .unwrap_or_default();
+        sqs_client
+            .delete_queue()
+            .queue_url(&queue_url)
+            .send()
+            .await?;
+        result?;
 
-            debug!("{}: deleting downloaded file", downloaded_file);
-            s3_download::delete(downloaded_file.clone())?;
+        assert_eq!(completed?, "order_00.parquet\n");
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        // both messages deleted, not just waiting to be visible again
+        for name in [
+            aws_sdk_sqs::types::QueueAttributeName::ApproximateNumberOfMessages,
+            aws_sdk_sqs::types::QueueAttributeName::ApproximateNumberOfMessagesNotVisible,
+        ] {
+            assert_eq!(
+                attributes.get(&name).map(String::as_str),
+                Some("0"),
+                "{}",
+                name
+            );
         }
+
+        Ok(())
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_run_sqs_source_failed_key() -> Result<()> {
+        let test_name = "test_run_sqs_source_failed_key";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use assert_fs::{fixture::TempDir, prelude::*};
-    use const_format::formatcp;
-    use once_cell::sync::Lazy;
-    use std::collections::HashMap;
-    use std::env;
-    use std::path::Path;
-    use tokio::sync::Mutex;
-    use tokio_postgres::Client;
+        // same files and table as test_run_on_file_error_skip
+        let (tmp_dir, db_client) =
+            runner_tests_setup(test_name, "customer_order_violated_constraint").await?;
+        let sqs_client = aws_sdk_sqs::Client::new(&aws_config::from_env().load().await);
+        let queue_url = sqs_client
+            .create_queue()
+            .queue_name(test_name)
+            .send()
+            .await?
+            .queue_url
+            .unwrap();
+        env::set_var("TEST_RUN_SQS_QUEUE_URL", &queue_url);
+
+        // one message, with a file that loads and one that's skipped
+        let body = r#"{"Records":[
+            {"eventVersion":"2.1","eventSource":"aws:s3","eventName":"ObjectCreated:Put",
+             "s3":{"bucket":{"name":"customer-orders-parquet"},"object":{"key":"order_00.parquet"}}},
+            {"eventVersion":"2.1","eventSource":"aws:s3","eventName":"ObjectCreated:Put",
+             "s3":{"bucket":{"name":"customer-orders-parquet"},"object":{"key":"order_01.parquet"}}}
+        ]}"#;
+        sqs_client
+            .send_message()
+            .queue_url(&queue_url)
+            .message_body(body)
+            .send()
+            .await?;
 
-    #[allow(unused_imports)]
-    use crate::test_setup::tests::{
-        create_table_return_client, get_rows_as_csv_string, render_tmpl_str, restore_env,
-        set_good_aws_vars, setup_docker,
-    };
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        let failed = std::fs::read_to_string("work/failed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
 
-    static RUNNER_TESTDATA: &str = formatcp!(
-        "{}/{}",
-        env!("CARGO_MANIFEST_DIR"),
-        "tests/testdata/unit-tests/runner"
-    );
+        let attributes = sqs_client
+            .get_queue_attributes()
+            .queue_url(&queue_url)
+            .attribute_names(aws_sdk_sqs::types::QueueAttributeName::All)
+            .send()
+            .await?
+            .attributes
+            .unwrap_or_default();
+        sqs_client
+            .delete_queue()
+            .queue_url(&queue_url)
+            .send()
+            .await?;
+        let summary = result?;
+        assert_eq!((summary.files_processed, summary.files_failed), (1, 1));
 
-    static LOCK_ENV_RUNNER_TESTS: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+        // order_00.parquet has a null customer_name
+        assert_eq!(completed?, "order_01.parquet\n");
+        assert!(failed?.starts_with("order_00.parquet\t"));
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        // deleted, as both keys are done with, so won't come round again to load order_01 twice
+        for name in [
+            aws_sdk_sqs::types::QueueAttributeName::ApproximateNumberOfMessages,
+            aws_sdk_sqs::types::QueueAttributeName::ApproximateNumberOfMessagesNotVisible,
+        ] {
+            assert_eq!(
+                attributes.get(&name).map(String::as_str),
+                Some("0"),
+                "{}",
+                name
+            );
+        }
 
-    // test with orders parquet files
-    // yaml file should use relative paths (as will be in a temp dir)
-    // create a temp dir and cd to it
-    // test run.rs creates expected csv
+        Ok(())
+    }
 
-    async fn runner_tests_setup(test_name: &str, table_columns: &str) -> Result<(TempDir, Client)> {
-        let tmp_dir = TempDir::new().unwrap();
-        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+    #[tokio::test]
+    async fn test_run_sqs_source_redelivered() -> Result<()> {
+        let test_name = "test_run_sqs_source_redelivered";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
 
-        let src_dir = format!("{}/{}", RUNNER_TESTDATA, test_name);
-        tmp_dir.copy_from(src_dir, &["*", "todo"])?;
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        let sqs_client = aws_sdk_sqs::Client::new(&aws_config::from_env().load().await);
+        let queue_url = sqs_client
+            .create_queue()
+            .queue_name(test_name)
+            .send()
+            .await?
+            .queue_url
+            .unwrap();
+        env::set_var("TEST_RUN_SQS_QUEUE_URL", &queue_url);
+
+        // sqs delivers at least once, so the same event can come again after its key's loaded
+        let body = r#"{"Records":[{"eventVersion":"2.1","eventSource":"aws:s3","eventName":"ObjectCreated:Put",
+            "s3":{"bucket":{"name":"customer-orders-parquet"},"object":{"key":"order_00.parquet"}}}]}"#;
+        let mut results = vec![];
+        for _ in 0..2 {
+            sqs_client
+                .send_message()
+                .queue_url(&queue_url)
+                .message_body(body)
+                .send()
+                .await?;
+            results.push(run("config.yml", false, None).await);
+        }
+        let completed = std::fs::read_to_string("work/completed");
+        let todo = std::fs::read_to_string("work/todo");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
 
-        setup_docker(); // do this before creating test table!
-                        // create expected db table
-        let db_client = create_table_return_client(test_name.to_string(), table_columns).await?;
+        let attributes = sqs_client
+            .get_queue_attributes()
+            .queue_url(&queue_url)
+            .attribute_names(aws_sdk_sqs::types::QueueAttributeName::All)
+            .send()
+            .await?
+            .attributes
+            .unwrap_or_default();
+        sqs_client
+            .delete_queue()
+            .queue_url(&queue_url)
+            .send()
+            .await?;
+        let summaries = results.into_iter().collect::<Result<Vec<RunSummary>>>()?;
+        assert_eq!(summaries[0].files_processed, 1);
+        assert_eq!(summaries[1].files_processed, 0);
 
-        set_good_aws_vars();
-        env::set_current_dir(Path::new(&tmp_dir_path)).expect("Failed to cd to temp dir for test");
+        // loaded once, and not put back in todo
+        assert_eq!(completed?, "order_00.parquet\n");
+        assert_eq!(todo?.trim(), "");
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        // the copy received again is deleted too, rather than waiting to be visible again
+        for name in [
+            aws_sdk_sqs::types::QueueAttributeName::ApproximateNumberOfMessages,
+            aws_sdk_sqs::types::QueueAttributeName::ApproximateNumberOfMessagesNotVisible,
+        ] {
+            assert_eq!(
+                attributes.get(&name).map(String::as_str),
+                Some("0"),
+                "{}",
+                name
+            );
+        }
 
-        Ok((tmp_dir, db_client))
+        Ok(())
     }
 
     #[tokio::test]
@@ -169,7 +2166,7 @@ mod tests {
         let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
 
         // env_logger::init(); // uncomment for logs during cargo test -- --nocapture
-        run("config.yml").await?;
+        run("config.yml", false, None).await?;
         tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
         restore_env(original_env);
 
@@ -244,7 +2241,7 @@ mod tests {
         let (tmp_dir, db_client) = runner_tests_setup(test_name, "delivery").await?;
 
         // env_logger::init(); // uncomment for logs during cargo test -- --nocapture
-        run("config.yml").await?;
+        run("config.yml", false, None).await?;
         tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
         restore_env(original_env);
 
@@ -303,7 +2300,7 @@ mod tests {
         let (tmp_dir, db_client) = runner_tests_setup(test_name, "types_full").await?;
 
         env_logger::init(); // uncomment for logs during cargo test -- --nocapture
-        run("config.yml").await?;
+        run("config.yml", false, None).await?;
         tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
         restore_env(original_env);
 
@@ -342,17 +2339,257 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_run_dry_run_customer_orders() -> Result<()> {
+        let test_name = "test_run_dry_run_customer_orders";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+        let src_todo = std::fs::read_to_string("work/todo")?;
+
+        // env_logger::init(); // uncomment for logs during cargo test -- --nocapture
+        run("config.yml", true, None).await?;
+        let todo_after_run = std::fs::read_to_string("work/todo")?;
+        let wip_exists = Path::new("work/wip").exists();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
+        restore_env(original_env);
+
+        assert_eq!(src_todo, todo_after_run, "dry run should not modify todo");
+        assert!(!wip_exists, "dry run should not create a wip file");
+
+        // VERIFY DB RESULTS
+        let sql = format!("SELECT count(id) AS total from {}", test_name);
+        let exp_string = "\
+            total\n\
+            0\n\
+        ";
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str())
+            .await
+            .unwrap();
+        assert_eq!(
+            csv_string,
+            exp_string.to_string(),
+            "Expected no rows inserted into the db on a dry run.",
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_on_file_error_skip() -> Result<()> {
+        let test_name = "test_run_on_file_error_skip";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        // same files and table as test_run_customer_orders_constraint_violation
+        let (tmp_dir, db_client) =
+            runner_tests_setup(test_name, "customer_order_violated_constraint").await?;
+
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        let failed = std::fs::read_to_string("work/failed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+        let summary = result?;
+        assert_eq!((summary.files_processed, summary.files_failed), (5, 1));
+
+        // order_00.parquet has a null some_fraction, the rest load
+        assert_eq!(
+            completed?,
+            "order_01.parquet\norder_02.parquet\norder_03.parquet\norder_04.parquet\norder_05.parquet\n",
+            "the failed file should not be marked completed"
+        );
+        let failed = failed?;
+        assert!(
+            failed.starts_with("order_00.parquet\t"),
+            "unexpected failed file: {}",
+            failed
+        );
+        assert!(failed.contains("violates not-null constraint"));
+        assert_eq!(failed.lines().count(), 1);
+
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n50\n", "the other 5 files should load");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_allowed_codecs() -> Result<()> {
+        let test_name = "test_run_allowed_codecs";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) = runner_tests_setup(test_name, "customer_order").await?;
+
+        // the files are snappy compressed, so are quarantined, even though on_file_error is abort
+        let result = run("config.yml", false, None).await;
+        let quarantine = std::fs::read_to_string("work/quarantine");
+        let wip = std::fs::read_to_string("work/wip");
+        let completed_exists = Path::new("work/completed").exists();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+        let summary = result?;
+        assert_eq!((summary.files_processed, summary.files_failed), (0, 2));
+
+        let quarantine = quarantine?;
+        let keys: Vec<&str> = quarantine
+            .lines()
+            .map(|line| line.split('\t').next().unwrap())
+            .collect();
+        assert_eq!(keys, vec!["order_00.parquet", "order_01.parquet"]);
+        assert!(
+            quarantine.contains("is compressed with SNAPPY"),
+            "unexpected quarantine file: {}",
+            quarantine
+        );
+        assert_eq!(wip?, "");
+        assert!(!completed_exists);
+
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n0\n", "rejected before loading any rows");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_parse_error_redownload() -> Result<()> {
+        let test_name = "test_run_parse_error_redownload";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        let (tmp_dir, db_client) =
+            runner_tests_setup(test_name, "customer_order_violated_constraint").await?;
+
+        let result = run("config.yml", false, None).await;
+        let completed = std::fs::read_to_string("work/completed");
+        let failed = std::fs::read_to_string("work/failed");
+        let leftover_download = std::path::Path::new("out/corrupt_order_00.parquet").exists();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+        result?;
+
+        // corrupt_order_00.parquet is truncated in the bucket, so downloading it again can't help
+        assert_eq!(completed?, "order_01.parquet\n");
+        let failed = failed?;
+        assert!(
+            failed.starts_with("corrupt_order_00.parquet\t"),
+            "unexpected failed file: {}",
+            failed
+        );
+        assert!(
+            failed.contains("after downloading it 1 more time(s)"),
+            "unexpected failed file: {}",
+            failed
+        );
+        assert_eq!(failed.lines().count(), 1);
+        assert!(!leftover_download, "the corrupt download should be deleted");
+
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n10\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_max_attempts() -> Result<()> {
+        let test_name = "test_run_max_attempts";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        // same files and table as test_run_on_file_error_skip, but with on_file_error: abort
+        let (tmp_dir, db_client) =
+            runner_tests_setup(test_name, "customer_order_violated_constraint").await?;
+
+        // order_00.parquet has a null some_fraction, so fails each time, until dead-lettered
+        let first_run = run("config.yml", false, None).await;
+        let attempts = std::fs::read_to_string("work/attempts");
+        let second_run = run("config.yml", false, None).await;
+        let third_run = run("config.yml", false, None).await;
+        let dead_letter = std::fs::read_to_string("work/dead_letter");
+        let completed = std::fs::read_to_string("work/completed");
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+
+        assert!(first_run.is_err(), "should abort on the failing file");
+        assert_eq!(attempts?, "order_00.parquet\t1\n");
+        assert!(
+            second_run.is_err(),
+            "should retry the failing file, and abort"
+        );
+        third_run?;
+
+        let dead_letter = dead_letter?;
+        assert!(
+            dead_letter.starts_with("order_00.parquet\t"),
+            "unexpected dead_letter file: {}",
+            dead_letter
+        );
+        assert!(dead_letter.contains("violates not-null constraint"));
+        assert_eq!(
+            completed?,
+            "order_01.parquet\norder_02.parquet\norder_03.parquet\norder_04.parquet\norder_05.parquet\n",
+            "the dead-lettered file should not hold up the rest"
+        );
+
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n50\n", "the other 5 files should load");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_copy_reports_constraint_violation() -> Result<()> {
+        let test_name = "test_run_validate_copy";
+        let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+
+        // order_00.parquet has a null some_fraction, after 2 files that are fine
+        let (tmp_dir, db_client) =
+            runner_tests_setup(test_name, "customer_order_violated_constraint").await?;
+
+        let src_todo = std::fs::read_to_string("work/todo")?;
+        let result = validate_copy("config.yml").await;
+        let todo_after_run = std::fs::read_to_string("work/todo")?;
+        let wip_exists = Path::new("work/wip").exists();
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        restore_env(original_env);
+
+        let Err(e) = result else {
+            panic!("should report the constraint violation")
+        };
+        assert!(
+            e.to_string().contains("violates not-null constraint"),
+            "unexpected error: {}",
+            e
+        );
+        assert_eq!(src_todo, todo_after_run, "should not modify todo");
+        assert!(!wip_exists, "should not create a wip file");
+
+        // the files copied before the failure were rolled back too
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n0\n");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_run_customer_orders_constraint_violation() -> Result<()> {
         let test_name = "test_run_customer_orders_constraint_violation";
         let _env_lock = LOCK_ENV_RUNNER_TESTS.lock().await;
         let original_env: HashMap<String, String> = env::vars().collect();
 
-        let (tmp_dir, _) =
+        let (tmp_dir, db_client) =
             runner_tests_setup(test_name, "customer_order_violated_constraint").await?;
 
         // env_logger::init(); // uncomment for logs during cargo test -- --nocapture
-        let result = run("config.yml").await;
+        let result = run("config.yml", false, None).await;
 
         tmp_dir.close().unwrap(); // can be deleted as read what we need, and we'll verify in db
         restore_env(original_env);
@@ -370,6 +2607,11 @@ mod tests {
             );
         };
 
+        // the failing file is the first, and none of its rows should remain
+        let sql = format!("SELECT count(*) AS total from {}", test_name);
+        let csv_string = get_rows_as_csv_string(&db_client, sql.as_str()).await?;
+        assert_eq!(csv_string, "total\n0\n");
+
         Ok(())
     }
 }
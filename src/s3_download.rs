@@ -1,13 +1,382 @@
 use anyhow::{bail, Context, Result};
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::SdkConfig;
 use aws_sdk_s3 as s3;
+use flate2::write::MultiGzDecoder;
+use flate2::CrcWriter;
 use futures::stream::{self, StreamExt};
-use log::debug;
+use log::{debug, info, warn};
+use s3::config::SharedCredentialsProvider;
+use s3::error::{ProvideErrorMetadata, SdkError};
+use s3::operation::get_object::{GetObjectError, GetObjectOutput};
+use s3::primitives::ByteStream;
+use s3::types::Tag;
+use serde::{Serialize, Serializer};
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::fs::remove_file;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+// A key ending in this is gzip-wrapped parquet, e.g. orders.parquet.gz.
+// It's decompressed as it downloads, and saved without the suffix.
+const GZIP_SUFFIX: &str = ".gz";
+// Added to a gzipped key's local file name, which is never a parquet key itself.
+const GUNZIPPED_SUFFIX: &str = ".gunzipped";
+
+// Shows in CloudTrail as the assumed role's session, for s3.assume_role_arn
+const ASSUME_ROLE_SESSION_NAME: &str = "s3-pq-to-pg";
+
+// How many times to retry a GetObject per key, and the initial delay between attempts.
+// The delay doubles after each failed attempt, with jitter so retries don't all land at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetRetry {
+    pub retries: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for GetRetry {
+    fn default() -> Self {
+        GetRetry {
+            retries: 0,
+            backoff_ms: 200,
+        }
+    }
+}
+
+// Overrides for the SDK client's own retries and timeouts, which sit under GetRetry:
+// each of our attempts is up to max_attempts SDK attempts. None keeps the SDK's default.
+// endpoint_url and region, if set, take precedence over AWS_ENDPOINT_URL, AWS_REGION etc.
+// assume_role_arn, if set, is assumed with the env's credentials, and its credentials used.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SdkSettings {
+    pub max_attempts: Option<u32>,
+    pub operation_timeout_ms: Option<u64>, // for a whole call, SDK retries and all
+    pub endpoint_url: Option<String>,
+    pub region: Option<String>,
+    pub assume_role_arn: Option<String>,
+    pub external_id: Option<String>, // only with assume_role_arn
+}
+
+// The shared config's other retry and timeout settings are kept.
+fn s3_client_config(shared: &SdkConfig, sdk: &SdkSettings) -> Result<s3::Config> {
+    let mut builder = s3::config::Builder::from(shared);
+    if let Some(max_attempts) = sdk.max_attempts {
+        if max_attempts < 1 {
+            bail!("s3.sdk_max_attempts must be at least 1");
+        }
+        let retry_config = shared
+            .retry_config()
+            .cloned()
+            .unwrap_or_else(s3::config::retry::RetryConfig::standard)
+            .with_max_attempts(max_attempts);
+        builder = builder.retry_config(retry_config);
+    }
+    if let Some(timeout_ms) = sdk.operation_timeout_ms {
+        let timeout_config = shared
+            .timeout_config()
+            .map(|t| t.to_builder())
+            .unwrap_or_default()
+            .operation_timeout(Duration::from_millis(timeout_ms))
+            .build();
+        builder = builder.timeout_config(timeout_config);
+    }
+    Ok(builder.build())
+}
+
+pub async fn s3_client(sdk: &SdkSettings) -> Result<s3::Client> {
+    let mut loader = aws_config::from_env();
+    if let Some(endpoint_url) = &sdk.endpoint_url {
+        loader = loader.endpoint_url(endpoint_url);
+    }
+    if let Some(region) = &sdk.region {
+        loader = loader.region(aws_config::Region::new(region.clone()));
+    }
+    let mut config = loader.load().await;
+    if let Some(role_arn) = &sdk.assume_role_arn {
+        config = with_assumed_role(config, role_arn, sdk.external_id.as_deref()).await;
+    }
+    Ok(s3::Client::from_conf(s3_client_config(&config, sdk)?))
+}
+
+// STS is only called when credentials are first needed, and again before they expire
+async fn with_assumed_role(
+    shared: SdkConfig,
+    role_arn: &str,
+    external_id: Option<&str>,
+) -> SdkConfig {
+    info!("s3 credentials from assuming role {}", role_arn);
+    let mut builder = AssumeRoleProvider::builder(role_arn)
+        .session_name(ASSUME_ROLE_SESSION_NAME)
+        .configure(&shared);
+    if let Some(external_id) = external_id {
+        builder = builder.external_id(external_id);
+    }
+    let provider = builder.build().await;
+    shared
+        .into_builder()
+        .credentials_provider(SharedCredentialsProvider::new(provider))
+        .build()
+}
+
+// Throttling and 5xx responses, and timeouts or dropped connections, may pass.
+// Anything else (NoSuchKey, AccessDenied, a bad request) won't, so fails fast.
+fn is_retryable(e: &SdkError<GetObjectError>) -> bool {
+    match e {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        SdkError::ServiceError(ctx) => {
+            let status = ctx.raw().status();
+            status.is_server_error()
+                || status.as_u16() == 429
+                || matches!(
+                    ctx.err().code(),
+                    Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestTimeout")
+                )
+        }
+        _ => false,
+    }
+}
+
+// somewhere between half and all of the delay
+fn with_jitter(delay_ms: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(nanos);
+    let half = delay_ms / 2;
+    half + hasher.finish() % (delay_ms - half + 1)
+}
+
+async fn get_object_with_retry(
+    client: &s3::Client,
+    bucket_name: &str,
+    key: &str,
+    retry: &GetRetry,
+) -> Result<GetObjectOutput> {
+    let mut attempt: u32 = 0;
+    loop {
+        let result = client
+            .get_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => return Ok(output),
+            Err(e) if attempt < retry.retries && is_retryable(&e) => {
+                let delay_ms = with_jitter(
+                    retry
+                        .backoff_ms
+                        .saturating_mul(2_u64.saturating_pow(attempt)),
+                );
+                attempt += 1;
+                warn!(
+                    "get object with key {} failed: {} ... retry {} of {} in {}ms",
+                    key, e, attempt, retry.retries, delay_ms
+                );
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            }
+            Err(e) if is_retryable(&e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Failed to get object with key: {} (gave up after {} retries)",
+                        key, attempt
+                    )
+                })
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to get object with key: {} (not retryable)", key)
+                })
+            }
+        }
+    }
+}
+
+// Limits the total size of objects being downloaded at once.
+// Semaphore permits are taken per KiB, as a single acquire is limited to u32 permits.
+// An object bigger than the limit is still downloaded, but only once nothing else is in flight.
+struct InflightLimit {
+    semaphore: Arc<Semaphore>,
+    max_kib: u32,
+}
+
+impl InflightLimit {
+    fn new(max_inflight_bytes: u64) -> Self {
+        // round down, so in-flight bytes never exceed max_inflight_bytes. Config keeps that
+        // at 1 KiB or more, so there's always a permit
+        let max_kib = (max_inflight_bytes / 1024).clamp(1, u32::MAX as u64) as u32;
+        InflightLimit {
+            semaphore: Arc::new(Semaphore::new(max_kib as usize)),
+            max_kib,
+        }
+    }
+
+    async fn acquire(&self, object_bytes: u64) -> Result<OwnedSemaphorePermit> {
+        // round up, so the permits always cover the object's bytes
+        let kib = object_bytes.div_ceil(1024).clamp(1, self.max_kib as u64) as u32;
+        let permit = self.semaphore.clone().acquire_many_owned(kib).await?;
+        Ok(permit)
+    }
+}
+
+async fn object_size(client: &s3::Client, bucket_name: &str, key: &str) -> Result<u64> {
+    let head = client
+        .head_object()
+        .bucket(bucket_name)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("Failed to get object metadata with key: {}", key))?;
+
+    Ok(head.content_length().unwrap_or(0).max(0) as u64)
+}
+
+// A .parquet key, or a gzipped one. Only one .gz is taken off, as it's only gunzipped once.
+pub fn is_parquet_key(key: &str) -> bool {
+    key.strip_suffix(GZIP_SUFFIX)
+        .unwrap_or(key)
+        .ends_with(".parquet")
+}
+
+// max_keys is the most keys per page, None for S3's default of 1000
+async fn list_parquet_keys(
+    client: &s3::Client,
+    bucket_name: &str,
+    prefix: &str,
+    max_keys: Option<i32>,
+) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = vec![];
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let page = client
+            .list_objects_v2()
+            .bucket(bucket_name)
+            .prefix(prefix)
+            .set_max_keys(max_keys)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to list objects in {} under {:?}",
+                    bucket_name, prefix
+                )
+            })?;
+
+        keys.extend(
+            page.contents()
+                .iter()
+                .filter_map(|object| object.key())
+                .filter(|key| is_parquet_key(key))
+                .map(String::from),
+        );
+
+        match page.next_continuation_token() {
+            Some(token) if page.is_truncated().unwrap_or(false) => {
+                continuation_token = Some(token.to_string())
+            }
+            _ => break,
+        }
+    }
+
+    Ok(keys)
+}
+
+// returns the .parquet (and .parquet.gz) keys under prefix, in S3's (lexicographic) key order.
+// An empty prefix lists the whole bucket.
+pub async fn list_keys(bucket_name: &str, prefix: &str, sdk: &SdkSettings) -> Result<Vec<String>> {
+    let client = s3_client(sdk).await?;
+
+    let keys = list_parquet_keys(&client, bucket_name, prefix, None).await?;
+    debug!("listed {} parquet keys under {:?}", keys.len(), prefix);
+    Ok(keys)
+}
+
+// Whether tag_set has every one of require_tags, with the same value. Other tags don't matter.
+fn has_tags(tag_set: &[Tag], require_tags: &HashMap<String, String>) -> bool {
+    require_tags.iter().all(|(key, value)| {
+        tag_set
+            .iter()
+            .any(|tag| tag.key() == key && tag.value() == value)
+    })
+}
+
+async fn object_has_tags(
+    client: &s3::Client,
+    bucket_name: &str,
+    key: &str,
+    require_tags: &HashMap<String, String>,
+) -> Result<bool> {
+    let tagging = client
+        .get_object_tagging()
+        .bucket(bucket_name)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("Failed to get the tags of object with key: {}", key))?;
+
+    Ok(has_tags(tagging.tag_set(), require_tags))
+}
+
+// For s3.require_tags: the keys whose objects have all the tags, then those that don't (yet),
+// each in the order given. download_concurrency is the most tag lookups at once.
+pub async fn split_by_tags(
+    bucket_name: &str,
+    keys: Vec<String>,
+    require_tags: &HashMap<String, String>,
+    download_concurrency: usize,
+    sdk: &SdkSettings,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let client = s3_client(sdk).await?;
+    let checked: Vec<Result<(String, bool)>> = stream::iter(keys)
+        .map(|key| {
+            let client = &client;
+            async move {
+                let tagged = object_has_tags(client, bucket_name, &key, require_tags).await?;
+                Ok((key, tagged))
+            }
+        })
+        .buffered(download_concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut ready: Vec<String> = vec![];
+    let mut not_ready: Vec<String> = vec![];
+    for result in checked {
+        match result? {
+            (key, true) => ready.push(key),
+            (key, false) => not_ready.push(key),
+        }
+    }
+    Ok((ready, not_ready))
+}
+
+// A truncated download would only show up later, as a confusing corrupt parquet error.
+// The ETag isn't checked: for multipart uploads it isn't an MD5 of the object.
+fn check_downloaded_size(key: &str, content_length: Option<i64>, file_size: u64) -> Result<()> {
+    match content_length {
+        Some(expected) if expected != file_size as i64 => bail!(
+            "Downloaded object with key: {} is incomplete: wrote {} bytes, expected {}",
+            key,
+            file_size,
+            expected
+        ),
+        _ => Ok(()), // no Content-Length, so nothing to check against
+    }
+}
 
 fn get_dirpath(path_str: &str) -> Result<Option<String>> {
     let path = Path::new(path_str);
@@ -30,14 +399,125 @@ fn get_dirpath(path_str: &str) -> Result<Option<String>> {
     Ok(None)
 }
 
+// Where the key is downloaded to. A gzipped key is saved decompressed, under its key
+// plus GUNZIPPED_SUFFIX, rather than without the .gz, so x.parquet and x.parquet.gz
+// in one batch don't download over each other.
+fn local_file_name(output_dir: &str, key: &str) -> String {
+    if key.ends_with(GZIP_SUFFIX) {
+        format!("{}/{}{}", output_dir, key, GUNZIPPED_SUFFIX)
+    } else {
+        format!("{}/{}", output_dir, key)
+    }
+}
+
+// Downloads one object to local_file_name(), creating any dirs in the key's path.
+async fn download_object(
+    client: &s3::Client,
+    bucket_name: &str,
+    key: &str,
+    output_dir: &str,
+    inflight_limit: Option<&InflightLimit>,
+    retry: &GetRetry,
+) -> Result<()> {
+    // create local dirpath to match s3 object path
+    if let Some(dirpath) = get_dirpath(key)? {
+        debug!("... creating dir {}", dirpath);
+        tokio::fs::create_dir_all(format!("{}/{}", output_dir, dirpath))
+            .await
+            .with_context(|| format!("Failed to create dirpath {}", dirpath))?;
+    }
+
+    // hold any permit until the object is written to file
+    let _permit = match inflight_limit {
+        None => None,
+        Some(limit) => Some(
+            limit
+                .acquire(object_size(client, bucket_name, key).await?)
+                .await?,
+        ),
+    };
+
+    let mut output = get_object_with_retry(client, bucket_name, key, retry).await?;
+    let content_length = output.content_length();
+    let file_name = local_file_name(output_dir, key);
+    let mut file = File::create(&file_name)
+        .await
+        .with_context(|| format!("Failed to create file {}", file_name))?;
+    // decompressed chunks collect in the decoder's Vec, and are written out as they come
+    let mut gunzip = key
+        .ends_with(GZIP_SUFFIX)
+        .then(|| MultiGzDecoder::new(Vec::new()));
+    let mut received: u64 = 0;
+    while let Some(bytes) = output
+        .body
+        .try_next()
+        .await
+        .with_context(|| format!("Failed to read chunk of object with key: {}", key))?
+    {
+        received += bytes.len() as u64;
+        let bytes = match gunzip.as_mut() {
+            None => &bytes[..],
+            Some(decoder) => {
+                decoder.get_mut().clear();
+                decoder
+                    .write_all(&bytes)
+                    .with_context(|| format!("Failed to gunzip object with key: {}", key))?;
+                &decoder.get_ref()[..]
+            }
+        };
+        file.write_all(bytes)
+            .await
+            .with_context(|| format!("Failed to write to file {}", file_name))?;
+    }
+    if let Some(mut decoder) = gunzip {
+        // errors if the gzip stream is incomplete, or its checksum doesn't match
+        decoder.get_mut().clear();
+        decoder
+            .try_finish()
+            .with_context(|| format!("Failed to gunzip object with key: {}", key))?;
+        file.write_all(decoder.get_ref())
+            .await
+            .with_context(|| format!("Failed to write to file {}", file_name))?;
+        file.flush()
+            .await
+            .with_context(|| format!("Failed to write to file {}", file_name))?;
+        // the file is bigger than the object, so check what was received
+        return check_downloaded_size(key, content_length, received);
+    }
+    // tokio writes in the background, so flush before checking the size
+    file.flush()
+        .await
+        .with_context(|| format!("Failed to write to file {}", file_name))?;
+    let file_size = file
+        .metadata()
+        .await
+        .with_context(|| format!("Failed to stat file {}", file_name))?
+        .len();
+    check_downloaded_size(key, content_length, file_size)
+}
+
 // returns a Vec of the locally downloaded files to process
+// download_concurrency is the most GETs in flight at once.
+// If max_inflight_bytes is set, each object's size is fetched first, so the
+// total size of the objects being downloaded at once stays under the limit.
+// Each key's GET is retried on its own, so one throttled key doesn't fail the batch.
 pub async fn get(
     bucket_name: String,
     s3_keys: Vec<String>,
     output_dir: String,
+    download_concurrency: usize,
+    max_inflight_bytes: Option<u64>,
+    retry: &GetRetry,
+    sdk: &SdkSettings,
 ) -> Result<HashMap<String, String>> {
-    let config = aws_config::load_from_env().await;
-    let client = s3::Client::new(&config);
+    if download_concurrency < 1 {
+        bail!("download concurrency must be at least 1");
+    }
+
+    let client = s3_client(sdk).await?;
+    let inflight_limit: Option<Arc<InflightLimit>> =
+        max_inflight_bytes.map(|max| Arc::new(InflightLimit::new(max)));
+    let retry = retry.clone();
 
     debug!("Handling these parquet files:");
     for k in s3_keys.iter() {
@@ -47,65 +527,35 @@ pub async fn get(
     // Note that we can always clone a Vec<String> because String is cloneable (even if Vec is not)
     let map_ids_to_downloads: HashMap<String, String> = s3_keys
         .iter()
-        .map(|k| (k.clone(), format!("{}/{}", output_dir.clone(), k)))
+        .map(|k| (k.clone(), local_file_name(&output_dir, k)))
         .collect();
 
     // Create an mpsc channel to handle errors
     let (tx, mut rx) = mpsc::channel(1);
 
-    let s3_get_object_requests = stream::iter(s3_keys.into_iter())
+    let s3_get_object_requests = stream::iter(s3_keys)
         .map(move |key| {
             let client = client.clone();
             let bucket_name = bucket_name.clone();
-            // create local dirpath to match s3 object path
-            if let Some(dirpath) = get_dirpath(key.as_str()).unwrap() {
-                debug!("... creating dir {}", dirpath);
-                std::fs::create_dir_all(format!("{}/{}", output_dir.as_str(), dirpath))
-                    .with_context(|| format!("Failed to create dirpath {}", dirpath))
-                    .unwrap();
-            }
             let tx = tx.clone(); // Clone the sender for each async task
             let local_output_dir = output_dir.clone();
+            let inflight_limit = inflight_limit.clone();
+            let retry = retry.clone();
             async move {
-                let result = client
-                    .get_object()
-                    .bucket(bucket_name)
-                    .key(key.clone())
-                    .send()
-                    .await
-                    .with_context(|| format!("Failed to get object with key: {}", key));
-
-                match result {
-                    Ok(mut output) => {
-                        let file_name = format!("{}/{}", local_output_dir, key.clone());
-                        let mut file = File::create(&file_name)
-                            .await
-                            .with_context(|| format!("Failed to create file {}", file_name))
-                            .unwrap();
-                        while let Some(bytes) = output
-                            .body
-                            .try_next()
-                            .await
-                            .context("Failed to read chunk")
-                            .unwrap()
-                        {
-                            file.write_all(&bytes)
-                                .await
-                                .context("Failed to write to file")
-                                .unwrap();
-                        }
-                    }
-                    Err(err) => {
-                        // Send the error to the channel
-                        let _ = tx.send(Err(err)).await;
-                    }
-                }
-
-                // Send a success message
-                let _ = tx.send(Ok(())).await;
+                // any failure goes back through the channel, so a bad key can't panic the task
+                let result = download_object(
+                    &client,
+                    &bucket_name,
+                    &key,
+                    &local_output_dir,
+                    inflight_limit.as_deref(),
+                    &retry,
+                )
+                .await;
+                let _ = tx.send(result).await;
             }
         })
-        .buffer_unordered(5);
+        .buffer_unordered(download_concurrency);
 
     // Launch the concurrent processing
     tokio::spawn(async move {
@@ -122,6 +572,66 @@ pub async fn get(
     Ok(map_ids_to_downloads)
 }
 
+// For s3.receipt_prefix: what was loaded from a key, written back to the bucket as JSON.
+// The crc32 is of the parquet file as loaded, so of a gzipped key once decompressed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Receipt {
+    pub key: String,
+    pub rows: u64,
+    #[serde(serialize_with = "crc32_hex")]
+    pub crc32: u32,
+}
+
+// The crc32 as 8 hex digits, as crc32 tools print it
+fn crc32_hex<S: Serializer>(crc32: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:08x}", crc32))
+}
+
+impl Receipt {
+    pub fn new(key: &str, rows: u64, downloaded_file: &str) -> Result<Self> {
+        let mut crc_writer = CrcWriter::new(io::sink());
+        let mut file = std::fs::File::open(downloaded_file)
+            .with_context(|| format!("Failed to open file {}", downloaded_file))?;
+        io::copy(&mut file, &mut crc_writer)
+            .with_context(|| format!("Failed to read file {}", downloaded_file))?;
+        Ok(Receipt {
+            key: key.to_string(),
+            rows,
+            crc32: crc_writer.crc().sum(),
+        })
+    }
+
+    // e.g. receipts/2024/order_00.parquet.receipt.json for key 2024/order_00.parquet
+    pub fn receipt_key(&self, receipt_prefix: &str) -> String {
+        format!("{}{}.receipt.json", receipt_prefix, self.key)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+// Writes the receipt under receipt_prefix, replacing any from an earlier load of the key
+pub async fn put_receipt(
+    client: &s3::Client,
+    bucket_name: &str,
+    receipt_prefix: &str,
+    receipt: &Receipt,
+) -> Result<()> {
+    let receipt_key = receipt.receipt_key(receipt_prefix);
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key(&receipt_key)
+        .content_type("application/json")
+        .body(ByteStream::from(receipt.to_json()?.into_bytes()))
+        .send()
+        .await
+        .with_context(|| format!("Failed to put receipt with key: {}", receipt_key))?;
+    debug!("put receipt {}", receipt_key);
+    Ok(())
+}
+
 pub fn delete(filename: String) -> Result<()> {
     remove_file(filename)?;
     Ok(())
@@ -134,11 +644,14 @@ mod tests {
     use assert_fs::fixture::TempDir;
     use once_cell::sync::Lazy;
     use std::env;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use tokio::sync::Mutex;
+    use tokio::time::{sleep, Duration};
 
     use crate::test_setup::tests::{
         get_downloaded_and_src_file_contents, restore_env, set_good_aws_vars, setup_docker,
-        unset_aws_env_vars, LOCALSTACK_PARQUET_DIR_CUSTOMERS, LOCALSTACK_PARQUET_DIR_DELIVERIES,
+        unset_aws_env_vars, LOCALSTACK_PARQUET_DIR_CARS, LOCALSTACK_PARQUET_DIR_CUSTOMERS,
+        LOCALSTACK_PARQUET_DIR_DELIVERIES,
     };
 
     static LOCK_ENV_S3_DOWNLOAD_TESTS: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
@@ -258,6 +771,261 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_local_file_name() {
+        assert_eq!(local_file_name("/dl", "a/b.parquet"), "/dl/a/b.parquet");
+        assert_eq!(
+            local_file_name("/dl", "a/b.parquet.gz"),
+            "/dl/a/b.parquet.gz.gunzipped"
+        );
+        // a key and its gzipped copy don't download over each other
+        assert_ne!(
+            local_file_name("/dl", "b.parquet"),
+            local_file_name("/dl", "b.parquet.gz")
+        );
+    }
+
+    #[test]
+    fn test_is_parquet_key() {
+        assert!(is_parquet_key("a/b.parquet"));
+        assert!(is_parquet_key("a/b.parquet.gz"));
+        assert!(!is_parquet_key("a/b.parquet.gz.gz"));
+        assert!(!is_parquet_key("a/b.parquet.gz.gunzipped"));
+        assert!(!is_parquet_key("a/b.csv.gz"));
+    }
+
+    #[test]
+    fn test_receipt() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let downloaded_file = format!("{}/a.parquet", tmp_dir.path().display());
+        std::fs::write(&downloaded_file, "123456789")?;
+
+        let receipt = Receipt::new("2024/\"a\".parquet", 10, &downloaded_file)?;
+        tmp_dir.close().unwrap();
+
+        // the standard CRC-32 check value
+        assert_eq!(receipt.crc32, 0xcbf43926);
+        assert_eq!(
+            receipt.receipt_key("receipts/"),
+            "receipts/2024/\"a\".parquet.receipt.json"
+        );
+        assert_eq!(
+            receipt.to_json()?,
+            r#"{"key":"2024/\"a\".parquet","rows":10,"crc32":"cbf43926"}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_tags() -> Result<()> {
+        let tag_set = vec![
+            Tag::builder().key("status").value("ready").build()?,
+            Tag::builder().key("team").value("orders").build()?,
+        ];
+        let require = |tags: &[(&str, &str)]| -> HashMap<String, String> {
+            tags.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        assert!(has_tags(&tag_set, &require(&[("status", "ready")])));
+        assert!(has_tags(&tag_set, &require(&[])), "nothing required");
+        assert!(!has_tags(&tag_set, &require(&[("status", "uploading")])));
+        assert!(!has_tags(
+            &tag_set,
+            &require(&[("status", "ready"), ("owner", "me")])
+        ));
+        assert!(!has_tags(&[], &require(&[("status", "ready")])));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_downloaded_size() {
+        assert!(check_downloaded_size("a.parquet", Some(1024), 1024).is_ok());
+        assert!(
+            check_downloaded_size("a.parquet", None, 1024).is_ok(),
+            "nothing to check without a content length"
+        );
+
+        let result = check_downloaded_size("a.parquet", Some(1024), 1000);
+        assert!(result.is_err(), "should fail on a truncated download");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Downloaded object with key: a.parquet is incomplete: wrote 1000 bytes, expected 1024"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inflight_limit_caps_inflight_bytes() -> Result<()> {
+        let max_inflight_bytes: u64 = 100 * 1024;
+        let limit = Arc::new(InflightLimit::new(max_inflight_bytes));
+        let inflight = Arc::new(AtomicU64::new(0));
+        let peak = Arc::new(AtomicU64::new(0));
+
+        // varied sizes, including odd byte counts that don't fill a KiB
+        let sizes: Vec<u64> = vec![
+            60 * 1024,
+            30 * 1024 + 1,
+            45 * 1024,
+            10,
+            99 * 1024,
+            1024,
+            70 * 1024,
+        ];
+        let tasks: Vec<_> = sizes
+            .into_iter()
+            .map(|size| {
+                let limit = limit.clone();
+                let inflight = inflight.clone();
+                let peak = peak.clone();
+                tokio::spawn(async move {
+                    let _permit = limit.acquire(size).await.unwrap();
+                    let now = inflight.fetch_add(size, Ordering::SeqCst) + size;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    sleep(Duration::from_millis(20)).await; // pretend to download
+                    inflight.fetch_sub(size, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await?;
+        }
+
+        let peak = peak.load(Ordering::SeqCst);
+        assert!(
+            peak <= max_inflight_bytes,
+            "in-flight bytes peaked at {}, over the limit of {}",
+            peak,
+            max_inflight_bytes
+        );
+        assert!(
+            peak > 99 * 1024,
+            "should still download more than one at once"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inflight_limit_object_bigger_than_limit_downloads_alone() -> Result<()> {
+        let limit = InflightLimit::new(10 * 1024);
+
+        let big = limit.acquire(50 * 1024).await?; // doesn't wait forever
+        assert_eq!(
+            limit.semaphore.available_permits(),
+            0,
+            "nothing else in flight"
+        );
+        drop(big);
+
+        assert_eq!(limit.semaphore.available_permits(), 10);
+
+        Ok(())
+    }
+
+    fn get_object_service_error(status: u16, err: GetObjectError) -> SdkError<GetObjectError> {
+        use s3::config::http::HttpResponse;
+        use s3::primitives::SdkBody;
+
+        let raw = HttpResponse::new(status.try_into().unwrap(), SdkBody::empty());
+        SdkError::service_error(err, raw)
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        use s3::error::ErrorMetadata;
+        use s3::types::error::NoSuchKey;
+
+        let throttled = GetObjectError::generic(ErrorMetadata::builder().code("SlowDown").build());
+        let unavailable = GetObjectError::generic(ErrorMetadata::builder().build());
+        let denied = GetObjectError::generic(ErrorMetadata::builder().code("AccessDenied").build());
+
+        assert!(is_retryable(&get_object_service_error(503, throttled)));
+        assert!(is_retryable(&get_object_service_error(500, unavailable)));
+        assert!(is_retryable(&SdkError::timeout_error("timed out")));
+        assert!(
+            !is_retryable(&get_object_service_error(
+                404,
+                GetObjectError::NoSuchKey(NoSuchKey::builder().build())
+            )),
+            "NoSuchKey should fail fast"
+        );
+        assert!(
+            !is_retryable(&get_object_service_error(403, denied)),
+            "AccessDenied should fail fast"
+        );
+    }
+
+    #[test]
+    fn test_s3_client_config() -> Result<()> {
+        let shared = SdkConfig::builder()
+            .timeout_config(
+                s3::config::timeout::TimeoutConfig::builder()
+                    .connect_timeout(Duration::from_secs(3))
+                    .build(),
+            )
+            .build();
+
+        let sdk = SdkSettings {
+            max_attempts: Some(7),
+            operation_timeout_ms: Some(1500),
+            ..Default::default()
+        };
+        let config = s3_client_config(&shared, &sdk)?;
+        let timeouts = config.timeout_config().unwrap();
+        assert_eq!(config.retry_config().unwrap().max_attempts(), 7);
+        assert_eq!(
+            timeouts.operation_timeout(),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            timeouts.connect_timeout(),
+            Some(Duration::from_secs(3)),
+            "should keep the shared config's other timeouts"
+        );
+
+        // unset keeps the shared config's settings
+        let config = s3_client_config(&shared, &SdkSettings::default())?;
+        assert_eq!(config.timeout_config().unwrap().operation_timeout(), None);
+
+        let zero_attempts = SdkSettings {
+            max_attempts: Some(0),
+            ..Default::default()
+        };
+        assert!(s3_client_config(&shared, &zero_attempts).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_jitter_stays_between_half_and_all_of_delay() {
+        for _ in 0..100 {
+            let delay_ms = with_jitter(1000);
+            assert!((500..=1000).contains(&delay_ms), "got {}ms", delay_ms);
+        }
+        assert_eq!(with_jitter(0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_zero_download_concurrency() -> Result<()> {
+        let res = get(
+            String::from("customer-orders-parquet"),
+            vec_stringify!["order_00.parquet"],
+            ".".to_string(),
+            0,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
+        )
+        .await;
+
+        assert!(res.is_err(), "should need at least 1 download at a time");
+
+        Ok(())
+    }
+
     // pub async fn get(bucket_name: String, s3_keys: Vec<String>, output_dir: String,) -> Result<HashMap<String, String>>
     #[tokio::test]
     async fn test_bad_aws_creds() -> Result<()> {
@@ -271,6 +1039,10 @@ mod tests {
             String::from("no-such-bucket"),
             vec_stringify!["order_001.parquet", "order_002.parquet"],
             ".".to_string(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
         )
         .await;
 
@@ -293,6 +1065,10 @@ mod tests {
             String::from("no-such-bucket"),
             vec_stringify!["order_001.parquet", "order_002.parquet"],
             ".".to_string(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
         )
         .await;
 
@@ -318,6 +1094,10 @@ mod tests {
             String::from("customer-orders-parquet"),
             vec_stringify!["not-a-real-key", "order_01.parquet"], // [not real, real] key
             tmp_dir_path.clone(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
         )
         .await;
 
@@ -333,6 +1113,287 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_unknown_key_with_retries_fails_fast() -> Result<()> {
+        setup_docker();
+        // set up aws env vars for localstack
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+
+        let retry = GetRetry {
+            retries: 5,
+            backoff_ms: 10_000, // would take minutes if NoSuchKey were retried
+        };
+        let started = std::time::Instant::now();
+        let res = get(
+            String::from("customer-orders-parquet"),
+            vec_stringify!["not-a-real-key"],
+            tmp_dir_path.clone(),
+            5,
+            None,
+            &retry,
+            &SdkSettings::default(),
+        )
+        .await;
+
+        restore_env(original_env);
+        tmp_dir.close().unwrap();
+
+        let Err(err) = res else {
+            bail!("should fail on not-a-real-key")
+        };
+        assert!(
+            err.to_string().contains("not retryable"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "NoSuchKey should not be retried"
+        );
+
+        Ok(())
+    }
+
+    // root ignores a read-only dir, so use an output dir under a regular file:
+    // creating anything in it fails with ENOTDIR for any user.
+    #[tokio::test]
+    async fn test_get_file_creation_failure_is_an_error() -> Result<()> {
+        setup_docker();
+        // set up aws env vars for localstack
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let not_a_dir = tmp_dir.path().join("not-a-dir");
+        std::fs::write(&not_a_dir, "")?;
+        let output_dir = format!("{}", not_a_dir.display());
+
+        let res = get(
+            String::from("customer-orders-parquet"),
+            vec_stringify!["order_00.parquet"],
+            output_dir.clone(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
+        )
+        .await;
+        let res_subdirs = get(
+            String::from("deliveries-parquet"),
+            vec_stringify!["parent_dir/subdir_a/001.parquet"],
+            output_dir,
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
+        )
+        .await;
+
+        restore_env(original_env);
+        tmp_dir.close().unwrap();
+
+        let Err(err) = res else {
+            bail!("should fail to create the file")
+        };
+        assert!(
+            err.to_string().contains("Failed to create file"),
+            "unexpected error: {}",
+            err
+        );
+        let Err(err) = res_subdirs else {
+            bail!("should fail to create the dirpath")
+        };
+        assert!(
+            err.to_string().contains("Failed to create dirpath"),
+            "unexpected error: {}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_dir_like_key_is_an_error() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+
+        let res = get(
+            String::from("deliveries-parquet"),
+            vec_stringify!["parent_dir/subdir_a/"],
+            tmp_dir_path,
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
+        )
+        .await;
+        tmp_dir.close().unwrap();
+
+        assert!(res.is_err(), "should fail, not panic, on a dir-like key");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_happy_path_with_retries() -> Result<()> {
+        setup_docker();
+        // set up aws env vars for localstack
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+        let retry = GetRetry {
+            retries: 3,
+            backoff_ms: 50,
+        };
+        let res = get(
+            String::from("customer-orders-parquet"),
+            vec_stringify!["order_00.parquet", "order_01.parquet"], // [real, real] key
+            tmp_dir_path.clone(),
+            5,
+            None,
+            &retry,
+            &SdkSettings::default(),
+        )
+        .await;
+
+        restore_env(original_env);
+
+        assert!(res.is_ok(), "should download with retries configured");
+
+        for (s3_key, downloaded_file) in &res.unwrap() {
+            let (src_contents, downloaded_contents) = get_downloaded_and_src_file_contents(
+                format!("{}/{}", LOCALSTACK_PARQUET_DIR_CUSTOMERS, s3_key),
+                downloaded_file.to_string(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(src_contents, downloaded_contents,);
+        }
+
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_under_prefix() -> Result<()> {
+        setup_docker();
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let res = list_keys("deliveries-parquet", "parent_dir/", &SdkSettings::default()).await;
+
+        restore_env(original_env);
+
+        assert_eq!(
+            res?,
+            vec_stringify![
+                "parent_dir/subdir_a/001.parquet",
+                "parent_dir/subdir_b/001.parquet"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_s3_client_with_assume_role() -> Result<()> {
+        setup_docker();
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let sdk = SdkSettings {
+            assume_role_arn: Some("arn:aws:iam::123456789012:role/parquet-reader".to_string()),
+            external_id: Some("orders-pipeline".to_string()),
+            ..Default::default()
+        };
+        // localstack's STS hands out credentials for any role, and its S3 takes them
+        let res = list_keys("deliveries-parquet", "parent_dir/", &sdk).await;
+
+        restore_env(original_env);
+
+        assert_eq!(
+            res?,
+            vec_stringify![
+                "parent_dir/subdir_a/001.parquet",
+                "parent_dir/subdir_b/001.parquet"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_follows_continuation_tokens() -> Result<()> {
+        setup_docker();
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let config = aws_config::load_from_env().await;
+        let client = s3::Client::new(&config);
+        // a page per key, so only complete if every page is fetched
+        let paged = list_parquet_keys(&client, "deliveries-parquet", "parent_dir/", Some(1)).await;
+        // types-parquet also has non-parquet files, which should be skipped
+        let filtered = list_parquet_keys(&client, "types-parquet", "", None).await;
+
+        restore_env(original_env);
+
+        assert_eq!(
+            paged?,
+            vec_stringify![
+                "parent_dir/subdir_a/001.parquet",
+                "parent_dir/subdir_b/001.parquet"
+            ]
+        );
+        assert_eq!(filtered?, vec_stringify!["001.parquet", "full.parquet"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_includes_gzipped_parquet() -> Result<()> {
+        setup_docker();
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let res = list_keys("cars-parquet", "", &SdkSettings::default()).await;
+
+        restore_env(original_env);
+
+        assert_eq!(res?, vec_stringify!["cars.parquet", "cars.parquet.gz"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_unknown_bucket() -> Result<()> {
+        setup_docker();
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let res = list_keys("no-such-bucket", "", &SdkSettings::default()).await;
+
+        restore_env(original_env);
+
+        assert!(res.is_err(), "aws api should fail as no such bucket");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_happy_path_files_at_bucket_root() -> Result<()> {
         setup_docker();
@@ -347,6 +1408,10 @@ mod tests {
             String::from("customer-orders-parquet"),
             vec_stringify!["order_00.parquet", "order_01.parquet"], // [real, real] key
             tmp_dir_path.clone(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
         )
         .await;
 
@@ -367,6 +1432,52 @@ mod tests {
             .await
             .unwrap();
 
+            assert_eq!(src_contents, downloaded_contents,);
+            // the size check in get() passed, and the sizes really do match
+            assert_eq!(
+                std::fs::metadata(downloaded_file)?.len(),
+                std::fs::metadata(format!("{}/{}", LOCALSTACK_PARQUET_DIR_CUSTOMERS, s3_key))?
+                    .len()
+            );
+        }
+
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_happy_path_with_max_inflight_bytes() -> Result<()> {
+        setup_docker();
+        // set up aws env vars for localstack
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+        let res = get(
+            String::from("customer-orders-parquet"),
+            vec_stringify!["order_00.parquet", "order_01.parquet", "order_02.parquet"],
+            tmp_dir_path.clone(),
+            5,
+            Some(1024), // smaller than any of the files, so one at a time
+            &GetRetry::default(),
+            &SdkSettings::default(),
+        )
+        .await;
+
+        restore_env(original_env);
+
+        assert!(res.is_ok(), "should download all files, one at a time");
+
+        for (s3_key, downloaded_file) in &res.unwrap() {
+            let (src_contents, downloaded_contents) = get_downloaded_and_src_file_contents(
+                format!("{}/{}", LOCALSTACK_PARQUET_DIR_CUSTOMERS, s3_key),
+                downloaded_file.to_string(),
+            )
+            .await
+            .unwrap();
+
             assert_eq!(src_contents, downloaded_contents,);
         }
 
@@ -393,6 +1504,10 @@ mod tests {
             String::from("deliveries-parquet"),
             s3_keys.clone(), // [real, real] key
             tmp_dir_path.clone(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
         )
         .await;
 
@@ -419,4 +1534,50 @@ mod tests {
         tmp_dir.close().unwrap(); // can be deleted as read what we need
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_get_happy_path_gzipped_key() -> Result<()> {
+        setup_docker();
+        // set up aws env vars for localstack
+        let _env_lock = LOCK_ENV_S3_DOWNLOAD_TESTS.lock().await;
+        let original_env: HashMap<String, String> = env::vars().collect();
+        set_good_aws_vars();
+
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_dir_path = format!("{}", tmp_dir.path().display());
+        let res = get(
+            String::from("cars-parquet"),
+            vec_stringify!["cars.parquet.gz"], // gzipped copy of cars.parquet
+            tmp_dir_path.clone(),
+            5,
+            None,
+            &GetRetry::default(),
+            &SdkSettings::default(),
+        )
+        .await;
+
+        restore_env(original_env);
+
+        let my_map = res?;
+        let downloaded_file = &my_map["cars.parquet.gz"];
+        assert_eq!(
+            downloaded_file,
+            &format!("{}/cars.parquet.gz.gunzipped", tmp_dir_path),
+            "should keep the .gz in the local file name"
+        );
+
+        let (src_contents, downloaded_contents) = get_downloaded_and_src_file_contents(
+            format!("{}/cars.parquet", LOCALSTACK_PARQUET_DIR_CARS),
+            downloaded_file.to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            src_contents, downloaded_contents,
+            "should be saved decompressed"
+        );
+
+        tmp_dir.close().unwrap(); // can be deleted as read what we need
+        Ok(())
+    }
 }
@@ -0,0 +1,311 @@
+use anyhow::{bail, Context, Result};
+use aws_sdk_sqs as sqs;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::config::SqsConfig;
+use crate::s3_download;
+
+// An S3 event notification, as S3 sends it to a queue. Only the parts we need.
+// A queue's s3:TestEvent, sent when notifications are set up, has no Records.
+#[derive(Debug, Deserialize)]
+struct S3Event {
+    #[serde(rename = "Records", default)]
+    records: Vec<S3EventRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3EventRecord {
+    #[serde(rename = "eventName")]
+    event_name: String,
+    s3: S3Entity,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Entity {
+    bucket: S3Bucket,
+    object: S3Object,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Bucket {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Object {
+    key: String,
+}
+
+// Keys in an event are URL-encoded, with a space as +
+fn url_decode(encoded: &str) -> Result<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                // from_str_radix alone would take a sign, e.g. %+5
+                let hex = bytes.get(i + 1..i + 3).unwrap_or_default();
+                if hex.len() != 2 || !hex.iter().all(u8::is_ascii_hexdigit) {
+                    bail!("bad %-escape in {:?}", encoded);
+                }
+                let hex = std::str::from_utf8(hex)?;
+                decoded.push(u8::from_str_radix(hex, 16)?);
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).with_context(|| format!("{:?} isn't UTF-8 once decoded", encoded))
+}
+
+// The .parquet keys created in bucket, going by the event notification in body.
+// Anything else, e.g. a delete, or another bucket's object, is left out.
+fn s3_event_keys(body: &str, bucket: &str) -> Result<Vec<String>> {
+    let event: S3Event = serde_json::from_str(body).context("not an S3 event notification")?;
+    let mut keys = Vec::new();
+    for record in event.records {
+        let key = url_decode(&record.s3.object.key)?;
+        if !record.event_name.starts_with("ObjectCreated:") {
+            debug!("ignoring {} event for {}", record.event_name, key);
+        } else if record.s3.bucket.name != bucket {
+            warn!(
+                "ignoring {}, as it's in bucket {}, not {}",
+                key, record.s3.bucket.name, bucket
+            );
+        } else if !s3_download::is_parquet_key(&key) {
+            debug!("ignoring {}, as not a .parquet key", key);
+        } else {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+// A message received, to delete once each of its keys is done with. keys are those left.
+#[derive(Debug)]
+struct Received {
+    receipt_handle: String,
+    keys: Vec<String>,
+}
+
+// For source sqs: S3 event notifications on a queue, polled for the keys to load.
+// A message is deleted once all its keys have left wip, whether completed, skipped,
+// quarantined or dead-lettered. Until then, it's left on the queue, so if the run stops,
+// or a key fails to be retried, it becomes visible again after the queue's visibility
+// timeout, to be received again.
+#[derive(Debug)]
+pub struct SqsSource {
+    client: sqs::Client,
+    queue_url: String,
+    wait_time_secs: i32,
+    max_messages: i32,
+    bucket: String,
+    received: Vec<Received>,
+}
+
+impl SqsSource {
+    // region is s3.region, as S3 only notifies a queue in the bucket's region
+    pub async fn new(cfg: &SqsConfig, region: Option<&str>, bucket: &str) -> Result<Self> {
+        let mut loader = aws_config::from_env();
+        if let Some(endpoint_url) = &cfg.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url);
+        }
+        if let Some(region) = region {
+            loader = loader.region(aws_config::Region::new(region.to_string()));
+        }
+        let config = loader.load().await;
+        info!(
+            "polling sqs queue {} for s3 event notifications",
+            cfg.queue_url
+        );
+        Ok(SqsSource {
+            client: sqs::Client::new(&config),
+            queue_url: cfg.queue_url.clone(),
+            wait_time_secs: cfg.wait_time_secs,
+            max_messages: cfg.max_messages,
+            bucket: bucket.to_string(),
+            received: vec![],
+        })
+    }
+
+    // Waits up to wait_time_secs for messages, and returns the keys in them.
+    // None if none came, so the queue's drained. A message with no keys to load, e.g. a
+    // test event, is deleted straight away. One that's not an event is left on the queue.
+    pub async fn receive(&mut self) -> Result<Option<Vec<String>>> {
+        let output = self
+            .client
+            .receive_message()
+            .queue_url(&self.queue_url)
+            .max_number_of_messages(self.max_messages)
+            .wait_time_seconds(self.wait_time_secs)
+            .send()
+            .await
+            .with_context(|| format!("Failed to receive from sqs queue {}", self.queue_url))?;
+
+        let messages = output.messages.unwrap_or_default();
+        if messages.is_empty() {
+            info!("no messages on sqs queue {}", self.queue_url);
+            return Ok(None);
+        }
+        let mut keys: Vec<String> = Vec::new();
+        for message in messages {
+            let id = message.message_id.unwrap_or_default();
+            let (Some(receipt_handle), Some(body)) = (message.receipt_handle, message.body) else {
+                warn!("sqs message {}: no body, leaving it on the queue", id);
+                continue;
+            };
+            match s3_event_keys(&body, &self.bucket) {
+                Err(e) => warn!("sqs message {}: leaving it on the queue, as {:#}", id, e),
+                Ok(message_keys) if message_keys.is_empty() => {
+                    debug!("sqs message {}: nothing to load, so deleting it", id);
+                    self.delete(&receipt_handle).await?;
+                }
+                Ok(message_keys) => {
+                    debug!("sqs message {}: keys {:?}", id, message_keys);
+                    keys.extend(message_keys.iter().cloned());
+                    self.received.push(Received {
+                        receipt_handle,
+                        keys: message_keys,
+                    });
+                }
+            }
+        }
+        Ok(Some(keys))
+    }
+
+    // Deletes each message received once all its keys are done with, i.e. have left wip.
+    // A key that's skipped, quarantined or dead-lettered won't be loaded again either, so
+    // counts as done as much as a completed one. A message's keys can be loaded over more
+    // than one batch, so those done are dropped as we go.
+    pub async fn delete_done(&mut self, done: &[String]) -> Result<()> {
+        for received in self.take_done(done) {
+            debug!("keys all done with, so deleting their sqs message");
+            self.delete(&received.receipt_handle).await?;
+        }
+        Ok(())
+    }
+
+    // Drops the keys done with from each message, and takes those messages with none left
+    fn take_done(&mut self, done: &[String]) -> Vec<Received> {
+        let done: HashSet<&str> = done.iter().map(String::as_str).collect();
+        for received in &mut self.received {
+            received.keys.retain(|key| !done.contains(key.as_str()));
+        }
+        let (done, pending): (Vec<Received>, Vec<Received>) =
+            self.received.drain(..).partition(|r| r.keys.is_empty());
+        self.received = pending;
+        done
+    }
+
+    async fn delete(&self, receipt_handle: &str) -> Result<()> {
+        self.client
+            .delete_message()
+            .queue_url(&self.queue_url)
+            .receipt_handle(receipt_handle)
+            .send()
+            .await
+            .with_context(|| format!("Failed to delete a message from {}", self.queue_url))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(records: &[(&str, &str, &str)]) -> String {
+        let records: Vec<String> = records
+            .iter()
+            .map(|(event_name, bucket, key)| {
+                format!(
+                    r#"{{"eventVersion":"2.1","eventSource":"aws:s3","eventName":"{}",
+                        "s3":{{"bucket":{{"name":"{}"}},"object":{{"key":"{}","size":1}}}}}}"#,
+                    event_name, bucket, key
+                )
+            })
+            .collect();
+        format!(r#"{{"Records":[{}]}}"#, records.join(","))
+    }
+
+    #[test]
+    fn test_take_done() {
+        let config = sqs::Config::builder().behavior_version_latest().build();
+        let mut source = SqsSource {
+            client: sqs::Client::from_conf(config),
+            queue_url: "queue".to_string(),
+            wait_time_secs: 1,
+            max_messages: 10,
+            bucket: "orders".to_string(),
+            received: vec![
+                Received {
+                    receipt_handle: "good_and_bad".to_string(),
+                    keys: vec!["good.parquet".to_string(), "bad.parquet".to_string()],
+                },
+                Received {
+                    receipt_handle: "later".to_string(),
+                    keys: vec!["later.parquet".to_string()],
+                },
+            ],
+        };
+
+        // a message with a key not done with yet stays
+        let done = source.take_done(&["good.parquet".to_string()]);
+        assert!(done.is_empty());
+
+        // a key skipped, quarantined or dead-lettered is done with, as much as a completed one
+        let done = source.take_done(&["bad.parquet".to_string()]);
+        let handles: Vec<&str> = done.iter().map(|r| r.receipt_handle.as_str()).collect();
+        assert_eq!(handles, vec!["good_and_bad"]);
+        assert_eq!(source.received.len(), 1);
+        assert_eq!(source.received[0].receipt_handle, "later");
+    }
+
+    #[test]
+    fn test_url_decode() -> Result<()> {
+        assert_eq!(url_decode("a/b.parquet")?, "a/b.parquet");
+        assert_eq!(
+            url_decode("dt%3D2024-09-24/my+file.parquet")?,
+            "dt=2024-09-24/my file.parquet"
+        );
+        assert_eq!(url_decode("caf%C3%A9.parquet")?, "café.parquet");
+        assert!(url_decode("bad%2").is_err(), "escape cut short");
+        assert!(url_decode("bad%zz").is_err(), "not hex");
+        assert!(url_decode("bad%+5").is_err(), "a sign isn't hex");
+        assert!(url_decode("bad%").is_err(), "trailing %");
+        assert!(url_decode("%FF").is_err(), "not UTF-8");
+        Ok(())
+    }
+
+    #[test]
+    fn test_s3_event_keys() -> Result<()> {
+        let body = event(&[
+            ("ObjectCreated:Put", "orders", "in/order+00.parquet"),
+            (
+                "ObjectCreated:CompleteMultipartUpload",
+                "orders",
+                "in/order_01.parquet.gz",
+            ),
+            ("ObjectRemoved:Delete", "orders", "in/order_02.parquet"),
+            ("ObjectCreated:Put", "other-bucket", "in/order_03.parquet"),
+            ("ObjectCreated:Put", "orders", "in/_SUCCESS"),
+        ]);
+        assert_eq!(
+            s3_event_keys(&body, "orders")?,
+            vec!["in/order 00.parquet", "in/order_01.parquet.gz"]
+        );
+
+        let test_event = r#"{"Service":"Amazon S3","Event":"s3:TestEvent","Bucket":"orders"}"#;
+        assert!(s3_event_keys(test_event, "orders")?.is_empty());
+
+        let Err(e) = s3_event_keys("not json", "orders") else {
+            panic!("not an event")
+        };
+        assert_eq!(e.to_string(), "not an S3 event notification");
+        Ok(())
+    }
+}
@@ -10,20 +10,33 @@
 #[cfg(test)]
 pub mod tests {
     use anyhow::{bail, Result};
+    use arrow_ipc::writer::{IpcDataGenerator, IpcWriteOptions};
+    use arrow_schema::{DataType, Field as ArrowField, Schema as ArrowSchema, TimeUnit};
     use assert_fs::{fixture::TempDir, prelude::*};
+    use base64::prelude::{Engine, BASE64_STANDARD};
     use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
     use const_format::formatcp;
     use csv::WriterBuilder;
     use lazy_static::lazy_static;
     use log::debug;
     use once_cell::sync::Lazy;
+    use parquet::arrow::ARROW_SCHEMA_META_KEY;
+    use parquet::data_type::{
+        BoolType, ByteArray, ByteArrayType, DoubleType, FixedLenByteArray, FixedLenByteArrayType,
+        Int32Type, Int64Type,
+    };
+    use parquet::file::properties::WriterProperties;
     use parquet::file::reader::SerializedFileReader;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::format::KeyValue;
+    use parquet::schema::parser::parse_message_type;
     use std::collections::HashMap;
     use std::env;
     use std::fs::File;
     use std::io::Cursor;
     use std::path::Path;
     use std::process::Command;
+    use std::sync::Arc;
     use tokio::fs;
     use tokio::io::AsyncReadExt;
     use tokio_postgres::Client;
@@ -46,6 +59,33 @@ pub mod tests {
                 carb SMALLINT
     "#;
 
+    // model is unique in cars.parquet, so can be used as the conflict target for upserts
+    pub const CARS_PK_COLS_FOR_CREATE: &str = r#"
+                model VARCHAR (255) PRIMARY KEY,
+                miles_per_gallon FLOAT8,
+                num_of_cyl INT,
+                gear INT
+    "#;
+
+    // cars.parquet has 5 gear cars, but only after several rows that pass the check
+    pub const CARS_CHECK_COLS_FOR_CREATE: &str = r#"
+                model VARCHAR (255),
+                miles_per_gallon FLOAT8,
+                num_of_cyl INT,
+                gear INT CHECK (gear < 5)
+    "#;
+
+    // cars, plus cols for db.load_metadata
+    pub const CARS_LOAD_METADATA_COLS_FOR_CREATE: &str = r#"
+                model VARCHAR (255),
+                miles_per_gallon FLOAT8,
+                num_of_cyl INT,
+                gear INT,
+                source_key TEXT,
+                loaded_at TIMESTAMPTZ,
+                run_id VARCHAR (64)
+    "#;
+
     // parquet data yields i64 type for num of cyls field, not a smallint
     pub const CARS_INCORRECT_DB_TYPE_COLS_FOR_CREATE: &str = r#"
                 model INT,
@@ -113,8 +153,128 @@ pub mod tests {
                 my_big_int BIGINT
     "#;
 
+    // col names match tests/testdata/unit-tests/parquet_ops/special_field_names.parquet
+    // except "order id", so it needs an alias in parquet_to_db
+    pub const SPECIAL_NAMES_COLS_FOR_CREATE: &str = r#"
+                "order id" BIGINT,
+                "full name" VARCHAR (255)
+    "#;
+
+    pub const MIXED_CASE_COLS_FOR_CREATE: &str = r#"
+                "MixedCase" BIGINT,
+                "full name" VARCHAR (255)
+    "#;
+
+    pub const XML_COLS_FOR_CREATE: &str = r#"
+                body XML
+    "#;
+
+    pub const JSONB_COLS_FOR_CREATE: &str = r#"
+                doc JSONB
+    "#;
+
+    pub const TIMESTAMPTZ_COLS_FOR_CREATE: &str = r#"
+                happened_at TIMESTAMPTZ
+    "#;
+
+    pub const FLOAT8_COLS_FOR_CREATE: &str = r#"
+                measured FLOAT8
+    "#;
+
+    pub const DATE_COLS_FOR_CREATE: &str = r#"
+                sold_on DATE
+    "#;
+
+    pub const NUMERIC_COLS_FOR_CREATE: &str = r#"
+                price NUMERIC
+    "#;
+
+    pub const TIMESTAMP_COLS_FOR_CREATE: &str = r#"
+                happened_at TIMESTAMP
+    "#;
+
+    pub const INTERVAL_COLS_FOR_CREATE: &str = r#"
+                took INTERVAL
+    "#;
+
+    pub const BYTEA_COLS_FOR_CREATE: &str = r#"
+                payload BYTEA
+    "#;
+
+    pub const TSVECTOR_COLS_FOR_CREATE: &str = r#"
+                doc TSVECTOR
+    "#;
+
+    pub const UUID_COLS_FOR_CREATE: &str = r#"
+                id UUID
+    "#;
+
+    // for xy_parquet_reader()
+    pub const POINT_COLS_FOR_CREATE: &str = r#"
+                id INTEGER,
+                loc POINT
+    "#;
+
+    pub const MACADDR_COLS_FOR_CREATE: &str = r#"
+                mac MACADDR
+    "#;
+
+    // for the fields of nested_parquet_reader()
+    pub const ADDRESS_COLS_FOR_CREATE: &str = r#"
+                id INT,
+                city VARCHAR (255),
+                billing_city VARCHAR (255)
+    "#;
+
+    // for nested_parquet_reader(), with cols for fields it doesn't have
+    pub const OPTIONAL_FIELDS_COLS_FOR_CREATE: &str = r#"
+                id INT,
+                city VARCHAR (255),
+                country VARCHAR (255) NOT NULL,
+                region VARCHAR (255)
+    "#;
+
+    // for int32_row_groups_parquet_reader()
+    pub const INT4_COLS_FOR_CREATE: &str = r#"
+                n INT4
+    "#;
+
+    // for int_8_parquet_reader()
+    pub const INT2_COLS_FOR_CREATE: &str = r#"
+                n INT2
+    "#;
+
+    // for bools_parquet_reader(), with a, b and c packed into flags
+    pub const BITMASK_COLS_FOR_CREATE: &str = r#"
+                id INT,
+                flags SMALLINT,
+                note TEXT
+    "#;
+
+    // for repeated_parquet_reader()
+    pub const SCORES_COLS_FOR_CREATE: &str = r#"
+                id INT,
+                scores INT4[]
+    "#;
+
+    pub const TIME_COLS_FOR_CREATE: &str = r#"
+                sold_at TIME,
+                sold_at_text TEXT
+    "#;
+
+    pub const SOURCE_KEY_COLS_FOR_CREATE: &str = r#"
+                source_key VARCHAR (255),
+                amount INT
+    "#;
+
     const LOCALSTACK_PARQUET_DIR: &str = "local/localstack/bucket_data";
 
+    pub static TESTDATA_PARQUET_OPS_DIR: &str = formatcp!(
+        "{}/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        "tests/testdata/unit-tests/parquet_ops"
+    );
+
     pub static LOCALSTACK_PARQUET_DIR_CARS: &str = formatcp!(
         "{}/{}/{}",
         env!("CARGO_MANIFEST_DIR"),
@@ -147,6 +307,9 @@ pub mod tests {
         static ref COLS_FOR_CREATE: HashMap<&'static str, &'static str> = {
             let mut m = HashMap::new();
             m.insert("car", CARS_COLS_FOR_CREATE);
+            m.insert("car_pk", CARS_PK_COLS_FOR_CREATE);
+            m.insert("car_check", CARS_CHECK_COLS_FOR_CREATE);
+            m.insert("car_load_metadata", CARS_LOAD_METADATA_COLS_FOR_CREATE);
             m.insert(
                 "car_incorrect_db_type",
                 CARS_INCORRECT_DB_TYPE_COLS_FOR_CREATE,
@@ -160,6 +323,29 @@ pub mod tests {
             m.insert("iris", IRIS_COLS_FOR_CREATE);
             m.insert("types", TYPES_COLS_FOR_CREATE);
             m.insert("types_full", TYPES_FULL_COLS_FOR_CREATE);
+            m.insert("special_names", SPECIAL_NAMES_COLS_FOR_CREATE);
+            m.insert("mixed_case", MIXED_CASE_COLS_FOR_CREATE);
+            m.insert("xml", XML_COLS_FOR_CREATE);
+            m.insert("jsonb", JSONB_COLS_FOR_CREATE);
+            m.insert("timestamptz", TIMESTAMPTZ_COLS_FOR_CREATE);
+            m.insert("timestamp", TIMESTAMP_COLS_FOR_CREATE);
+            m.insert("numeric", NUMERIC_COLS_FOR_CREATE);
+            m.insert("interval", INTERVAL_COLS_FOR_CREATE);
+            m.insert("date", DATE_COLS_FOR_CREATE);
+            m.insert("float8", FLOAT8_COLS_FOR_CREATE);
+            m.insert("source_key", SOURCE_KEY_COLS_FOR_CREATE);
+            m.insert("bytea", BYTEA_COLS_FOR_CREATE);
+            m.insert("address", ADDRESS_COLS_FOR_CREATE);
+            m.insert("optional_fields", OPTIONAL_FIELDS_COLS_FOR_CREATE);
+            m.insert("macaddr", MACADDR_COLS_FOR_CREATE);
+            m.insert("uuid", UUID_COLS_FOR_CREATE);
+            m.insert("tsvector", TSVECTOR_COLS_FOR_CREATE);
+            m.insert("point", POINT_COLS_FOR_CREATE);
+            m.insert("int4", INT4_COLS_FOR_CREATE);
+            m.insert("int2", INT2_COLS_FOR_CREATE);
+            m.insert("time", TIME_COLS_FOR_CREATE);
+            m.insert("scores", SCORES_COLS_FOR_CREATE);
+            m.insert("bitmask", BITMASK_COLS_FOR_CREATE);
             m
         };
     }
@@ -261,6 +447,527 @@ pub mod tests {
         Ok((tmp_dir, reader))
     }
 
+    // special_field_names.parquet has fields "order-id" (INT64) and "full name" (UTF8)
+    pub async fn special_field_names_reader() -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir
+            .copy_from(TESTDATA_PARQUET_OPS_DIR, &["special_field_names.parquet"])
+            .unwrap();
+        let parquet_file = format!("{}/special_field_names.parquet", tmp_dir.path().display());
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with a single optional UTF8 column, so tests can create
+    // string data for a converter without having to add yet another file to localstack.
+    // A None value is written as a parquet null.
+    #[allow(dead_code)]
+    pub fn utf8_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<&str>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/utf8.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            format!(
+                "message schema {{ optional binary {} (UTF8); }}",
+                field_name
+            )
+            .as_str(),
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let data: Vec<ByteArray> = values
+            .iter()
+            .flatten()
+            .map(|v| ByteArray::from(*v))
+            .collect();
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<ByteArrayType>()
+                .write_batch(&data, Some(&def_levels), None)?;
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // AES-256-GCM ciphertext as parquet_to_db.decrypt expects it: the nonce, then the
+    // ciphertext with its tag. A fixed nonce is fine for test data only.
+    #[allow(dead_code)]
+    pub fn aes_gcm_seal(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+        use aes_gcm::aead::Aead;
+        use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+        let nonce = [9u8; 12];
+        let ciphertext = Aes256Gcm::new(key.into())
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .unwrap();
+        [nonce.as_slice(), ciphertext.as_slice()].concat()
+    }
+
+    // As utf8_parquet_reader(), but a fixed_len_byte_array(16) field, e.g. uuids as raw bytes
+    pub fn fixed_16_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<[u8; 16]>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/fixed_16.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            format!(
+                "message schema {{ optional fixed_len_byte_array(16) {}; }}",
+                field_name
+            )
+            .as_str(),
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let data: Vec<FixedLenByteArray> = values
+            .iter()
+            .flatten()
+            .map(|v| ByteArray::from(v.to_vec()).into())
+            .collect();
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<FixedLenByteArrayType>()
+                .write_batch(&data, Some(&def_levels), None)?;
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with groups, both with a city field:
+    //   id, address { city, zip }, billing { city }
+    // Rows are: id 1 with all fields set, id 2 with a null address and billing city,
+    // and id 3 with an address, but a null zip.
+    #[allow(dead_code)]
+    pub fn nested_parquet_reader() -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/nested.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            "message schema {
+                required int32 id;
+                optional group address {
+                    optional binary city (UTF8);
+                    optional binary zip (UTF8);
+                }
+                optional group billing {
+                    optional binary city (UTF8);
+                }
+            }",
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        // def level 0 is a null group, 1 a null field in a group, 2 a value
+        let strs = |values: &[&str]| -> Vec<ByteArray> {
+            values.iter().map(|v| ByteArray::from(*v)).collect()
+        };
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int32Type>()
+                .write_batch(&[1, 2, 3], None, None)?;
+            col.close()?;
+        }
+        let str_cols: [(Vec<ByteArray>, [i16; 3]); 3] = [
+            (strs(&["Leeds", "York"]), [2, 0, 2]), // address.city
+            (strs(&["LS1"]), [2, 0, 1]),           // address.zip
+            (strs(&["Hull"]), [2, 1, 0]),          // billing.city
+        ];
+        for (data, def_levels) in str_cols {
+            if let Some(mut col) = row_group.next_column()? {
+                col.typed::<ByteArrayType>()
+                    .write_batch(&data, Some(&def_levels), None)?;
+                col.close()?;
+            }
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with an INT32 id from 1, and an optional group loc of DOUBLE
+    // fields y then x, the wrong way round, as a point is read by name. None is a null loc.
+    #[allow(dead_code)]
+    pub fn xy_parquet_reader(
+        points: Vec<Option<(f64, f64)>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/xy.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            "message schema {
+                required int32 id;
+                optional group loc {
+                    required double y;
+                    required double x;
+                }
+            }",
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let ids: Vec<i32> = (1..=points.len() as i32).collect();
+        // def level 0 is a null loc, 1 a loc, with both its fields
+        let def_levels: Vec<i16> = points.iter().map(|p| p.is_some() as i16).collect();
+        let ys: Vec<f64> = points.iter().flatten().map(|(_, y)| *y).collect();
+        let xs: Vec<f64> = points.iter().flatten().map(|(x, _)| *x).collect();
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int32Type>().write_batch(&ids, None, None)?;
+            col.close()?;
+        }
+        for data in [ys, xs] {
+            if let Some(mut col) = row_group.next_column()? {
+                col.typed::<DoubleType>()
+                    .write_batch(&data, Some(&def_levels), None)?;
+                col.close()?;
+            }
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with an INT32 id, and optional BOOLEAN fields a, b and c.
+    // Each row of flags is a, b then c, with a None written as a parquet null.
+    #[allow(dead_code)]
+    pub fn bools_parquet_reader(
+        flags: Vec<[Option<bool>; 3]>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/bools.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            "message schema {
+                required int32 id;
+                optional boolean a;
+                optional boolean b;
+                optional boolean c;
+            }",
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let ids: Vec<i32> = (1..=flags.len() as i32).collect();
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int32Type>().write_batch(&ids, None, None)?;
+            col.close()?;
+        }
+        for i in 0..3 {
+            let values: Vec<Option<bool>> = flags.iter().map(|row| row[i]).collect();
+            let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+            let data: Vec<bool> = values.iter().flatten().copied().collect();
+            if let Some(mut col) = row_group.next_column()? {
+                col.typed::<BoolType>()
+                    .write_batch(&data, Some(&def_levels), None)?;
+                col.close()?;
+            }
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with a repeated INT32 field, not in a list group
+    #[allow(dead_code)]
+    pub fn repeated_parquet_reader() -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/repeated.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            "message schema {
+                required int32 id;
+                repeated int32 scores;
+            }",
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int32Type>()
+                .write_batch(&[1, 2, 3], None, None)?;
+            col.close()?;
+        }
+        // scores [1, 2, 3], [], [4]. Rep level 0 starts a row, def level 0 is no values
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int32Type>().write_batch(
+                &[1, 2, 3, 4],
+                Some(&[1, 1, 1, 0, 1]),
+                Some(&[0, 1, 1, 0, 0]),
+            )?;
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with a single optional DOUBLE column, e.g. for NaN and Infinity.
+    // A None value is written as a parquet null.
+    #[allow(dead_code)]
+    pub fn double_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<f64>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/double.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            format!("message schema {{ optional double {}; }}", field_name).as_str(),
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let data: Vec<f64> = values.iter().flatten().copied().collect();
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<DoubleType>()
+                .write_batch(&data, Some(&def_levels), None)?;
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with a single optional INT32 column, without a converted type.
+    // A None value is written as a parquet null.
+    #[allow(dead_code)]
+    pub fn int32_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<i32>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        annotated_int32_parquet_reader(field_name, "", values)
+    }
+
+    // As int32_parquet_reader(), but an INT_8 field, read as Bytes
+    #[allow(dead_code)]
+    pub fn int_8_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<i8>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let values = values.into_iter().map(|v| v.map(i32::from)).collect();
+        annotated_int32_parquet_reader(field_name, "(INT_8)", values)
+    }
+
+    // As int32_parquet_reader(), but a DECIMAL field of that scale, so 1234 at scale 2 is 12.34
+    pub fn int32_decimal_parquet_reader(
+        field_name: &str,
+        scale: i32,
+        values: Vec<Option<i32>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let annotation = format!("(DECIMAL(9,{}))", scale);
+        annotated_int32_parquet_reader(field_name, annotation.as_str(), values)
+    }
+
+    // Writes a parquet file, int32.parquet, with a single optional INT32 column annotated
+    // with e.g. "(INT_8)". A None value is written as a parquet null.
+    fn annotated_int32_parquet_reader(
+        field_name: &str,
+        annotation: &str,
+        values: Vec<Option<i32>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/int32.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            format!(
+                "message schema {{ optional int32 {} {}; }}",
+                field_name, annotation
+            )
+            .as_str(),
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let data: Vec<i32> = values.iter().flatten().copied().collect();
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int32Type>()
+                .write_batch(&data, Some(&def_levels), None)?;
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // As int32_parquet_reader(), but a TIME_MILLIS field, of milliseconds since midnight
+    #[allow(dead_code)]
+    pub fn time_millis_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<i32>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        annotated_int32_parquet_reader(field_name, "(TIME_MILLIS)", values)
+    }
+
+    // As time_millis_parquet_reader(), but an INT64 TIME_MICROS field
+    #[allow(dead_code)]
+    pub fn time_micros_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<i64>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        annotated_int64_parquet_reader(field_name, "(TIME_MICROS)", None, values)
+    }
+
+    // As annotated_int32_parquet_reader(), but an INT64 field, written to int64.parquet.
+    // key_value_metadata goes in the file's footer, e.g. an embedded arrow schema.
+    fn annotated_int64_parquet_reader(
+        field_name: &str,
+        annotation: &str,
+        key_value_metadata: Option<Vec<KeyValue>>,
+        values: Vec<Option<i64>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/int64.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            format!(
+                "message schema {{ optional int64 {} {}; }}",
+                field_name, annotation
+            )
+            .as_str(),
+        )?);
+        let props = Arc::new(
+            WriterProperties::builder()
+                .set_key_value_metadata(key_value_metadata)
+                .build(),
+        );
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let data: Vec<i64> = values.iter().flatten().copied().collect();
+
+        let mut row_group = writer.next_row_group()?;
+        if let Some(mut col) = row_group.next_column()? {
+            col.typed::<Int64Type>()
+                .write_batch(&data, Some(&def_levels), None)?;
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with a single required INT32 column, one row group per Vec.
+    #[allow(dead_code)]
+    pub fn int32_row_groups_parquet_reader(
+        field_name: &str,
+        row_groups: &[Vec<i32>],
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let tmp_dir = TempDir::new().unwrap();
+        let parquet_file = format!("{}/int32_row_groups.parquet", tmp_dir.path().display());
+
+        let schema = Arc::new(parse_message_type(
+            format!("message schema {{ required int32 {}; }}", field_name).as_str(),
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(&parquet_file)?, schema, props)?;
+
+        for data in row_groups {
+            let mut row_group = writer.next_row_group()?;
+            if let Some(mut col) = row_group.next_column()? {
+                col.typed::<Int32Type>().write_batch(data, None, None)?;
+                col.close()?;
+            }
+            row_group.close()?;
+        }
+        writer.close()?;
+
+        let f = File::open(Path::new(parquet_file.as_str())).unwrap();
+        let reader = SerializedFileReader::new(f).unwrap();
+
+        Ok((tmp_dir, reader))
+    }
+
+    // Writes a parquet file with a single optional arrow duration column, the way
+    // pyarrow or polars would: an INT64, with the unit in the embedded arrow schema.
+    #[allow(dead_code)]
+    pub fn duration_parquet_reader(
+        field_name: &str,
+        unit: TimeUnit,
+        values: Vec<Option<i64>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        let arrow_schema = ArrowSchema::new(vec![ArrowField::new(
+            field_name,
+            DataType::Duration(unit),
+            true,
+        )]);
+        let encoded = IpcDataGenerator::default()
+            .schema_to_bytes(&arrow_schema, &IpcWriteOptions::default())
+            .ipc_message;
+        let arrow_schema_meta = KeyValue::new(
+            ARROW_SCHEMA_META_KEY.to_string(),
+            BASE64_STANDARD.encode(encoded),
+        );
+        annotated_int64_parquet_reader(field_name, "", Some(vec![arrow_schema_meta]), values)
+    }
+
+    // An INT64 TIMESTAMP(NANOS) field, as e.g. pyarrow writes with parquet format 2.6
+    pub fn timestamp_nanos_parquet_reader(
+        field_name: &str,
+        values: Vec<Option<i64>>,
+    ) -> Result<(TempDir, SerializedFileReader<File>)> {
+        annotated_int64_parquet_reader(field_name, "(TIMESTAMP(NANOS,true))", None, values)
+    }
+
     pub async fn get_rows_as_csv_string(client: &Client, sql: &str) -> Result<String> {
         let rows = client.query(sql, &[]).await.unwrap();
 
@@ -0,0 +1,163 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::config::SslMode;
+
+// sslmode require only asks for an encrypted connection, like libpq's require.
+// So we accept any server cert, but still check the handshake signatures.
+#[derive(Debug)]
+struct NoCertVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// Use the CA cert(s) in the pem file if given, else the mozilla root certs
+fn root_cert_store(ca_cert: Option<&str>) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    let Some(ca_cert) = ca_cert else {
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        return Ok(roots);
+    };
+
+    info!("using CA cert(s) from {}", ca_cert);
+    let file =
+        File::open(ca_cert).with_context(|| format!("Failed to open CA cert {}", ca_cert))?;
+    for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+        let cert = cert.with_context(|| format!("Failed to read CA cert {}", ca_cert))?;
+        roots.add(cert)?;
+    }
+
+    if roots.is_empty() {
+        bail!("No certificates found in CA cert file {}", ca_cert);
+    }
+    Ok(roots)
+}
+
+pub fn make_tls_connect(sslmode: &SslMode, ca_cert: Option<&str>) -> Result<MakeRustlsConnect> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let tls_config = match sslmode {
+        SslMode::Disable => bail!("TLS was requested, but sslmode is disable"),
+        SslMode::Require => {
+            if ca_cert.is_some() {
+                warn!("sslmode is require, so ca_cert is ignored. Use verify-full to verify the server");
+            }
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoCertVerification(provider)))
+                .with_no_client_auth()
+        }
+        SslMode::VerifyFull => builder
+            .with_root_certificates(root_cert_store(ca_cert)?)
+            .with_no_client_auth(),
+    };
+
+    Ok(MakeRustlsConnect::new(tls_config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{fixture::TempDir, prelude::*};
+
+    #[test]
+    fn test_make_tls_connect_require() {
+        let result = make_tls_connect(&SslMode::Require, None);
+        assert!(result.is_ok(), "require needs no CA cert");
+    }
+
+    #[test]
+    fn test_make_tls_connect_verify_full_default_roots() {
+        let result = make_tls_connect(&SslMode::VerifyFull, None);
+        assert!(result.is_ok(), "should fall back to the mozilla root certs");
+    }
+
+    #[test]
+    fn test_make_tls_connect_disable_is_an_error() {
+        let result = make_tls_connect(&SslMode::Disable, None);
+        assert!(
+            result.is_err(),
+            "should not build a TLS connector if disabled"
+        );
+    }
+
+    #[test]
+    fn test_make_tls_connect_verify_full_no_such_ca_cert() {
+        let result = make_tls_connect(&SslMode::VerifyFull, Some("/no/such/ca.pem"));
+        assert!(
+            result.is_err(),
+            "should fail as CA cert file does not exist"
+        );
+    }
+
+    #[test]
+    fn test_make_tls_connect_verify_full_ca_cert_without_certs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let ca_cert = tmp_dir.child("ca.pem");
+        ca_cert.write_str("this is not a pem file\n").unwrap();
+
+        let result = make_tls_connect(
+            &SslMode::VerifyFull,
+            Some(format!("{}", ca_cert.path().display()).as_str()),
+        );
+        tmp_dir.close().unwrap();
+
+        assert!(
+            result.is_err(),
+            "should fail as no certs in the CA cert file"
+        );
+    }
+}
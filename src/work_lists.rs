@@ -1,8 +1,11 @@
-use anyhow::Result;
-use log::{debug, error, info};
+use crate::config::OnStartupWip;
+use anyhow::{bail, Result};
+use fs2::FileExt;
+use log::{debug, error, info, warn};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
-use std::fs::OpenOptions;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::Path;
 
@@ -42,23 +45,233 @@ fn wip_file_to_wip_list(work_lists_dir: &str, num_items: usize) -> Result<Vec<St
     Ok(wip_list)
 }
 
+pub fn todo_exists(work_lists_dir: &str) -> bool {
+    Path::new(format!("{}/todo", work_lists_dir).as_str()).is_file()
+}
+
+// Writes a new todo file, one item per line. Never overwrites an existing one,
+// as that would throw away whatever is left to do.
+pub fn write_todo(work_lists_dir: &str, items: &[String]) -> Result<()> {
+    let filename_todo = format!("{}/todo", work_lists_dir);
+    info!(
+        "writing {} items to new todo file {}",
+        items.len(),
+        filename_todo
+    );
+    let file_todo = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(filename_todo.clone())?;
+
+    let mut todo_writer = io::BufWriter::new(file_todo);
+    for item in items {
+        writeln!(todo_writer, "{}", item)?;
+    }
+    todo_writer.flush()?;
+    Ok(())
+}
+
+// Items in a work list file, skipping blank and # comment lines. No file means no items.
+fn read_items(filename: &str) -> Result<Vec<String>> {
+    if !Path::new(filename).is_file() {
+        return Ok(vec![]);
+    }
+    let file = OpenOptions::new().read(true).open(filename)?;
+    let items = BufReader::new(file)
+        .lines()
+        .filter_map(|line| match line {
+            Ok(line) if !line.trim().is_empty() && !line.trim_start().starts_with('#') => {
+                Some(Ok(line))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(items)
+}
+
+// A todo line as the item it names. lines() has already dropped any \r of a \r\n.
+// Other whitespace around it is trimmed with trim_keys, but s3 keys can start or end
+// with a space, so that can be turned off.
+fn todo_item(line: String, trim_keys: bool) -> String {
+    match trim_keys {
+        true => line.trim().to_string(),
+        false => line,
+    }
+}
+
+// Replaces the file with items, one per line. They're written to a temp file alongside,
+// which is renamed over the file. A rename within a filesystem is atomic, so if we're
+// killed part way, the file is as it was before, never truncated or half written.
+fn write_items(filename: &str, items: &[String]) -> Result<()> {
+    let filename_tmp = format!("{}.tmp", filename);
+    let file_tmp = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(filename_tmp.clone())?;
+
+    let mut writer = io::BufWriter::new(file_tmp);
+    for item in items {
+        writeln!(writer, "{}", item)?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?; // on disk before the rename makes it the file
+    fs::rename(filename_tmp, filename)?;
+    Ok(())
+}
+
+// Appends item<TAB>error, the error on one line, to the failed or dead_letter file
+fn append_failure(filename: &str, item: &str, error: &str) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(filename)?;
+
+    let mut writer = io::BufWriter::new(file);
+    let error: Vec<&str> = error.split_whitespace().collect();
+    writeln!(writer, "{}\t{}", item, error.join(" "))?;
+    writer.flush()?;
+    Ok(())
+}
+
+// The attempts file has a line of item<TAB>count for each item that has failed to load
+fn read_attempts(filename: &str) -> Result<BTreeMap<String, u32>> {
+    let mut attempts = BTreeMap::new();
+    for line in read_items(filename)? {
+        let Some((item, count)) = line.rsplit_once('\t') else {
+            bail!("{}: expected item<TAB>count, got {:?}", filename, line);
+        };
+        let Ok(count) = count.parse::<u32>() else {
+            bail!("{}: attempt count for {} is not a number", filename, item);
+        };
+        attempts.insert(item.to_string(), count);
+    }
+    Ok(attempts)
+}
+
+pub fn completed_items(work_lists_dir: &str) -> Result<Vec<String>> {
+    read_items(format!("{}/completed", work_lists_dir).as_str())
+}
+
+// Moves items from completed back to the front of todo, so the next run does them first.
+// todo is written first: if we stop in between, an item is in both, so is only done again.
+pub fn requeue(work_lists_dir: &str, items: &[String]) -> Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    let filename_todo = format!("{}/todo", work_lists_dir);
+    let filename_completed = format!("{}/completed", work_lists_dir);
+    info!(
+        "moving {} items from {} back to {}",
+        items.len(),
+        filename_completed,
+        filename_todo
+    );
+
+    let mut todo_items: Vec<String> = items.to_vec();
+    todo_items.extend(
+        read_items(&filename_todo)?
+            .into_iter()
+            .filter(|item| !items.contains(item)),
+    );
+    write_items(&filename_todo, &todo_items)?;
+
+    let completed_items: Vec<String> = read_items(&filename_completed)?
+        .into_iter()
+        .filter(|item| !items.contains(item))
+        .collect();
+    write_items(&filename_completed, &completed_items)?;
+    Ok(())
+}
+
+// An exclusive advisory lock on the work lists dir, so two overlapping runs can't
+// both rewrite todo, wip and completed. The OS releases it when the file is closed,
+// i.e. when dropped, or if the process dies. The .lock file itself is left in place.
+#[derive(Debug, Default)]
+pub struct DirLock {
+    _file: Option<File>, // only held for the lock
+}
+
+// A lock isn't part of the work lists' state, so any two compare equal
+impl PartialEq for DirLock {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DirLock {}
+
+pub fn lock_dir(work_lists_dir: &str) -> Result<DirLock> {
+    let filename_lock = format!("{}/.lock", work_lists_dir);
+    let file_lock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(filename_lock.clone())?;
+
+    if let Err(e) = file_lock.try_lock_exclusive() {
+        bail!(
+            "work lists dir {} is locked, so another run may be using it ({}). \
+             If not, something else has {} open",
+            work_lists_dir,
+            e,
+            filename_lock
+        );
+    }
+    debug!("locked work lists dir with {}", filename_lock);
+    Ok(DirLock {
+        _file: Some(file_lock),
+    })
+}
+
 // we control when to pull more items from todo with size of wip_list
 // i.e. we remove from wip list when item is completed successfully
 // Once wip list is empty, we know length is 0 and we can overwrite the wip file with new items
-#[derive(Clone, Debug, PartialEq, Eq)]
+//
+// Failed attempts at each item are counted in the attempts file, across runs. With max_attempts
+// set, an item is moved to the dead_letter file once it has failed that many times, so it stops
+// being retried and holding up the items behind it.
+//
+// Completed items are only written to the completed and wip files every completed_flush_every
+// items. Until flushed, they sit in pending_completed. A crash before a flush means those items
+// are still in the wip file, so will be processed again on the next run.
+#[derive(Debug, PartialEq, Eq)]
 pub struct WorkLists {
     pub batch_size: usize,
+    pub completed_flush_every: usize,
 
     pub work_lists_dir: String,
+    pub filename_attempts: String,
     pub filename_completed: String,
+    pub filename_dead_letter: String,
+    pub filename_failed: String,
+    pub filename_quarantine: String,
     pub filename_todo: String,
     pub filename_wip: String,
 
     pub wip_list: Vec<String>,
+    pub pending_completed: Vec<String>,
+
+    pub max_attempts: Option<u32>, // no limit if None
+    pub attempts: BTreeMap<String, u32>,
+
+    pub trim_keys: bool, // trims whitespace around each todo item
+
+    lock: DirLock, // held until dropped
 }
 
 impl WorkLists {
-    pub fn new(work_lists_dir: &str, num_items: usize) -> Result<Self> {
+    pub fn new(
+        work_lists_dir: &str,
+        num_items: usize,
+        completed_flush_every: usize,
+    ) -> Result<Self> {
+        if completed_flush_every == 0 {
+            bail!("completed_flush_every must be 1 or more");
+        }
+
         // check todo file exists or nothing to do
         let filename_todo = format!("{}/todo", work_lists_dir);
         let filename_wip = format!("{}/wip", work_lists_dir);
@@ -66,24 +279,114 @@ impl WorkLists {
         {
             let _ = OpenOptions::new().read(true).open(filename_todo.clone())?;
         }
+        let lock = lock_dir(work_lists_dir)?;
 
         let wip_list = wip_file_to_wip_list(work_lists_dir, num_items)?;
         info!("inital wip_list read from file: {:?}", wip_list);
 
+        let filename_attempts = format!("{}/attempts", work_lists_dir);
+        let attempts = read_attempts(&filename_attempts)?;
+
         Ok(WorkLists {
             batch_size: num_items,
+            completed_flush_every,
             work_lists_dir: work_lists_dir.to_string(),
+            filename_attempts,
             filename_completed: format!("{}/completed", work_lists_dir),
+            filename_dead_letter: format!("{}/dead_letter", work_lists_dir),
+            filename_failed: format!("{}/failed", work_lists_dir),
+            filename_quarantine: format!("{}/quarantine", work_lists_dir),
             filename_todo,
             filename_wip,
             wip_list,
+            pending_completed: vec![],
+            max_attempts: None,
+            attempts,
+            trim_keys: true,
+            lock,
         })
     }
 
+    pub fn with_max_attempts(mut self, max_attempts: Option<u32>) -> Result<Self> {
+        if max_attempts == Some(0) {
+            bail!("max_attempts must be 1 or more");
+        }
+        self.max_attempts = max_attempts;
+        Ok(self)
+    }
+
+    pub fn with_trim_keys(mut self, trim_keys: bool) -> Self {
+        self.trim_keys = trim_keys;
+        self
+    }
+
+    // Checks wip against completed, per work_lists.on_startup_wip. Call before the first batch.
+    pub fn with_on_startup_wip(mut self, on_startup_wip: &OnStartupWip) -> Result<Self> {
+        if self.wip_list.is_empty() || !completed_items(&self.work_lists_dir)?.is_empty() {
+            return Ok(self);
+        }
+        error!(
+            "{} has {} items, but {} is missing or empty, so they may already have been loaded: {:?}",
+            self.filename_wip,
+            self.wip_list.len(),
+            self.filename_completed,
+            self.wip_list
+        );
+        match on_startup_wip {
+            OnStartupWip::Resume => {
+                warn!("work_lists.on_startup_wip is resume, so loading the wip items anyway");
+            }
+            OnStartupWip::Requeue => {
+                warn!(
+                    "work_lists.on_startup_wip is requeue, so moving the wip items back to {}",
+                    self.filename_todo
+                );
+                // todo first: if we stop in between, an item is in both, so is only done again
+                let mut todo_items = self.wip_list.clone();
+                todo_items.extend(
+                    read_items(&self.filename_todo)?
+                        .into_iter()
+                        .filter(|item| !self.wip_list.contains(item)),
+                );
+                write_items(&self.filename_todo, &todo_items)?;
+                self.wip_list.clear();
+                self.write_wip_file()?;
+            }
+            OnStartupWip::Abort => {
+                bail!(
+                    "work_lists.on_startup_wip is abort: check whether the items in {} were loaded, \
+                     then empty it, or restore {}",
+                    self.filename_wip,
+                    self.filename_completed
+                );
+            }
+        }
+        Ok(self)
+    }
+
     pub fn mark_completed(&mut self, completed_item: String) -> Result<()> {
         // remove item from wip_list
         debug!("... marking {} as completed", completed_item);
         self.wip_list.retain(|x| *x != completed_item);
+        self.pending_completed.push(completed_item);
+
+        if self.pending_completed.len() >= self.completed_flush_every {
+            self.flush_completed()?;
+        }
+        Ok(())
+    }
+
+    // Writes any pending completed items to file. Call at the end of a batch,
+    // and before giving up on a run, so we don't redo work that was done.
+    pub fn flush_completed(&mut self) -> Result<()> {
+        if self.pending_completed.is_empty() {
+            return Ok(());
+        }
+        debug!(
+            "... flushing {} completed items to {}",
+            self.pending_completed.len(),
+            self.filename_completed
+        );
 
         // FIRST add to completed file - we want to know it was done even if remaining steps fail
         let file_completed = OpenOptions::new()
@@ -92,22 +395,187 @@ impl WorkLists {
             .open(self.filename_completed.clone())?;
 
         let mut completed_writer = io::BufWriter::new(file_completed);
-        writeln!(completed_writer, "{}", completed_item)?;
+        for item in &self.pending_completed {
+            writeln!(completed_writer, "{}", item)?;
+        }
+        completed_writer.flush()?;
 
         // 2. rewrite wip file without the item (was removed from self.wip_list)
-        let file_wip = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(self.filename_wip.clone())?;
-
-        let mut wip_writer = io::BufWriter::new(file_wip);
-        for item in self.wip_list.clone() {
-            writeln!(wip_writer, "{}", item)?;
+        self.write_wip_file()?;
+
+        // 3. a completed item's failed attempts no longer matter
+        let had_attempts = self.attempts.len();
+        for item in &self.pending_completed {
+            self.attempts.remove(item);
+        }
+        self.pending_completed.clear();
+        if self.attempts.len() != had_attempts {
+            self.write_attempts_file()?;
         }
         Ok(())
     }
 
+    fn write_wip_file(&self) -> Result<()> {
+        write_items(&self.filename_wip, &self.wip_list)
+    }
+
+    fn write_attempts_file(&self) -> Result<()> {
+        let lines: Vec<String> = self
+            .attempts
+            .iter()
+            .map(|(item, count)| format!("{}\t{}", item, count))
+            .collect();
+        write_items(&self.filename_attempts, &lines)
+    }
+
+    // Drops the item from wip without completing it, after appending it to filename
+    fn drop_failed(&mut self, failed_item: &str, error: &str, filename: &str) -> Result<()> {
+        append_failure(filename, failed_item, error)?;
+        self.wip_list.retain(|x| *x != failed_item);
+        // pending completed items must be on file before the wip file drops them
+        self.flush_completed()?;
+        self.write_wip_file()
+    }
+
+    // For runner.on_file_error: skip. The item is dropped from wip without being completed,
+    // and appended to the failed file as item<TAB>error, the error on one line.
+    // Items not in todo, wip or completed won't be picked up again, so requeue from failed by hand.
+    pub fn mark_skipped(&mut self, failed_item: String, error: &str) -> Result<()> {
+        debug!("... marking {} as skipped", failed_item);
+        let filename_failed = self.filename_failed.clone();
+        self.drop_failed(&failed_item, error, &filename_failed)
+    }
+
+    // For parquet.allowed_codecs: as mark_skipped(), but to the quarantine file, whatever
+    // runner.on_file_error is. A file with a codec not allowed will never load, so isn't retried.
+    pub fn mark_quarantined(&mut self, quarantined_item: String, error: &str) -> Result<()> {
+        debug!("... marking {} as quarantined", quarantined_item);
+        let filename_quarantine = self.filename_quarantine.clone();
+        self.drop_failed(&quarantined_item, error, &filename_quarantine)
+    }
+
+    // Counts a failed attempt at the item. It stays in wip, to be retried, until it has
+    // failed max_attempts times. Then it's dropped from wip and appended to the dead_letter
+    // file, like mark_skipped() does with failed. Returns whether it was dead-lettered.
+    pub fn mark_failed(&mut self, failed_item: String, error: &str) -> Result<bool> {
+        let attempts = self.attempts.entry(failed_item.clone()).or_insert(0);
+        *attempts += 1;
+        let attempts = *attempts;
+        debug!("... {} has failed {} time(s)", failed_item, attempts);
+
+        let Some(max_attempts) = self.max_attempts.filter(|max| attempts >= *max) else {
+            self.write_attempts_file()?;
+            return Ok(false);
+        };
+
+        warn!(
+            "{} has failed {} time(s), so moving it to {}",
+            failed_item, max_attempts, self.filename_dead_letter
+        );
+        let filename_dead_letter = self.filename_dead_letter.clone();
+        self.drop_failed(&failed_item, error, &filename_dead_letter)?;
+        self.attempts.remove(&failed_item);
+        self.write_attempts_file()?;
+        Ok(true)
+    }
+
+    // For s3.require_tags: moves items not ready yet from wip to the end of todo, to try again.
+    // todo is written first: if we stop in between, an item is in both, so is only done again.
+    pub fn mark_not_ready(&mut self, items: &[String]) -> Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        debug!(
+            "... moving {} items not ready back to {}",
+            items.len(),
+            self.filename_todo
+        );
+        let mut todo_items: Vec<String> = read_items(&self.filename_todo)?
+            .into_iter()
+            .filter(|item| !items.contains(item))
+            .collect();
+        todo_items.extend(items.iter().cloned());
+        write_items(&self.filename_todo, &todo_items)?;
+
+        self.wip_list.retain(|item| !items.contains(item));
+        // pending completed items must be on file before the wip file drops them
+        self.flush_completed()?;
+        self.write_wip_file()
+    }
+
+    // All items not yet completed: the wip list, then the todo file.
+    // Unlike next_batch(), this doesn't modify any of the work list files.
+    pub fn pending_items(&self) -> Result<Vec<String>> {
+        let file_todo = OpenOptions::new()
+            .read(true)
+            .open(self.filename_todo.clone())?;
+
+        let todo_items: Vec<String> = BufReader::new(file_todo)
+            .lines()
+            .filter_map(|line| match line {
+                Ok(line) if !line.trim().is_empty() && !line.trim_start().starts_with('#') => {
+                    Some(Ok(todo_item(line, self.trim_keys)))
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut items = self.wip_list.clone();
+        items.extend(todo_items);
+        Ok(items)
+    }
+
+    // Items done with, that won't be loaded again: those completed, and those in
+    // the failed, quarantine and dead_letter files, whose lines are item<TAB>error.
+    fn done_items(&self) -> Result<Vec<String>> {
+        let mut items = read_items(&self.filename_completed)?;
+        items.extend(self.pending_completed.iter().cloned());
+        for filename in [
+            &self.filename_failed,
+            &self.filename_quarantine,
+            &self.filename_dead_letter,
+        ] {
+            for line in read_items(filename)? {
+                let item = line
+                    .rsplit_once('\t')
+                    .map_or(line.as_str(), |(item, _)| item);
+                items.push(item.to_string());
+            }
+        }
+        Ok(items)
+    }
+
+    // Appends items to the todo file, bar any already in wip or todo, or done with,
+    // e.g. from an sqs message received again. Returns how many were added, and those
+    // left out as done with, which won't leave wip again to say so.
+    pub fn add_todo(&self, items: &[String]) -> Result<(usize, Vec<String>)> {
+        let mut todo_items = read_items(&self.filename_todo)?;
+        let done_items = self.done_items()?;
+        let mut added: usize = 0;
+        let mut already_done: Vec<String> = Vec::new();
+        for item in items {
+            if done_items.contains(item) {
+                debug!(
+                    "not adding {} to {}, as done with it",
+                    item, self.filename_todo
+                );
+                already_done.push(item.clone());
+            } else if !self.wip_list.contains(item) && !todo_items.contains(item) {
+                todo_items.push(item.clone());
+                added += 1;
+            }
+        }
+        if added > 0 {
+            debug!("adding {} items to {}", added, self.filename_todo);
+            write_items(&self.filename_todo, &todo_items)?;
+        }
+        Ok((added, already_done))
+    }
+
     pub fn next_batch(&mut self) -> Result<&mut Self> {
+        self.flush_completed()?; // so wip file and list are comparable
+
         if !self.wip_list.is_empty() {
             // should check that wip list file has same content or fail
             // as wip_list and file are out of sync
@@ -143,7 +611,7 @@ impl WorkLists {
             .lines()
             .filter_map(|line| match line {
                 Ok(line) if !line.trim().is_empty() && !line.trim_start().starts_with('#') => {
-                    Some(Ok(line))
+                    Some(Ok(todo_item(line, self.trim_keys)))
                 }
                 Ok(_) => None,
                 Err(e) => Some(Err(e)),
@@ -154,22 +622,12 @@ impl WorkLists {
             items.push(item?);
         }
 
-        // Open the 'wip' file for writing
+        // Write the batch to the 'wip' file, created on the first run
         debug!(
             "Writing next {} items to add to wip file: {}  ...",
             self.batch_size, self.filename_wip
         );
-        let file_wip = OpenOptions::new()
-            .write(true)
-            .create(true) // first run, won't exist, so this allows creation, but if exists, will reuse.
-            .truncate(true)
-            .open(self.filename_wip.clone())?;
-
-        // Write the first 5 lines to the 'wip' file
-        let mut wip_writer = io::BufWriter::new(file_wip);
-        for item in &items {
-            writeln!(wip_writer, "{}", item)?;
-        }
+        write_items(&self.filename_wip, &items)?;
 
         // Collect remaining lines
         let remaining_items: Vec<String> = reader
@@ -183,24 +641,12 @@ impl WorkLists {
             })
             .collect::<Result<_, _>>()?;
 
-        // Now reopen the 'todo' file for writing and truncate it
-        debug!(
-            "Open {}, truncated, for rewriting without items in progress ...",
-            self.filename_todo
-        );
-        let mut file_todo = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(self.filename_todo.clone())?;
-
         // Write the remaining lines back to the 'todo' file
         debug!(
             "Writing remaining s3 files back to {}  ...",
             self.filename_todo
         );
-        for item in remaining_items {
-            writeln!(file_todo, "{}", item)?;
-        }
+        write_items(&self.filename_todo, &remaining_items)?;
 
         self.wip_list = items.clone();
 
@@ -233,17 +679,27 @@ mod tests {
     ) -> WorkLists {
         WorkLists {
             batch_size,
+            completed_flush_every: 1,
             work_lists_dir: work_lists_dir.clone(),
+            filename_attempts: format!("{}/attempts", work_lists_dir),
             filename_completed: format!("{}/completed", work_lists_dir),
+            filename_dead_letter: format!("{}/dead_letter", work_lists_dir),
+            filename_failed: format!("{}/failed", work_lists_dir),
+            filename_quarantine: format!("{}/quarantine", work_lists_dir),
             filename_todo: format!("{}/todo", work_lists_dir),
             filename_wip: format!("{}/wip", work_lists_dir),
             wip_list,
+            pending_completed: vec![],
+            max_attempts: None,
+            attempts: BTreeMap::new(),
+            trim_keys: true,
+            lock: DirLock::default(),
         }
     }
 
     #[test]
     fn test_new_nonexistent_work_dir() {
-        let result = WorkLists::new("non_existent_work_dir", 1);
+        let result = WorkLists::new("non_existent_work_dir", 1, 1);
         assert!(
             result.is_err(),
             "Expected an error when reading a non-existent file."
@@ -256,6 +712,7 @@ mod tests {
         let result = WorkLists::new(
             work_lists_dir.as_str(), // dir exists
             1,
+            1,
         );
         assert!(
             result.is_err(),
@@ -263,12 +720,146 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_todo() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        assert!(!todo_exists(work_lists_dir.as_str()));
+
+        let items = vec_stringify!["a/001.parquet", "b/001.parquet"];
+        write_todo(work_lists_dir.as_str(), &items)?;
+        assert!(todo_exists(work_lists_dir.as_str()));
+
+        let wl = WorkLists::new(work_lists_dir.as_str(), 1, 1)?;
+        assert_eq!(wl.pending_items()?, items);
+
+        let result = write_todo(work_lists_dir.as_str(), &["c/001.parquet".to_string()]);
+        let todo = fs::read_to_string(format!("{}/todo", work_lists_dir))?;
+        tmp_dir.close().unwrap();
+
+        assert!(
+            result.is_err(),
+            "should not overwrite an existing todo file"
+        );
+        assert_eq!(todo, "a/001.parquet\nb/001.parquet\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_items_replaces_file_via_rename() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let filename = format!("{}/todo", tmp_dir.path().display());
+        tmp_dir
+            .child("todo")
+            .write_str("a/001.parquet\nb/001.parquet\nc/001.parquet\n")?;
+
+        let items = vec_stringify!["d/001.parquet", "e/001.parquet"];
+        write_items(filename.as_str(), &items)?;
+        let content = fs::read_to_string(filename.as_str())?;
+        let tmp_exists = Path::new(&format!("{}.tmp", filename)).exists();
+        tmp_dir.close().unwrap();
+
+        // same as writing each item and a newline to the truncated file
+        assert_eq!(content, "d/001.parquet\ne/001.parquet\n");
+        assert!(!tmp_exists, "temp file should have been renamed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_requeue() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        tmp_dir.child("todo").write_str("d/001.parquet\n")?;
+        tmp_dir
+            .child("completed")
+            .write_str("a/001.parquet\nb/001.parquet\nc/001.parquet\n")?;
+
+        requeue(
+            work_lists_dir.as_str(),
+            &["c/001.parquet".to_string(), "a/001.parquet".to_string()],
+        )?;
+        let todo = fs::read_to_string(format!("{}/todo", work_lists_dir))?;
+        let completed = completed_items(work_lists_dir.as_str())?;
+        tmp_dir.close().unwrap();
+
+        assert_eq!(todo, "c/001.parquet\na/001.parquet\nd/001.parquet\n");
+        assert_eq!(completed, vec_stringify!["b/001.parquet"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_on_startup_wip_without_completed() -> Result<()> {
+        let new_dir = || -> Result<TempDir> {
+            let tmp_dir = TempDir::new().unwrap();
+            tmp_dir
+                .child("todo")
+                .write_str("c/001.parquet\nb/001.parquet\n")?;
+            tmp_dir
+                .child("wip")
+                .write_str("a/001.parquet\nb/001.parquet\n")?;
+            Ok(tmp_dir)
+        };
+        let wip = vec_stringify!["a/001.parquet", "b/001.parquet"];
+
+        // resume keeps the wip items to load first
+        let tmp_dir = new_dir()?;
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?
+            .with_on_startup_wip(&OnStartupWip::Resume)?;
+        assert_eq!(wl.wip_list, wip);
+        drop(wl);
+        tmp_dir.close().unwrap();
+
+        // requeue puts them back at the front of todo, once each
+        let tmp_dir = new_dir()?;
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?
+            .with_on_startup_wip(&OnStartupWip::Requeue)?;
+        assert!(wl.wip_list.is_empty());
+        let todo = fs::read_to_string(format!("{}/todo", work_lists_dir))?;
+        let wip_file = fs::read_to_string(format!("{}/wip", work_lists_dir))?;
+        drop(wl);
+        tmp_dir.close().unwrap();
+        assert_eq!(todo, "a/001.parquet\nb/001.parquet\nc/001.parquet\n");
+        assert_eq!(wip_file, "");
+
+        // abort stops the run, changing nothing. An empty completed file counts as missing.
+        let tmp_dir = new_dir()?;
+        tmp_dir.child("completed").write_str("\n")?;
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let res = WorkLists::new(work_lists_dir.as_str(), 2, 1)?
+            .with_on_startup_wip(&OnStartupWip::Abort);
+        let wip_file = fs::read_to_string(format!("{}/wip", work_lists_dir))?;
+        tmp_dir.close().unwrap();
+        let Err(e) = res else {
+            panic!("should abort with wip items and an empty completed")
+        };
+        assert!(e.to_string().contains("on_startup_wip is abort"));
+        assert_eq!(wip_file, "a/001.parquet\nb/001.parquet\n");
+
+        // with completed items, it's a run that stopped part way, so abort is never hit
+        let tmp_dir = new_dir()?;
+        tmp_dir.child("completed").write_str("z/001.parquet\n")?;
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?
+            .with_on_startup_wip(&OnStartupWip::Abort)?;
+        assert_eq!(wl.wip_list, wip);
+        drop(wl);
+        tmp_dir.close().unwrap();
+
+        Ok(())
+    }
+
     #[test]
     fn test_new_with_todo_file() {
         let work_lists_dir = format!("{}/{}", TESTDATA_DIR, "todo_only");
         let result = WorkLists::new(
             work_lists_dir.as_str(), // dir exists
             1,
+            1,
         );
         let expected_wl: WorkLists = work_lists_test_struct(1, work_lists_dir.clone(), vec![]);
 
@@ -281,6 +872,26 @@ mod tests {
         assert_eq!(expected_wl, wl);
     }
 
+    #[test]
+    fn test_new_fails_if_dir_locked() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child("todo").write_str("a/001.parquet\n")?;
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+
+        let wl = WorkLists::new(work_lists_dir.as_str(), 1, 1)?;
+        let second = WorkLists::new(work_lists_dir.as_str(), 1, 1);
+        assert!(second.is_err(), "should not get the lock while held");
+        assert!(second.unwrap_err().to_string().contains("is locked"));
+
+        // released once the first is dropped
+        drop(wl);
+        let third = WorkLists::new(work_lists_dir.as_str(), 1, 1);
+        tmp_dir.close().unwrap();
+        assert!(third.is_ok(), "should get the lock once released");
+
+        Ok(())
+    }
+
     #[test]
     fn test_new_with_wip_no_comments() {
         let work_lists_dir = format!("{}/{}", TESTDATA_DIR, "wip_no_whitespace_or_comments");
@@ -292,6 +903,7 @@ mod tests {
         let result = WorkLists::new(
             work_lists_dir.as_str(), // dir exists
             2,
+            1,
         );
         assert!(
             result.is_ok(),
@@ -314,6 +926,7 @@ mod tests {
         let result = WorkLists::new(
             work_lists_dir.as_str(), // dir exists
             1, // there are 2 items in wip file, even though we only request 1 batch
+            1,
         );
 
         assert!(
@@ -337,6 +950,7 @@ mod tests {
         let result = WorkLists::new(
             work_lists_dir.as_str(), // dir exists
             2,
+            1,
         );
         assert!(
             result.is_ok(),
@@ -408,6 +1022,326 @@ mod tests {
         assert_eq!(wl.wip_list, vec!["dates".to_string()]);
     }
 
+    #[test]
+    fn test_new_completed_flush_every_zero() {
+        let work_lists_dir = format!("{}/{}", TESTDATA_DIR, "todo_only");
+        let result = WorkLists::new(work_lists_dir.as_str(), 1, 0);
+        assert!(
+            result.is_err(),
+            "Expected an error as must flush completed items at least every item."
+        );
+    }
+
+    #[test]
+    fn test_mark_completed_batched_flush_same_final_state() {
+        let tmp_dir = TempDir::new().unwrap();
+        let src_dir = format!("{}/{}", TESTDATA_DIR, "mark_completed_new");
+        tmp_dir.copy_from(src_dir.as_str(), &["*"]).unwrap();
+
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wip_list: Vec<String> = vec_stringify!["apple", "banana"];
+
+        // We don't use new(), as we aren't unit testing that here.
+        let mut wl: WorkLists = work_lists_test_struct(2, work_lists_dir.clone(), wip_list);
+        wl.completed_flush_every = 3;
+
+        // TEST THE METHOD - nothing written until we flush
+        wl.mark_completed("apple".to_string()).unwrap();
+        wl.mark_completed("banana".to_string()).unwrap();
+
+        assert!(
+            !Path::new(wl.filename_completed.as_str()).exists(),
+            "should not have written completed file before flushing"
+        );
+        let wip_file: String = fs::read_to_string(wl.filename_wip.as_str()).unwrap();
+        assert_eq!(wip_file, "apple\nbanana\n");
+
+        // final flush at end of batch
+        let result = wl.flush_completed();
+        assert!(
+            result.is_ok(),
+            "should have flushed pending completed items"
+        );
+
+        let completed_file: String = fs::read_to_string(wl.filename_completed.as_str()).unwrap();
+        let wip_file: String = fs::read_to_string(wl.filename_wip.as_str()).unwrap();
+        tmp_dir.close().unwrap(); // can close file now
+
+        // same final state as marking each as completed with a flush every item
+        assert_eq!(completed_file, "apple\nbanana\n");
+        assert_eq!(wip_file, "");
+        assert!(wl.wip_list.is_empty());
+        assert!(wl.pending_completed.is_empty());
+    }
+
+    #[test]
+    fn test_mark_skipped() {
+        let tmp_dir = TempDir::new().unwrap();
+        let src_dir = format!("{}/{}", TESTDATA_DIR, "mark_completed_new");
+        tmp_dir.copy_from(src_dir.as_str(), &["*"]).unwrap();
+
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wip_list: Vec<String> = vec_stringify!["apple", "banana"];
+
+        // We don't use new(), as we aren't unit testing that here.
+        let mut wl: WorkLists = work_lists_test_struct(2, work_lists_dir.clone(), wip_list);
+        wl.completed_flush_every = 3;
+
+        // TEST THE METHOD - apple's completion is still pending when banana fails
+        wl.mark_completed("apple".to_string()).unwrap();
+        let result = wl.mark_skipped("banana".to_string(), "db error:\n  it broke");
+
+        assert!(result.is_ok(), "should have written failed and wip files");
+        let completed_file: String = fs::read_to_string(wl.filename_completed.as_str()).unwrap();
+        let failed_file: String = fs::read_to_string(wl.filename_failed.as_str()).unwrap();
+        let wip_file: String = fs::read_to_string(wl.filename_wip.as_str()).unwrap();
+        tmp_dir.close().unwrap(); // can close file now
+
+        assert_eq!(completed_file, "apple\n", "failed items are not completed");
+        assert_eq!(failed_file, "banana\tdb error: it broke\n");
+        assert_eq!(wip_file, "");
+        assert!(wl.wip_list.is_empty());
+    }
+
+    #[test]
+    fn test_mark_quarantined() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        tmp_dir.child("todo").write_str("")?;
+        tmp_dir.child("wip").write_str("apple\nbanana\n")?;
+
+        let mut wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?.with_max_attempts(Some(3))?;
+        wl.mark_quarantined("banana".to_string(), "column id is compressed with GZIP")?;
+        let quarantine_file = fs::read_to_string(wl.filename_quarantine.as_str())?;
+        let wip_file = fs::read_to_string(wl.filename_wip.as_str())?;
+        let failed_exists = Path::new(wl.filename_failed.as_str()).exists();
+        let attempts_exists = Path::new(wl.filename_attempts.as_str()).exists();
+        drop(wl); // releases the lock
+        tmp_dir.close().unwrap();
+
+        assert_eq!(
+            quarantine_file,
+            "banana\tcolumn id is compressed with GZIP\n"
+        );
+        assert_eq!(wip_file, "apple\n");
+        assert!(!failed_exists, "quarantined items aren't failed");
+        assert!(!attempts_exists, "nor counted as attempts");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_not_ready() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        tmp_dir.child("todo").write_str("cherry\ndate\n")?;
+        tmp_dir.child("wip").write_str("apple\nbanana\n")?;
+
+        let mut wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?;
+        wl.mark_not_ready(&["banana".to_string()])?;
+        let todo_file = fs::read_to_string(wl.filename_todo.as_str())?;
+        let wip_file = fs::read_to_string(wl.filename_wip.as_str())?;
+        let wip_list = wl.wip_list.clone();
+        drop(wl); // releases the lock
+        tmp_dir.close().unwrap();
+
+        assert_eq!(todo_file, "cherry\ndate\nbanana\n", "to the end of todo");
+        assert_eq!(wip_file, "apple\n");
+        assert_eq!(wip_list, vec_stringify!["apple"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_failed_counts_attempts() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        tmp_dir.child("todo").write_str("")?;
+        tmp_dir.child("wip").write_str("apple\nbanana\n")?;
+
+        let mut wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?.with_max_attempts(Some(3))?;
+        let dead_lettered = [
+            wl.mark_failed("banana".to_string(), "it broke")?,
+            wl.mark_failed("apple".to_string(), "it broke")?,
+            wl.mark_failed("banana".to_string(), "it broke")?,
+        ];
+        let attempts_file = fs::read_to_string(wl.filename_attempts.as_str())?;
+        let wip_file = fs::read_to_string(wl.filename_wip.as_str())?;
+        drop(wl); // releases the lock
+
+        // the counts are read back on the next run, and dropped once an item completes
+        let mut wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?;
+        let attempts_read = wl.attempts.clone();
+        wl.mark_completed("banana".to_string())?;
+        let attempts_after_completed = fs::read_to_string(wl.filename_attempts.as_str())?;
+        tmp_dir.close().unwrap();
+
+        assert_eq!(dead_lettered, [false, false, false]);
+        assert_eq!(attempts_file, "apple\t1\nbanana\t2\n");
+        assert_eq!(wip_file, "apple\nbanana\n", "failed items stay in wip");
+        assert_eq!(
+            attempts_read,
+            BTreeMap::from([("apple".to_string(), 1), ("banana".to_string(), 2)])
+        );
+        assert_eq!(attempts_after_completed, "apple\t1\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_failed_dead_letters_at_max_attempts() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        tmp_dir.child("todo").write_str("cherry\n")?;
+        tmp_dir.child("wip").write_str("apple\nbanana\n")?;
+        tmp_dir.child("attempts").write_str("banana\t1\n")?; // from a previous run
+
+        let mut wl = WorkLists::new(work_lists_dir.as_str(), 2, 1)?.with_max_attempts(Some(2))?;
+        let dead_lettered = wl.mark_failed("banana".to_string(), "db error:\n  it broke")?;
+        let dead_letter_file = fs::read_to_string(wl.filename_dead_letter.as_str())?;
+        let attempts_file = fs::read_to_string(wl.filename_attempts.as_str())?;
+        let wip_file = fs::read_to_string(wl.filename_wip.as_str())?;
+        let wip_list = wl.wip_list.clone();
+        tmp_dir.close().unwrap();
+
+        assert!(dead_lettered, "second failure should reach max_attempts");
+        assert_eq!(dead_letter_file, "banana\tdb error: it broke\n");
+        assert_eq!(attempts_file, "");
+        assert_eq!(wip_file, "apple\n");
+        assert_eq!(wip_list, vec_stringify!["apple"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_max_attempts_zero_is_an_error() -> Result<()> {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        tmp_dir.child("todo").write_str("")?;
+
+        let result = WorkLists::new(work_lists_dir.as_str(), 1, 1)?.with_max_attempts(Some(0));
+        tmp_dir.close().unwrap();
+
+        assert!(result.is_err(), "an item must get at least one attempt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_items_does_not_modify_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let src_dir = format!("{}/{}", TESTDATA_DIR, "wip_with_whitespace_and_comments");
+        tmp_dir.copy_from(src_dir.as_str(), &["*"]).unwrap();
+
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wip_list: Vec<String> = vec_stringify!["ITEM_A", "ITEM_B"];
+        let wl: WorkLists = work_lists_test_struct(1, work_lists_dir.clone(), wip_list);
+
+        // TEST THE METHOD
+        let result = wl.pending_items();
+
+        let current_wip_file: String = fs::read_to_string(wl.filename_wip.as_str()).unwrap();
+        let current_todo_file: String = fs::read_to_string(wl.filename_todo.as_str()).unwrap();
+        tmp_dir.close().unwrap(); // can close file now
+
+        assert!(result.is_ok(), "should have read the todo file");
+        assert_eq!(
+            result.unwrap(),
+            vec_stringify!["ITEM_A", "ITEM_B", "ITEM_C", "ITEM_D", "ITEM_E"]
+        );
+
+        let src_wip_file: String = fs::read_to_string(format!("{}/wip", src_dir)).unwrap();
+        let src_todo_file: String = fs::read_to_string(format!("{}/todo", src_dir)).unwrap();
+        assert_eq!(current_wip_file, src_wip_file);
+        assert_eq!(current_todo_file, src_todo_file);
+    }
+
+    #[test]
+    fn test_add_todo() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_dir = format!("{}/{}", TESTDATA_DIR, "wip_with_whitespace_and_comments");
+        tmp_dir.copy_from(src_dir.as_str(), &["*"])?;
+
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let wip_list: Vec<String> = vec_stringify!["ITEM_A", "ITEM_B"];
+        let wl: WorkLists = work_lists_test_struct(1, work_lists_dir.clone(), wip_list);
+
+        // ITEM_A is in wip and ITEM_C in todo, so only ITEM_F and ITEM_G are new
+        let (added, already_done) =
+            wl.add_todo(&["ITEM_A", "ITEM_C", "ITEM_F", "ITEM_G", "ITEM_F"].map(String::from))?;
+        assert_eq!(added, 2);
+        assert!(already_done.is_empty());
+        assert_eq!(
+            wl.pending_items()?,
+            vec_stringify!["ITEM_A", "ITEM_B", "ITEM_C", "ITEM_D", "ITEM_E", "ITEM_F", "ITEM_G"]
+        );
+
+        // nothing new leaves todo alone
+        let todo_before = fs::read_to_string(wl.filename_todo.as_str())?;
+        assert_eq!(
+            wl.add_todo(&["ITEM_B", "ITEM_G"].map(String::from))?,
+            (0, vec![])
+        );
+        assert_eq!(fs::read_to_string(wl.filename_todo.as_str())?, todo_before);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_todo_skips_items_done_with() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_dir = format!("{}/{}", TESTDATA_DIR, "wip_with_whitespace_and_comments");
+        tmp_dir.copy_from(src_dir.as_str(), &["*"])?;
+
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+        let mut wl: WorkLists = work_lists_test_struct(1, work_lists_dir.clone(), vec![]);
+        fs::write(&wl.filename_completed, "ITEM_F\n")?;
+        wl.pending_completed = vec_stringify!["ITEM_G"];
+        fs::write(&wl.filename_failed, "ITEM_H\tfailed to load\n")?;
+        fs::write(&wl.filename_quarantine, "ITEM_I\tcodec not allowed\n")?;
+        fs::write(&wl.filename_dead_letter, "ITEM_J\tfailed 3 times\n")?;
+
+        let todo_before = fs::read_to_string(wl.filename_todo.as_str())?;
+        let done = ["ITEM_F", "ITEM_G", "ITEM_H", "ITEM_I", "ITEM_J"].map(String::from);
+        assert_eq!(wl.add_todo(&done)?, (0, done.to_vec()));
+        assert_eq!(fs::read_to_string(wl.filename_todo.as_str())?, todo_before);
+
+        assert_eq!(
+            wl.add_todo(&["ITEM_H", "ITEM_K"].map(String::from))?,
+            (1, vec_stringify!["ITEM_H"])
+        );
+        assert_eq!(
+            wl.pending_items()?.last().map(String::as_str),
+            Some("ITEM_K")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_batch_trim_keys() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        tmp_dir
+            .child("todo")
+            .write_str("  padded.parquet\t\r\ntrailing space.parquet \n")?;
+        let work_lists_dir = format!("{}", tmp_dir.path().display());
+
+        let trimmed = WorkLists::new(work_lists_dir.as_str(), 2, 1)?;
+        assert_eq!(
+            trimmed.pending_items()?,
+            vec_stringify!["padded.parquet", "trailing space.parquet"]
+        );
+        drop(trimmed); // frees the dir's lock
+
+        // the key's trailing space is part of it
+        let mut untrimmed = WorkLists::new(work_lists_dir.as_str(), 2, 1)?.with_trim_keys(false);
+        assert_eq!(
+            untrimmed.next_batch()?.wip_list,
+            vec_stringify!["  padded.parquet\t", "trailing space.parquet "]
+        );
+        assert_eq!(
+            fs::read_to_string(untrimmed.filename_wip.as_str())?,
+            "  padded.parquet\t\ntrailing space.parquet \n"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_next_batch_existing_wip_list() {
         let tmp_dir = TempDir::new().unwrap();